@@ -0,0 +1,121 @@
+extern crate rucene;
+
+use rucene::core::codec::CodecEnum;
+use rucene::core::index::check_index::CheckIndex;
+use rucene::core::store::directory::FSDirectory;
+use rucene::error::Result;
+
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+const USAGE: &str = "\
+Usage: check_index <index_dir> [-exorcise] [-segment X] [-segment Y] ...
+
+  -exorcise: actually write a new segments_N file, removing any problematic segments
+  -segment X: only check the specified segments. This can be specified multiple
+              times, to check more than one segment, eg '-segment _2 -segment _a'.
+              You can't use this with the -exorcise option
+
+**WARNING**: -exorcise *LOSES DATA*. This should only be used on an emergency basis as it will
+cause documents (perhaps many) to be permanently removed from the index. Always make a backup
+copy of your index before running this! Do not run this tool on an index that is actively being
+written to. You have been warned!
+
+Run without -exorcise, this tool will open the index, report version information and report any
+exceptions it hits and what action it would take if -exorcise were specified. With -exorcise,
+this tool will remove any segments that have issues and write a new segments_N file. This means
+all documents contained in the affected segments will be removed.
+
+This tool exits with exit code 1 if the index cannot be opened or has any corruption, else 0.";
+
+struct Options {
+    index_path: String,
+    exorcise: bool,
+    only_segments: Vec<String>,
+}
+
+fn parse_options() -> std::result::Result<Options, String> {
+    let mut index_path = None;
+    let mut exorcise = false;
+    let mut only_segments = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-exorcise" => exorcise = true,
+            "-segment" => match args.next() {
+                Some(segment) => only_segments.push(segment),
+                None => return Err("missing name for -segment option".into()),
+            },
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ => {
+                if index_path.is_some() {
+                    return Err(format!("unexpected extra argument: {}", arg));
+                }
+                index_path = Some(arg);
+            }
+        }
+    }
+    let index_path = index_path.ok_or_else(|| "index directory is required".to_string())?;
+    if exorcise && !only_segments.is_empty() {
+        return Err("-exorcise cannot be used with -segment".into());
+    }
+    Ok(Options {
+        index_path,
+        exorcise,
+        only_segments,
+    })
+}
+
+fn run(options: &Options) -> Result<bool> {
+    // `FSDirectory` would create a missing directory
+    if !Path::new(&options.index_path).is_dir() {
+        return Err(format!("{} is not a directory", options.index_path).into());
+    }
+    let directory = Arc::new(FSDirectory::with_path(&options.index_path)?);
+    let mut checker: CheckIndex<FSDirectory, CodecEnum> = CheckIndex::new(directory)?;
+    checker.set_info_stream(Box::new(io::stdout()));
+    println!("\nOpening index @ {}\n", options.index_path);
+
+    let status = checker.check_index(&options.only_segments)?;
+    if status.missing_segments {
+        return Ok(false);
+    }
+    if !status.clean {
+        if !options.exorcise {
+            println!(
+                "WARNING: would write new segments file, and {} documents would be lost, if \
+                 -exorcise were specified\n",
+                status.tot_lose_doc_count
+            );
+        } else {
+            println!(
+                "WARNING: {} documents will be lost\n",
+                status.tot_lose_doc_count
+            );
+            checker.exorcise_index(&status)?;
+            println!("OK");
+        }
+    }
+    Ok(status.clean)
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("ERROR: {}\n\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+    match run(&options) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("ERROR: {:?}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+extern crate rucene;
+
+use rucene::core::codec::field_infos::FieldInfo;
+use rucene::core::codec::segment_infos::{
+    generation_from_segments_file_name, SegmentInfos, INDEX_FILE_OLD_SEGMENT_GEN,
+    INDEX_FILE_SEGMENTS,
+};
+use rucene::core::codec::{Codec, CodecEnum, Fields, TermIterator, Terms};
+use rucene::core::doc::{DocumentStoredFieldVisitor, Fieldable};
+use rucene::core::index::reader::{LeafReader, SegmentReader};
+use rucene::core::store::directory::{Directory, FSDirectory};
+use rucene::core::store::IOContext;
+use rucene::error::Result;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+const USAGE: &str = "\
+Usage: rucene-tool <index_dir> <command> [args]
+
+Commands:
+  segments          list the segments of the latest commit with their doc counts, delete
+                    counts, codec, size and diagnostics
+  fields            list the fields with their index options, doc values type and point dims
+  terms <field> [n] list the top n (default 20) terms of the field by doc freq
+  doc <id>          dump the stored fields of the document with the (global) doc id
+  commits           list the commit points of the index
+
+The index is only read, it's safe to run the tool on an index being written to.";
+
+type Infos = SegmentInfos<FSDirectory, CodecEnum>;
+type Reader = SegmentReader<FSDirectory, CodecEnum>;
+
+fn open_readers(infos: &Infos) -> Result<Vec<Reader>> {
+    infos
+        .segments
+        .iter()
+        .map(|si| SegmentReader::open(si, &IOContext::READ))
+        .collect()
+}
+
+fn size_mb(directory: &FSDirectory, files: impl IntoIterator<Item = String>) -> f64 {
+    let size: i64 = files
+        .into_iter()
+        .filter_map(|f| directory.file_length(&f).ok())
+        .sum();
+    size as f64 / (1024.0 * 1024.0)
+}
+
+fn segments(directory: &Arc<FSDirectory>) -> Result<()> {
+    let infos: Infos = SegmentInfos::read_latest_commit(directory)?;
+    println!(
+        "{}: {} segments, version={}",
+        infos.segment_file_name().unwrap_or_default(),
+        infos.len(),
+        infos.version
+    );
+    println!(
+        "{:<12} {:>10} {:>10} {:>10} {:<10} {:>8} {:>10}",
+        "name", "max_doc", "num_docs", "del_count", "codec", "compound", "size(MB)"
+    );
+    for si in &infos.segments {
+        println!(
+            "{:<12} {:>10} {:>10} {:>10} {:<10} {:>8} {:>10.3}",
+            si.info.name,
+            si.info.max_doc(),
+            si.info.max_doc() - si.del_count(),
+            si.del_count(),
+            si.info.codec().name(),
+            si.info.is_compound_file(),
+            size_mb(directory, si.files())
+        );
+        let diagnostics: BTreeMap<_, _> = si.info.diagnostics.iter().collect();
+        for (key, value) in diagnostics {
+            println!("    {}={}", key, value);
+        }
+    }
+    Ok(())
+}
+
+fn fields(directory: &Arc<FSDirectory>) -> Result<()> {
+    let infos: Infos = SegmentInfos::read_latest_commit(directory)?;
+    // the field infos of a field are the same in all segments, except for
+    // the features the later segments added
+    let mut fields: BTreeMap<String, (FieldInfo, usize)> = BTreeMap::new();
+    for reader in open_readers(&infos)? {
+        for fi in reader.field_infos().by_name.values() {
+            fields
+                .entry(fi.name.clone())
+                .and_modify(|e| e.1 += 1)
+                .or_insert_with(|| (fi.as_ref().clone(), 1));
+        }
+    }
+    println!(
+        "{:<24} {:>6} {:<36} {:<14} {:>5} {:>7} {:>10} {:>8}",
+        "name",
+        "number",
+        "index_options",
+        "doc_values",
+        "norms",
+        "vectors",
+        "point_dims",
+        "segments"
+    );
+    for (name, (fi, segments)) in &fields {
+        println!(
+            "{:<24} {:>6} {:<36} {:<14} {:>5} {:>7} {:>10} {:>8}",
+            name,
+            fi.number,
+            format!("{:?}", fi.index_options),
+            format!("{:?}", fi.doc_values_type),
+            fi.has_norms(),
+            fi.has_store_term_vector,
+            format!("{}x{}", fi.point_dimension_count, fi.point_num_bytes),
+            segments
+        );
+    }
+    Ok(())
+}
+
+fn terms(directory: &Arc<FSDirectory>, field: &str, top_n: usize) -> Result<()> {
+    let infos: Infos = SegmentInfos::read_latest_commit(directory)?;
+    // k-way merge of the sorted terms of the segments, keyed by the current
+    // term of each segment's iterator
+    let mut iters = vec![];
+    let mut queue = BinaryHeap::new();
+    for reader in open_readers(&infos)? {
+        if let Some(terms) = reader.fields()?.terms(field)? {
+            let mut iter = terms.iterator()?;
+            if let Some(term) = iter.next()? {
+                queue.push(Reverse((term, iters.len())));
+                iters.push(iter);
+            }
+        }
+    }
+
+    // min heap of the top n terms, the worst one (lowest doc freq, then
+    // greatest term) on top
+    let mut top = BinaryHeap::with_capacity(top_n + 1);
+    let mut unique_terms = 0;
+    while let Some(Reverse((term, idx))) = queue.pop() {
+        let mut doc_freq = 0;
+        let mut pending = Some(idx);
+        while let Some(idx) = pending {
+            doc_freq += i64::from(iters[idx].doc_freq()?);
+            if let Some(next_term) = iters[idx].next()? {
+                queue.push(Reverse((next_term, idx)));
+            }
+            pending = match queue.peek() {
+                Some(Reverse((t, i))) if *t == term => Some(*i),
+                _ => None,
+            };
+            if pending.is_some() {
+                queue.pop();
+            }
+        }
+        unique_terms += 1;
+        top.push(Reverse((doc_freq, Reverse(term))));
+        if top.len() > top_n {
+            top.pop();
+        }
+    }
+    println!("field {}: {} unique terms", field, unique_terms);
+
+    println!("{:>10}  term", "doc_freq");
+    for Reverse((doc_freq, Reverse(term))) in top.into_sorted_vec() {
+        println!("{:>10}  {}", doc_freq, String::from_utf8_lossy(&term));
+    }
+    Ok(())
+}
+
+fn doc(directory: &Arc<FSDirectory>, doc_id: i32) -> Result<()> {
+    let infos: Infos = SegmentInfos::read_latest_commit(directory)?;
+    let mut doc_base = 0;
+    for reader in open_readers(&infos)? {
+        let max_doc = reader.si.info.max_doc();
+        if doc_id >= doc_base && doc_id < doc_base + max_doc {
+            let leaf_doc = doc_id - doc_base;
+            let deleted = !reader.live_docs.get(leaf_doc as usize)?;
+            println!(
+                "doc {} (segment {}, doc {}{})",
+                doc_id,
+                reader.si.info.name,
+                leaf_doc,
+                if deleted { ", deleted" } else { "" }
+            );
+            let mut visitor = DocumentStoredFieldVisitor::new(&[]);
+            LeafReader::document(&reader, leaf_doc, &mut visitor)?;
+            for field in visitor.document().fields {
+                match field.field.field_data() {
+                    Some(value) => println!("  {}: {}", field.field.name(), value),
+                    None => println!("  {}: <none>", field.field.name()),
+                }
+            }
+            return Ok(());
+        }
+        doc_base += max_doc;
+    }
+    Err(format!("doc {} is out of bounds, max_doc={}", doc_id, doc_base).into())
+}
+
+fn commits(directory: &Arc<FSDirectory>) -> Result<()> {
+    let mut commits = vec![];
+    for name in directory.list_all()? {
+        if name.starts_with(INDEX_FILE_SEGMENTS) && name != INDEX_FILE_OLD_SEGMENT_GEN {
+            commits.push((generation_from_segments_file_name(&name)?, name));
+        }
+    }
+    commits.sort();
+    println!(
+        "{:>10} {:<16} {:>8} {:>10} {:>10}",
+        "generation", "segments_file", "segments", "max_doc", "size(MB)"
+    );
+    for (generation, name) in commits {
+        match SegmentInfos::<FSDirectory, CodecEnum>::read_commit(directory, &name) {
+            Ok(infos) => {
+                println!(
+                    "{:>10} {:<16} {:>8} {:>10} {:>10.3}",
+                    generation,
+                    name,
+                    infos.len(),
+                    infos.total_max_doc(),
+                    size_mb(directory, infos.files(true))
+                );
+                let mut user_data: Vec<_> = infos.user_data().iter().collect();
+                user_data.sort();
+                for (k, v) in user_data {
+                    println!("{:>10}   {}={}", "", k, v);
+                }
+            }
+            Err(e) => println!("{:>10} {:<16} unreadable: {:?}", generation, name, e),
+        }
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<()> {
+    if args.len() < 2 {
+        return Err("missing index directory or command".into());
+    }
+    // `FSDirectory` would create a missing directory
+    if !Path::new(&args[0]).is_dir() {
+        return Err(format!("{} is not a directory", args[0]).into());
+    }
+    let directory = Arc::new(FSDirectory::with_path(&args[0])?);
+    match (args[1].as_str(), &args[2..]) {
+        ("segments", []) => segments(&directory),
+        ("fields", []) => fields(&directory),
+        ("terms", [field]) => terms(&directory, field, 20),
+        ("terms", [field, n]) => match n.parse() {
+            Ok(n) => terms(&directory, field, n),
+            Err(_) => Err(format!("invalid number of terms: {}", n).into()),
+        },
+        ("doc", [id]) => match id.parse() {
+            Ok(id) => doc(&directory, id),
+            Err(_) => Err(format!("invalid doc id: {}", id).into()),
+        },
+        ("commits", []) => commits(&directory),
+        (command, _) => Err(format!("unknown command or arguments: {}", command).into()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("ERROR: {}\n\n{}", e, USAGE);
+        process::exit(1);
+    }
+}
@@ -18,7 +18,7 @@ use std::fmt;
 use std::sync::Arc;
 
 use core::codec::{codec_util, Codec};
-use core::store::directory::Directory;
+use core::store::directory::{Directory, Lock};
 use core::store::io::{DataInput, DataOutput, IndexInput, IndexOutput};
 use core::store::IOContext;
 
@@ -314,6 +314,12 @@ impl<D: Directory> Directory for Lucene50CompoundReader<D> {
     fn rename(&self, _source: &str, _dest: &str) -> Result<()> {
         unimplemented!()
     }
+
+    fn obtain_lock(&self, _name: &str) -> Result<Box<dyn Lock>> {
+        bail!(ErrorKind::UnsupportedOperation(Cow::Borrowed(
+            "compound file can't be locked"
+        )))
+    }
 }
 
 impl<D: Directory> fmt::Display for Lucene50CompoundReader<D> {
@@ -14,12 +14,16 @@
 use core::codec::doc_values::{DocValuesConsumer, NumericDocValues};
 use core::codec::field_infos::FieldInfo;
 use core::codec::segment_infos::SegmentWriteState;
+use core::codec::Sorter as IndexSorter;
 use core::codec::{Codec, INT_BYTES, LONG_BYTES};
 use core::codec::{DVSortDocComparator, SorterDocComparator, SorterDocMap};
 use core::doc::DocValuesType;
 use core::doc::Term;
 use core::index::reader::{CachedBinaryDVs, CachedNumericDVs};
-use core::search::sort_field::{SortField, SortFieldType, SortedNumericSelectorType};
+use core::search::sort_field::{
+    SortField, SortFieldMissingValue, SortFieldType, SortedNumericSelectorType,
+    SortedSetSelectorType,
+};
 use core::search::NO_MORE_DOCS;
 use core::store::directory::Directory;
 use core::store::io::DataOutput;
@@ -251,7 +255,10 @@ impl DocValuesWriter for BinaryDocValuesWriter {
         _num_doc: i32,
         _sort_field: &SortField,
     ) -> Result<Box<dyn SorterDocComparator>> {
-        unreachable!()
+        bail!(IllegalArgument(format!(
+            "Binary doc values field '{}' can't be used to sort an index",
+            self.field_info.name
+        )))
     }
 }
 
@@ -352,34 +359,18 @@ impl DocValuesWriter for NumericDocValuesWriter {
     ) -> Result<Box<dyn SorterDocComparator>> {
         debug_assert!(self.final_values.is_none());
         let final_values = self.pending.build();
-        let default_value = if let Some(d) = sort_field.missing_value() {
-            match sort_field.field_type() {
-                SortFieldType::Int => d.get_int().unwrap() as i64,
-                SortFieldType::Long => d.get_long().unwrap(),
-                _ => unreachable!(),
-            }
-        } else {
-            0
-        };
-        let mut values = vec![default_value; num_doc as usize];
+        let field_type = IndexSorter::sort_field_type(sort_field);
+        let mut values = vec![IndexSorter::numeric_missing_sort_key(sort_field); num_doc as usize];
+        // only the documents with a value are buffered
         let iter = BitSetIterator::new(&self.docs_with_field);
-        for doc_id in iter {
-            values[doc_id as usize] = final_values.get(doc_id)?;
+        for (doc_id, value) in iter.zip(final_values.iterator()) {
+            values[doc_id as usize] = IndexSorter::numeric_sort_key(field_type, value);
         }
 
-        // the float type is only 32 bits valid for sort
         let cmp_fn: fn(v1: &i64, v2: &i64) -> Ordering = if sort_field.is_reverse() {
-            if sort_field.field_type() == SortFieldType::Float {
-                |d1: &i64, d2: &i64| (*d2 as i32).cmp(&(*d1 as i32))
-            } else {
-                |d1: &i64, d2: &i64| d2.cmp(d1)
-            }
+            |d1: &i64, d2: &i64| d2.cmp(d1)
         } else {
-            if sort_field.field_type() == SortFieldType::Float {
-                |d1: &i64, d2: &i64| (*d1 as i32).cmp(&(*d2 as i32))
-            } else {
-                |d1: &i64, d2: &i64| d1.cmp(d2)
-            }
+            |d1: &i64, d2: &i64| d1.cmp(d2)
         };
 
         self.final_values = Some(final_values);
@@ -621,15 +612,13 @@ impl DocValuesWriter for SortedNumericDocValuesWriter {
         let counts_iter = self.final_values_count.as_ref().unwrap().iterator();
         let mut values_iter = self.final_values.as_ref().unwrap().iterator();
 
-        let select_type = match sort_field {
-            SortField::SortedNumeric(s) => s.selector(),
+        let (select_type, numeric_type) = match sort_field {
+            SortField::SortedNumeric(s) => (s.selector(), s.numeric_type()),
             _ => unreachable!(),
         };
 
-        // TODO: currently the missing value is auto set to 0.
-        // NOTE: if missing is explicitly set to none zero, then you must convert it to sorted bit
-        // if type is float/double, else the compare may be in-corrent.
-        let mut data = vec![0; num_doc as usize];
+        // floats and doubles are indexed as sortable bits, which are the sort keys
+        let mut data = vec![IndexSorter::numeric_missing_sort_key(sort_field); num_doc as usize];
         for (i, v) in counts_iter.enumerate() {
             if v > 0 {
                 let val = match select_type {
@@ -647,23 +636,19 @@ impl DocValuesWriter for SortedNumericDocValuesWriter {
                         values_iter.next().unwrap()
                     }
                 };
-                data[i] = val;
+                data[i] = if numeric_type == SortFieldType::Float {
+                    // the float type is only 32 bits valid for sort
+                    i64::from(val as i32)
+                } else {
+                    val
+                };
             }
         }
 
-        // the float type is only 32 bits valid for sort
         let cmp_fn: fn(v1: &i64, v2: &i64) -> Ordering = if sort_field.is_reverse() {
-            if sort_field.field_type() == SortFieldType::Float {
-                |d1: &i64, d2: &i64| (*d2 as i32).cmp(&(*d1 as i32))
-            } else {
-                |d1: &i64, d2: &i64| d2.cmp(d1)
-            }
+            |d1: &i64, d2: &i64| d2.cmp(d1)
         } else {
-            if sort_field.field_type() == SortFieldType::Float {
-                |d1: &i64, d2: &i64| (*d1 as i32).cmp(&(*d2 as i32))
-            } else {
-                |d1: &i64, d2: &i64| d1.cmp(d2)
-            }
+            |d1: &i64, d2: &i64| d1.cmp(d2)
         };
 
         Ok(Box::new(DVSortDocComparator::new(data, cmp_fn)))
@@ -881,13 +866,14 @@ impl SortedDocValuesWriter {
         &self,
         max_doc: i32,
         doc_map: &dyn SorterDocMap,
+        ord_map: &[i32],
         mut iter: LongValuesIterator,
     ) -> Vec<i32> {
         let mut values = vec![-1; max_doc as usize];
         let doc_id_iter = BitSetIterator::new(&self.docs_with_field);
         for doc_id in doc_id_iter {
             let new_id = doc_map.old_to_new(doc_id);
-            values[new_id as usize] = iter.next().unwrap() as i32;
+            values[new_id as usize] = ord_map[iter.next().unwrap() as usize];
         }
         debug_assert!(iter.next().is_none());
         values
@@ -904,7 +890,8 @@ impl DocValuesWriter for SortedDocValuesWriter {
         consumer: &mut W,
     ) -> Result<()> {
         let max_doc = state.segment_info.max_doc();
-        debug_assert!(self.pending.size() == max_doc as i64);
+        // only the documents with a value are buffered
+        debug_assert!(self.pending.size() <= max_doc as i64);
 
         let value_count = self.hash.len();
 
@@ -925,7 +912,7 @@ impl DocValuesWriter for SortedDocValuesWriter {
         let mut values_iter = SortedValuesIterator::new(&self.hash.ids, value_count, &self.hash);
 
         if let Some(sort_map) = sort_map {
-            let values = self.sort_doc_values(max_doc, sort_map, pending.iterator());
+            let values = self.sort_doc_values(max_doc, sort_map, &ord_map, pending.iterator());
             let mut ords_iter = SortSortedOrdsIter {
                 ords: values,
                 doc_upto: 0,
@@ -949,7 +936,11 @@ impl DocValuesWriter for SortedDocValuesWriter {
     ) -> Result<Box<dyn SorterDocComparator>> {
         debug_assert!(self.final_ords.is_none() && self.final_ord_map.is_empty());
 
-        let mut data = vec![i32::min_value(); num_doc as usize];
+        let missing_ord = match sort_field.missing_placement() {
+            Some(SortFieldMissingValue::StringLast) => i32::max_value(),
+            _ => i32::min_value(),
+        };
+        let mut data = vec![missing_ord; num_doc as usize];
         self.hash.sort();
         let value_count = self.hash.len();
 
@@ -963,7 +954,7 @@ impl DocValuesWriter for SortedDocValuesWriter {
         let doc_id_iter = BitSetIterator::new(&self.docs_with_field);
         for doc in doc_id_iter {
             let i = value_iter.next().unwrap() as usize;
-            data[doc as usize] = self.final_ord_map[i];
+            data[doc as usize] = ord_map[i];
         }
         self.final_ord_map = ord_map;
 
@@ -1103,6 +1094,10 @@ pub struct SortedSetDocValuesWriter {
     hash: BytesRefHash,
     // the hash.pool is pointed to this, so it must be boxed
     _bytes_block_pool: Box<ByteBlockPool>,
+
+    final_ords: Option<PackedLongValues>,
+    final_ord_counts: Option<PackedLongValues>,
+    final_ord_map: Vec<i32>,
 }
 
 impl SortedSetDocValuesWriter {
@@ -1131,7 +1126,21 @@ impl SortedSetDocValuesWriter {
             max_count: 0,
             hash,
             _bytes_block_pool: bytes_block_pool,
+            final_ords: None,
+            final_ord_counts: None,
+            final_ord_map: vec![],
+        }
+    }
+
+    // sorts the values and returns the mapping from term id to ordinal
+    fn sorted_ord_map(&mut self) -> Vec<i32> {
+        let value_count = self.hash.len();
+        self.hash.sort();
+        let mut ord_map = vec![0i32; value_count];
+        for ord in 0..value_count {
+            ord_map[self.hash.ids[ord] as usize] = ord as i32;
         }
+        ord_map
     }
 
     pub fn add_value(&mut self, doc_id: DocId, value: &BytesRef) -> Result<()> {
@@ -1223,11 +1232,15 @@ impl SortedSetDocValuesWriter {
 }
 
 impl DocValuesWriter for SortedSetDocValuesWriter {
+    // `finish` may be called twice when the field is used for the index sort
     fn finish(&mut self, num_doc: i32) {
-        self.finish_current_doc();
+        if self.current_doc < num_doc {
+            self.finish_current_doc();
 
-        for _ in self.current_doc..num_doc {
-            self.pending_counts.add(0);
+            for _ in self.current_doc..num_doc {
+                self.pending_counts.add(0);
+            }
+            self.current_doc = num_doc;
         }
     }
 
@@ -1242,15 +1255,16 @@ impl DocValuesWriter for SortedSetDocValuesWriter {
         debug_assert!(self.pending_counts.size() == max_doc as i64);
 
         let value_count = self.hash.len();
-        let pending = self.pending.build();
-        let pending_counts = self.pending_counts.build();
-
-        self.hash.sort();
-        let mut ord_map = vec![0i32; value_count];
-
-        for ord in 0..value_count {
-            ord_map[self.hash.ids[ord] as usize] = ord as i32;
-        }
+        let (pending, pending_counts, ord_map) = if self.final_ords.is_some() {
+            (
+                self.final_ords.take().unwrap(),
+                self.final_ord_counts.take().unwrap(),
+                mem::replace(&mut self.final_ord_map, vec![]),
+            )
+        } else {
+            let ord_map = self.sorted_ord_map();
+            (self.pending.build(), self.pending_counts.build(), ord_map)
+        };
 
         let mut value_iter = SortedValuesIterator::new(&self.hash.ids, value_count, &self.hash);
 
@@ -1287,10 +1301,53 @@ impl DocValuesWriter for SortedSetDocValuesWriter {
 
     fn get_doc_comparator(
         &mut self,
-        _num_doc: i32,
-        _sort_field: &SortField,
+        num_doc: i32,
+        sort_field: &SortField,
     ) -> Result<Box<dyn SorterDocComparator>> {
-        unimplemented!()
+        debug_assert!(self.final_ords.is_none() && self.final_ord_counts.is_none());
+        let missing_last = match sort_field.missing_placement() {
+            Some(placement) => placement == SortFieldMissingValue::StringLast,
+            None => bail!(IllegalArgument(format!(
+                "SortedSet doc values field '{}' must be sorted with a string sort",
+                self.field_info.name
+            ))),
+        };
+        // a simple string sort selects the smallest value
+        let selector = match sort_field {
+            SortField::SortedSet(s) => s.selector(),
+            _ => SortedSetSelectorType::Min,
+        };
+
+        let ord_map = self.sorted_ord_map();
+        let ords = self.pending.build();
+        let ord_counts = self.pending_counts.build();
+
+        let missing_ord = if missing_last { i32::max_value() } else { -1 };
+        let mut data = vec![missing_ord; num_doc as usize];
+        let mut ords_iter = ords.iterator();
+        let mut doc_ords = Vec::with_capacity(self.max_count as usize);
+        for (doc, count) in ord_counts.iterator().enumerate() {
+            if count > 0 {
+                doc_ords.clear();
+                for _ in 0..count {
+                    doc_ords.push(ord_map[ords_iter.next().unwrap() as usize]);
+                }
+                // term ids are not in the order of the values
+                doc_ords.sort();
+                data[doc] = selector.select(&doc_ords);
+            }
+        }
+        self.final_ords = Some(ords);
+        self.final_ord_counts = Some(ord_counts);
+        self.final_ord_map = ord_map;
+
+        let cmp_fn: fn(v1: &i32, v2: &i32) -> Ordering = if sort_field.is_reverse() {
+            |d1: &i32, d2: &i32| d2.cmp(d1)
+        } else {
+            |d1: &i32, d2: &i32| d1.cmp(d2)
+        };
+
+        Ok(Box::new(DVSortDocComparator::new(data, cmp_fn)))
     }
 }
 
@@ -33,6 +33,12 @@ use serde::{Serialize, Serializer};
 use core::codec::points::{MAX_DIMENSIONS, MAX_NUM_BYTES};
 use core::codec::postings::{PER_FIELD_POSTING_FORMAT_KEY, PER_FIELD_POSTING_SUFFIX_KEY};
 use core::doc::{DocValuesType, IndexOptions};
+use core::util::VectorSimilarity;
+
+/// Attribute holding the dimension of the knn vectors of a field
+pub const KNN_VECTOR_DIMENSION_KEY: &str = "knn_vector.dimension";
+/// Attribute holding the similarity of the knn vectors of a field
+pub const KNN_VECTOR_SIMILARITY_KEY: &str = "knn_vector.similarity";
 
 /// Access to the Field Info file that describes document fields and whether or
 /// not they are indexed. Each segment has a separate Field Info file. Objects
@@ -295,6 +301,41 @@ impl FieldInfo {
     pub fn put_attribute(&self, key: String, value: String) -> Option<String> {
         self.attributes.write().unwrap().insert(key, value)
     }
+
+    /// Returns the dimension of the knn vectors of this field, 0 if it has none.
+    pub fn knn_vector_dimension(&self) -> u32 {
+        self.attribute(KNN_VECTOR_DIMENSION_KEY)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0)
+    }
+
+    pub fn knn_vector_similarity(&self) -> Result<VectorSimilarity> {
+        match self.attribute(KNN_VECTOR_SIMILARITY_KEY) {
+            Some(name) => VectorSimilarity::from_name(&name),
+            None => Ok(VectorSimilarity::default()),
+        }
+    }
+
+    pub fn set_knn_vector_attributes(
+        &self,
+        dimension: u32,
+        similarity: VectorSimilarity,
+    ) -> Result<()> {
+        let current = self.knn_vector_dimension();
+        if current == 0 {
+            self.put_attribute(KNN_VECTOR_DIMENSION_KEY.to_string(), dimension.to_string());
+            self.put_attribute(
+                KNN_VECTOR_SIMILARITY_KEY.to_string(),
+                similarity.name().to_string(),
+            );
+        } else if current != dimension || self.knn_vector_similarity()? != similarity {
+            bail!(IllegalArgument(format!(
+                "cannot change field '{}' knn vector dimension or similarity",
+                self.name
+            )));
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for FieldInfo {
@@ -381,6 +422,7 @@ pub struct FieldInfos {
     pub has_norms: bool,
     pub has_doc_values: bool,
     pub has_point_values: bool,
+    pub has_knn_vectors: bool,
 
     pub by_number: BTreeMap<u32, Arc<FieldInfo>>,
     pub by_name: HashMap<String, Arc<FieldInfo>>,
@@ -391,7 +433,7 @@ impl Serialize for FieldInfos {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("FieldInfos", 9)?;
+        let mut s = serializer.serialize_struct("FieldInfos", 10)?;
         s.serialize_field("has_freq", &self.has_freq)?;
         s.serialize_field("has_prox", &self.has_prox)?;
         s.serialize_field("has_payloads", &self.has_payloads)?;
@@ -400,6 +442,7 @@ impl Serialize for FieldInfos {
         s.serialize_field("has_norms", &self.has_norms)?;
         s.serialize_field("has_doc_values", &self.has_doc_values)?;
         s.serialize_field("has_point_values", &self.has_point_values)?;
+        s.serialize_field("has_knn_vectors", &self.has_knn_vectors)?;
 
         let fields: HashMap<&String, &FieldInfo> = self
             .by_name
@@ -431,6 +474,7 @@ impl FieldInfos {
         let mut has_norms = false;
         let mut has_doc_values = false;
         let mut has_point_values = false;
+        let mut has_knn_vectors = false;
 
         let mut by_number: BTreeMap<u32, Arc<FieldInfo>> = BTreeMap::new();
         let mut by_name: HashMap<String, Arc<FieldInfo>> = HashMap::new();
@@ -464,6 +508,7 @@ impl FieldInfos {
                 has_doc_values |= !info.doc_values_type.null();
                 has_payloads |= info.has_store_payloads;
                 has_point_values |= info.point_dimension_count != 0;
+                has_knn_vectors |= info.knn_vector_dimension() != 0;
             }
 
             if let Some(previous) = by_number.insert(number, info.clone()) {
@@ -491,6 +536,7 @@ impl FieldInfos {
             has_norms,
             has_doc_values,
             has_point_values,
+            has_knn_vectors,
             by_number,
             by_name,
         })
@@ -590,7 +636,13 @@ impl<T: AsRef<FieldNumbers>> FieldInfosBuilder<T> {
             fi.doc_values_type,
             fi.point_dimension_count,
             fi.point_num_bytes,
-        )
+        )?;
+        let dimension = fi.knn_vector_dimension();
+        if dimension > 0 {
+            self.by_name[&fi.name]
+                .set_knn_vector_attributes(dimension, fi.knn_vector_similarity()?)?;
+        }
+        Ok(())
     }
 
     #[allow(clippy::too_many_arguments)]
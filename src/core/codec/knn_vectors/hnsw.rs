@@ -0,0 +1,591 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::store::io::{DataInput, DataOutput};
+use core::util::VectorSimilarity;
+
+use error::{ErrorKind, Result};
+
+use rand::{thread_rng, Rng};
+
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Default number of neighbors per node on the upper levels, the bottom
+/// level keeps twice as many.
+pub const DEFAULT_MAX_CONN: usize = 16;
+/// Default size of the candidate queue used while building the graph.
+pub const DEFAULT_BEAM_WIDTH: usize = 100;
+
+/// Random access to the vectors of a field, by ordinal.
+pub trait RandomAccessVectors {
+    fn size(&self) -> usize;
+
+    fn dimension(&self) -> usize;
+
+    fn vector(&self, ord: usize) -> Result<Cow<[f32]>>;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScoredNode {
+    pub node: u32,
+    pub score: f32,
+}
+
+impl PartialEq for ScoredNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScoredNode {}
+
+impl PartialOrd for ScoredNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScoredNode {
+    // higher score first, ties broken by the smaller node
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .partial_cmp(&other.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+/// A hierarchical navigable small world graph.
+///
+/// Every node lives on level 0, a node that lives on level `l` also lives on
+/// all the levels below. Searches start from the entry point, on the top level,
+/// and greedily walk down to level 0.
+#[derive(Debug, Default)]
+pub struct HnswGraph {
+    // neighbors of each node of level 0, indexed by ord
+    level0: Vec<Vec<u32>>,
+    // neighbors of the nodes of the upper levels
+    upper_levels: Vec<HashMap<u32, Vec<u32>>>,
+    entry_point: Option<u32>,
+}
+
+impl HnswGraph {
+    pub fn size(&self) -> usize {
+        self.level0.len()
+    }
+
+    pub fn num_levels(&self) -> usize {
+        if self.level0.is_empty() {
+            0
+        } else {
+            self.upper_levels.len() + 1
+        }
+    }
+
+    pub fn entry_point(&self) -> Option<u32> {
+        self.entry_point
+    }
+
+    pub fn neighbors(&self, level: usize, node: u32) -> &[u32] {
+        if level == 0 {
+            &self.level0[node as usize]
+        } else {
+            self.upper_levels[level - 1]
+                .get(&node)
+                .map(Vec::as_slice)
+                .unwrap_or(&[])
+        }
+    }
+
+    fn neighbors_mut(&mut self, level: usize, node: u32) -> &mut Vec<u32> {
+        if level == 0 {
+            &mut self.level0[node as usize]
+        } else {
+            self.upper_levels[level - 1]
+                .entry(node)
+                .or_insert_with(Vec::new)
+        }
+    }
+
+    /// Returns the `top_k` nodes closest to `query`, best first.
+    ///
+    /// Only nodes for which `accept` returns true are collected, but all nodes
+    /// are used to navigate the graph. `ef` is the size of the queue of the
+    /// search on level 0, the higher the better the recall.
+    pub fn search(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+        vectors: &impl RandomAccessVectors,
+        similarity: VectorSimilarity,
+        accept: &mut dyn FnMut(u32) -> Result<bool>,
+    ) -> Result<Vec<ScoredNode>> {
+        let entry = match self.entry_point {
+            Some(e) => e,
+            None => return Ok(vec![]),
+        };
+        let mut eps = vec![ScoredNode {
+            node: entry,
+            score: similarity.compare(query, &vectors.vector(entry as usize)?),
+        }];
+        for level in (1..self.num_levels()).rev() {
+            eps = self.search_level(query, &eps, 1, level, vectors, similarity, None)?;
+        }
+        let mut results = self.search_level(
+            query,
+            &eps,
+            ef.max(top_k),
+            0,
+            vectors,
+            similarity,
+            Some(accept),
+        )?;
+        results.truncate(top_k);
+        Ok(results)
+    }
+
+    /// Greedy beam search on one level, returns at most `ef` nodes, best first.
+    #[allow(clippy::too_many_arguments)]
+    fn search_level(
+        &self,
+        query: &[f32],
+        entry_points: &[ScoredNode],
+        ef: usize,
+        level: usize,
+        vectors: &impl RandomAccessVectors,
+        similarity: VectorSimilarity,
+        mut accept: Option<&mut dyn FnMut(u32) -> Result<bool>>,
+    ) -> Result<Vec<ScoredNode>> {
+        let mut visited = HashSet::new();
+        let mut candidates = BinaryHeap::new();
+        let mut results: BinaryHeap<Reverse<ScoredNode>> = BinaryHeap::with_capacity(ef + 1);
+        for ep in entry_points {
+            if visited.insert(ep.node) {
+                candidates.push(*ep);
+                if Self::accepted(&mut accept, ep.node)? {
+                    Self::collect(&mut results, *ep, ef);
+                }
+            }
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            if results.len() >= ef && candidate.score < results.peek().unwrap().0.score {
+                break;
+            }
+            for &neighbor in self.neighbors(level, candidate.node) {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let score = similarity.compare(query, &vectors.vector(neighbor as usize)?);
+                if results.len() < ef || score > results.peek().unwrap().0.score {
+                    let node = ScoredNode {
+                        node: neighbor,
+                        score,
+                    };
+                    candidates.push(node);
+                    if Self::accepted(&mut accept, neighbor)? {
+                        Self::collect(&mut results, node, ef);
+                    }
+                }
+            }
+        }
+
+        let mut results: Vec<ScoredNode> = results.into_iter().map(|r| r.0).collect();
+        results.sort_by(|a, b| b.cmp(a));
+        Ok(results)
+    }
+
+    fn accepted(
+        accept: &mut Option<&mut dyn FnMut(u32) -> Result<bool>>,
+        node: u32,
+    ) -> Result<bool> {
+        match accept {
+            Some(f) => f(node),
+            None => Ok(true),
+        }
+    }
+
+    fn collect(results: &mut BinaryHeap<Reverse<ScoredNode>>, node: ScoredNode, ef: usize) {
+        results.push(Reverse(node));
+        if results.len() > ef {
+            results.pop();
+        }
+    }
+
+    /// Writes the graph: the neighbors of all the nodes of level 0, then for
+    /// each upper level its nodes with their neighbors, then the entry point.
+    pub fn write(&self, out: &mut impl DataOutput) -> Result<()> {
+        out.write_vint(self.level0.len() as i32)?;
+        out.write_vint(self.upper_levels.len() as i32)?;
+        for neighbors in &self.level0 {
+            Self::write_neighbors(out, neighbors)?;
+        }
+        for level in &self.upper_levels {
+            let mut nodes: Vec<&u32> = level.keys().collect();
+            nodes.sort();
+            out.write_vint(nodes.len() as i32)?;
+            for node in nodes {
+                out.write_vint(*node as i32)?;
+                Self::write_neighbors(out, &level[node])?;
+            }
+        }
+        out.write_int(self.entry_point.map(|e| e as i32).unwrap_or(-1))
+    }
+
+    fn write_neighbors(out: &mut impl DataOutput, neighbors: &[u32]) -> Result<()> {
+        out.write_vint(neighbors.len() as i32)?;
+        for n in neighbors {
+            out.write_vint(*n as i32)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<T: DataInput + ?Sized>(input: &mut T) -> Result<HnswGraph> {
+        let size = input.read_vint()? as usize;
+        let num_upper_levels = input.read_vint()? as usize;
+        let mut level0 = Vec::with_capacity(size);
+        for _ in 0..size {
+            level0.push(Self::read_neighbors(input, size)?);
+        }
+        let mut upper_levels = Vec::with_capacity(num_upper_levels);
+        for _ in 0..num_upper_levels {
+            let num_nodes = input.read_vint()? as usize;
+            let mut level = HashMap::with_capacity(num_nodes);
+            for _ in 0..num_nodes {
+                let node = input.read_vint()? as u32;
+                level.insert(node, Self::read_neighbors(input, size)?);
+            }
+            upper_levels.push(level);
+        }
+        let entry_point = input.read_int()?;
+        if entry_point >= size as i32 || (entry_point < 0) != (size == 0) {
+            bail!(ErrorKind::CorruptIndex(format!(
+                "invalid hnsw entry point {} for {} nodes",
+                entry_point, size
+            )));
+        }
+        Ok(HnswGraph {
+            level0,
+            upper_levels,
+            entry_point: if entry_point < 0 {
+                None
+            } else {
+                Some(entry_point as u32)
+            },
+        })
+    }
+
+    fn read_neighbors<T: DataInput + ?Sized>(input: &mut T, size: usize) -> Result<Vec<u32>> {
+        let count = input.read_vint()? as usize;
+        let mut neighbors = Vec::with_capacity(count);
+        for _ in 0..count {
+            let n = input.read_vint()?;
+            if n < 0 || n as usize >= size {
+                bail!(ErrorKind::CorruptIndex(format!(
+                    "invalid hnsw neighbor {} for {} nodes",
+                    n, size
+                )));
+            }
+            neighbors.push(n as u32);
+        }
+        Ok(neighbors)
+    }
+}
+
+/// Builds a `HnswGraph` by inserting the vectors one by one, in ord order.
+pub struct HnswGraphBuilder {
+    similarity: VectorSimilarity,
+    max_conn: usize,
+    beam_width: usize,
+    level_multiplier: f64,
+    graph: HnswGraph,
+    // level of the entry point
+    top_level: usize,
+}
+
+impl HnswGraphBuilder {
+    pub fn new(similarity: VectorSimilarity, max_conn: usize, beam_width: usize) -> Self {
+        debug_assert!(max_conn > 1 && beam_width > 0);
+        HnswGraphBuilder {
+            similarity,
+            max_conn,
+            beam_width,
+            level_multiplier: 1.0 / (max_conn as f64).ln(),
+            graph: HnswGraph::default(),
+            top_level: 0,
+        }
+    }
+
+    pub fn build(mut self, vectors: &impl RandomAccessVectors) -> Result<HnswGraph> {
+        for ord in 0..vectors.size() {
+            self.add(vectors, ord as u32)?;
+        }
+        Ok(self.graph)
+    }
+
+    fn random_level(&self) -> usize {
+        // 1 - gen() is in (0, 1], so ln never returns -inf
+        let r: f64 = 1.0 - thread_rng().gen::<f64>();
+        (-r.ln() * self.level_multiplier).floor() as usize
+    }
+
+    fn max_conn(&self, level: usize) -> usize {
+        if level == 0 {
+            self.max_conn * 2
+        } else {
+            self.max_conn
+        }
+    }
+
+    fn add(&mut self, vectors: &impl RandomAccessVectors, node: u32) -> Result<()> {
+        debug_assert_eq!(node as usize, self.graph.level0.len());
+        let level = self.random_level();
+        self.graph.level0.push(vec![]);
+        while self.graph.upper_levels.len() < level {
+            self.graph.upper_levels.push(HashMap::new());
+        }
+        for l in 1..=level {
+            self.graph.upper_levels[l - 1].insert(node, vec![]);
+        }
+
+        let entry = match self.graph.entry_point {
+            Some(e) => e,
+            None => {
+                self.graph.entry_point = Some(node);
+                self.top_level = level;
+                return Ok(());
+            }
+        };
+
+        let query = vectors.vector(node as usize)?.into_owned();
+        let mut eps = vec![ScoredNode {
+            node: entry,
+            score: self
+                .similarity
+                .compare(&query, &vectors.vector(entry as usize)?),
+        }];
+        for l in (level + 1..=self.top_level).rev() {
+            eps = self
+                .graph
+                .search_level(&query, &eps, 1, l, vectors, self.similarity, None)?;
+        }
+        for l in (0..=level.min(self.top_level)).rev() {
+            let candidates = self.graph.search_level(
+                &query,
+                &eps,
+                self.beam_width,
+                l,
+                vectors,
+                self.similarity,
+                None,
+            )?;
+            let neighbors = self.select_diverse(&candidates, self.max_conn(l), vectors)?;
+            *self.graph.neighbors_mut(l, node) = neighbors.iter().map(|n| n.node).collect();
+            for neighbor in &neighbors {
+                self.connect(vectors, l, neighbor.node, node)?;
+            }
+            eps = candidates;
+        }
+
+        if level > self.top_level {
+            self.graph.entry_point = Some(node);
+            self.top_level = level;
+        }
+        Ok(())
+    }
+
+    // adds `node` to the neighbors of `target`, pruning them when it has too many
+    fn connect(
+        &mut self,
+        vectors: &impl RandomAccessVectors,
+        level: usize,
+        target: u32,
+        node: u32,
+    ) -> Result<()> {
+        let max_conn = self.max_conn(level);
+        self.graph.neighbors_mut(level, target).push(node);
+        if self.graph.neighbors(level, target).len() <= max_conn {
+            return Ok(());
+        }
+
+        let target_vector = vectors.vector(target as usize)?.into_owned();
+        let mut candidates = Vec::with_capacity(max_conn + 1);
+        for &n in self.graph.neighbors(level, target) {
+            candidates.push(ScoredNode {
+                node: n,
+                score: self
+                    .similarity
+                    .compare(&target_vector, &vectors.vector(n as usize)?),
+            });
+        }
+        candidates.sort_by(|a, b| b.cmp(a));
+        let selected = self.select_diverse(&candidates, max_conn, vectors)?;
+        *self.graph.neighbors_mut(level, target) = selected.iter().map(|n| n.node).collect();
+        Ok(())
+    }
+
+    // Keeps the candidates, best first, that are closer to the base node than to
+    // any candidate already kept, which spreads the edges in all directions.
+    // The remaining slots are filled with the best pruned candidates.
+    fn select_diverse(
+        &self,
+        candidates: &[ScoredNode],
+        max: usize,
+        vectors: &impl RandomAccessVectors,
+    ) -> Result<Vec<ScoredNode>> {
+        let mut selected: Vec<ScoredNode> = Vec::with_capacity(max);
+        let mut pruned = vec![];
+        for candidate in candidates {
+            if selected.len() >= max {
+                break;
+            }
+            let vector = vectors.vector(candidate.node as usize)?;
+            let mut diverse = true;
+            for s in &selected {
+                let score = self
+                    .similarity
+                    .compare(&vector, &vectors.vector(s.node as usize)?);
+                if score > candidate.score {
+                    diverse = false;
+                    break;
+                }
+            }
+            if diverse {
+                selected.push(*candidate);
+            } else {
+                pruned.push(*candidate);
+            }
+        }
+        for candidate in pruned {
+            if selected.len() >= max {
+                break;
+            }
+            selected.push(candidate);
+        }
+        Ok(selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::squared_distance;
+
+    struct TestVectors(Vec<Vec<f32>>);
+
+    impl RandomAccessVectors for TestVectors {
+        fn size(&self) -> usize {
+            self.0.len()
+        }
+
+        fn dimension(&self) -> usize {
+            2
+        }
+
+        fn vector(&self, ord: usize) -> Result<Cow<[f32]>> {
+            Ok(Cow::Borrowed(&self.0[ord]))
+        }
+    }
+
+    fn grid(n: usize) -> TestVectors {
+        let mut vectors = vec![];
+        for i in 0..n {
+            for j in 0..n {
+                vectors.push(vec![i as f32, j as f32]);
+            }
+        }
+        TestVectors(vectors)
+    }
+
+    #[test]
+    fn test_hnsw_search() {
+        let vectors = grid(20);
+        let graph = HnswGraphBuilder::new(VectorSimilarity::Euclidean, 8, 50)
+            .build(&vectors)
+            .unwrap();
+        assert_eq!(graph.size(), 400);
+
+        let query = [7.2f32, 11.9];
+        let results = graph
+            .search(
+                &query,
+                5,
+                50,
+                &vectors,
+                VectorSimilarity::Euclidean,
+                &mut |_| Ok(true),
+            )
+            .unwrap();
+        assert_eq!(results.len(), 5);
+        // the closest point of the grid is (7, 12)
+        assert_eq!(results[0].node, 7 * 20 + 12);
+        for w in results.windows(2) {
+            assert!(w[0].score >= w[1].score);
+        }
+
+        let mut expected: Vec<(f32, u32)> = (0..400)
+            .map(|i| (squared_distance(&query, &vectors.0[i]), i as u32))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let found: HashSet<u32> = results.iter().map(|r| r.node).collect();
+        let matched = expected[..5]
+            .iter()
+            .filter(|e| found.contains(&e.1))
+            .count();
+        assert!(matched >= 4);
+    }
+
+    #[test]
+    fn test_hnsw_search_with_filter() {
+        let vectors = grid(10);
+        let graph = HnswGraphBuilder::new(VectorSimilarity::Euclidean, 4, 20)
+            .build(&vectors)
+            .unwrap();
+
+        let results = graph
+            .search(
+                &[0.0, 0.0],
+                3,
+                20,
+                &vectors,
+                VectorSimilarity::Euclidean,
+                &mut |node| Ok(node % 2 == 1),
+            )
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|r| r.node % 2 == 1));
+        assert_eq!(results[0].node, 1);
+    }
+
+    #[test]
+    fn test_hnsw_empty() {
+        let graph = HnswGraphBuilder::new(VectorSimilarity::Cosine, 16, 100)
+            .build(&TestVectors(vec![]))
+            .unwrap();
+        assert_eq!(graph.num_levels(), 0);
+        let results = graph
+            .search(
+                &[1.0, 0.0],
+                1,
+                10,
+                &TestVectors(vec![]),
+                VectorSimilarity::Cosine,
+                &mut |_| Ok(true),
+            )
+            .unwrap();
+        assert!(results.is_empty());
+    }
+}
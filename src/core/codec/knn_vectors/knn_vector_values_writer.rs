@@ -0,0 +1,87 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::field_infos::FieldInfo;
+use core::codec::knn_vectors::{KnnVectorValues, Lucene90HnswVectorsWriter};
+use core::codec::segment_infos::SegmentWriteState;
+use core::codec::{Codec, SorterDocMap};
+use core::doc::decode_vector;
+use core::store::directory::Directory;
+use core::util::DocId;
+
+use error::Result;
+
+use std::mem;
+
+/// Buffers the knn vectors of one field until the segment is flushed.
+pub struct KnnVectorValuesWriter {
+    field_info: FieldInfo,
+    values: KnnVectorValues,
+    last_doc_id: DocId,
+}
+
+impl KnnVectorValuesWriter {
+    pub fn new(field_info: &FieldInfo) -> Result<KnnVectorValuesWriter> {
+        let values = KnnVectorValues::new(
+            field_info.knn_vector_dimension() as usize,
+            field_info.knn_vector_similarity()?,
+        );
+        Ok(KnnVectorValuesWriter {
+            field_info: field_info.clone(),
+            values,
+            last_doc_id: -1,
+        })
+    }
+
+    pub fn add_value(&mut self, doc_id: DocId, value: &[u8]) -> Result<()> {
+        if doc_id == self.last_doc_id {
+            bail!(
+                "field={}: only one knn vector per document is allowed",
+                self.field_info.name
+            );
+        }
+        if value.len() != self.values.dimension * 4 {
+            bail!(
+                "field={}: this field's vector has dimension={} but should be {}",
+                self.field_info.name,
+                value.len() / 4,
+                self.values.dimension
+            );
+        }
+        debug_assert!(doc_id > self.last_doc_id);
+        self.values.add(doc_id, &decode_vector(value));
+        self.last_doc_id = doc_id;
+        Ok(())
+    }
+
+    pub fn flush<D: Directory, DW: Directory, C: Codec, M: SorterDocMap>(
+        &mut self,
+        _state: &SegmentWriteState<D, DW, C>,
+        sort_map: Option<&M>,
+        writer: &mut Lucene90HnswVectorsWriter<D, DW, C>,
+    ) -> Result<()> {
+        let dimension = self.values.dimension;
+        let similarity = self.values.similarity;
+        let mut values = mem::replace(
+            &mut self.values,
+            KnnVectorValues::new(dimension, similarity),
+        );
+        if let Some(sort_map) = sort_map {
+            for doc in &mut values.docs {
+                *doc = sort_map.old_to_new(*doc);
+            }
+            values.sort_by_doc();
+        }
+        writer.write_field(&self.field_info, &values)
+    }
+}
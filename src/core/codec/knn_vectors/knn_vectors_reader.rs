@@ -0,0 +1,447 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::field_infos::FieldInfos;
+use core::codec::knn_vectors::{
+    HnswGraph, KnnVectorValues, KnnVectorsFormat, KnnVectorsReader, Lucene90HnswVectorsWriter,
+    RandomAccessVectors, ScoredNode, DEFAULT_BEAM_WIDTH, DEFAULT_MAX_CONN,
+};
+use core::codec::segment_infos::{segment_file_name, SegmentReadState, SegmentWriteState};
+use core::codec::{codec_util, Codec};
+use core::store::directory::Directory;
+use core::store::io::{DataInput, IndexInput, RandomAccessInput};
+use core::util::{Bits, DocId, VectorSimilarity};
+
+use error::{ErrorKind, Result};
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const VECTOR_DATA_CODEC_NAME: &str = "Lucene90HnswVectorsFormatData";
+pub const VECTOR_META_CODEC_NAME: &str = "Lucene90HnswVectorsFormatMeta";
+
+// Filename extension for the vectors and the graphs
+pub const VECTOR_DATA_EXTENSION: &str = "vec";
+// Filename extension for the metadata of each field
+pub const VECTOR_META_EXTENSION: &str = "vem";
+
+pub const VECTOR_VERSION_START: i32 = 0;
+pub const VECTOR_VERSION_CURRENT: i32 = VECTOR_VERSION_START;
+
+// the size of the queue of a search on the bottom level is at least this
+const MIN_SEARCH_BEAM_WIDTH: usize = 50;
+
+/// Stores the vectors of each field as raw floats, next to a HNSW graph
+/// built over them.
+#[derive(Copy, Clone)]
+pub struct Lucene90HnswVectorsFormat {
+    /// Max number of neighbors of a node on the upper levels of the graph
+    pub max_conn: usize,
+    /// Number of candidates tracked while inserting a node in the graph
+    pub beam_width: usize,
+}
+
+impl Default for Lucene90HnswVectorsFormat {
+    fn default() -> Self {
+        Lucene90HnswVectorsFormat {
+            max_conn: DEFAULT_MAX_CONN,
+            beam_width: DEFAULT_BEAM_WIDTH,
+        }
+    }
+}
+
+impl KnnVectorsFormat for Lucene90HnswVectorsFormat {
+    type Reader = Lucene90HnswVectorsReader;
+
+    fn fields_writer<D: Directory, DW: Directory, C: Codec>(
+        &self,
+        state: &SegmentWriteState<D, DW, C>,
+    ) -> Result<Lucene90HnswVectorsWriter<D, DW, C>> {
+        Lucene90HnswVectorsWriter::new(state, self.max_conn, self.beam_width)
+    }
+
+    fn fields_reader<D: Directory, DW: Directory, C: Codec>(
+        &self,
+        state: &SegmentReadState<'_, D, DW, C>,
+    ) -> Result<Self::Reader> {
+        Lucene90HnswVectorsReader::new(state)
+    }
+}
+
+struct FieldEntry {
+    similarity: VectorSimilarity,
+    dimension: usize,
+    docs: Vec<DocId>,
+    vectors: Box<dyn RandomAccessInput>,
+    graph: HnswGraph,
+}
+
+struct OffHeapVectors<'a> {
+    input: &'a dyn RandomAccessInput,
+    dimension: usize,
+    size: usize,
+}
+
+impl<'a> RandomAccessVectors for OffHeapVectors<'a> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn vector(&self, ord: usize) -> Result<Cow<[f32]>> {
+        let mut vector = Vec::with_capacity(self.dimension);
+        let start = (ord * self.dimension * 4) as u64;
+        for i in 0..self.dimension {
+            let bits = self.input.read_int(start + i as u64 * 4)?;
+            vector.push(f32::from_bits(bits as u32));
+        }
+        Ok(Cow::Owned(vector))
+    }
+}
+
+pub struct Lucene90HnswVectorsReader {
+    field_infos: Arc<FieldInfos>,
+    fields: HashMap<u32, FieldEntry>,
+    data_in: Box<dyn IndexInput>,
+}
+
+impl Lucene90HnswVectorsReader {
+    pub fn new<D: Directory, DW: Directory, C: Codec>(
+        read_state: &SegmentReadState<'_, D, DW, C>,
+    ) -> Result<Lucene90HnswVectorsReader> {
+        let meta_file_name = segment_file_name(
+            &read_state.segment_info.name,
+            &read_state.segment_suffix,
+            VECTOR_META_EXTENSION,
+        );
+        let mut meta_in = read_state
+            .directory
+            .open_checksum_input(&meta_file_name, read_state.context)?;
+        codec_util::check_index_header(
+            &mut meta_in,
+            VECTOR_META_CODEC_NAME,
+            VECTOR_VERSION_START,
+            VECTOR_VERSION_CURRENT,
+            &read_state.segment_info.id,
+            &read_state.segment_suffix,
+        )?;
+
+        let data_file_name = segment_file_name(
+            &read_state.segment_info.name,
+            &read_state.segment_suffix,
+            VECTOR_DATA_EXTENSION,
+        );
+        let mut data_in = read_state
+            .directory
+            .open_input(&data_file_name, read_state.context)?;
+        codec_util::check_index_header(
+            data_in.as_mut(),
+            VECTOR_DATA_CODEC_NAME,
+            VECTOR_VERSION_START,
+            VECTOR_VERSION_CURRENT,
+            &read_state.segment_info.id,
+            &read_state.segment_suffix,
+        )?;
+        // like points, only verify the structure of the checksum footer here,
+        // the whole file is checked by `check_integrity`.
+        codec_util::retrieve_checksum(data_in.as_mut())?;
+
+        let mut fields = HashMap::new();
+        loop {
+            let field_number = meta_in.read_int()?;
+            if field_number == -1 {
+                break;
+            }
+            let field_info = match read_state
+                .field_infos
+                .field_info_by_number(field_number as u32)
+            {
+                Some(fi) => fi,
+                None => bail!(ErrorKind::CorruptIndex(format!(
+                    "invalid knn vectors field number: {}",
+                    field_number
+                ))),
+            };
+            let similarity = VectorSimilarity::from_ordinal(meta_in.read_byte()?)?;
+            let dimension = meta_in.read_vint()? as usize;
+            if dimension as u32 != field_info.knn_vector_dimension()
+                || similarity != field_info.knn_vector_similarity()?
+            {
+                bail!(ErrorKind::CorruptIndex(format!(
+                    "knn vectors of field '{}' do not match its field info",
+                    field_info.name
+                )));
+            }
+            let vectors_offset = meta_in.read_vlong()?;
+            let vectors_length = meta_in.read_vlong()?;
+            let size = meta_in.read_vint()? as usize;
+            if vectors_length != (size * dimension * 4) as i64 {
+                bail!(ErrorKind::CorruptIndex(format!(
+                    "invalid knn vectors length {} of field '{}'",
+                    vectors_length, field_info.name
+                )));
+            }
+            let mut docs = Vec::with_capacity(size);
+            let mut doc = 0;
+            for _ in 0..size {
+                doc += meta_in.read_vint()?;
+                docs.push(doc);
+            }
+            let graph_offset = meta_in.read_vlong()?;
+
+            let vectors = data_in.random_access_slice(vectors_offset, vectors_length)?;
+            let mut graph_in = data_in.as_ref().clone()?;
+            graph_in.seek(graph_offset)?;
+            let graph = HnswGraph::read(graph_in.as_mut())?;
+            if graph.size() != size {
+                bail!(ErrorKind::CorruptIndex(format!(
+                    "knn vectors graph of field '{}' has {} nodes, expected {}",
+                    field_info.name,
+                    graph.size(),
+                    size
+                )));
+            }
+
+            fields.insert(
+                field_number as u32,
+                FieldEntry {
+                    similarity,
+                    dimension,
+                    docs,
+                    vectors,
+                    graph,
+                },
+            );
+        }
+        codec_util::check_footer(&mut meta_in)?;
+
+        Ok(Lucene90HnswVectorsReader {
+            field_infos: Arc::clone(&read_state.field_infos),
+            fields,
+            data_in,
+        })
+    }
+
+    fn field_entry(&self, field: &str) -> Result<Option<&FieldEntry>> {
+        if let Some(field_info) = self.field_infos.field_info_by_name(field) {
+            if field_info.knn_vector_dimension() == 0 {
+                bail!(ErrorKind::IllegalArgument(format!(
+                    "field '{}' did not index knn vectors!",
+                    field
+                )));
+            }
+            // all the docs having a vector for this field may have been deleted
+            Ok(self.fields.get(&field_info.number))
+        } else {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "field '{}' is unrecognized!",
+                field
+            )));
+        }
+    }
+}
+
+impl KnnVectorsReader for Lucene90HnswVectorsReader {
+    fn check_integrity(&self) -> Result<()> {
+        codec_util::checksum_entire_file(self.data_in.as_ref())?;
+        Ok(())
+    }
+
+    fn vector_values(&self, field: &str) -> Result<Option<KnnVectorValues>> {
+        let entry = match self.field_entry(field)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let vectors = OffHeapVectors {
+            input: entry.vectors.as_ref(),
+            dimension: entry.dimension,
+            size: entry.docs.len(),
+        };
+        let mut values = KnnVectorValues::new(entry.dimension, entry.similarity);
+        for (ord, doc) in entry.docs.iter().enumerate() {
+            values.add(*doc, &vectors.vector(ord)?);
+        }
+        Ok(Some(values))
+    }
+
+    fn search(
+        &self,
+        field: &str,
+        target: &[f32],
+        k: usize,
+        accept_docs: Option<&dyn Bits>,
+    ) -> Result<Vec<(DocId, f32)>> {
+        let entry = match self.field_entry(field)? {
+            Some(entry) => entry,
+            None => return Ok(vec![]),
+        };
+        if target.len() != entry.dimension {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "vector query dimension: {} differs from field dimension: {}",
+                target.len(),
+                entry.dimension
+            )));
+        }
+        if k == 0 {
+            return Ok(vec![]);
+        }
+
+        let vectors = OffHeapVectors {
+            input: entry.vectors.as_ref(),
+            dimension: entry.dimension,
+            size: entry.docs.len(),
+        };
+        let docs = &entry.docs;
+        let mut accept = |ord: u32| match accept_docs {
+            Some(bits) => bits.get(docs[ord as usize] as usize),
+            None => Ok(true),
+        };
+        let mut results = entry.graph.search(
+            target,
+            k,
+            k.max(MIN_SEARCH_BEAM_WIDTH),
+            &vectors,
+            entry.similarity,
+            &mut accept,
+        )?;
+        if results.len() < k && accept_docs.is_some() {
+            // a graph split by deletions or a selective filter may hide some of
+            // the accepted docs, only an exact scan can find all of them.
+            let mut all = Vec::new();
+            for ord in 0..docs.len() {
+                if accept(ord as u32)? {
+                    let score = entry.similarity.compare(target, &vectors.vector(ord)?);
+                    all.push(ScoredNode {
+                        node: ord as u32,
+                        score,
+                    });
+                }
+            }
+            all.sort_by(|a, b| b.cmp(a));
+            all.truncate(k);
+            results = all;
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|r| (docs[r.node as usize], r.score))
+            .collect())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    use core::doc::{Field, FieldType, Fieldable, IndexOptions, KnnVectorField, Term};
+    use core::index::reader::IndexReader;
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::store::directory::FSDirectory;
+    use core::util::VariantValue;
+
+    fn new_doc(id: i32) -> Vec<Box<dyn Fieldable>> {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        field_type.omit_norms = true;
+        let vector = [id as f32, 0.0];
+        vec![
+            Box::new(Field::new(
+                "id".into(),
+                field_type,
+                Some(VariantValue::VString(id.to_string())),
+                None,
+            )),
+            Box::new(KnnVectorField::new("vec", &vector, VectorSimilarity::Euclidean).unwrap()),
+        ]
+    }
+
+    fn id_term(id: i32) -> Term {
+        Term::new("id".into(), id.to_string().into_bytes())
+    }
+
+    #[test]
+    fn test_knn_vectors_flush_and_merge() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(IndexWriterConfig::default())).unwrap();
+        for id in 0..10 {
+            writer.add_document(new_doc(id)).unwrap();
+        }
+        writer.commit().unwrap();
+        for id in 10..20 {
+            writer.add_document(new_doc(id)).unwrap();
+        }
+        writer.commit().unwrap();
+        let deleted = [3, 12, 15];
+        writer
+            .delete_documents_by_terms(deleted.iter().map(|id| id_term(*id)).collect())
+            .unwrap();
+        writer.commit().unwrap();
+        let live: Vec<i32> = (0..20).filter(|id| !deleted.contains(id)).collect();
+
+        // the flushed segments keep the vectors of the deleted docs, but the
+        // search never returns them
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.leaves().len(), 2);
+        let mut found = vec![];
+        for leaf in reader.leaves() {
+            let vectors = leaf.reader.knn_vectors().unwrap();
+            let values = vectors.vector_values("vec").unwrap().unwrap();
+            assert_eq!(values.len(), 10);
+            let live_docs = leaf.reader.live_docs();
+            let results = vectors
+                .search("vec", &[3.0, 0.0], 20, Some(live_docs.as_ref()))
+                .unwrap();
+            for (doc, _) in results {
+                found.push(leaf.doc_base + doc);
+            }
+        }
+        found.sort();
+        assert_eq!(found, live);
+
+        // the merge drops the deleted docs and rebuilds the graph on the new doc ids
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        let reader = writer.get_reader(true, false).unwrap();
+        let leaves = reader.leaves();
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].reader.max_doc(), live.len() as i32);
+        let vectors = leaves[0].reader.knn_vectors().unwrap();
+        let values = vectors.vector_values("vec").unwrap().unwrap();
+        assert_eq!(values.docs, (0..live.len() as i32).collect::<Vec<_>>());
+        let xs: Vec<i32> = (0..values.len())
+            .map(|ord| values.get(ord)[0] as i32)
+            .collect();
+        assert_eq!(xs, live);
+
+        let results = vectors.search("vec", &[12.2, 0.0], 3, None).unwrap();
+        let nearest: Vec<i32> = results
+            .iter()
+            .map(|(doc, _)| values.get(*doc as usize)[0] as i32)
+            .collect();
+        assert_eq!(nearest, vec![13, 11, 14]);
+        assert!(vectors.check_integrity().is_ok());
+    }
+}
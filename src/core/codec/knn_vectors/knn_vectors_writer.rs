@@ -0,0 +1,187 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::field_infos::FieldInfo;
+use core::codec::knn_vectors::knn_vectors_reader::{
+    VECTOR_DATA_CODEC_NAME, VECTOR_DATA_EXTENSION, VECTOR_META_CODEC_NAME, VECTOR_META_EXTENSION,
+    VECTOR_VERSION_CURRENT,
+};
+use core::codec::knn_vectors::{HnswGraphBuilder, KnnVectorValues, KnnVectorsReader};
+use core::codec::segment_infos::{segment_file_name, SegmentWriteState};
+use core::codec::{codec_util, Codec};
+use core::index::merge::{DocMap, MergeState};
+use core::store::directory::Directory;
+use core::store::io::{DataOutput, IndexOutput};
+
+use error::{ErrorKind, Result};
+
+/// Writes the vectors of each field followed by their HNSW graph.
+pub struct Lucene90HnswVectorsWriter<D: Directory, DW: Directory, C: Codec> {
+    data_out: DW::IndexOutput,
+    meta_out: DW::IndexOutput,
+    write_state: SegmentWriteState<D, DW, C>,
+    max_conn: usize,
+    beam_width: usize,
+    finished: bool,
+}
+
+impl<D: Directory, DW: Directory, C: Codec> Lucene90HnswVectorsWriter<D, DW, C> {
+    pub fn new(
+        write_state: &SegmentWriteState<D, DW, C>,
+        max_conn: usize,
+        beam_width: usize,
+    ) -> Result<Lucene90HnswVectorsWriter<D, DW, C>> {
+        let write_state = write_state.clone();
+        let data_file_name = segment_file_name(
+            &write_state.segment_info.name,
+            &write_state.segment_suffix,
+            VECTOR_DATA_EXTENSION,
+        );
+        let mut data_out = write_state
+            .directory
+            .create_output(&data_file_name, &write_state.context)?;
+        codec_util::write_index_header(
+            &mut data_out,
+            VECTOR_DATA_CODEC_NAME,
+            VECTOR_VERSION_CURRENT,
+            write_state.segment_info.get_id(),
+            &write_state.segment_suffix,
+        )?;
+
+        let meta_file_name = segment_file_name(
+            &write_state.segment_info.name,
+            &write_state.segment_suffix,
+            VECTOR_META_EXTENSION,
+        );
+        let mut meta_out = write_state
+            .directory
+            .create_output(&meta_file_name, &write_state.context)?;
+        codec_util::write_index_header(
+            &mut meta_out,
+            VECTOR_META_CODEC_NAME,
+            VECTOR_VERSION_CURRENT,
+            write_state.segment_info.get_id(),
+            &write_state.segment_suffix,
+        )?;
+
+        Ok(Lucene90HnswVectorsWriter {
+            data_out,
+            meta_out,
+            write_state,
+            max_conn,
+            beam_width,
+            finished: false,
+        })
+    }
+
+    /// Writes the vectors of one field, which must be sorted by doc, and
+    /// builds their graph.
+    pub fn write_field(&mut self, field_info: &FieldInfo, values: &KnnVectorValues) -> Result<()> {
+        // We could have 0 vectors on merge since all docs with vectors may be deleted
+        if values.is_empty() {
+            return Ok(());
+        }
+        if values.dimension as u32 != field_info.knn_vector_dimension()
+            || values.similarity != field_info.knn_vector_similarity()?
+        {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "knn vectors of field '{}' do not match its field info",
+                field_info.name
+            )));
+        }
+        debug_assert!(values.docs.windows(2).all(|w| w[0] < w[1]));
+
+        let graph = HnswGraphBuilder::new(values.similarity, self.max_conn, self.beam_width)
+            .build(values)?;
+
+        let vectors_offset = self.data_out.file_pointer();
+        for v in &values.vectors {
+            self.data_out.write_int(v.to_bits() as i32)?;
+        }
+        let vectors_length = self.data_out.file_pointer() - vectors_offset;
+        let graph_offset = self.data_out.file_pointer();
+        graph.write(&mut self.data_out)?;
+
+        self.meta_out.write_int(field_info.number as i32)?;
+        self.meta_out.write_byte(values.similarity.ordinal())?;
+        self.meta_out.write_vint(values.dimension as i32)?;
+        self.meta_out.write_vlong(vectors_offset)?;
+        self.meta_out.write_vlong(vectors_length)?;
+        self.meta_out.write_vint(values.len() as i32)?;
+        let mut last_doc = 0;
+        for doc in &values.docs {
+            self.meta_out.write_vint(*doc - last_doc)?;
+            last_doc = *doc;
+        }
+        self.meta_out.write_vlong(graph_offset)
+    }
+
+    /// Merges the vectors of all the segments and rebuilds the graphs, the
+    /// graphs of the merged segments can't be reused since doc ids change.
+    pub fn merge<D1: Directory, C1: Codec>(
+        &mut self,
+        merge_state: &MergeState<D1, C1>,
+    ) -> Result<()> {
+        for field_info in merge_state
+            .merge_field_infos
+            .as_ref()
+            .unwrap()
+            .by_number
+            .values()
+        {
+            let dimension = field_info.knn_vector_dimension();
+            if dimension == 0 {
+                continue;
+            }
+            let mut merged =
+                KnnVectorValues::new(dimension as usize, field_info.knn_vector_similarity()?);
+            for (i, reader) in merge_state.knn_vectors_readers.iter().enumerate() {
+                if let Some(reader) = reader {
+                    // field numbers may differ between segments, resolve by name
+                    let has_vectors = merge_state.fields_infos[i]
+                        .field_info_by_name(&field_info.name)
+                        .map_or(false, |fi| fi.knn_vector_dimension() > 0);
+                    if !has_vectors {
+                        continue;
+                    }
+                    if let Some(values) = reader.vector_values(&field_info.name)? {
+                        for (ord, doc) in values.docs.iter().enumerate() {
+                            // first apply the index sort of this segment, if it was
+                            // sorted for this merge, then remove deletions
+                            let doc = merge_state.leaf_doc_maps[i].get(*doc)?;
+                            let new_doc = merge_state.doc_maps[i].get(doc)?;
+                            if new_doc != -1 {
+                                merged.add(new_doc, values.get(ord));
+                            }
+                        }
+                    }
+                }
+            }
+            merged.sort_by_doc();
+            self.write_field(field_info, &merged)?;
+        }
+
+        self.finish()
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        if self.finished {
+            bail!(ErrorKind::IllegalState("already finished".into()));
+        }
+        self.finished = true;
+
+        self.meta_out.write_int(-1)?;
+        codec_util::write_footer(&mut self.meta_out)?;
+        codec_util::write_footer(&mut self.data_out)
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod hnsw;
+
+pub use self::hnsw::*;
+
+mod knn_vectors_reader;
+
+pub use self::knn_vectors_reader::*;
+
+mod knn_vectors_writer;
+
+pub use self::knn_vectors_writer::*;
+
+mod knn_vector_values_writer;
+
+pub use self::knn_vector_values_writer::*;
+
+use core::codec::segment_infos::{SegmentReadState, SegmentWriteState};
+use core::codec::Codec;
+use core::store::directory::Directory;
+use core::util::{Bits, DocId, VectorSimilarity};
+
+use error::Result;
+
+use std::any::Any;
+use std::borrow::Cow;
+
+/// Encodes/decodes per-document dense vectors and the graphs used to search them.
+pub trait KnnVectorsFormat {
+    type Reader: KnnVectorsReader;
+
+    // TODO we need GAT to make the writer an associated type
+    fn fields_writer<D: Directory, DW: Directory, C: Codec>(
+        &self,
+        state: &SegmentWriteState<D, DW, C>,
+    ) -> Result<Lucene90HnswVectorsWriter<D, DW, C>>;
+
+    /// Reads a segment.  NOTE: by the time this call
+    /// returns, it must hold open any files it will need to
+    /// use; else, those files may be deleted.
+    fn fields_reader<D: Directory, DW: Directory, C: Codec>(
+        &self,
+        state: &SegmentReadState<'_, D, DW, C>,
+    ) -> Result<Self::Reader>;
+}
+
+/// Reads the vectors of a segment and searches the nearest neighbors of a target.
+pub trait KnnVectorsReader: Send + Sync {
+    fn check_integrity(&self) -> Result<()>;
+
+    /// Returns all the vectors of the field, ordered by doc, or None if no
+    /// document of this segment has a vector for this field.
+    fn vector_values(&self, field: &str) -> Result<Option<KnnVectorValues>>;
+
+    /// Returns the (approximately) `k` docs whose vector is the most similar to
+    /// `target`, best first. Docs not set in `accept_docs` are never returned.
+    fn search(
+        &self,
+        field: &str,
+        target: &[f32],
+        k: usize,
+        accept_docs: Option<&dyn Bits>,
+    ) -> Result<Vec<(DocId, f32)>>;
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// In memory vectors of one field, ordered by doc.
+#[derive(Clone, Debug)]
+pub struct KnnVectorValues {
+    pub dimension: usize,
+    pub similarity: VectorSimilarity,
+    pub docs: Vec<DocId>,
+    pub vectors: Vec<f32>,
+}
+
+impl KnnVectorValues {
+    pub fn new(dimension: usize, similarity: VectorSimilarity) -> Self {
+        KnnVectorValues {
+            dimension,
+            similarity,
+            docs: vec![],
+            vectors: vec![],
+        }
+    }
+
+    pub fn add(&mut self, doc: DocId, vector: &[f32]) {
+        debug_assert_eq!(vector.len(), self.dimension);
+        self.docs.push(doc);
+        self.vectors.extend_from_slice(vector);
+    }
+
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    pub fn get(&self, ord: usize) -> &[f32] {
+        &self.vectors[ord * self.dimension..(ord + 1) * self.dimension]
+    }
+
+    /// Reorders the vectors by doc, the docs must be distinct.
+    pub fn sort_by_doc(&mut self) {
+        let mut ords: Vec<usize> = (0..self.docs.len()).collect();
+        ords.sort_by_key(|&ord| self.docs[ord]);
+        let mut docs = Vec::with_capacity(self.docs.len());
+        let mut vectors = Vec::with_capacity(self.vectors.len());
+        for ord in ords {
+            docs.push(self.docs[ord]);
+            vectors.extend_from_slice(self.get(ord));
+        }
+        self.docs = docs;
+        self.vectors = vectors;
+    }
+}
+
+impl RandomAccessVectors for KnnVectorValues {
+    fn size(&self) -> usize {
+        self.docs.len()
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn vector(&self, ord: usize) -> Result<Cow<[f32]>> {
+        Ok(Cow::Borrowed(self.get(ord)))
+    }
+}
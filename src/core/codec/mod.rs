@@ -13,6 +13,7 @@
 
 pub mod doc_values;
 pub mod field_infos;
+pub mod knn_vectors;
 pub mod norms;
 pub mod points;
 pub mod postings;
@@ -62,6 +63,7 @@ pub use self::posting_iterator::*;
 
 use core::codec::doc_values::{DocValuesFormat, DocValuesFormatEnum, PerFieldDocValuesFormat};
 use core::codec::field_infos::{FieldInfosFormat, Lucene60FieldInfosFormat};
+use core::codec::knn_vectors::{KnnVectorsFormat, Lucene90HnswVectorsFormat};
 use core::codec::norms::{Lucene53NormsFormat, NormsFormat};
 use core::codec::points::{Lucene60PointsFormat, PointsFormat};
 use core::codec::postings::{
@@ -102,6 +104,7 @@ pub trait Codec: TryFrom<String, Error = Error> + 'static {
     type LiveDocFmt: LiveDocsFormat;
     type CompoundFmt: CompoundFormat;
     type PointFmt: PointsFormat;
+    type KnnVectorsFmt: KnnVectorsFormat;
 
     fn name(&self) -> &str;
     fn postings_format(&self) -> Self::PostingFmt;
@@ -114,6 +117,7 @@ pub trait Codec: TryFrom<String, Error = Error> + 'static {
     fn live_docs_format(&self) -> Self::LiveDocFmt;
     fn compound_format(&self) -> Self::CompoundFmt;
     fn points_format(&self) -> Self::PointFmt;
+    fn knn_vectors_format(&self) -> Self::KnnVectorsFmt;
 }
 
 pub type CodecFieldsProducer<C> = <<C as Codec>::PostingFmt as PostingsFormat>::FieldsProducer;
@@ -128,6 +132,7 @@ pub type CodecTVFields<C> =
     <<<C as Codec>::TVFmt as TermVectorsFormat>::TVReader as TermVectorsReader>::Fields;
 pub type CodecNormsProducer<C> = <<C as Codec>::NormFmt as NormsFormat>::NormsProducer;
 pub type CodecPointsReader<C> = <<C as Codec>::PointFmt as PointsFormat>::Reader;
+pub type CodecKnnVectorsReader<C> = <<C as Codec>::KnnVectorsFmt as KnnVectorsFormat>::Reader;
 
 pub enum CodecEnum {
     Lucene62(Lucene62Codec),
@@ -145,6 +150,7 @@ impl Codec for CodecEnum {
     type LiveDocFmt = Lucene50LiveDocsFormat;
     type CompoundFmt = Lucene50CompoundFormat;
     type PointFmt = Lucene60PointsFormat;
+    type KnnVectorsFmt = Lucene90HnswVectorsFormat;
 
     fn name(&self) -> &str {
         match self {
@@ -203,6 +209,13 @@ impl Codec for CodecEnum {
             CodecEnum::Lucene62(c) => c.points_format(),
         }
     }
+
+    /// Encodes/decodes knn vectors and their graphs
+    fn knn_vectors_format(&self) -> Self::KnnVectorsFmt {
+        match self {
+            CodecEnum::Lucene62(c) => c.knn_vectors_format(),
+        }
+    }
 }
 
 impl TryFrom<String> for CodecEnum {
@@ -239,6 +252,7 @@ pub struct Lucene62Codec {
     stored_fields_format: Lucene50StoredFieldsFormat,
     norms_format: Lucene53NormsFormat,
     points_format: Lucene60PointsFormat,
+    knn_vectors_format: Lucene90HnswVectorsFormat,
 }
 
 impl Default for Lucene62Codec {
@@ -256,6 +270,7 @@ impl Default for Lucene62Codec {
             doc_values_format: PerFieldDocValuesFormat::default(),
             norms_format: Lucene53NormsFormat::default(),
             points_format: Lucene60PointsFormat {},
+            knn_vectors_format: Lucene90HnswVectorsFormat::default(),
         }
     }
 }
@@ -272,6 +287,7 @@ impl Codec for Lucene62Codec {
     type LiveDocFmt = Lucene50LiveDocsFormat;
     type CompoundFmt = Lucene50CompoundFormat;
     type PointFmt = Lucene60PointsFormat;
+    type KnnVectorsFmt = Lucene90HnswVectorsFormat;
 
     fn name(&self) -> &str {
         "Lucene62"
@@ -316,6 +332,10 @@ impl Codec for Lucene62Codec {
     fn points_format(&self) -> Self::PointFmt {
         self.points_format
     }
+
+    fn knn_vectors_format(&self) -> Self::KnnVectorsFmt {
+        self.knn_vectors_format
+    }
 }
 
 impl TryFrom<String> for Lucene62Codec {
@@ -239,7 +239,7 @@ pub fn point_values_max_packed_value<C: Codec>(
 }
 
 /// Used by {@link #intersect} to check how each recursive cell corresponds to the query.
-#[derive(Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Relation {
     /// Return this if the cell is fully contained by the query
     CellInsideQuery,
@@ -123,6 +123,11 @@ impl<T: PostingsArray + 'static> TermsHashPerFieldBase<T> {
         unsafe { &*self.term_byte_pool }
     }
 
+    /// Returns true if some buffered document of this field has the term.
+    pub fn contains_term(&self, bytes: &BytesRef) -> bool {
+        self.inited && unsafe { self.bytes_hash.get_ref().find(bytes) >= 0 }
+    }
+
     fn add(&mut self, term_id: i32) {
         debug_assert!(self.inited);
         if term_id >= 0 {
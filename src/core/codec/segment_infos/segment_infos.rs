@@ -74,6 +74,8 @@ pub struct SegmentInfos<D: Directory, C: Codec> {
     pub lucene_version: Option<Version>,
     /// Version of the oldest segment in the index, or null if there are no segments.
     pub min_seg_version: Option<Version>,
+    /// Opaque map<String, String> that user can specify during IndexWriter::commit
+    pub user_data: HashMap<String, String>,
     // Only true after prepareCommit has been called and
     // before finishCommit is called
     pending_commit: bool,
@@ -90,6 +92,7 @@ impl<D: Directory, C: Codec> Default for SegmentInfos<D, C> {
             id: [0u8; ID_LENGTH],
             lucene_version: None,
             min_seg_version: None,
+            user_data: HashMap::new(),
             pending_commit: false,
         }
     }
@@ -117,10 +120,22 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
             id,
             lucene_version,
             min_seg_version,
+            user_data: HashMap::new(),
             pending_commit: false,
         }
     }
 
+    /// Return the user data map saved with this commit.
+    pub fn user_data(&self) -> &HashMap<String, String> {
+        &self.user_data
+    }
+
+    /// Sets the commit data, and marks this `SegmentInfos` as changed.
+    pub fn set_user_data(&mut self, data: HashMap<String, String>) {
+        self.user_data = data;
+        self.changed();
+    }
+
     /// return generation of the next pending_segments_N that will be written
     fn next_pending_generation(&self) -> u64 {
         if self.generation == -1 {
@@ -297,7 +312,7 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
                 output.write_set_of_strings(files)?;
             }
         }
-        output.write_map_of_strings(&HashMap::with_capacity(0))?;
+        output.write_map_of_strings(&self.user_data)?;
         write_footer(output)
     }
 
@@ -443,6 +458,17 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
     pub fn read_commit(directory: &Arc<D>, segment_file_name: &str) -> Result<Self> {
         let generation = generation_from_segments_file_name(segment_file_name)?;
         let input = directory.open_input(segment_file_name, &IOContext::READ)?;
+        Self::read_from_input(directory, input, generation)
+    }
+
+    /// Reads a `SegmentInfos` serialized by `write_output` from `input`, eg the
+    /// in-memory infos of a near real-time reader shipped to a replica.
+    /// `generation` must be the one the infos was written with.
+    pub fn read_from_input(
+        directory: &Arc<D>,
+        input: Box<dyn IndexInput>,
+        generation: i64,
+    ) -> Result<Self> {
         let mut checksum = BufferedChecksumIndexInput::new(input);
         let infos = Self::read_commit_generation(directory, &mut checksum, generation)?;
         validate_footer(&mut checksum)?;
@@ -554,9 +580,9 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
                 // TODO check version
             }
         }
-        let _user_data = input.read_map_of_strings();
+        let user_data = input.read_map_of_strings()?;
 
-        Ok(SegmentInfos::new(
+        let mut infos = SegmentInfos::new(
             counter as i32,
             version,
             generation,
@@ -565,7 +591,9 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
             id,
             lucene_version,
             min_seg_ver,
-        ))
+        );
+        infos.user_data = user_data;
+        Ok(infos)
     }
 
     pub fn read_latest_commit(directory: &Arc<D>) -> Result<Self> {
@@ -593,6 +621,7 @@ impl<D: Directory, C: Codec> Clone for SegmentInfos<D, C> {
             id,
             lucene_version: self.lucene_version,
             min_seg_version: self.min_seg_version,
+            user_data: self.user_data.clone(),
             pending_commit: self.pending_commit,
         }
     }
@@ -15,10 +15,10 @@ use core::codec::segment_infos::{
     parse_segment_name, segment_file_name, SegmentInfo, SEGMENT_USE_COMPOUND_NO,
     SEGMENT_USE_COMPOUND_YES,
 };
-use core::codec::{codec_util, Codec};
+use core::codec::{codec_util, Codec, Sorter};
 use core::search::sort_field::{
-    SimpleSortField, Sort, SortField, SortFieldType, SortedNumericSelectorType,
-    SortedNumericSortField, SortedSetSelectorType,
+    SimpleSortField, Sort, SortField, SortFieldMissingValue, SortFieldType,
+    SortedNumericSelectorType, SortedNumericSortField, SortedSetSelectorType, SortedSetSortField,
 };
 use core::store::directory::Directory;
 use core::store::io::{BufferedChecksumIndexInput, ChecksumIndexInput, DataOutput, IndexInput};
@@ -163,9 +163,13 @@ fn read_segment_info_from_index<D: Directory, C: Codec>(
                 bail!(CorruptIndex(format!("invalid index sort reverse: {}", b)));
             };
 
-            // TODO: not support sort by SortedSet field yet
-            debug_assert!(sorted_set_selector.is_none());
-            let mut sort_field = if let Some(sorted_numeric_selector) = sorted_numeric_selector {
+            let mut sort_field = if let Some(sorted_set_selector) = sorted_set_selector {
+                SortField::SortedSet(SortedSetSortField::new(
+                    field_name,
+                    reverse,
+                    sorted_set_selector,
+                ))
+            } else if let Some(sorted_numeric_selector) = sorted_numeric_selector {
                 SortField::SortedNumeric(SortedNumericSortField::new(
                     field_name,
                     sort_type,
@@ -182,15 +186,14 @@ fn read_segment_info_from_index<D: Directory, C: Codec>(
             if bv != 0 {
                 match sort_type {
                     SortFieldType::String => {
-                        unreachable!()
-                        //                        if bv == 1 {
-                        // missing_value =
-                        // Some(SortFieldMissingValue::StringLast);
-                        // } else if bv == 2 {
-                        // missing_value = Some(SortFieldMissingValue::StringFirst);
-                        //                        } else {
-                        //                            bail!("invalid missing value flag: {}", bv);
-                        //                        }
+                        let missing = match bv {
+                            1 => SortFieldMissingValue::StringLast,
+                            2 => SortFieldMissingValue::StringFirst,
+                            _ => {
+                                bail!(CorruptIndex(format!("invalid missing value flag: {}", bv)));
+                            }
+                        };
+                        sort_field.set_missing_placement(missing)?;
                     }
                     SortFieldType::Long => {
                         if bv != 1 {
@@ -339,15 +342,13 @@ impl SegmentInfoFormat for Lucene62SegmentInfoFormat {
                     SortFieldType::Int => 2,
                     SortFieldType::Double => 3,
                     SortFieldType::Float => 4,
-                    SortFieldType::Custom => {
-                        match sort_field {
-                            // SortField::SortedSet(_) => 5,
-                            SortField::SortedNumeric(_) => 6,
-                            _ => {
-                                bail!(IllegalState("Unexpected SortedNumericSortField".into()));
-                            }
+                    SortFieldType::Custom => match sort_field {
+                        SortField::SortedSet(_) => 5,
+                        SortField::SortedNumeric(_) => 6,
+                        _ => {
+                            bail!(IllegalState("Unexpected SortedNumericSortField".into()));
                         }
-                    }
+                    },
                     _ => {
                         bail!(IllegalState(format!(
                             "Unexpected sort type: {:?}",
@@ -357,7 +358,15 @@ impl SegmentInfoFormat for Lucene62SegmentInfoFormat {
                 };
                 output.write_vint(type_id)?;
                 if type_id == 5 {
-                    unimplemented!();
+                    if let SortField::SortedSet(sssf) = sort_field {
+                        let select_value = match sssf.selector() {
+                            SortedSetSelectorType::Min => 0,
+                            SortedSetSelectorType::Max => 1,
+                            SortedSetSelectorType::MiddleMin => 2,
+                            SortedSetSelectorType::MiddleMax => 3,
+                        };
+                        output.write_byte(select_value)?;
+                    }
                 } else if type_id == 6 {
                     if let SortField::SortedNumeric(snsf) = sort_field {
                         let v = match snsf.numeric_type() {
@@ -379,25 +388,42 @@ impl SegmentInfoFormat for Lucene62SegmentInfoFormat {
                 output.write_byte(reverse)?;
 
                 // write missing value
-                if let Some(missing_value) = sort_field.missing_value() {
+                if let Some(placement) = sort_field.missing_placement() {
+                    match placement {
+                        SortFieldMissingValue::StringLast => output.write_byte(1)?,
+                        SortFieldMissingValue::StringFirst => output.write_byte(2)?,
+                    }
+                } else if let Some(missing_value) = sort_field.missing_value() {
                     match missing_value {
                         VariantValue::Long(l) => {
-                            debug_assert_eq!(sort_field.field_type(), SortFieldType::Long);
+                            debug_assert_eq!(
+                                Sorter::sort_field_type(sort_field),
+                                SortFieldType::Long
+                            );
                             output.write_byte(1)?;
                             output.write_long(*l)?;
                         }
                         VariantValue::Int(i) => {
-                            debug_assert_eq!(sort_field.field_type(), SortFieldType::Int);
+                            debug_assert_eq!(
+                                Sorter::sort_field_type(sort_field),
+                                SortFieldType::Int
+                            );
                             output.write_byte(1)?;
                             output.write_int(*i)?;
                         }
                         VariantValue::Double(d) => {
-                            debug_assert_eq!(sort_field.field_type(), SortFieldType::Double);
+                            debug_assert_eq!(
+                                Sorter::sort_field_type(sort_field),
+                                SortFieldType::Double
+                            );
                             output.write_byte(1)?;
                             output.write_long((*d).to_bits() as i64)?;
                         }
                         VariantValue::Float(f) => {
-                            debug_assert_eq!(sort_field.field_type(), SortFieldType::Float);
+                            debug_assert_eq!(
+                                Sorter::sort_field_type(sort_field),
+                                SortFieldType::Float
+                            );
                             output.write_byte(1)?;
                             output.write_int((*f).to_bits() as i32)?;
                         }
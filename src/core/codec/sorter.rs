@@ -17,11 +17,17 @@ use core::index::merge::{LiveDocsDocMap, ReaderWrapperEnum};
 use core::index::reader::{LeafReader, LeafReaderContext};
 use core::search::sort_field::Sort;
 use core::search::sort_field::{ComparatorValue, FieldComparator, FieldComparatorEnum};
-use core::search::sort_field::{SortField, SortFieldType, SortedNumericSelector};
+use core::search::sort_field::{
+    SortField, SortFieldMissingValue, SortFieldType, SortedNumericSelector, SortedSetSelector,
+    SortedSetSelectorType,
+};
 use core::util::packed::COMPACT;
 use core::util::packed::{
     PackedLongValues, PackedLongValuesBuilder, PackedLongValuesBuilderType, DEFAULT_PAGE_SIZE,
 };
+use core::util::{
+    double2sortable_long, float2sortable_int, sortable_double_bits, sortable_float_bits,
+};
 use core::util::{BitsMut, BitsRef, DocId};
 
 use error::ErrorKind::IllegalArgument;
@@ -47,9 +53,37 @@ impl Sorter {
         match sort {
             SortField::Simple(s) => s.field_type(),
             SortField::SortedNumeric(s) => s.numeric_type(),
+            SortField::SortedSet(_) => SortFieldType::String,
+            // can't be used to sort an index
+            SortField::LatLonDistance(_) => SortFieldType::Custom,
         }
     }
 
+    /// Maps the raw doc value of a numeric sort to a long with the same
+    /// order, the bits of floats and doubles are made comparable as signed
+    /// integers.
+    pub fn numeric_sort_key(field_type: SortFieldType, raw: i64) -> i64 {
+        match field_type {
+            SortFieldType::Float => i64::from(sortable_float_bits(raw as i32)),
+            SortFieldType::Double => sortable_double_bits(raw),
+            _ => raw,
+        }
+    }
+
+    /// Returns the sort key of the documents without a value for a numeric
+    /// sort, see `numeric_sort_key`.
+    pub fn numeric_missing_sort_key(sort_field: &SortField) -> i64 {
+        // a zero raw value is a zero key for every type
+        sort_field
+            .missing_value()
+            .map_or(0, |missing| match Self::sort_field_type(sort_field) {
+                SortFieldType::Int => i64::from(missing.get_int().unwrap()),
+                SortFieldType::Float => i64::from(float2sortable_int(missing.get_float().unwrap())),
+                SortFieldType::Double => double2sortable_long(missing.get_double().unwrap()),
+                _ => missing.get_long().unwrap(),
+            })
+    }
+
     #[allow(dead_code)]
     /// Check consistency of a `SorterDocMap`, useful for assertions.
     fn is_consistent(doc_map: &dyn SorterDocMap) -> bool {
@@ -166,7 +200,7 @@ impl Sorter {
         let mut comparators = Vec::with_capacity(fields.len());
         for field in fields {
             reverses.push(field.is_reverse());
-            let mut comparator = field.get_comparator(1, None);
+            let mut comparator = field.get_comparator(1, field.missing_value());
             comparator.get_information_from_reader(reader)?;
             comparators.push(comparator);
         }
@@ -365,57 +399,44 @@ impl MultiSorter {
         let reverse = sort_field.is_reverse();
         let field_type = Sorter::sort_field_type(sort_field);
         match field_type {
-            SortFieldType::String => unimplemented!(),
-            SortFieldType::Long | SortFieldType::Int => {
+            SortFieldType::String => {
+                let (selector, missing_last) = match sort_field {
+                    SortField::SortedSet(s) => (
+                        s.selector(),
+                        s.missing_value() == SortFieldMissingValue::StringLast,
+                    ),
+                    _ => (
+                        SortedSetSelectorType::Min,
+                        sort_field.missing_placement() == Some(SortFieldMissingValue::StringLast),
+                    ),
+                };
                 let mut values = Vec::with_capacity(readers.len());
-                let mut docs_with_fields = Vec::with_capacity(readers.len());
                 for reader in readers {
-                    values.push(Sorter::get_or_wrap_numeric(reader, sort_field)?);
-                    docs_with_fields.push(reader.get_docs_with_field(sort_field.field())?);
+                    values.push(SortedSetSelector::wrap(
+                        SortedSetSelector::doc_values(reader, sort_field.field())?,
+                        selector,
+                    ));
                 }
-                let missing_value = if let Some(missing) = sort_field.missing_value() {
-                    if field_type == SortFieldType::Long {
-                        missing.get_long().unwrap()
-                    } else if field_type == SortFieldType::Int {
-                        missing.get_int().unwrap() as i64
-                    } else {
-                        unreachable!()
-                    }
-                } else {
-                    0
-                };
-                Ok(CrossReaderComparatorEnum::Long(
-                    LongCrossReaderComparator::new(
-                        docs_with_fields,
-                        values,
-                        missing_value,
-                        reverse,
-                    ),
+                Ok(CrossReaderComparatorEnum::Bytes(
+                    BytesCrossReaderComparator::new(values, missing_last, reverse),
                 ))
             }
-            SortFieldType::Double | SortFieldType::Float => {
+            SortFieldType::Long
+            | SortFieldType::Int
+            | SortFieldType::Double
+            | SortFieldType::Float => {
                 let mut values = Vec::with_capacity(readers.len());
                 let mut docs_with_fields = Vec::with_capacity(readers.len());
                 for reader in readers {
                     values.push(Sorter::get_or_wrap_numeric(reader, sort_field)?);
                     docs_with_fields.push(reader.get_docs_with_field(sort_field.field())?);
                 }
-                let missing_value = if let Some(missing) = sort_field.missing_value() {
-                    if field_type == SortFieldType::Double {
-                        missing.get_double().unwrap()
-                    } else if field_type == SortFieldType::Float {
-                        missing.get_float().unwrap() as f64
-                    } else {
-                        unreachable!()
-                    }
-                } else {
-                    0.0
-                };
-                Ok(CrossReaderComparatorEnum::Double(
-                    DoubleCrossReaderComparator::new(
+                Ok(CrossReaderComparatorEnum::Numeric(
+                    NumericCrossReaderComparator::new(
                         docs_with_fields,
                         values,
-                        missing_value,
+                        field_type,
+                        Sorter::numeric_missing_sort_key(sort_field),
                         reverse,
                     ),
                 ))
@@ -498,8 +519,8 @@ impl PartialOrd for LeafAndDocId {
 }
 
 enum CrossReaderComparatorEnum {
-    Long(LongCrossReaderComparator),
-    Double(DoubleCrossReaderComparator),
+    Numeric(NumericCrossReaderComparator),
+    Bytes(BytesCrossReaderComparator),
 }
 
 impl CrossReaderComparator for CrossReaderComparatorEnum {
@@ -511,11 +532,11 @@ impl CrossReaderComparator for CrossReaderComparatorEnum {
         doc_id2: DocId,
     ) -> Result<Ordering> {
         match self {
-            CrossReaderComparatorEnum::Long(l) => {
-                l.compare(reader_index1, doc_id1, reader_index2, doc_id2)
+            CrossReaderComparatorEnum::Numeric(n) => {
+                n.compare(reader_index1, doc_id1, reader_index2, doc_id2)
             }
-            CrossReaderComparatorEnum::Double(d) => {
-                d.compare(reader_index1, doc_id1, reader_index2, doc_id2)
+            CrossReaderComparatorEnum::Bytes(b) => {
+                b.compare(reader_index1, doc_id1, reader_index2, doc_id2)
             }
         }
     }
@@ -531,30 +552,43 @@ trait CrossReaderComparator {
     ) -> Result<Ordering>;
 }
 
-struct LongCrossReaderComparator {
+// compares the sort keys of the values, see `Sorter::numeric_sort_key`
+struct NumericCrossReaderComparator {
     docs_with_fields: Vec<Box<dyn BitsMut>>,
     values: Vec<Box<dyn NumericDocValues>>,
-    missing_value: i64,
+    field_type: SortFieldType,
+    missing_key: i64,
     reverse: bool,
 }
 
-impl LongCrossReaderComparator {
+impl NumericCrossReaderComparator {
     fn new(
         docs_with_fields: Vec<Box<dyn BitsMut>>,
         values: Vec<Box<dyn NumericDocValues>>,
-        missing_value: i64,
+        field_type: SortFieldType,
+        missing_key: i64,
         reverse: bool,
     ) -> Self {
-        LongCrossReaderComparator {
+        NumericCrossReaderComparator {
             docs_with_fields,
             values,
-            missing_value,
+            field_type,
+            missing_key,
             reverse,
         }
     }
+
+    fn key(&mut self, idx: usize, doc_id: DocId) -> Result<i64> {
+        if self.docs_with_fields[idx].get(doc_id as usize)? {
+            let raw = self.values[idx].get_mut(doc_id)?;
+            Ok(Sorter::numeric_sort_key(self.field_type, raw))
+        } else {
+            Ok(self.missing_key)
+        }
+    }
 }
 
-impl CrossReaderComparator for LongCrossReaderComparator {
+impl CrossReaderComparator for NumericCrossReaderComparator {
     fn compare(
         &mut self,
         idx1: usize,
@@ -562,17 +596,7 @@ impl CrossReaderComparator for LongCrossReaderComparator {
         idx2: usize,
         doc_id2: DocId,
     ) -> Result<Ordering> {
-        let value1 = if self.docs_with_fields[idx1].get(doc_id1 as usize)? {
-            self.values[idx1].get_mut(doc_id1)?
-        } else {
-            self.missing_value
-        };
-        let value2 = if self.docs_with_fields[idx2].get(doc_id2 as usize)? {
-            self.values[idx2].get_mut(doc_id2)?
-        } else {
-            self.missing_value
-        };
-        let res = value1.cmp(&value2);
+        let res = self.key(idx1, doc_id1)?.cmp(&self.key(idx2, doc_id2)?);
         if self.reverse {
             Ok(res.reverse())
         } else {
@@ -581,30 +605,25 @@ impl CrossReaderComparator for LongCrossReaderComparator {
     }
 }
 
-struct DoubleCrossReaderComparator {
-    docs_with_fields: Vec<Box<dyn BitsMut>>,
-    values: Vec<Box<dyn NumericDocValues>>,
-    missing_value: f64,
+// ordinals of different segments are not comparable, so this compares the
+// selected values
+struct BytesCrossReaderComparator {
+    values: Vec<SortedSetSelector>,
+    missing_last: bool,
     reverse: bool,
 }
 
-impl DoubleCrossReaderComparator {
-    fn new(
-        docs_with_fields: Vec<Box<dyn BitsMut>>,
-        values: Vec<Box<dyn NumericDocValues>>,
-        missing_value: f64,
-        reverse: bool,
-    ) -> Self {
-        DoubleCrossReaderComparator {
-            docs_with_fields,
+impl BytesCrossReaderComparator {
+    fn new(values: Vec<SortedSetSelector>, missing_last: bool, reverse: bool) -> Self {
+        BytesCrossReaderComparator {
             values,
-            missing_value,
+            missing_last,
             reverse,
         }
     }
 }
 
-impl CrossReaderComparator for DoubleCrossReaderComparator {
+impl CrossReaderComparator for BytesCrossReaderComparator {
     fn compare(
         &mut self,
         idx1: usize,
@@ -612,26 +631,20 @@ impl CrossReaderComparator for DoubleCrossReaderComparator {
         idx2: usize,
         doc_id2: DocId,
     ) -> Result<Ordering> {
-        let value1 = if self.docs_with_fields[idx1].get(doc_id1 as usize)? {
-            f64::from_bits(self.values[idx1].get_mut(doc_id1)? as u64)
-        } else {
-            self.missing_value
+        let value1 = self.values[idx1].get(doc_id1)?;
+        let value2 = self.values[idx2].get(doc_id2)?;
+        let res = match (value1, value2) {
+            (Some(v1), Some(v2)) => v1.cmp(&v2),
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) if self.missing_last => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) if self.missing_last => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
         };
-        let value2 = if self.docs_with_fields[idx2].get(doc_id2 as usize)? {
-            f64::from_bits(self.values[idx2].get_mut(doc_id2)? as u64)
-        } else {
-            self.missing_value
-        };
-
-        // NaN
-        if let Some(res) = value1.partial_cmp(&value2) {
-            if self.reverse {
-                Ok(res.reverse())
-            } else {
-                Ok(res)
-            }
+        if self.reverse {
+            Ok(res.reverse())
         } else {
-            Ok(Ordering::Equal)
+            Ok(res)
         }
     }
 }
@@ -655,3 +668,55 @@ impl<T> SorterDocComparator for DVSortDocComparator<T> {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::sort_field::{SimpleSortField, SortedNumericSortField};
+    use core::util::VariantValue;
+
+    #[test]
+    fn test_numeric_sort_key() {
+        let floats = [-2.5f32, -0.5, 0.0, 1.0, 3.5];
+        let keys: Vec<i64> = floats
+            .iter()
+            .map(|f| Sorter::numeric_sort_key(SortFieldType::Float, i64::from(f.to_bits())))
+            .collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        let doubles = [-7.25f64, -1.0, 0.0, 2.0];
+        let keys: Vec<i64> = doubles
+            .iter()
+            .map(|d| Sorter::numeric_sort_key(SortFieldType::Double, d.to_bits() as i64))
+            .collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(Sorter::numeric_sort_key(SortFieldType::Long, -3), -3);
+    }
+
+    #[test]
+    fn test_numeric_missing_sort_key() {
+        let mut sort_field = SortField::Simple(SimpleSortField::new(
+            "price".into(),
+            SortFieldType::Float,
+            false,
+        ));
+        assert_eq!(Sorter::numeric_missing_sort_key(&sort_field), 0);
+        sort_field.set_missing_value(Some(VariantValue::Float(-1.5)));
+        let missing = Sorter::numeric_missing_sort_key(&sort_field);
+        let zero = Sorter::numeric_sort_key(SortFieldType::Float, 0);
+        let lowest = Sorter::numeric_sort_key(SortFieldType::Float, i64::from((-2.0f32).to_bits()));
+        assert!(lowest < missing && missing < zero);
+
+        let mut sort_field = SortField::SortedNumeric(SortedNumericSortField::with_field(
+            "price".into(),
+            SortFieldType::Double,
+        ));
+        sort_field
+            .set_missing_placement(SortFieldMissingValue::StringLast)
+            .unwrap();
+        assert_eq!(
+            Sorter::numeric_missing_sort_key(&sort_field),
+            double2sortable_long(::std::f64::INFINITY)
+        );
+    }
+}
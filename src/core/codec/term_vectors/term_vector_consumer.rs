@@ -103,7 +103,10 @@ where
         debug_assert!(self.inited);
         if self.writer.is_none() {
             let doc_writer = unsafe { &*self.doc_writer };
-            let context = IOContext::Flush(FlushInfo::new(doc_writer.num_docs_in_ram));
+            let context = IOContext::Flush(FlushInfo::with_segment_size(
+                doc_writer.num_docs_in_ram,
+                doc_writer.bytes_used(),
+            ));
             self.writer = Some(doc_writer.codec().term_vectors_format().tv_writer(
                 self.out_dir.as_ref(),
                 &doc_writer.segment_info,
@@ -16,6 +16,8 @@ use core::doc::{
     Field, FieldType, Fieldable, BINARY_DOC_VALUES_FIELD_TYPE, NUMERIC_DOC_VALUES_FIELD_TYPE,
     SORTED_NUMERIC_DOC_VALUES_FIELD_TYPE, SORTED_SET_DOC_VALUES_FIELD_TYPE,
 };
+use core::search::sort_field::{LatLonDistanceSortField, SortField};
+use core::util::geo::{decode_lat_lon, encode_lat_lon};
 use core::util::{BytesRef, Numeric, VariantValue};
 
 use error::Result;
@@ -285,6 +287,92 @@ impl Fieldable for SortedNumericDocValuesField {
     }
 }
 
+/// Per-document geo points, for distance sorting.
+///
+/// The point is quantized like in `LatLonPoint` and stored as a single
+/// sorted numeric value, with the encoded latitude in the upper 32 bits and
+/// the encoded longitude in the lower ones. A document may have several
+/// points, the closest one is used to sort.
+pub struct LatLonDocValuesField {
+    field: Field,
+}
+
+impl LatLonDocValuesField {
+    /// Returns an error if the latitude or longitude is out of bounds.
+    pub fn new(name: &str, latitude: f64, longitude: f64) -> Result<LatLonDocValuesField> {
+        let encoded = encode_lat_lon(latitude, longitude)?;
+        Ok(LatLonDocValuesField {
+            field: Field::new(
+                String::from(name),
+                SORTED_NUMERIC_DOC_VALUES_FIELD_TYPE,
+                Some(VariantValue::Long(encoded)),
+                None,
+            ),
+        })
+    }
+
+    fn encoded_value(&self) -> i64 {
+        match self.field.field_data().unwrap() {
+            VariantValue::Long(v) => *v,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the quantized latitude.
+    pub fn latitude(&self) -> f64 {
+        decode_lat_lon(self.encoded_value()).0
+    }
+
+    /// Returns the quantized longitude.
+    pub fn longitude(&self) -> f64 {
+        decode_lat_lon(self.encoded_value()).1
+    }
+
+    /// Creates a `SortField` sorting by the distance in meters from the given
+    /// origin, ascending. Documents without a point sort last.
+    pub fn new_distance_sort(field: &str, latitude: f64, longitude: f64) -> Result<SortField> {
+        Ok(SortField::LatLonDistance(LatLonDistanceSortField::new(
+            field.to_string(),
+            latitude,
+            longitude,
+        )?))
+    }
+}
+
+impl Fieldable for LatLonDocValuesField {
+    fn name(&self) -> &str {
+        self.field.name()
+    }
+
+    fn field_type(&self) -> &FieldType {
+        self.field.field_type()
+    }
+
+    fn boost(&self) -> f32 {
+        self.field.boost()
+    }
+
+    fn field_data(&self) -> Option<&VariantValue> {
+        self.field.field_data()
+    }
+
+    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
+        self.field.token_stream()
+    }
+
+    fn binary_value(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn string_value(&self) -> Option<&str> {
+        None
+    }
+
+    fn numeric_value(&self) -> Option<Numeric> {
+        self.field.numeric_value()
+    }
+}
+
 pub struct SortedSetDocValuesField {
     field: Field,
 }
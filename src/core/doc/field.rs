@@ -13,7 +13,7 @@
 
 use core::analysis::{BinaryTokenStream, StringTokenStream, TokenStream};
 use core::doc::{DocValuesType, IndexOptions};
-use core::util::{ByteBlockPool, BytesRef, Numeric, VariantValue};
+use core::util::{ByteBlockPool, BytesRef, Numeric, VariantValue, VectorSimilarity};
 
 use error::ErrorKind::IllegalArgument;
 use error::{ErrorKind, Result};
@@ -211,6 +211,8 @@ pub struct FieldType {
     pub doc_values_type: DocValuesType,
     pub dimension_count: u32,
     pub dimension_num_bytes: u32,
+    pub vector_dimension: u32,
+    pub vector_similarity: VectorSimilarity,
 }
 
 impl Default for FieldType {
@@ -227,6 +229,8 @@ impl Default for FieldType {
             doc_values_type: DocValuesType::Null,
             dimension_count: 0,
             dimension_num_bytes: 0,
+            vector_dimension: 0,
+            vector_similarity: VectorSimilarity::Euclidean,
         }
     }
 }
@@ -235,6 +239,8 @@ impl Default for FieldType {
 pub const POINT_MAX_NUM_BYTES: u32 = 16;
 /// Maximum number of dimensions
 pub const POINT_MAX_DIMENSIONS: u32 = 8; // TODO should be replaced by BKDWriter.MAX_DIMS
+/// Maximum number of dimensions of a knn vector
+pub const VECTOR_MAX_DIMENSIONS: u32 = 1024;
 
 impl FieldType {
     #[allow(clippy::too_many_arguments)]
@@ -263,6 +269,8 @@ impl FieldType {
             doc_values_type,
             dimension_count,
             dimension_num_bytes,
+            vector_dimension: 0,
+            vector_similarity: VectorSimilarity::Euclidean,
         }
     }
 
@@ -363,6 +371,24 @@ impl FieldType {
 
         Ok(())
     }
+
+    pub fn set_vector_attributes(
+        &mut self,
+        vector_dimension: u32,
+        vector_similarity: VectorSimilarity,
+    ) -> Result<()> {
+        if vector_dimension == 0 || vector_dimension > VECTOR_MAX_DIMENSIONS {
+            bail!(IllegalArgument(format!(
+                "vector_dimension must be in [1, {}], got {}",
+                VECTOR_MAX_DIMENSIONS, vector_dimension
+            )));
+        }
+
+        self.vector_dimension = vector_dimension;
+        self.vector_similarity = vector_similarity;
+
+        Ok(())
+    }
 }
 
 impl fmt::Display for FieldType {
@@ -387,6 +413,8 @@ pub const NUMERIC_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
     doc_values_type: DocValuesType::Numeric,
     dimension_count: 0,
     dimension_num_bytes: 0,
+    vector_dimension: 0,
+    vector_similarity: VectorSimilarity::Euclidean,
 };
 
 pub const SORTED_NUMERIC_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
@@ -401,6 +429,8 @@ pub const SORTED_NUMERIC_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
     doc_values_type: DocValuesType::SortedNumeric,
     dimension_count: 0,
     dimension_num_bytes: 0,
+    vector_dimension: 0,
+    vector_similarity: VectorSimilarity::Euclidean,
 };
 
 pub const BINARY_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
@@ -415,6 +445,8 @@ pub const BINARY_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
     doc_values_type: DocValuesType::Binary,
     dimension_count: 0,
     dimension_num_bytes: 0,
+    vector_dimension: 0,
+    vector_similarity: VectorSimilarity::Euclidean,
 };
 
 pub const SORTED_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
@@ -429,6 +461,8 @@ pub const SORTED_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
     doc_values_type: DocValuesType::Sorted,
     dimension_count: 0,
     dimension_num_bytes: 0,
+    vector_dimension: 0,
+    vector_similarity: VectorSimilarity::Euclidean,
 };
 
 pub const SORTED_SET_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
@@ -443,6 +477,8 @@ pub const SORTED_SET_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
     doc_values_type: DocValuesType::SortedSet,
     dimension_count: 0,
     dimension_num_bytes: 0,
+    vector_dimension: 0,
+    vector_similarity: VectorSimilarity::Euclidean,
 };
 
 pub const STORE_FIELD_TYPE: FieldType = FieldType {
@@ -457,4 +493,6 @@ pub const STORE_FIELD_TYPE: FieldType = FieldType {
     doc_values_type: DocValuesType::Null,
     dimension_count: 0,
     dimension_num_bytes: 0,
+    vector_dimension: 0,
+    vector_similarity: VectorSimilarity::Euclidean,
 };
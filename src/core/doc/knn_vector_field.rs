@@ -0,0 +1,133 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::analysis::TokenStream;
+use core::doc::{Field, FieldType, Fieldable};
+use core::util::{Numeric, VariantValue, VectorSimilarity};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+/// A dense vector of `f32` values, indexed for approximate nearest neighbor
+/// search with `KnnVectorQuery`.
+///
+/// All the vectors of one field must have the same dimension and similarity.
+/// A document may have at most one vector per field.
+pub struct KnnVectorField {
+    field: Field,
+}
+
+impl KnnVectorField {
+    /// Returns an error if the vector is empty, too large or has non finite values.
+    pub fn new(name: &str, vector: &[f32], similarity: VectorSimilarity) -> Result<KnnVectorField> {
+        if vector.iter().any(|v| !v.is_finite()) {
+            bail!(IllegalArgument(format!(
+                "vector of field '{}' has non finite values",
+                name
+            )));
+        }
+        let mut field_type = FieldType {
+            tokenized: false,
+            ..FieldType::default()
+        };
+        field_type.set_vector_attributes(vector.len() as u32, similarity)?;
+
+        Ok(KnnVectorField {
+            field: Field::new_bytes(name.to_string(), encode_vector(vector), field_type),
+        })
+    }
+
+    pub fn vector(&self) -> Vec<f32> {
+        decode_vector(self.field.binary_value().unwrap())
+    }
+}
+
+/// Encodes the vector as the big endian bits of each value.
+pub fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(vector.len() * 4);
+    for v in vector {
+        bytes.extend_from_slice(&v.to_bits().to_be_bytes());
+    }
+    bytes
+}
+
+pub fn decode_vector(bytes: &[u8]) -> Vec<f32> {
+    let mut vector = vec![0f32; bytes.len() / 4];
+    decode_vector_into(bytes, &mut vector);
+    vector
+}
+
+/// Decodes the vector into a reusable buffer, `vector` must have
+/// `bytes.len() / 4` values.
+pub fn decode_vector_into(bytes: &[u8], vector: &mut [f32]) {
+    debug_assert_eq!(bytes.len() % 4, 0);
+    debug_assert_eq!(bytes.len() / 4, vector.len());
+    for (v, c) in vector.iter_mut().zip(bytes.chunks_exact(4)) {
+        *v = f32::from_bits(u32::from_be_bytes([c[0], c[1], c[2], c[3]]));
+    }
+}
+
+impl Fieldable for KnnVectorField {
+    fn name(&self) -> &str {
+        self.field.name()
+    }
+
+    fn field_type(&self) -> &FieldType {
+        self.field.field_type()
+    }
+
+    fn boost(&self) -> f32 {
+        self.field.boost()
+    }
+
+    fn field_data(&self) -> Option<&VariantValue> {
+        self.field.field_data()
+    }
+
+    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
+        self.field.token_stream()
+    }
+
+    fn binary_value(&self) -> Option<&[u8]> {
+        self.field.binary_value()
+    }
+
+    fn string_value(&self) -> Option<&str> {
+        None
+    }
+
+    fn numeric_value(&self) -> Option<Numeric> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_knn_vector_field() {
+        let field =
+            KnnVectorField::new("vec", &[1.0, -2.5, 0.0], VectorSimilarity::Cosine).unwrap();
+        assert_eq!(field.vector(), vec![1.0, -2.5, 0.0]);
+        assert_eq!(field.field_type().vector_dimension, 3);
+        assert_eq!(
+            field.field_type().vector_similarity,
+            VectorSimilarity::Cosine
+        );
+
+        assert!(KnnVectorField::new("vec", &[], VectorSimilarity::Cosine).is_err());
+        let invalid = KnnVectorField::new("vec", &[::std::f32::NAN], VectorSimilarity::Cosine);
+        assert!(invalid.is_err());
+    }
+}
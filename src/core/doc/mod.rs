@@ -30,3 +30,7 @@ pub use self::index_options::*;
 mod doc_values;
 
 pub use self::doc_values::*;
+
+mod knn_vector_field;
+
+pub use self::knn_vector_field::*;
@@ -0,0 +1,1491 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::doc_values::NO_MORE_ORDS;
+use core::codec::field_infos::FieldInfo;
+use core::codec::points::{IntersectVisitor, PointValues, Relation};
+use core::codec::segment_infos::{generation_from_segments_file_name, SegmentCommitInfo};
+use core::codec::segment_infos::{SegmentInfos, INDEX_FILE_OLD_SEGMENT_GEN, INDEX_FILE_SEGMENTS};
+use core::codec::{checksum_entire_file, retrieve_checksum, Codec};
+use core::codec::{Fields, PostingIterator, PostingIteratorFlags, TermIterator, Terms};
+use core::doc::{DocValuesType, IndexOptions, Status as VisitStatus, StoredFieldVisitor};
+use core::index::reader::{LeafReader, SegmentReader};
+use core::search::{DocIterator, NO_MORE_DOCS};
+use core::store::directory::{Directory, Lock, WRITE_LOCK_NAME};
+use core::store::IOContext;
+use core::util::{BitSet, DocId, FixedBitSet, ImmutableBitSet, VERSION_LATEST};
+
+use error::ErrorKind::{CorruptIndex, IllegalArgument, IllegalState};
+use error::{Error, Result};
+
+use std::any::Any;
+use std::collections::HashSet;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+/// Basic tool and API to check the health of an index and write a new
+/// segments file that removes reference to problematic segments.
+///
+/// As this tool checks every byte in the index, on a large index it can take
+/// quite a long time to run. The write lock of the index is held while the
+/// `CheckIndex` is alive, so no `IndexWriter` may change the index meanwhile.
+///
+/// *WARNING:* `exorcise_index` removes the documents of the broken segments
+/// from the index for good, make a backup copy of the index first.
+pub struct CheckIndex<D: Directory + 'static, C: Codec> {
+    directory: Arc<D>,
+    info_stream: Option<Box<dyn Write>>,
+    _write_lock: Box<dyn Lock>,
+    _codec: PhantomData<C>,
+}
+
+/// Returned from `CheckIndex::check_index` detailing the health and status
+/// of the index.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// True if no problems were found with the index.
+    pub clean: bool,
+    /// True if we were unable to locate and load the segments_N file.
+    pub missing_segments: bool,
+    /// Name of latest segments_N file in the index.
+    pub segments_file_name: Option<String>,
+    /// Number of segments in the index.
+    pub num_segments: usize,
+    /// Only the segments in this list were checked, empty if all were.
+    pub segments_checked: Vec<String>,
+    /// True if the index was created with a newer version of rucene.
+    pub tool_out_of_date: bool,
+    /// Status of each segment in the index.
+    pub segment_infos: Vec<SegmentInfoStatus>,
+    /// How many documents will be lost to bad segments.
+    pub tot_lose_doc_count: i32,
+    /// How many bad segments were found.
+    pub num_bad_segments: usize,
+    /// True if we checked only specific segments, the index can't be
+    /// exorcised then.
+    pub partial: bool,
+    /// Status of every commit of the index, oldest first.
+    pub commits: Vec<CommitStatus>,
+    /// How many broken commits were found, their segments are not checked
+    /// unless they are in the latest commit.
+    pub num_bad_commits: usize,
+}
+
+/// Status from checking a commit of the index, that is its `segments_N`
+/// file and the files it references.
+#[derive(Debug, Default)]
+pub struct CommitStatus {
+    pub segments_file_name: String,
+    pub num_segments: usize,
+    /// Number of referenced files whose footer was verified.
+    pub files_checked: usize,
+    pub error: Option<String>,
+}
+
+/// Holds the status of each segment in the index.
+#[derive(Debug, Default)]
+pub struct SegmentInfoStatus {
+    pub name: String,
+    pub codec: String,
+    pub max_doc: i32,
+    pub compound: bool,
+    pub num_files: usize,
+    pub size_mb: f64,
+    pub has_deletions: bool,
+    pub del_count: i32,
+    /// True if we were able to open a `SegmentReader` on this segment.
+    pub open_reader_passed: bool,
+    /// Error of the first failed test, `None` if the segment is healthy.
+    pub error: Option<String>,
+    pub checksum_status: Option<ChecksumStatus>,
+    pub live_docs_status: Option<LiveDocStatus>,
+    pub field_info_status: Option<FieldInfoStatus>,
+    pub field_norm_status: Option<FieldNormStatus>,
+    pub term_index_status: Option<TermIndexStatus>,
+    pub stored_field_status: Option<StoredFieldStatus>,
+    pub term_vector_status: Option<TermVectorStatus>,
+    pub doc_values_status: Option<DocValuesStatus>,
+    pub points_status: Option<PointsStatus>,
+}
+
+impl SegmentInfoStatus {
+    fn new<D: Directory, C: Codec>(si: &SegmentCommitInfo<D, C>) -> SegmentInfoStatus {
+        SegmentInfoStatus {
+            name: si.info.name.clone(),
+            codec: si.info.codec().name().to_string(),
+            max_doc: si.info.max_doc(),
+            compound: si.info.is_compound_file(),
+            has_deletions: si.has_deletions(),
+            del_count: si.del_count(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Status from verifying the checksum footer of the segment files.
+#[derive(Debug, Default)]
+pub struct ChecksumStatus {
+    pub files_checked: usize,
+    pub error: Option<String>,
+}
+
+/// Status from testing the live docs.
+#[derive(Debug, Default)]
+pub struct LiveDocStatus {
+    pub num_deleted: usize,
+    pub error: Option<String>,
+}
+
+/// Status from testing the field infos.
+#[derive(Debug, Default)]
+pub struct FieldInfoStatus {
+    pub tot_fields: usize,
+    pub error: Option<String>,
+}
+
+/// Status from testing the field norms.
+#[derive(Debug, Default)]
+pub struct FieldNormStatus {
+    pub tot_fields: usize,
+    pub error: Option<String>,
+}
+
+/// Status from testing the postings.
+#[derive(Debug, Default)]
+pub struct TermIndexStatus {
+    pub term_count: i64,
+    /// Number of terms with all of their documents deleted.
+    pub del_term_count: i64,
+    /// Total frequency across all terms.
+    pub tot_freq: i64,
+    /// Total number of positions.
+    pub tot_pos: i64,
+    pub error: Option<String>,
+}
+
+/// Status from testing the stored fields.
+#[derive(Debug, Default)]
+pub struct StoredFieldStatus {
+    pub doc_count: i32,
+    pub tot_fields: i64,
+    pub error: Option<String>,
+}
+
+/// Status from testing the term vectors.
+#[derive(Debug, Default)]
+pub struct TermVectorStatus {
+    pub doc_count: i32,
+    pub tot_vector_fields: i64,
+    pub error: Option<String>,
+}
+
+/// Status from testing the doc values.
+#[derive(Debug, Default)]
+pub struct DocValuesStatus {
+    pub tot_numeric_fields: usize,
+    pub tot_binary_fields: usize,
+    pub tot_sorted_fields: usize,
+    pub tot_sorted_numeric_fields: usize,
+    pub tot_sorted_set_fields: usize,
+    pub error: Option<String>,
+}
+
+/// Status from testing the points.
+#[derive(Debug, Default)]
+pub struct PointsStatus {
+    pub tot_value_points: i64,
+    pub tot_value_fields: usize,
+    pub error: Option<String>,
+}
+
+impl<D: Directory + 'static, C: Codec> CheckIndex<D, C> {
+    /// Creates a new `CheckIndex` on the directory, obtaining its write lock.
+    pub fn new(directory: Arc<D>) -> Result<Self> {
+        let write_lock = directory.obtain_lock(WRITE_LOCK_NAME)?;
+        Ok(CheckIndex {
+            directory,
+            info_stream: None,
+            _write_lock: write_lock,
+            _codec: PhantomData,
+        })
+    }
+
+    /// Sets the stream to print the per-segment report to.
+    pub fn set_info_stream(&mut self, info_stream: Box<dyn Write>) {
+        self.info_stream = Some(info_stream);
+    }
+
+    fn msg(&mut self, msg: &str) {
+        if let Some(ref mut out) = self.info_stream {
+            let _ = writeln!(out, "{}", msg);
+        }
+    }
+
+    /// Checks the latest commit of the index. If `only_segments` is not
+    /// empty, only the segments of these names are checked.
+    ///
+    /// The `segments_N` file of every commit of the index is verified as
+    /// well, with the footers of the files it references: older commits may
+    /// still be opened or rolled back to.
+    ///
+    /// This method reports the problems it finds in the returned `Status`
+    /// rather than as an error, an error only means it could not run.
+    pub fn check_index(&mut self, only_segments: &[String]) -> Result<Status> {
+        let mut result = Status::default();
+
+        let infos: SegmentInfos<D, C> = match SegmentInfos::read_latest_commit(&self.directory) {
+            Ok(infos) => infos,
+            Err(e) => {
+                self.msg(&format!(
+                    "ERROR: could not read any segments file in directory: {:?}",
+                    e
+                ));
+                result.missing_segments = true;
+                return Ok(result);
+            }
+        };
+        let segments_file_name = infos.segment_file_name().unwrap_or_default();
+        let num_segments = infos.len();
+        result.segments_file_name = Some(segments_file_name.clone());
+        result.num_segments = num_segments;
+        if let Some(ref version) = infos.lucene_version {
+            result.tool_out_of_date = !VERSION_LATEST.on_or_after(version);
+        }
+        self.msg(&format!(
+            "Segments file={} numSegments={} version={}",
+            segments_file_name,
+            num_segments,
+            infos
+                .lucene_version
+                .as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "<unknown>".into())
+        ));
+
+        if !only_segments.is_empty() {
+            result.partial = true;
+            result.segments_checked = only_segments.to_vec();
+            self.msg(&format!(
+                "\nChecking only these segments: {:?}",
+                only_segments
+            ));
+        }
+
+        if result.tool_out_of_date {
+            self.msg("WARNING: this index was written by a newer version, the check may fail");
+        }
+
+        result.commits = self.check_commits()?;
+        result.num_bad_commits = result.commits.iter().filter(|c| c.error.is_some()).count();
+
+        for (i, si) in infos.segments.iter().enumerate() {
+            if !only_segments.is_empty() && !only_segments.contains(&si.info.name) {
+                continue;
+            }
+            self.msg(&format!(
+                "  {} of {}: name={} maxDoc={}",
+                i + 1,
+                num_segments,
+                si.info.name,
+                si.info.max_doc()
+            ));
+
+            // a corrupt file may panic the reader rather than return an error,
+            // which must not abort the check of the other segments
+            let status = match panic::catch_unwind(AssertUnwindSafe(|| self.check_segment(si))) {
+                Ok(status) => status,
+                Err(payload) => {
+                    let mut status = SegmentInfoStatus::new(si);
+                    status.error = Some(format!(
+                        "check of segment panicked: {}",
+                        panic_message(payload.as_ref())
+                    ));
+                    status
+                }
+            };
+            if let Some(ref e) = status.error {
+                self.msg("FAILED");
+                self.msg(&format!(
+                    "    WARNING: exorcise_index() would remove reference to this segment; full \
+                     error:\n    {}",
+                    e
+                ));
+                result.tot_lose_doc_count += si.info.max_doc() - si.del_count();
+                result.num_bad_segments += 1;
+            } else {
+                self.msg("");
+            }
+            result.segment_infos.push(status);
+        }
+
+        if result.num_bad_segments == 0 && result.num_bad_commits == 0 {
+            result.clean = true;
+            self.msg("No problems were detected with this index.\n");
+        }
+        if result.num_bad_segments > 0 {
+            self.msg(&format!(
+                "WARNING: {} broken segments (containing {} documents) detected",
+                result.num_bad_segments, result.tot_lose_doc_count
+            ));
+        }
+        if result.num_bad_commits > 0 {
+            self.msg(&format!(
+                "WARNING: {} broken commits detected",
+                result.num_bad_commits
+            ));
+        }
+        Ok(result)
+    }
+
+    fn check_commits(&mut self) -> Result<Vec<CommitStatus>> {
+        let mut names = vec![];
+        for name in self.directory.list_all()? {
+            if name.starts_with(INDEX_FILE_SEGMENTS) && name != INDEX_FILE_OLD_SEGMENT_GEN {
+                names.push((generation_from_segments_file_name(&name)?, name));
+            }
+        }
+        names.sort();
+
+        let mut commits = Vec::with_capacity(names.len());
+        for (_, name) in names {
+            let mut status = CommitStatus::default();
+            status.segments_file_name = name.clone();
+            if let Err(e) = self.check_commit(&name, &mut status) {
+                status.error = error_of(e);
+            }
+            self.report(
+                &format!("commit {}.....", name),
+                &status.error,
+                format!(
+                    "[{} segments; {} files]",
+                    status.num_segments, status.files_checked
+                ),
+            );
+            commits.push(status);
+        }
+        Ok(commits)
+    }
+
+    // verifies the whole segments file, but only the footer of the other files
+    fn check_commit(&self, name: &str, status: &mut CommitStatus) -> Result<()> {
+        {
+            let input = self.directory.open_input(name, &IOContext::READ_ONCE)?;
+            checksum_entire_file(input.as_ref())?;
+        }
+        let infos: SegmentInfos<D, C> = SegmentInfos::read_commit(&self.directory, name)?;
+        status.num_segments = infos.len();
+
+        let mut files: Vec<String> = infos.files(false).into_iter().collect();
+        files.sort();
+        for file in &files {
+            let mut input = match self.directory.open_input(file, &IOContext::READ) {
+                Ok(input) => input,
+                Err(e) => bail!(CorruptIndex(format!(
+                    "file {} of the commit can't be opened: {:?}",
+                    file, e
+                ))),
+            };
+            retrieve_checksum(input.as_mut())?;
+            status.files_checked += 1;
+        }
+        Ok(())
+    }
+
+    fn check_segment(&mut self, si: &Arc<SegmentCommitInfo<D, C>>) -> SegmentInfoStatus {
+        let mut status = SegmentInfoStatus::new(si);
+
+        let mut files: Vec<String> = si.files().into_iter().collect();
+        files.sort();
+        status.num_files = files.len();
+        let size: i64 = files
+            .iter()
+            .filter_map(|f| self.directory.file_length(f).ok())
+            .sum();
+        status.size_mb = size as f64 / (1024.0 * 1024.0);
+
+        self.msg(&format!("    codec={}", status.codec));
+        self.msg(&format!("    compound={}", status.compound));
+        self.msg(&format!("    numFiles={}", status.num_files));
+        self.msg(&format!("    size (MB)={:.3}", status.size_mb));
+        if !si.info.diagnostics.is_empty() {
+            self.msg(&format!("    diagnostics = {:?}", si.info.diagnostics));
+        }
+        if status.has_deletions {
+            self.msg(&format!(
+                "    has deletions [delCount={}]",
+                status.del_count
+            ));
+        } else {
+            self.msg("    no deletions");
+        }
+
+        let checksum_status = self.check_checksums(&files);
+        let checksum_error = checksum_status.error.clone();
+        status.checksum_status = Some(checksum_status);
+        if let Some(e) = checksum_error {
+            status.error = Some(e);
+            return status;
+        }
+
+        self.msg("    test: open reader.........");
+        let reader = match SegmentReader::open(si, &IOContext::READ) {
+            Ok(reader) => reader,
+            Err(e) => {
+                status.error = Some(format!("failed to open segment reader: {:?}", e));
+                return status;
+            }
+        };
+        status.open_reader_passed = true;
+        self.msg("OK");
+
+        let live_docs_status = test_live_docs(&reader, si.del_count());
+        self.report_live_docs(&live_docs_status);
+        let field_info_status = test_field_infos(&reader);
+        self.report(
+            "field infos.........",
+            &field_info_status.error,
+            format!("[{} fields]", field_info_status.tot_fields),
+        );
+        let field_norm_status = test_field_norms(&reader);
+        self.report(
+            "field norms.........",
+            &field_norm_status.error,
+            format!("[{} fields]", field_norm_status.tot_fields),
+        );
+        let term_index_status = test_postings(&reader);
+        self.report(
+            "terms, freq, prox...",
+            &term_index_status.error,
+            format!(
+                "[{} terms; {} terms/docs pairs; {} tokens]",
+                term_index_status.term_count, term_index_status.tot_freq, term_index_status.tot_pos
+            ),
+        );
+        let stored_field_status = test_stored_fields(&reader);
+        self.report(
+            "stored fields.......",
+            &stored_field_status.error,
+            format!(
+                "[{} total field count; avg {:.1} fields per doc]",
+                stored_field_status.tot_fields,
+                per_doc(
+                    stored_field_status.tot_fields,
+                    stored_field_status.doc_count
+                )
+            ),
+        );
+        let term_vector_status = test_term_vectors(&reader);
+        self.report(
+            "term vectors........",
+            &term_vector_status.error,
+            format!(
+                "[{} total term vector count; avg {:.1} term/freq vector fields per doc]",
+                term_vector_status.tot_vector_fields,
+                per_doc(
+                    term_vector_status.tot_vector_fields,
+                    term_vector_status.doc_count
+                )
+            ),
+        );
+        let doc_values_status = test_doc_values(&reader);
+        self.report(
+            "docvalues...........",
+            &doc_values_status.error,
+            format!(
+                "[{} docvalues fields; {} BINARY; {} NUMERIC; {} SORTED; {} SORTED_NUMERIC; {} \
+                 SORTED_SET]",
+                doc_values_status.tot_binary_fields
+                    + doc_values_status.tot_numeric_fields
+                    + doc_values_status.tot_sorted_fields
+                    + doc_values_status.tot_sorted_numeric_fields
+                    + doc_values_status.tot_sorted_set_fields,
+                doc_values_status.tot_binary_fields,
+                doc_values_status.tot_numeric_fields,
+                doc_values_status.tot_sorted_fields,
+                doc_values_status.tot_sorted_numeric_fields,
+                doc_values_status.tot_sorted_set_fields
+            ),
+        );
+        let points_status = test_points(&reader);
+        self.report(
+            "points..............",
+            &points_status.error,
+            format!(
+                "[{} fields, {} points]",
+                points_status.tot_value_fields, points_status.tot_value_points
+            ),
+        );
+
+        status.error = live_docs_status
+            .error
+            .clone()
+            .or_else(|| field_info_status.error.clone())
+            .or_else(|| field_norm_status.error.clone())
+            .or_else(|| term_index_status.error.clone())
+            .or_else(|| stored_field_status.error.clone())
+            .or_else(|| term_vector_status.error.clone())
+            .or_else(|| doc_values_status.error.clone())
+            .or_else(|| points_status.error.clone());
+        status.live_docs_status = Some(live_docs_status);
+        status.field_info_status = Some(field_info_status);
+        status.field_norm_status = Some(field_norm_status);
+        status.term_index_status = Some(term_index_status);
+        status.stored_field_status = Some(stored_field_status);
+        status.term_vector_status = Some(term_vector_status);
+        status.doc_values_status = Some(doc_values_status);
+        status.points_status = Some(points_status);
+        status
+    }
+
+    fn check_checksums(&mut self, files: &[String]) -> ChecksumStatus {
+        let mut status = ChecksumStatus::default();
+        for name in files {
+            let res = self
+                .directory
+                .open_input(name, &IOContext::READ_ONCE)
+                .and_then(|input| checksum_entire_file(input.as_ref()));
+            if let Err(e) = res {
+                status.error = Some(format!("checksum failed for file {}: {:?}", name, e));
+                break;
+            }
+            status.files_checked += 1;
+        }
+        self.report(
+            "check integrity.....",
+            &status.error,
+            format!("[{} files]", status.files_checked),
+        );
+        status
+    }
+
+    fn report_live_docs(&mut self, status: &LiveDocStatus) {
+        self.report(
+            "check live docs.....",
+            &status.error,
+            format!("[{} deleted docs]", status.num_deleted),
+        );
+    }
+
+    fn report(&mut self, test: &str, error: &Option<String>, summary: String) {
+        match error {
+            Some(e) => self.msg(&format!("    test: {}ERROR [{}]", test, e)),
+            None => self.msg(&format!("    test: {}OK {}", test, summary)),
+        }
+    }
+
+    /// Writes a new segments file, removing reference to the segments that
+    /// failed the check. The documents of these segments are lost.
+    pub fn exorcise_index(&mut self, result: &Status) -> Result<()> {
+        if result.partial {
+            bail!(IllegalArgument(
+                "can only exorcise an index that was fully checked (this status checked a subset \
+                 of segments)"
+                    .into()
+            ));
+        }
+        let segments_file_name = match result.segments_file_name {
+            Some(ref name) => name,
+            None => bail!(IllegalState("the index has no segments file".into())),
+        };
+        let broken: HashSet<&str> = result
+            .segment_infos
+            .iter()
+            .filter(|s| s.error.is_some())
+            .map(|s| s.name.as_str())
+            .collect();
+
+        let mut infos: SegmentInfos<D, C> =
+            SegmentInfos::read_commit(&self.directory, segments_file_name)?;
+        infos
+            .segments
+            .retain(|s| !broken.contains(s.info.name.as_str()));
+        infos.changed();
+        infos.prepare_commit(self.directory.as_ref())?;
+        let new_segments_file_name = infos.finish_commit(self.directory.as_ref())?;
+        self.msg(&format!(
+            "Wrote new segments file \"{}\"",
+            new_segments_file_name
+        ));
+        Ok(())
+    }
+}
+
+fn per_doc(total: i64, doc_count: i32) -> f64 {
+    if doc_count > 0 {
+        total as f64 / doc_count as f64
+    } else {
+        0.0
+    }
+}
+
+fn error_of(e: Error) -> Option<String> {
+    Some(format!("{:?}", e))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "<unknown panic>".into()
+    }
+}
+
+/// Checks the live docs match the deletion count of the segment.
+pub fn test_live_docs<R: LeafReader>(reader: &R, del_count: i32) -> LiveDocStatus {
+    let mut status = LiveDocStatus::default();
+    let max_doc = reader.max_doc();
+    let live_docs = reader.live_docs();
+    let res = (|| -> Result<()> {
+        if live_docs.len() != max_doc as usize {
+            bail!(CorruptIndex(format!(
+                "live docs length {} doesn't match max_doc {}",
+                live_docs.len(),
+                max_doc
+            )));
+        }
+        for doc in 0..max_doc as usize {
+            if !live_docs.get(doc)? {
+                status.num_deleted += 1;
+            }
+        }
+        if status.num_deleted != del_count as usize {
+            bail!(CorruptIndex(format!(
+                "live docs count mismatch: info={}, vs bits={}",
+                del_count, status.num_deleted
+            )));
+        }
+        if reader.num_docs() != max_doc - del_count {
+            bail!(CorruptIndex(format!(
+                "num_docs {} doesn't match max_doc {} - del_count {}",
+                reader.num_docs(),
+                max_doc,
+                del_count
+            )));
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        status.error = error_of(e);
+    }
+    status
+}
+
+/// Checks the field infos.
+pub fn test_field_infos<R: LeafReader>(reader: &R) -> FieldInfoStatus {
+    let mut status = FieldInfoStatus::default();
+    for fi in reader.field_infos().by_number.values() {
+        if let Err(e) = fi.check_consistency() {
+            status.error = error_of(e);
+            break;
+        }
+        status.tot_fields += 1;
+    }
+    status
+}
+
+/// Checks every norm of every field with norms.
+pub fn test_field_norms<R: LeafReader>(reader: &R) -> FieldNormStatus {
+    let mut status = FieldNormStatus::default();
+    let res = (|| -> Result<()> {
+        for fi in reader.field_infos().by_number.values() {
+            match reader.norm_values(&fi.name)? {
+                Some(norms) => {
+                    if !fi.has_norms() {
+                        bail!(CorruptIndex(format!(
+                            "field {} does not have norms but has a norms reader",
+                            fi.name
+                        )));
+                    }
+                    for doc in 0..reader.max_doc() {
+                        norms.get(doc)?;
+                    }
+                    status.tot_fields += 1;
+                }
+                None if fi.has_norms() => {
+                    bail!(CorruptIndex(format!(
+                        "field {} should have norms but has none",
+                        fi.name
+                    )));
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        status.error = error_of(e);
+    }
+    status
+}
+
+/// Walks all the postings of every field and cross-checks the term and
+/// field statistics.
+pub fn test_postings<R: LeafReader>(reader: &R) -> TermIndexStatus {
+    let mut status = TermIndexStatus::default();
+    let res = (|| -> Result<()> {
+        let fields = reader.fields()?;
+        let max_doc = reader.max_doc();
+        let live_docs = reader.live_docs();
+        for field in fields.fields() {
+            let fi = match reader.field_info(&field) {
+                Some(fi) => fi,
+                None => bail!(CorruptIndex(format!(
+                    "fields has field {} but it's not in the field infos",
+                    field
+                ))),
+            };
+            if fi.index_options == IndexOptions::Null {
+                bail!(CorruptIndex(format!(
+                    "field {} has postings but isn't indexed",
+                    field
+                )));
+            }
+            let terms = match fields.terms(&field)? {
+                Some(terms) => terms,
+                None => continue,
+            };
+            let has_freqs = terms.has_freqs()?;
+            let has_positions = terms.has_positions()?;
+            let has_offsets = terms.has_offsets()?;
+            let flags = if has_offsets {
+                PostingIteratorFlags::OFFSETS
+            } else if has_positions {
+                PostingIteratorFlags::POSITIONS
+            } else if has_freqs {
+                PostingIteratorFlags::FREQS
+            } else {
+                PostingIteratorFlags::NONE
+            };
+
+            let mut docs_with_field = FixedBitSet::new(max_doc as usize);
+            let mut term_count = 0i64;
+            let mut sum_doc_freq = 0i64;
+            let mut sum_total_term_freq = 0i64;
+            let mut last_term: Option<Vec<u8>> = None;
+            let mut terms_iter = terms.iterator()?;
+            while let Some(term) = terms_iter.next()? {
+                if let Some(ref last) = last_term {
+                    if last >= &term {
+                        bail!(CorruptIndex(format!(
+                            "field {}: terms out of order: {:?} >= {:?}",
+                            field, last, term
+                        )));
+                    }
+                }
+                let doc_freq = terms_iter.doc_freq()?;
+                if doc_freq <= 0 {
+                    bail!(CorruptIndex(format!(
+                        "field {}: doc_freq {} is out of bounds",
+                        field, doc_freq
+                    )));
+                }
+                let total_term_freq = terms_iter.total_term_freq()?;
+
+                let mut postings = terms_iter.postings_with_flags(flags)?;
+                let mut last_doc = -1;
+                let mut doc_count = 0;
+                let mut live_doc_count = 0;
+                let mut freq_sum = 0i64;
+                loop {
+                    let doc = postings.next()?;
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    if doc <= last_doc || doc >= max_doc {
+                        bail!(CorruptIndex(format!(
+                            "field {}: doc {} is out of order or out of bounds (last doc {}, \
+                             max_doc {})",
+                            field, doc, last_doc, max_doc
+                        )));
+                    }
+                    last_doc = doc;
+                    doc_count += 1;
+                    docs_with_field.set(doc as usize);
+                    if live_docs.get(doc as usize)? {
+                        live_doc_count += 1;
+                    }
+                    if has_freqs {
+                        let freq = postings.freq()?;
+                        if freq <= 0 {
+                            bail!(CorruptIndex(format!(
+                                "field {}: doc {} has freq {}",
+                                field, doc, freq
+                            )));
+                        }
+                        freq_sum += i64::from(freq);
+                        if has_positions {
+                            check_positions(&field, doc, freq, has_offsets, &mut postings)?;
+                            status.tot_pos += i64::from(freq);
+                        }
+                    }
+                }
+                if doc_count != doc_freq {
+                    bail!(CorruptIndex(format!(
+                        "field {}: term doc_freq {} != number of docs {}",
+                        field, doc_freq, doc_count
+                    )));
+                }
+                if has_freqs && total_term_freq != -1 && total_term_freq != freq_sum {
+                    bail!(CorruptIndex(format!(
+                        "field {}: term total_term_freq {} != sum of freqs {}",
+                        field, total_term_freq, freq_sum
+                    )));
+                }
+                if live_doc_count == 0 {
+                    status.del_term_count += 1;
+                }
+                term_count += 1;
+                sum_doc_freq += i64::from(doc_freq);
+                sum_total_term_freq += freq_sum;
+                last_term = Some(term);
+            }
+
+            check_field_stat(&field, "size", terms.size()?, term_count)?;
+            check_field_stat(&field, "sum_doc_freq", terms.sum_doc_freq()?, sum_doc_freq)?;
+            check_field_stat(
+                &field,
+                "doc_count",
+                i64::from(terms.doc_count()?),
+                docs_with_field.cardinality() as i64,
+            )?;
+            if has_freqs {
+                check_field_stat(
+                    &field,
+                    "sum_total_term_freq",
+                    terms.sum_total_term_freq()?,
+                    sum_total_term_freq,
+                )?;
+            }
+            status.term_count += term_count;
+            status.tot_freq += sum_doc_freq;
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        status.error = error_of(e);
+    }
+    status
+}
+
+fn check_positions<P: PostingIterator>(
+    field: &str,
+    doc: DocId,
+    freq: i32,
+    has_offsets: bool,
+    postings: &mut P,
+) -> Result<()> {
+    let mut last_pos = -1;
+    let mut last_offset = 0;
+    for _ in 0..freq {
+        let pos = postings.next_position()?;
+        if pos < 0 || pos < last_pos {
+            bail!(CorruptIndex(format!(
+                "field {}: doc {}: position {} is out of bounds or out of order (last {})",
+                field, doc, pos, last_pos
+            )));
+        }
+        last_pos = pos;
+        if has_offsets {
+            let start = postings.start_offset()?;
+            let end = postings.end_offset()?;
+            if start < last_offset || end < start {
+                bail!(CorruptIndex(format!(
+                    "field {}: doc {}: pos {}: offsets {}-{} are out of order",
+                    field, doc, pos, start, end
+                )));
+            }
+            last_offset = start;
+        }
+    }
+    Ok(())
+}
+
+fn check_field_stat(field: &str, stat: &str, stored: i64, computed: i64) -> Result<()> {
+    // -1 means the codec doesn't store the statistic
+    if stored != -1 && stored != computed {
+        bail!(CorruptIndex(format!(
+            "field {}: {}={} != recomputed {}",
+            field, stat, stored, computed
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct FieldCountingVisitor {
+    count: i64,
+}
+
+impl StoredFieldVisitor for FieldCountingVisitor {
+    fn add_binary_field(&mut self, _field_info: &FieldInfo, _value: Vec<u8>) -> Result<()> {
+        self.count += 1;
+        Ok(())
+    }
+
+    fn add_string_field(&mut self, _field_info: &FieldInfo, value: Vec<u8>) -> Result<()> {
+        String::from_utf8(value)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn add_int_field(&mut self, _field_info: &FieldInfo, _value: i32) -> Result<()> {
+        self.count += 1;
+        Ok(())
+    }
+
+    fn add_long_field(&mut self, _field_info: &FieldInfo, _value: i64) -> Result<()> {
+        self.count += 1;
+        Ok(())
+    }
+
+    fn add_float_field(&mut self, _field_info: &FieldInfo, _value: f32) -> Result<()> {
+        self.count += 1;
+        Ok(())
+    }
+
+    fn add_double_field(&mut self, _field_info: &FieldInfo, _value: f64) -> Result<()> {
+        self.count += 1;
+        Ok(())
+    }
+
+    fn needs_field(&self, _field_info: &FieldInfo) -> VisitStatus {
+        VisitStatus::Yes
+    }
+}
+
+/// Loads the stored fields of every document.
+pub fn test_stored_fields<R: LeafReader>(reader: &R) -> StoredFieldStatus {
+    let mut status = StoredFieldStatus::default();
+    let live_docs = reader.live_docs();
+    let res = (|| -> Result<()> {
+        for doc in 0..reader.max_doc() {
+            // deleted documents are loaded too, as they are still merged
+            let mut visitor = FieldCountingVisitor::default();
+            reader.document(doc, &mut visitor)?;
+            if live_docs.get(doc as usize)? {
+                status.doc_count += 1;
+                status.tot_fields += visitor.count;
+            }
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        status.error = error_of(e);
+    }
+    status
+}
+
+/// Loads and walks the term vectors of every document.
+pub fn test_term_vectors<R: LeafReader>(reader: &R) -> TermVectorStatus {
+    let mut status = TermVectorStatus::default();
+    let live_docs = reader.live_docs();
+    let res = (|| -> Result<()> {
+        for doc in 0..reader.max_doc() {
+            let vectors = match reader.term_vector(doc)? {
+                Some(vectors) => vectors,
+                None => continue,
+            };
+            let live = live_docs.get(doc as usize)?;
+            if live {
+                status.doc_count += 1;
+            }
+            for field in vectors.fields() {
+                match reader.field_info(&field) {
+                    Some(fi) if fi.has_store_term_vector => {}
+                    _ => bail!(CorruptIndex(format!(
+                        "doc {}: field {} has term vectors but doesn't store them",
+                        doc, field
+                    ))),
+                }
+                if live {
+                    status.tot_vector_fields += 1;
+                }
+                let terms = match vectors.terms(&field)? {
+                    Some(terms) => terms,
+                    None => continue,
+                };
+                let mut terms_iter = terms.iterator()?;
+                let mut last_term: Option<Vec<u8>> = None;
+                while let Some(term) = terms_iter.next()? {
+                    if let Some(ref last) = last_term {
+                        if last >= &term {
+                            bail!(CorruptIndex(format!(
+                                "doc {}: field {}: vector terms out of order",
+                                doc, field
+                            )));
+                        }
+                    }
+                    let freq = terms_iter.total_term_freq()?;
+                    if freq <= 0 {
+                        bail!(CorruptIndex(format!(
+                            "doc {}: field {}: vector term has freq {}",
+                            doc, field, freq
+                        )));
+                    }
+                    last_term = Some(term);
+                }
+            }
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        status.error = error_of(e);
+    }
+    status
+}
+
+/// Reads the doc values of every document for every doc values field.
+pub fn test_doc_values<R: LeafReader>(reader: &R) -> DocValuesStatus {
+    let mut status = DocValuesStatus::default();
+    let res = (|| -> Result<()> {
+        for fi in reader.field_infos().by_number.values() {
+            match fi.doc_values_type {
+                DocValuesType::Null => {}
+                DocValuesType::Numeric => {
+                    let dv = reader.get_numeric_doc_values(&fi.name)?;
+                    for doc in 0..reader.max_doc() {
+                        dv.get(doc)?;
+                    }
+                    status.tot_numeric_fields += 1;
+                }
+                DocValuesType::Binary => {
+                    let mut dv = reader.get_binary_doc_values(&fi.name)?;
+                    for doc in 0..reader.max_doc() {
+                        dv.get(doc)?;
+                    }
+                    status.tot_binary_fields += 1;
+                }
+                DocValuesType::Sorted => {
+                    let mut dv = reader.get_sorted_doc_values(&fi.name)?;
+                    let value_count = dv.value_count() as i32;
+                    for doc in 0..reader.max_doc() {
+                        let ord = dv.get_ord(doc)?;
+                        if ord < -1 || ord >= value_count {
+                            bail!(CorruptIndex(format!(
+                                "field {}: doc {}: ord {} is out of bounds",
+                                fi.name, doc, ord
+                            )));
+                        }
+                    }
+                    let mut last_value: Option<Vec<u8>> = None;
+                    for ord in 0..value_count {
+                        let value = dv.lookup_ord(ord)?;
+                        check_sorted_value(&fi.name, &last_value, &value)?;
+                        last_value = Some(value);
+                    }
+                    status.tot_sorted_fields += 1;
+                }
+                DocValuesType::SortedNumeric => {
+                    let mut dv = reader.get_sorted_numeric_doc_values(&fi.name)?;
+                    for doc in 0..reader.max_doc() {
+                        dv.set_document(doc)?;
+                        let mut last_value = i64::min_value();
+                        for i in 0..dv.count() {
+                            let value = dv.value_at(i)?;
+                            if value < last_value {
+                                bail!(CorruptIndex(format!(
+                                    "field {}: doc {}: values out of order",
+                                    fi.name, doc
+                                )));
+                            }
+                            last_value = value;
+                        }
+                    }
+                    status.tot_sorted_numeric_fields += 1;
+                }
+                DocValuesType::SortedSet => {
+                    let mut dv = reader.get_sorted_set_doc_values(&fi.name)?;
+                    let value_count = dv.get_value_count() as i64;
+                    for doc in 0..reader.max_doc() {
+                        dv.set_document(doc)?;
+                        let mut last_ord = -1;
+                        loop {
+                            let ord = dv.next_ord()?;
+                            if ord == NO_MORE_ORDS {
+                                break;
+                            }
+                            if ord <= last_ord || ord >= value_count {
+                                bail!(CorruptIndex(format!(
+                                    "field {}: doc {}: ord {} is out of order or out of bounds",
+                                    fi.name, doc, ord
+                                )));
+                            }
+                            last_ord = ord;
+                        }
+                    }
+                    let mut last_value: Option<Vec<u8>> = None;
+                    for ord in 0..value_count {
+                        let value = dv.lookup_ord(ord)?;
+                        check_sorted_value(&fi.name, &last_value, &value)?;
+                        last_value = Some(value);
+                    }
+                    status.tot_sorted_set_fields += 1;
+                }
+            }
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        status.error = error_of(e);
+    }
+    status
+}
+
+fn check_sorted_value(field: &str, last: &Option<Vec<u8>>, value: &[u8]) -> Result<()> {
+    if let Some(ref last) = *last {
+        if last.as_slice() >= value {
+            bail!(CorruptIndex(format!(
+                "field {}: doc values dictionary out of order",
+                field
+            )));
+        }
+    }
+    Ok(())
+}
+
+struct CheckPointsVisitor {
+    field: String,
+    num_dims: usize,
+    bytes_per_dim: usize,
+    min_packed_value: Vec<u8>,
+    max_packed_value: Vec<u8>,
+    max_doc: DocId,
+    point_count: i64,
+    docs_seen: FixedBitSet,
+}
+
+impl IntersectVisitor for CheckPointsVisitor {
+    fn visit(&mut self, _doc_id: DocId) -> Result<()> {
+        bail!(IllegalState(
+            "visit(doc) should not be called as every cell crosses the query".into()
+        ))
+    }
+
+    fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
+        if doc_id < 0 || doc_id >= self.max_doc {
+            bail!(CorruptIndex(format!(
+                "field {}: point doc {} is out of bounds",
+                self.field, doc_id
+            )));
+        }
+        if packed_value.len() != self.num_dims * self.bytes_per_dim {
+            bail!(CorruptIndex(format!(
+                "field {}: packed value length {} doesn't match {} dims * {} bytes",
+                self.field,
+                packed_value.len(),
+                self.num_dims,
+                self.bytes_per_dim
+            )));
+        }
+        for dim in 0..self.num_dims {
+            let range = dim * self.bytes_per_dim..(dim + 1) * self.bytes_per_dim;
+            let value = &packed_value[range.clone()];
+            if value < &self.min_packed_value[range.clone()]
+                || value > &self.max_packed_value[range]
+            {
+                bail!(CorruptIndex(format!(
+                    "field {}: doc {}: dim {} value is outside of the global min/max",
+                    self.field, doc_id, dim
+                )));
+            }
+        }
+        self.point_count += 1;
+        self.docs_seen.set(doc_id as usize);
+        Ok(())
+    }
+
+    fn compare(&self, _min_packed_value: &[u8], _max_packed_value: &[u8]) -> Relation {
+        Relation::CellCrossesQuery
+    }
+}
+
+/// Visits every point of every points field and cross-checks the point and
+/// document counts.
+pub fn test_points<R: LeafReader>(reader: &R) -> PointsStatus {
+    let mut status = PointsStatus::default();
+    let res = (|| -> Result<()> {
+        let points = match reader.point_values() {
+            Some(points) => points,
+            None => return Ok(()),
+        };
+        for fi in reader.field_infos().by_number.values() {
+            if fi.point_dimension_count == 0 {
+                continue;
+            }
+            let mut visitor = CheckPointsVisitor {
+                field: fi.name.clone(),
+                num_dims: points.num_dimensions(&fi.name)?,
+                bytes_per_dim: points.bytes_per_dimension(&fi.name)?,
+                min_packed_value: points.min_packed_value(&fi.name)?,
+                max_packed_value: points.max_packed_value(&fi.name)?,
+                max_doc: reader.max_doc(),
+                point_count: 0,
+                docs_seen: FixedBitSet::new(reader.max_doc() as usize),
+            };
+            points.intersect(&fi.name, &mut visitor)?;
+
+            let size = points.size(&fi.name)?;
+            if visitor.point_count != size {
+                bail!(CorruptIndex(format!(
+                    "field {}: point count {} != visited points {}",
+                    fi.name, size, visitor.point_count
+                )));
+            }
+            let doc_count = points.doc_count(&fi.name)?;
+            if visitor.docs_seen.cardinality() != doc_count as usize {
+                bail!(CorruptIndex(format!(
+                    "field {}: point doc_count {} != visited docs {}",
+                    fi.name,
+                    doc_count,
+                    visitor.docs_seen.cardinality()
+                )));
+            }
+            status.tot_value_fields += 1;
+            status.tot_value_points += visitor.point_count;
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        status.error = error_of(e);
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::{footer_length, write_footer, CodecEnum};
+    use core::doc::{Field, FieldType, Fieldable, NumericDocValuesField};
+    use core::index::writer::{IndexWriter, IndexWriterConfig, KeepLastNCommitsDeletionPolicy};
+    use core::store::directory::RAMDirectory;
+    use core::store::io::DataOutput;
+    use core::util::{VariantValue, ID_LENGTH};
+
+    fn new_doc(id: &str) -> Vec<Box<dyn Fieldable>> {
+        let mut field_type = FieldType::default();
+        field_type.stored = true;
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        vec![
+            Box::new(Field::new(
+                "id".into(),
+                field_type,
+                Some(VariantValue::VString(id.into())),
+                None,
+            )),
+            Box::new(NumericDocValuesField::new("version", 1)),
+        ]
+    }
+
+    fn add_segment(directory: &Arc<RAMDirectory>, ids: &[&str]) {
+        let mut config = IndexWriterConfig::default();
+        config.use_compound_file = false;
+        let writer = IndexWriter::new(Arc::clone(directory), Arc::new(config)).unwrap();
+        for id in ids {
+            writer.add_document(new_doc(id)).unwrap();
+        }
+        writer.commit().unwrap();
+        writer.close().unwrap();
+    }
+
+    fn corrupt_file(directory: &RAMDirectory, name: &str) {
+        let mut input = directory.open_input(name, &IOContext::READ).unwrap();
+        let len = input.len() as usize;
+        let mut bytes = vec![0u8; len];
+        input.read_bytes(&mut bytes, 0, len).unwrap();
+        bytes[len / 2] ^= 0xff;
+        let mut output = directory.create_output(name, &IOContext::Default).unwrap();
+        output.write_all(&bytes).unwrap();
+    }
+
+    #[test]
+    fn test_check_index() {
+        let directory = Arc::new(RAMDirectory::new());
+        add_segment(&directory, &["1", "2", "3"]);
+        add_segment(&directory, &["4", "5"]);
+
+        let mut checker: CheckIndex<RAMDirectory, CodecEnum> =
+            CheckIndex::new(Arc::clone(&directory)).unwrap();
+        let status = checker.check_index(&[]).unwrap();
+        assert!(status.clean);
+        assert_eq!(status.num_segments, 2);
+        let segment = &status.segment_infos[0];
+        assert!(segment.open_reader_passed);
+        assert_eq!(segment.stored_field_status.as_ref().unwrap().doc_count, 3);
+        assert_eq!(segment.term_index_status.as_ref().unwrap().term_count, 3);
+        assert_eq!(
+            segment
+                .doc_values_status
+                .as_ref()
+                .unwrap()
+                .tot_numeric_fields,
+            1
+        );
+
+        // the write lock is held while checking
+        assert!(directory.obtain_lock(WRITE_LOCK_NAME).is_err());
+
+        let broken = status.segment_infos[1].name.clone();
+        let stored_fields = format!("{}.fdt", broken);
+        corrupt_file(&directory, &stored_fields);
+        let status = checker.check_index(&[]).unwrap();
+        assert!(!status.clean);
+        assert_eq!(status.num_bad_segments, 1);
+        assert_eq!(status.tot_lose_doc_count, 2);
+        assert!(status.segment_infos[1].error.is_some());
+
+        // partial checks can't be exorcised
+        let partial = checker.check_index(&[broken]).unwrap();
+        assert!(partial.partial);
+        assert!(checker.exorcise_index(&partial).is_err());
+
+        checker.exorcise_index(&status).unwrap();
+        let status = checker.check_index(&[]).unwrap();
+        assert!(status.clean);
+        assert_eq!(status.num_segments, 1);
+        assert_eq!(status.segment_infos[0].max_doc, 3);
+    }
+
+    // overwrites everything between the index header and the footer of the
+    // file, with a valid checksum
+    fn corrupt_body(directory: &RAMDirectory, name: &str) {
+        let header_len = {
+            let mut input = directory.open_input(name, &IOContext::READ).unwrap();
+            input.read_int().unwrap();
+            input.read_string().unwrap();
+            input.read_int().unwrap();
+            let mut id = [0u8; ID_LENGTH];
+            input.read_bytes(&mut id, 0, ID_LENGTH).unwrap();
+            let suffix_len = input.read_byte().unwrap() as usize;
+            input.file_pointer() as usize + suffix_len
+        };
+        let mut input = directory.open_input(name, &IOContext::READ).unwrap();
+        let len = input.len() as usize;
+        let mut bytes = vec![0u8; len];
+        input.read_bytes(&mut bytes, 0, len).unwrap();
+        let body_end = len - footer_length();
+        assert!(header_len < body_end);
+        for b in &mut bytes[header_len..body_end] {
+            *b = 0xff;
+        }
+        let mut output = directory.create_output(name, &IOContext::Default).unwrap();
+        output.write_bytes(&bytes, 0, body_end).unwrap();
+        write_footer(&mut output).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_postings_with_valid_checksum() {
+        let directory = Arc::new(RAMDirectory::new());
+        let mut config = IndexWriterConfig::default();
+        config.use_compound_file = false;
+        let writer = IndexWriter::new(Arc::clone(&directory), Arc::new(config)).unwrap();
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        // a term shared by the docs, whose postings are not inlined in the
+        // terms dictionary
+        for _ in 0..10 {
+            let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(Field::new(
+                "group".into(),
+                field_type.clone(),
+                Some(VariantValue::VString("all".into())),
+                None,
+            ))];
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let postings: Vec<String> = directory
+            .list_all()
+            .unwrap()
+            .into_iter()
+            .filter(|name| name.ends_with(".doc"))
+            .collect();
+        assert_eq!(postings.len(), 1);
+        corrupt_body(&directory, &postings[0]);
+
+        let mut checker: CheckIndex<RAMDirectory, CodecEnum> =
+            CheckIndex::new(Arc::clone(&directory)).unwrap();
+        let status = checker.check_index(&[]).unwrap();
+        assert!(!status.clean);
+        assert_eq!(status.num_bad_segments, 1);
+        assert_eq!(status.tot_lose_doc_count, 10);
+        let segment = &status.segment_infos[0];
+        assert!(segment.checksum_status.as_ref().unwrap().error.is_none());
+        assert!(segment.error.is_some());
+        assert_eq!(status.num_bad_commits, 0);
+    }
+
+    #[test]
+    fn test_check_all_commits() {
+        let directory = Arc::new(RAMDirectory::new());
+        let mut config = IndexWriterConfig::default();
+        config.use_compound_file = false;
+        config.set_index_deletion_policy(Arc::new(KeepLastNCommitsDeletionPolicy::new(3).unwrap()));
+        let writer = IndexWriter::new(Arc::clone(&directory), Arc::new(config)).unwrap();
+        writer.add_document(new_doc("1")).unwrap();
+        writer.add_document(new_doc("2")).unwrap();
+        writer.commit().unwrap();
+        writer.add_document(new_doc("3")).unwrap();
+        writer.commit().unwrap();
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let mut checker: CheckIndex<RAMDirectory, CodecEnum> =
+            CheckIndex::new(Arc::clone(&directory)).unwrap();
+        let status = checker.check_index(&[]).unwrap();
+        assert!(status.clean);
+        assert_eq!(status.commits.len(), 3);
+        let num_segments: Vec<usize> = status.commits.iter().map(|c| c.num_segments).collect();
+        assert_eq!(num_segments, vec![1, 2, 1]);
+        assert_eq!(
+            status.commits[2].segments_file_name,
+            status.segments_file_name.clone().unwrap()
+        );
+
+        // break the segment of the first commit, merged away in the latest one
+        let first: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_commit(&directory, &status.commits[0].segments_file_name).unwrap();
+        let stored_fields = format!("{}.fdt", first.segments[0].info.name);
+        directory.delete_file(&stored_fields).unwrap();
+        let status = checker.check_index(&[]).unwrap();
+        assert!(!status.clean);
+        assert_eq!(status.num_bad_segments, 0);
+        assert_eq!(status.num_bad_commits, 2);
+        assert!(status.commits[0].error.is_some());
+        assert!(status.commits[1].error.is_some());
+        assert!(status.commits[2].error.is_none());
+
+        // a corrupt segments file
+        corrupt_file(&directory, &status.commits[0].segments_file_name);
+        let status = checker.check_index(&[]).unwrap();
+        assert_eq!(status.num_bad_commits, 2);
+        assert_eq!(status.commits[0].num_segments, 0);
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("corrupt block")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "corrupt block");
+        let payload = panic::catch_unwind(|| panic!("doc {} out of bounds", 7)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "doc 7 out of bounds");
+        let payload = panic::catch_unwind(|| panic::resume_unwind(Box::new(7))).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "<unknown panic>");
+    }
+}
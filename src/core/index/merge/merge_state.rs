@@ -23,8 +23,8 @@ use core::codec::segment_infos::SegmentInfo;
 use core::codec::stored_fields::StoredFieldsReader;
 use core::codec::term_vectors::TermVectorsReader;
 use core::codec::{
-    Codec, CodecFieldsProducer, CodecNormsProducer, CodecPointsReader, CodecStoredFieldsReader,
-    CodecTVFields, CodecTVReader,
+    Codec, CodecFieldsProducer, CodecKnnVectorsReader, CodecNormsProducer, CodecPointsReader,
+    CodecStoredFieldsReader, CodecTVFields, CodecTVReader,
 };
 use core::codec::{Fields, SeekStatus, TermIterator, Terms};
 use core::codec::{MultiSorter, PackedLongDocMap, Sorter, SorterDocMap};
@@ -79,6 +79,9 @@ pub struct MergeState<D: Directory + 'static, C: Codec> {
     pub live_docs: Vec<BitsRef>,
     pub fields_producers: Vec<MergeFieldsProducer<CodecFieldsProducer<C>>>,
     pub points_readers: Vec<Option<MergePointValuesEnum<Arc<CodecPointsReader<C>>>>>,
+    /// Knn vectors of the unsorted segments, use `leaf_doc_maps` to map their docs
+    /// to the sorted ones.
+    pub knn_vectors_readers: Vec<Option<Arc<CodecKnnVectorsReader<C>>>>,
     pub max_docs: Vec<i32>,
     /// Indicates if the index needs to be sorted
     pub needs_index_sort: bool,
@@ -90,6 +93,7 @@ impl<D: Directory + 'static, C: Codec> MergeState<D, C> {
         segment_info: &SegmentInfo<D, C>,
     ) -> Result<Self> {
         let num_readers = seg_readers.len();
+        let knn_vectors_readers = seg_readers.iter().map(|r| r.knn_vectors()).collect();
 
         let mut leaf_doc_maps = Vec::with_capacity(num_readers);
         for _ in 0..seg_readers.len() {
@@ -154,6 +158,7 @@ impl<D: Directory + 'static, C: Codec> MergeState<D, C> {
             live_docs,
             fields_producers,
             points_readers,
+            knn_vectors_readers,
             max_docs,
             needs_index_sort,
         })
@@ -184,12 +189,13 @@ impl<D: Directory + 'static, C: Codec> MergeState<D, C> {
         for leaf in seg_readers {
             let leaf_wrapper = if leaf.index_sort().is_some() {
                 if leaf.index_sort() != segment_info.index_sort() {
-                    error!(
-                        "leaf: {:?}, seg: {:?}",
-                        leaf.index_sort(),
-                        segment_info.index_sort()
-                    );
-                    bail!(IllegalArgument("index sort mismatch".into()))
+                    bail!(IllegalArgument(format!(
+                        "index sort mismatch: segment {} is sorted by {:?} but the merged segment \
+                         must be sorted by {:?}",
+                        leaf.si.info.name,
+                        leaf.index_sort().unwrap(),
+                        segment_info.index_sort().unwrap()
+                    )))
                 } else {
                     ReaderWrapperEnum::Segment(leaf)
                 }
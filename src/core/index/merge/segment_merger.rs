@@ -13,6 +13,7 @@
 
 use core::codec::doc_values::{DocValuesConsumer, DocValuesFormat};
 use core::codec::field_infos::{FieldInfosBuilder, FieldInfosFormat, FieldNumbersRef};
+use core::codec::knn_vectors::KnnVectorsFormat;
 use core::codec::norms::{NormsConsumer, NormsFormat};
 use core::codec::points::{PointsFormat, PointsWriter};
 use core::codec::postings::{FieldsConsumer, PostingsFormat};
@@ -121,6 +122,16 @@ where
             self.merge_points(&segment_write_state)?;
         }
 
+        if self
+            .merge_state
+            .merge_field_infos
+            .as_ref()
+            .unwrap()
+            .has_knn_vectors
+        {
+            self.merge_knn_vectors(&segment_write_state)?;
+        }
+
         if self
             .merge_state
             .merge_field_infos
@@ -175,6 +186,17 @@ where
         writer.merge(&self.merge_state)
     }
 
+    fn merge_knn_vectors(
+        &mut self,
+        segment_write_state: &SegmentWriteState<D, DW, C>,
+    ) -> Result<()> {
+        let mut writer = self
+            .codec
+            .knn_vectors_format()
+            .fields_writer(segment_write_state)?;
+        writer.merge(&self.merge_state)
+    }
+
     fn merge_norms(&mut self, segment_write_state: &SegmentWriteState<D, DW, C>) -> Result<()> {
         let mut consumer = self
             .codec
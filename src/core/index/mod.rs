@@ -11,8 +11,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod check_index;
 pub mod merge;
 pub mod reader;
+pub mod replication;
 pub mod writer;
 
 error_chain! {
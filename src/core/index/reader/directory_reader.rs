@@ -86,6 +86,12 @@ where
     pub fn open(directory: Arc<D>) -> Result<Self> {
         let segment_file_name = get_segment_file_name(directory.as_ref())?;
         let segment_infos = SegmentInfos::read_commit(&directory, &segment_file_name)?;
+        Self::open_with_infos(directory, segment_infos)
+    }
+
+    /// Opens a reader on `segment_infos`, which need not be committed, eg the
+    /// infos of a near real-time reader copied to a replica with its files.
+    pub fn open_with_infos(directory: Arc<D>, segment_infos: SegmentInfos<D, C>) -> Result<Self> {
         let mut readers = Vec::with_capacity(segment_infos.segments.len());
         for seg_info in &segment_infos.segments {
             let s = SegmentReader::open(seg_info, &IOContext::READ)?;
@@ -267,6 +273,19 @@ where
         self.segment_infos.version
     }
 
+    pub fn segment_infos(&self) -> &SegmentInfos<D, C> {
+        &self.segment_infos
+    }
+
+    /// Returns the commit user data of the `SegmentInfos` this reader was opened on.
+    pub fn user_data(&self) -> &HashMap<String, String> {
+        &self.segment_infos.user_data
+    }
+
+    pub fn segment_readers(&self) -> &[Arc<SegmentReader<D, C>>] {
+        &self.readers
+    }
+
     pub fn open_if_changed(&self, commit: Option<&CommitPoint>) -> Result<Option<Self>> {
         // If we were obtained by writer.getReader(), re-ask the
         // writer to get a new reader.
@@ -452,3 +471,59 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType, Fieldable, IndexOptions};
+    use core::index::merge::{SerialMergeScheduler, TieredMergePolicy};
+    use core::index::writer::IndexWriterConfig;
+    use core::store::directory::RAMDirectory;
+    use core::util::VariantValue;
+
+    type Reader =
+        StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    #[test]
+    fn test_commit_user_data() {
+        let directory = Arc::new(RAMDirectory::new());
+        let config = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(Arc::clone(&directory), config).unwrap();
+
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(Field::new(
+            "id".into(),
+            field_type,
+            Some(VariantValue::VString("1".into())),
+            None,
+        ))];
+        writer.add_document(doc).unwrap();
+        writer
+            .set_live_commit_data(vec![("offset".to_string(), "10".to_string())])
+            .unwrap();
+        writer.commit().unwrap();
+
+        let reader = Reader::open(Arc::clone(&directory)).unwrap();
+        assert_eq!(reader.user_data().get("offset"), Some(&"10".to_string()));
+
+        // changing only the commit data still produces a new commit
+        writer
+            .set_live_commit_data(vec![("offset".to_string(), "11".to_string())])
+            .unwrap();
+        writer.commit().unwrap();
+        assert_eq!(
+            writer.get_live_commit_data().unwrap().get("offset"),
+            Some(&"11".to_string())
+        );
+
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&directory).unwrap();
+        assert_eq!(infos.user_data().get("offset"), Some(&"11".to_string()));
+        assert_eq!(infos.generation, reader.segment_infos.generation + 1);
+        assert_eq!(infos.total_max_doc(), 1);
+        writer.close().unwrap();
+    }
+}
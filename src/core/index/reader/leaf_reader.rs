@@ -22,8 +22,8 @@ use core::codec::postings::FieldsProducer;
 use core::codec::stored_fields::StoredFieldsReader;
 use core::codec::term_vectors::TermVectorsReader;
 use core::codec::{
-    Codec, CodecFieldsProducer, CodecNormsProducer, CodecPointsReader, CodecStoredFieldsReader,
-    CodecTVFields, CodecTVReader,
+    Codec, CodecFieldsProducer, CodecKnnVectorsReader, CodecNormsProducer, CodecPointsReader,
+    CodecStoredFieldsReader, CodecTVFields, CodecTVReader,
 };
 use core::codec::{Fields, TermIterator, Terms};
 use core::doc::StoredFieldVisitor;
@@ -152,6 +152,12 @@ pub trait LeafReader {
     /// spatial searches, or None if there are no point fields.
     fn point_values(&self) -> Option<Self::PointsReader>;
 
+    /// Returns the knn vectors used for nearest neighbor searches, or None
+    /// if there are no knn vector fields.
+    fn knn_vectors(&self) -> Option<Arc<CodecKnnVectorsReader<Self::Codec>>> {
+        None
+    }
+
     /// Expert: Returns a key for this IndexReader, so CachingWrapperFilter can find
     // it again.
     // This key must not have equals()/hashCode() methods, so &quot;equals&quot; means
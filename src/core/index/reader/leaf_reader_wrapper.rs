@@ -232,6 +232,10 @@ impl<D: Directory + 'static, C: Codec> LeafReader for MergeReaderWrapper<D, C> {
         self.reader.point_values()
     }
 
+    fn knn_vectors(&self) -> Option<Arc<CodecKnnVectorsReader<C>>> {
+        self.reader.knn_vectors()
+    }
+
     fn core_cache_key(&self) -> &str {
         self.reader.core_cache_key()
     }
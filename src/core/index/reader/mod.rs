@@ -27,6 +27,10 @@ mod segment_reader;
 
 pub use self::segment_reader::*;
 
+mod soft_deletes_reader;
+
+pub use self::soft_deletes_reader::*;
+
 mod index_lookup;
 
 pub use self::index_lookup::*;
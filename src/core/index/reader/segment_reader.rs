@@ -29,6 +29,7 @@ use core::codec::doc_values::{
 };
 
 use core::codec::field_infos::{FieldInfo, FieldInfos, FieldInfosFormat};
+use core::codec::knn_vectors::KnnVectorsFormat;
 use core::codec::norms::{NormsFormat, NormsProducer};
 use core::codec::points::PointsFormat;
 use core::codec::postings::PostingsFormat;
@@ -36,13 +37,14 @@ use core::codec::segment_infos::{SegmentCommitInfo, SegmentInfo, SegmentReadStat
 use core::codec::stored_fields::{StoredFieldsFormat, StoredFieldsReader};
 use core::codec::term_vectors::{TermVectorsFormat, TermVectorsReader};
 use core::codec::{
-    Codec, CodecFieldsProducer, CodecNormsProducer, CodecPointsReader, CodecStoredFieldsReader,
-    CodecTVFields, CodecTVReader, CompoundFormat, LiveDocsFormat, Lucene50CompoundReader,
+    Codec, CodecFieldsProducer, CodecKnnVectorsReader, CodecNormsProducer, CodecPointsReader,
+    CodecStoredFieldsReader, CodecTVFields, CodecTVReader, CompoundFormat, LiveDocsFormat,
+    Lucene50CompoundReader,
 };
 use core::doc::{DocValuesType, Document, DocumentStoredFieldVisitor, StoredFieldVisitor};
 use core::index::reader::{IndexReader, LeafReader, LeafReaderContext};
 use core::search::sort_field::Sort;
-use core::store::directory::Directory;
+use core::store::directory::{Directory, Lock};
 use core::store::io::{BufferedChecksumIndexInput, IndexInput};
 use core::store::IOContext;
 use core::util::external::Deferred;
@@ -63,6 +65,7 @@ pub struct SegmentCoreReaders<D: Directory, C: Codec> {
     /// in the case of DV updates, SR may hold a newer version.
     pub core_field_infos: Arc<FieldInfos>,
     pub points_reader: Option<Arc<CodecPointsReader<C>>>,
+    pub knn_vectors_reader: Option<Arc<CodecKnnVectorsReader<C>>>,
     pub core_dropped_listeners: Mutex<Vec<Deferred>>,
     pub core_cache_key: String,
 }
@@ -139,6 +142,15 @@ impl<D: Directory, C: Codec> SegmentCoreReaders<D, C> {
         } else {
             None
         };
+        let knn_vectors_reader = if core_field_infos.has_knn_vectors {
+            Some(Arc::new(
+                codec
+                    .knn_vectors_format()
+                    .fields_reader(&segment_read_state)?,
+            ))
+        } else {
+            None
+        };
         // TODO process norms_producers/store_fields_reader/term vectors
 
         Ok(SegmentCoreReaders {
@@ -151,6 +163,7 @@ impl<D: Directory, C: Codec> SegmentCoreReaders<D, C> {
             cfs_reader,
             core_field_infos,
             points_reader,
+            knn_vectors_reader,
             core_dropped_listeners: Mutex::new(vec![]),
             core_cache_key: format!("{}@{}", si.name, id2str(&random_id())),
         })
@@ -280,6 +293,13 @@ impl<D: Directory> Directory for CfsDirectory<D> {
         }
     }
 
+    fn obtain_lock(&self, name: &str) -> Result<Box<dyn Lock>> {
+        match self {
+            CfsDirectory::Raw(d) => d.obtain_lock(name),
+            CfsDirectory::Cfs(d) => d.obtain_lock(name),
+        }
+    }
+
     fn copy_from<D1: Directory>(
         &self,
         from: Arc<D1>,
@@ -336,6 +356,8 @@ impl SegmentDocValues {
         let mut dv_gens: Vec<i64> = Vec::new();
 
         let mut base_doc_values_producer: Option<Arc<dyn DocValuesProducer>> = None;
+        // fields updated together share a generation, and its files
+        let mut updated_fields: HashMap<i64, Vec<FieldInfo>> = HashMap::new();
         for (field, fi) in &infos.by_name {
             if fi.doc_values_type == DocValuesType::Null {
                 continue;
@@ -362,18 +384,27 @@ impl SegmentDocValues {
                 );
             } else {
                 // updated field
-                let dvp: Arc<dyn DocValuesProducer> = Arc::from(
-                    Self::get_doc_values_producer(
-                        doc_values_gen,
-                        si,
-                        dir.clone(),
-                        Arc::new(FieldInfos::new(vec![fi.as_ref().clone()]).unwrap()),
-                    )
-                    .unwrap(),
-                );
-                dv_gens.push(doc_values_gen);
-                dv_producers.push(dvp.clone());
-                dv_producers_by_field.insert(fi.name.clone(), dvp.clone());
+                updated_fields
+                    .entry(doc_values_gen)
+                    .or_insert_with(Vec::new)
+                    .push(fi.as_ref().clone());
+            }
+        }
+        for (doc_values_gen, fields) in updated_fields {
+            let names: Vec<String> = fields.iter().map(|fi| fi.name.clone()).collect();
+            let dvp: Arc<dyn DocValuesProducer> = Arc::from(
+                Self::get_doc_values_producer(
+                    doc_values_gen,
+                    si,
+                    dir.clone(),
+                    Arc::new(FieldInfos::new(fields).unwrap()),
+                )
+                .unwrap(),
+            );
+            dv_gens.push(doc_values_gen);
+            dv_producers.push(dvp.clone());
+            for name in names {
+                dv_producers_by_field.insert(name, dvp.clone());
             }
         }
         Self {
@@ -1117,6 +1148,10 @@ where
         self.core.points_reader.clone()
     }
 
+    fn knn_vectors(&self) -> Option<Arc<CodecKnnVectorsReader<C>>> {
+        self.core.knn_vectors_reader.clone()
+    }
+
     fn core_cache_key(&self) -> &str {
         // use segment name as unique segment cache key
         &self.core.core_cache_key
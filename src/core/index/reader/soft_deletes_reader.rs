@@ -0,0 +1,449 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::doc_values::{
+    BinaryDocValues, DocValuesProducerRef, NumericDocValues, SortedDocValues,
+    SortedNumericDocValues, SortedSetDocValues,
+};
+use core::codec::field_infos::{FieldInfo, FieldInfos};
+use core::codec::{Codec, CodecKnnVectorsReader, CodecTVFields};
+use core::doc::{DocValuesType, Document, StoredFieldVisitor};
+use core::index::merge::{MergePolicy, MergeScheduler};
+use core::index::reader::{
+    IndexReader, LeafReader, LeafReaderContext, SearchLeafReader, SegmentReader,
+    StandardDirectoryReader,
+};
+use core::search::sort_field::Sort;
+use core::store::directory::Directory;
+use core::util::external::Deferred;
+use core::util::{BitSet, Bits, BitsMut, BitsRef, DocId, FixedBitSet};
+
+use error::Result;
+
+use std::sync::Arc;
+
+/// Returns the live docs of `reader` without the documents whose soft deletes
+/// `field` is non-zero, together with the number of remaining live docs, or
+/// `None` if no live document of `reader` is soft deleted.
+pub fn soft_deletes_live_docs<R: LeafReader + ?Sized>(
+    reader: &R,
+    field: &str,
+) -> Result<Option<(FixedBitSet, i32)>> {
+    match reader.field_info(field) {
+        Some(fi) if fi.doc_values_type == DocValuesType::Numeric => {}
+        _ => return Ok(None),
+    }
+
+    let values = reader.get_numeric_doc_values(field)?;
+    let live_docs = reader.live_docs();
+    let max_doc = reader.max_doc();
+    let mut soft_live_docs = FixedBitSet::new(max_doc as usize);
+    let mut num_docs = 0;
+    for doc in 0..max_doc {
+        if live_docs.get(doc as usize)? && values.get(doc)? == 0 {
+            soft_live_docs.set(doc as usize);
+            num_docs += 1;
+        }
+    }
+    if num_docs == reader.num_docs() {
+        Ok(None)
+    } else {
+        Ok(Some((soft_live_docs, num_docs)))
+    }
+}
+
+/// A `LeafReader` hiding the documents marked as soft deleted by a numeric
+/// doc values field, on top of the hard deleted ones.
+///
+/// Everything but the live docs and the number of docs is delegated, so the
+/// soft deleted documents can still be loaded by doc id.
+pub struct SoftDeletesLeafReader<R: LeafReader> {
+    reader: Arc<R>,
+    live_docs: BitsRef,
+    num_docs: i32,
+}
+
+impl<R: LeafReader> SoftDeletesLeafReader<R> {
+    pub fn new(reader: Arc<R>, field: &str) -> Result<Self> {
+        let (live_docs, num_docs): (BitsRef, i32) =
+            match soft_deletes_live_docs(reader.as_ref(), field)? {
+                Some((bits, num_docs)) => (Arc::new(bits), num_docs),
+                None => (reader.live_docs(), reader.num_docs()),
+            };
+        Ok(SoftDeletesLeafReader {
+            reader,
+            live_docs,
+            num_docs,
+        })
+    }
+
+    pub fn reader(&self) -> &Arc<R> {
+        &self.reader
+    }
+}
+
+impl<R: LeafReader + 'static> LeafReader for SoftDeletesLeafReader<R> {
+    type Codec = R::Codec;
+    type FieldsProducer = R::FieldsProducer;
+    type TVFields = R::TVFields;
+    type TVReader = R::TVReader;
+    type StoredReader = R::StoredReader;
+    type NormsReader = R::NormsReader;
+    type PointsReader = R::PointsReader;
+
+    fn codec(&self) -> &Self::Codec {
+        self.reader.codec()
+    }
+
+    fn fields(&self) -> Result<Self::FieldsProducer> {
+        self.reader.fields()
+    }
+
+    fn name(&self) -> &str {
+        self.reader.name()
+    }
+
+    fn term_vector(&self, doc_id: DocId) -> Result<Option<Self::TVFields>> {
+        self.reader.term_vector(doc_id)
+    }
+
+    fn document(&self, doc_id: DocId, visitor: &mut dyn StoredFieldVisitor) -> Result<()> {
+        self.reader.document(doc_id, visitor)
+    }
+
+    fn live_docs(&self) -> BitsRef {
+        Arc::clone(&self.live_docs)
+    }
+
+    fn field_info(&self, field: &str) -> Option<&FieldInfo> {
+        self.reader.field_info(field)
+    }
+
+    fn field_infos(&self) -> &FieldInfos {
+        self.reader.field_infos()
+    }
+
+    fn clone_field_infos(&self) -> Arc<FieldInfos> {
+        self.reader.clone_field_infos()
+    }
+
+    fn max_doc(&self) -> DocId {
+        self.reader.max_doc()
+    }
+
+    fn num_docs(&self) -> i32 {
+        self.num_docs
+    }
+
+    fn get_numeric_doc_values(&self, field: &str) -> Result<Box<dyn NumericDocValues>> {
+        self.reader.get_numeric_doc_values(field)
+    }
+
+    fn get_binary_doc_values(&self, field: &str) -> Result<Box<dyn BinaryDocValues>> {
+        self.reader.get_binary_doc_values(field)
+    }
+
+    fn get_sorted_doc_values(&self, field: &str) -> Result<Box<dyn SortedDocValues>> {
+        self.reader.get_sorted_doc_values(field)
+    }
+
+    fn get_sorted_numeric_doc_values(
+        &self,
+        field: &str,
+    ) -> Result<Box<dyn SortedNumericDocValues>> {
+        self.reader.get_sorted_numeric_doc_values(field)
+    }
+
+    fn get_sorted_set_doc_values(&self, field: &str) -> Result<Box<dyn SortedSetDocValues>> {
+        self.reader.get_sorted_set_doc_values(field)
+    }
+
+    fn norm_values(&self, field: &str) -> Result<Option<Box<dyn NumericDocValues>>> {
+        self.reader.norm_values(field)
+    }
+
+    fn get_docs_with_field(&self, field: &str) -> Result<Box<dyn BitsMut>> {
+        self.reader.get_docs_with_field(field)
+    }
+
+    fn point_values(&self) -> Option<Self::PointsReader> {
+        self.reader.point_values()
+    }
+
+    fn knn_vectors(&self) -> Option<Arc<CodecKnnVectorsReader<Self::Codec>>> {
+        self.reader.knn_vectors()
+    }
+
+    fn core_cache_key(&self) -> &str {
+        self.reader.core_cache_key()
+    }
+
+    fn index_sort(&self) -> Option<&Sort> {
+        self.reader.index_sort()
+    }
+
+    fn add_core_drop_listener(&self, listener: Deferred) {
+        self.reader.add_core_drop_listener(listener)
+    }
+
+    fn is_codec_reader(&self) -> bool {
+        self.reader.is_codec_reader()
+    }
+
+    fn store_fields_reader(&self) -> Result<Self::StoredReader> {
+        self.reader.store_fields_reader()
+    }
+
+    fn term_vectors_reader(&self) -> Result<Option<Self::TVReader>> {
+        self.reader.term_vectors_reader()
+    }
+
+    fn norms_reader(&self) -> Result<Option<Self::NormsReader>> {
+        self.reader.norms_reader()
+    }
+
+    fn doc_values_reader(&self) -> Result<Option<DocValuesProducerRef>> {
+        self.reader.doc_values_reader()
+    }
+
+    fn postings_reader(&self) -> Result<Self::FieldsProducer> {
+        self.reader.postings_reader()
+    }
+}
+
+/// Wraps a `StandardDirectoryReader` so that searches don't see the documents
+/// marked as soft deleted by `IndexWriter::soft_delete_documents` or
+/// `IndexWriter::soft_update_document`.
+///
+/// The wrapped reader still sees every soft deleted document that wasn't
+/// dropped by a merge, which is how they can be recovered.
+pub struct SoftDeletesDirectoryReaderWrapper<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    reader: StandardDirectoryReader<D, C, MS, MP>,
+    field: String,
+    leaves: Vec<SoftDeletesLeafReader<SegmentReader<D, C>>>,
+    starts: Vec<DocId>,
+    num_docs: i32,
+}
+
+impl<D, C, MS, MP> SoftDeletesDirectoryReaderWrapper<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    pub fn new(reader: StandardDirectoryReader<D, C, MS, MP>, field: &str) -> Result<Self> {
+        let mut leaves = Vec::with_capacity(reader.segment_readers().len());
+        let mut starts = Vec::with_capacity(leaves.capacity());
+        let mut num_docs = 0;
+        let mut max_doc = 0;
+        for segment_reader in reader.segment_readers() {
+            let leaf = SoftDeletesLeafReader::new(Arc::clone(segment_reader), field)?;
+            starts.push(max_doc);
+            max_doc += leaf.max_doc();
+            num_docs += leaf.num_docs();
+            leaves.push(leaf);
+        }
+        Ok(SoftDeletesDirectoryReaderWrapper {
+            reader,
+            field: field.to_string(),
+            leaves,
+            starts,
+            num_docs,
+        })
+    }
+
+    /// The wrapped reader, which also sees the soft deleted documents.
+    pub fn reader(&self) -> &StandardDirectoryReader<D, C, MS, MP> {
+        &self.reader
+    }
+
+    pub fn soft_deletes_field(&self) -> &str {
+        &self.field
+    }
+}
+
+impl<D, C, MS, MP> IndexReader for SoftDeletesDirectoryReaderWrapper<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    type Codec = C;
+    fn leaves(&self) -> Vec<LeafReaderContext<'_, C>> {
+        self.leaves
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                LeafReaderContext::new(self, r as &SearchLeafReader<C>, i, self.starts[i])
+            })
+            .collect()
+    }
+
+    fn term_vector(&self, doc_id: DocId) -> Result<Option<CodecTVFields<C>>> {
+        self.reader.term_vector(doc_id)
+    }
+
+    fn document(&self, doc_id: DocId, fields_load: &[String]) -> Result<Document> {
+        self.reader.document(doc_id, fields_load)
+    }
+
+    fn max_doc(&self) -> i32 {
+        self.reader.max_doc()
+    }
+
+    fn num_docs(&self) -> i32 {
+        self.num_docs
+    }
+
+    fn refresh(&self) -> Result<Option<Box<dyn IndexReader<Codec = C>>>> {
+        if let Some(reader) = self.reader.open_if_changed(None)? {
+            Ok(Some(Box::new(Self::new(reader, &self.field)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    use core::doc::{Field, FieldType, Fieldable, IndexOptions, NumericDocValuesField, Term};
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::search::query::TermQuery;
+    use core::store::directory::FSDirectory;
+    use core::util::VariantValue;
+
+    const SOFT_DELETES_FIELD: &str = "__soft_deletes";
+
+    fn new_doc(id: &str, version: i64) -> Vec<Box<dyn Fieldable>> {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        field_type.omit_norms = true;
+        vec![
+            Box::new(Field::new(
+                "id".into(),
+                field_type,
+                Some(VariantValue::VString(id.into())),
+                None,
+            )),
+            Box::new(NumericDocValuesField::new("version", version)),
+        ]
+    }
+
+    fn id_term(id: &str) -> Term {
+        Term::new("id".into(), id.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_soft_deletes_reader() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = IndexWriterConfig::default();
+        config.set_soft_deletes_field(SOFT_DELETES_FIELD).unwrap();
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(config)).unwrap();
+
+        for id in &["1", "2", "3"] {
+            writer.add_document(new_doc(id, 1)).unwrap();
+        }
+        writer.commit().unwrap();
+
+        writer
+            .soft_update_document(new_doc("2", 2), id_term("2"))
+            .unwrap();
+        writer.soft_delete_documents(id_term("3")).unwrap();
+        writer.commit().unwrap();
+
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.max_doc(), 4);
+        assert_eq!(reader.num_docs(), 4);
+
+        let reader = SoftDeletesDirectoryReaderWrapper::new(reader, SOFT_DELETES_FIELD).unwrap();
+        assert_eq!(reader.max_doc(), 4);
+        assert_eq!(reader.num_docs(), 2);
+
+        let mut live = vec![];
+        for leaf in reader.leaves() {
+            let live_docs = leaf.reader.live_docs();
+            let versions = leaf.reader.get_numeric_doc_values("version").unwrap();
+            for doc in 0..leaf.reader.max_doc() {
+                if live_docs.get(doc as usize).unwrap() {
+                    live.push(versions.get(doc).unwrap());
+                }
+            }
+        }
+        assert_eq!(live, vec![1, 2]);
+
+        // recover the soft deleted document
+        writer
+            .update_numeric_doc_value(id_term("3"), SOFT_DELETES_FIELD, 0)
+            .unwrap();
+        writer.commit().unwrap();
+        let reader = writer.get_reader(true, false).unwrap();
+        let reader = SoftDeletesDirectoryReaderWrapper::new(reader, SOFT_DELETES_FIELD).unwrap();
+        assert_eq!(reader.num_docs(), 3);
+
+        // without a retention query merges drop the soft deleted documents
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.max_doc(), 3);
+        assert_eq!(reader.num_docs(), 3);
+    }
+
+    #[test]
+    fn test_soft_deletes_retention_query() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = IndexWriterConfig::default();
+        config.set_soft_deletes_field(SOFT_DELETES_FIELD).unwrap();
+        config.set_soft_deletes_retention_query(Arc::new(TermQuery::new(id_term("2"), 1.0, None)));
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(config)).unwrap();
+
+        for (id, version) in &[("1", 1), ("2", 2), ("3", 3), ("4", 4)] {
+            writer.add_document(new_doc(id, *version)).unwrap();
+        }
+        writer.commit().unwrap();
+        writer.soft_delete_documents(id_term("2")).unwrap();
+        writer.soft_delete_documents(id_term("3")).unwrap();
+        writer.commit().unwrap();
+
+        // the merge keeps the soft deleted document matching the retention query
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.max_doc(), 3);
+        let mut versions = vec![];
+        for leaf in reader.leaves() {
+            let values = leaf.reader.get_numeric_doc_values("version").unwrap();
+            for doc in 0..leaf.reader.max_doc() {
+                versions.push(values.get(doc).unwrap());
+            }
+        }
+        assert_eq!(versions, vec![1, 2, 4]);
+
+        let reader = SoftDeletesDirectoryReaderWrapper::new(reader, SOFT_DELETES_FIELD).unwrap();
+        assert_eq!(reader.num_docs(), 2);
+    }
+}
@@ -0,0 +1,501 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::segment_infos::{get_last_commit_generation, SegmentInfos};
+use core::codec::Codec;
+use core::index::merge::{MergePolicy, MergeScheduler};
+use core::index::reader::StandardDirectoryReader;
+use core::index::replication::{nrt_revision_file_version, revision_file_generation};
+use core::index::replication::{NrtRevision, Revision};
+use core::index::writer::IndexWriter;
+use core::store::directory::Directory;
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The indexing side of replication.
+///
+/// `publish` pins the latest commit of the `IndexWriter`: its `SegmentInfos`
+/// is inc-ref'd in the writer's deleter so its files survive later commits
+/// and merges, and its file list and checksums are published as a `Revision`.
+/// A revision stays pinned while it is the latest one or while a replica
+/// has it checked out, the pin is dropped by the last `release`.
+///
+/// `publish_nrt` does the same for the segments flushed but not committed
+/// yet: it opens a near real-time reader, which pins its files while open,
+/// and publishes its serialized `SegmentInfos` as a `NrtRevision`.
+pub struct PrimaryNode<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    writer: IndexWriter<D, C, MS, MP>,
+    published: Mutex<PublishedRevisions<D, C>>,
+    published_nrt: Mutex<PublishedNrtRevisions<D, C, MS, MP>>,
+}
+
+struct PublishedRevision<D: Directory, C: Codec> {
+    revision: Arc<Revision>,
+    infos: SegmentInfos<D, C>,
+    /// one for being the latest revision plus one per checkout
+    ref_count: usize,
+}
+
+struct PublishedRevisions<D: Directory, C: Codec> {
+    latest: Option<i64>,
+    revisions: HashMap<i64, PublishedRevision<D, C>>,
+}
+
+struct PublishedNrtRevision<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    revision: Arc<NrtRevision>,
+    /// keeps the files of the revision from being deleted
+    _reader: StandardDirectoryReader<D, C, MS, MP>,
+    /// one for being the latest revision plus one per checkout
+    ref_count: usize,
+}
+
+struct PublishedNrtRevisions<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    latest: Option<i64>,
+    revisions: HashMap<i64, PublishedNrtRevision<D, C, MS, MP>>,
+}
+
+impl<D, C, MS, MP> PrimaryNode<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    pub fn new(writer: IndexWriter<D, C, MS, MP>) -> Self {
+        // the revisions published by a previous primary are not pinned any more
+        match writer.directory().list_all() {
+            Ok(files) => {
+                for name in files {
+                    if revision_file_generation(&name).is_some()
+                        || nrt_revision_file_version(&name).is_some()
+                    {
+                        Self::delete_revision_file(writer.directory().as_ref(), &name);
+                    }
+                }
+            }
+            Err(e) => warn!("PrimaryNode: list published revisions failed by '{:?}'", e),
+        }
+        PrimaryNode {
+            writer,
+            published: Mutex::new(PublishedRevisions {
+                latest: None,
+                revisions: HashMap::new(),
+            }),
+            published_nrt: Mutex::new(PublishedNrtRevisions {
+                latest: None,
+                revisions: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn writer(&self) -> &IndexWriter<D, C, MS, MP> {
+        &self.writer
+    }
+
+    /// The directory replicas copy the files of the published revisions from.
+    pub fn directory(&self) -> &Arc<D> {
+        self.writer.directory()
+    }
+
+    /// Commits pending changes and publishes the resulting commit.
+    pub fn commit_and_publish(&self) -> Result<Arc<Revision>> {
+        self.writer.commit()?;
+        self.publish()
+    }
+
+    /// Pins the latest commit of the index and publishes it as the latest
+    /// revision, replacing the previously published one.
+    ///
+    /// The revision is also written to `Revision::file_name` in the index
+    /// directory, for the replicas reading it with a `DirectoryTransport`. The
+    /// file is deleted once the revision is released.
+    pub fn publish(&self) -> Result<Arc<Revision>> {
+        let mut published = self.published.lock()?;
+        let (infos, revision) = self.pin_latest_commit()?;
+        if let Some(generation) = published.latest {
+            if generation == revision.generation {
+                // nothing was committed since the last publish
+                self.writer.dec_ref_deleter(&infos)?;
+                return Ok(Arc::clone(&published.revisions[&generation].revision));
+            }
+        }
+        if let Err(e) = revision.write(self.directory().as_ref()) {
+            self.writer.dec_ref_deleter(&infos)?;
+            return Err(e);
+        }
+
+        let revision = Arc::new(revision);
+        published.revisions.insert(
+            revision.generation,
+            PublishedRevision {
+                revision: Arc::clone(&revision),
+                infos,
+                ref_count: 1,
+            },
+        );
+        if let Some(previous) = published.latest.replace(revision.generation) {
+            self.dec_ref(&mut published, previous)?;
+        }
+        Ok(revision)
+    }
+
+    /// Returns the latest published revision, if any. The revision stays
+    /// pinned until it is passed to `release`.
+    pub fn checkout(&self) -> Result<Option<Arc<Revision>>> {
+        let mut published = self.published.lock()?;
+        match published.latest {
+            Some(generation) => {
+                let entry = published.revisions.get_mut(&generation).unwrap();
+                entry.ref_count += 1;
+                Ok(Some(Arc::clone(&entry.revision)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Releases a revision returned by `checkout`.
+    pub fn release(&self, generation: i64) -> Result<()> {
+        let mut published = self.published.lock()?;
+        self.dec_ref(&mut published, generation)
+    }
+
+    /// Flushes the pending documents and deletes and publishes the resulting
+    /// segments, committed or not, as the latest near real-time revision.
+    ///
+    /// Like `publish`, the revision is also written to `NrtRevision::file_name`
+    /// in the index directory until it is released.
+    pub fn publish_nrt(&self) -> Result<Arc<NrtRevision>> {
+        let mut published = self.published_nrt.lock()?;
+        // deletes must be written to disk for the replicas to copy them
+        let reader = self.writer.get_reader(true, true)?;
+        if let Some(version) = published.latest {
+            if version == reader.version() {
+                // nothing changed since the last publish
+                return Ok(Arc::clone(&published.revisions[&version].revision));
+            }
+        }
+
+        let revision = NrtRevision::from_infos(self.directory().as_ref(), reader.segment_infos())?;
+        revision.write(self.directory().as_ref())?;
+        let revision = Arc::new(revision);
+        published.revisions.insert(
+            revision.version,
+            PublishedNrtRevision {
+                revision: Arc::clone(&revision),
+                _reader: reader,
+                ref_count: 1,
+            },
+        );
+        if let Some(previous) = published.latest.replace(revision.version) {
+            self.dec_ref_nrt(&mut published, previous)?;
+        }
+        Ok(revision)
+    }
+
+    /// Returns the latest published near real-time revision, if any. The
+    /// revision stays pinned until it is passed to `release_nrt`.
+    pub fn checkout_nrt(&self) -> Result<Option<Arc<NrtRevision>>> {
+        let mut published = self.published_nrt.lock()?;
+        match published.latest {
+            Some(version) => {
+                let entry = published.revisions.get_mut(&version).unwrap();
+                entry.ref_count += 1;
+                Ok(Some(Arc::clone(&entry.revision)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Releases a revision returned by `checkout_nrt`.
+    pub fn release_nrt(&self, version: i64) -> Result<()> {
+        let mut published = self.published_nrt.lock()?;
+        self.dec_ref_nrt(&mut published, version)
+    }
+
+    /// Returns the generations of the revisions currently pinned.
+    pub fn pinned_generations(&self) -> Result<Vec<i64>> {
+        let published = self.published.lock()?;
+        let mut generations: Vec<i64> = published.revisions.keys().cloned().collect();
+        generations.sort();
+        Ok(generations)
+    }
+
+    /// Drops the pin on every published revision, call it before closing the
+    /// writer. Revisions still checked out must not be copied any more.
+    pub fn close(&self) -> Result<()> {
+        {
+            let mut published_nrt = self.published_nrt.lock()?;
+            published_nrt.latest = None;
+            for (_, entry) in published_nrt.revisions.drain() {
+                Self::delete_revision_file(self.directory().as_ref(), &entry.revision.file_name());
+            }
+        }
+        let mut published = self.published.lock()?;
+        published.latest = None;
+        let mut res = Ok(());
+        for (_, entry) in published.revisions.drain() {
+            Self::delete_revision_file(self.directory().as_ref(), &entry.revision.file_name());
+            if let Err(e) = self.writer.dec_ref_deleter(&entry.infos) {
+                res = Err(e);
+            }
+        }
+        res
+    }
+
+    fn dec_ref(&self, published: &mut PublishedRevisions<D, C>, generation: i64) -> Result<()> {
+        let ref_count = match published.revisions.get_mut(&generation) {
+            Some(entry) => {
+                debug_assert!(entry.ref_count > 0);
+                entry.ref_count -= 1;
+                entry.ref_count
+            }
+            None => bail!(IllegalArgument(format!(
+                "revision gen={} is not published",
+                generation
+            ))),
+        };
+        if ref_count == 0 {
+            let entry = published.revisions.remove(&generation).unwrap();
+            // unpublish the revision before its files may be deleted
+            Self::delete_revision_file(self.directory().as_ref(), &entry.revision.file_name());
+            self.writer.dec_ref_deleter(&entry.infos)?;
+        }
+        Ok(())
+    }
+
+    fn delete_revision_file(directory: &D, name: &str) {
+        if let Err(e) = directory.delete_file(name) {
+            warn!(
+                "PrimaryNode: delete revision file '{}' failed by '{:?}'",
+                name, e
+            );
+        }
+    }
+
+    fn dec_ref_nrt(
+        &self,
+        published: &mut PublishedNrtRevisions<D, C, MS, MP>,
+        version: i64,
+    ) -> Result<()> {
+        let ref_count = match published.revisions.get_mut(&version) {
+            Some(entry) => {
+                debug_assert!(entry.ref_count > 0);
+                entry.ref_count -= 1;
+                entry.ref_count
+            }
+            None => bail!(IllegalArgument(format!(
+                "nrt revision version={} is not published",
+                version
+            ))),
+        };
+        if ref_count == 0 {
+            // dropping the reader releases its files
+            let entry = published.revisions.remove(&version).unwrap();
+            Self::delete_revision_file(self.directory().as_ref(), &entry.revision.file_name());
+        }
+        Ok(())
+    }
+
+    fn pin_latest_commit(&self) -> Result<(SegmentInfos<D, C>, Revision)> {
+        self.pin_latest_commit_with(Revision::from_commit)
+    }
+
+    fn pin_latest_commit_with<F>(
+        &self,
+        mut from_commit: F,
+    ) -> Result<(SegmentInfos<D, C>, Revision)>
+    where
+        F: FnMut(&D, &SegmentInfos<D, C>) -> Result<Revision>,
+    {
+        let directory = self.writer.directory();
+        loop {
+            let infos: SegmentInfos<D, C> = SegmentInfos::read_latest_commit(directory)?;
+            self.writer.inc_ref_deleter(&infos)?;
+            match from_commit(directory.as_ref(), &infos) {
+                Ok(revision) => return Ok((infos, revision)),
+                Err(e) => {
+                    self.writer.dec_ref_deleter(&infos)?;
+                    // a concurrent commit may have deleted the files before they were
+                    // pinned, retry with the new commit in that case
+                    let last_generation = get_last_commit_generation(&directory.list_all()?)?;
+                    if last_generation == infos.generation {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<D, C, MS, MP> Drop for PrimaryNode<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("PrimaryNode: release pinned revisions failed by '{:?}'", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType, Fieldable, IndexOptions};
+    use core::index::merge::{SerialMergeScheduler, TieredMergePolicy};
+    use core::index::replication::latest_revision_file;
+    use core::index::writer::IndexWriterConfig;
+    use core::store::directory::RAMDirectory;
+    use core::util::VariantValue;
+    use error::ErrorKind::IllegalState;
+
+    type Primary = PrimaryNode<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    fn new_primary() -> (Arc<RAMDirectory>, Primary) {
+        let directory = Arc::new(RAMDirectory::new());
+        let config = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(Arc::clone(&directory), config).unwrap();
+        (directory, Primary::new(writer))
+    }
+
+    fn add_doc(primary: &Primary, id: &str) {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(Field::new(
+            "id".into(),
+            field_type,
+            Some(VariantValue::VString(id.to_string())),
+            None,
+        ))];
+        primary.writer().add_document(doc).unwrap();
+    }
+
+    #[test]
+    fn test_publish_and_release() {
+        let (directory, primary) = new_primary();
+        assert!(primary.checkout().unwrap().is_none());
+
+        add_doc(&primary, "1");
+        let first = primary.commit_and_publish().unwrap();
+        assert_eq!(
+            primary.pinned_generations().unwrap(),
+            vec![first.generation]
+        );
+        let files = directory.list_all().unwrap();
+        assert_eq!(latest_revision_file(&files), Some(&first.file_name()));
+        let published = Revision::read(directory.as_ref(), &first.file_name()).unwrap();
+        assert_eq!(published.generation, first.generation);
+        assert_eq!(published.files, first.files);
+
+        // nothing was committed since
+        assert!(Arc::ptr_eq(&primary.publish().unwrap(), &first));
+
+        // a checked out revision stays pinned once a newer one is published
+        let checked_out = primary.checkout().unwrap().unwrap();
+        assert!(Arc::ptr_eq(&checked_out, &first));
+        add_doc(&primary, "2");
+        primary.writer().commit().unwrap();
+        primary.writer().force_merge(1, true).unwrap();
+        let second = primary.commit_and_publish().unwrap();
+        assert_eq!(
+            primary.pinned_generations().unwrap(),
+            vec![first.generation, second.generation]
+        );
+        for file in &first.files {
+            assert!(directory.file_exists(&file.name).unwrap());
+        }
+        let files = directory.list_all().unwrap();
+        assert_eq!(latest_revision_file(&files), Some(&second.file_name()));
+
+        // releasing the last reference unpublishes the revision and its files
+        primary.release(first.generation).unwrap();
+        assert_eq!(
+            primary.pinned_generations().unwrap(),
+            vec![second.generation]
+        );
+        assert!(!directory.file_exists(&first.file_name()).unwrap());
+        for file in &first.files {
+            assert!(!directory.file_exists(&file.name).unwrap());
+        }
+        assert!(primary.release(first.generation).is_err());
+
+        primary.close().unwrap();
+        assert!(primary.pinned_generations().unwrap().is_empty());
+        assert!(latest_revision_file(&directory.list_all().unwrap()).is_none());
+        primary.writer().close().unwrap();
+    }
+
+    #[test]
+    fn test_pin_latest_commit_retry() {
+        let (_, primary) = new_primary();
+        add_doc(&primary, "1");
+        primary.writer().commit().unwrap();
+
+        // a commit deleting the files of the commit being pinned is retried
+        let mut attempts = 0;
+        let mut first_generation = 0;
+        let (infos, revision) = primary
+            .pin_latest_commit_with(|directory, infos| {
+                attempts += 1;
+                if attempts == 1 {
+                    first_generation = infos.generation;
+                    add_doc(&primary, "2");
+                    primary.writer().commit().unwrap();
+                    bail!(IllegalState("files deleted by a concurrent commit".into()));
+                }
+                Revision::from_commit(directory, infos)
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert!(revision.generation > first_generation);
+        assert_eq!(revision.generation, infos.generation);
+        primary.writer().dec_ref_deleter(&infos).unwrap();
+
+        // without a newer commit the error is returned
+        let mut attempts = 0;
+        let res = primary.pin_latest_commit_with(|_, _| {
+            attempts += 1;
+            bail!(IllegalState("files deleted".into()))
+        });
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+
+        primary.writer().close().unwrap();
+    }
+}
@@ -0,0 +1,491 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::segment_infos::{file_name_from_generation, SegmentInfos};
+use core::codec::segment_infos::{
+    INDEX_FILE_OLD_SEGMENT_GEN, INDEX_FILE_PENDING_SEGMENTS, INDEX_FILE_SEGMENTS,
+};
+use core::codec::{checksum_entire_file, retrieve_checksum, Codec};
+use core::index::merge::{MergePolicy, MergeScheduler};
+use core::index::reader::{index_exist, StandardDirectoryReader};
+use core::index::replication::{FileMetaData, NrtRevision, Revision};
+use core::index::replication::{NrtReplicationTransport, ReplicationTransport};
+use core::store::directory::{Directory, Lock, WRITE_LOCK_NAME};
+use core::store::io::{IndexOutput, RAMIndexInput};
+use core::store::IOContext;
+use error::ErrorKind::CorruptIndex;
+use error::Result;
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The search side of replication.
+///
+/// `update` copies the files of the latest committed revision missing from
+/// the replica's own `Directory`, verifies their checksums, writes the
+/// `segments_N` file last and only then swaps in a new
+/// `StandardDirectoryReader`, so searches see either the old or the new
+/// revision, never a partial one.
+///
+/// `update_nrt` does the same for the near real-time revisions of the
+/// primary, without syncing nor writing a `segments_N` file: the reader is
+/// opened on the deserialized `SegmentInfos`. The files of the last commit
+/// are kept, so a restarted replica opens the last committed revision.
+///
+/// Files no longer referenced are deleted after the new reader is swapped in;
+/// readers still holding them keep working on filesystems that defer deletion
+/// of open files, and on `RAMDirectory`.
+///
+/// The replica holds the write lock of its directory while alive.
+pub struct ReplicaNode<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    directory: Arc<D>,
+    _write_lock: Box<dyn Lock>,
+    /// serializes updates
+    state: Mutex<ReplicaState>,
+    reader: RwLock<Option<Arc<StandardDirectoryReader<D, C, MS, MP>>>>,
+}
+
+#[derive(Default)]
+struct ReplicaState {
+    /// generation of the last commit written to the replica's directory
+    generation: Option<i64>,
+    /// version of the `SegmentInfos` the current reader was opened on
+    version: Option<i64>,
+    /// files referenced by the last commit, including the `segments_N` file
+    commit_files: HashSet<String>,
+    /// files referenced by the current reader
+    reader_files: HashSet<String>,
+}
+
+impl<D, C, MS, MP> ReplicaNode<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    /// Opens a replica on `directory`, starting from the index it already
+    /// contains, if any.
+    pub fn new(directory: Arc<D>) -> Result<Self> {
+        let write_lock = directory.obtain_lock(WRITE_LOCK_NAME)?;
+        let mut state = ReplicaState::default();
+        let reader = if index_exist(directory.as_ref())? {
+            let infos: SegmentInfos<D, C> = SegmentInfos::read_latest_commit(&directory)?;
+            state.generation = Some(infos.generation);
+            state.version = Some(infos.version);
+            state.commit_files = infos.files(true);
+            state.reader_files = state.commit_files.clone();
+            let reader = StandardDirectoryReader::open_with_infos(Arc::clone(&directory), infos)?;
+            Some(Arc::new(reader))
+        } else {
+            None
+        };
+        Ok(ReplicaNode {
+            directory,
+            _write_lock: write_lock,
+            state: Mutex::new(state),
+            reader: RwLock::new(reader),
+        })
+    }
+
+    pub fn directory(&self) -> &Arc<D> {
+        &self.directory
+    }
+
+    /// Returns the generation of the last commit installed.
+    pub fn generation(&self) -> Result<Option<i64>> {
+        Ok(self.state.lock()?.generation)
+    }
+
+    /// Returns the `SegmentInfos` version of the current reader.
+    pub fn version(&self) -> Result<Option<i64>> {
+        Ok(self.state.lock()?.version)
+    }
+
+    /// Returns the reader on the revision currently installed.
+    pub fn reader(&self) -> Result<Option<Arc<StandardDirectoryReader<D, C, MS, MP>>>> {
+        Ok(self.reader.read()?.clone())
+    }
+
+    /// Installs the latest committed revision of the primary, returns false
+    /// if there was nothing new to install.
+    ///
+    /// The reader is only reopened if the revision is not older than the
+    /// near real-time revision currently searched.
+    pub fn update<T: ReplicationTransport>(&self, transport: &T) -> Result<bool> {
+        let mut state = self.state.lock()?;
+        let revision = match transport.checkout()? {
+            Some(revision) => revision,
+            None => return Ok(false),
+        };
+        let res = if state.generation == Some(revision.generation) {
+            Ok(false)
+        } else {
+            self.install(&mut state, transport.source(), &revision)
+                .map(|_| true)
+        };
+        let release_res = transport.release(&revision);
+        let updated = res?;
+        release_res?;
+        Ok(updated)
+    }
+
+    /// Installs the latest near real-time revision of the primary, returns
+    /// false if there was nothing new to install.
+    pub fn update_nrt<T: NrtReplicationTransport>(&self, transport: &T) -> Result<bool> {
+        let mut state = self.state.lock()?;
+        let revision = match transport.checkout_nrt()? {
+            Some(revision) => revision,
+            None => return Ok(false),
+        };
+        let res = if state.version.map_or(false, |v| v >= revision.version) {
+            Ok(false)
+        } else {
+            self.install_nrt(&mut state, transport.source(), &revision)
+                .map(|_| true)
+        };
+        let release_res = transport.release_nrt(&revision);
+        let updated = res?;
+        release_res?;
+        Ok(updated)
+    }
+
+    fn install<S: Directory>(
+        &self,
+        state: &mut ReplicaState,
+        source: Arc<S>,
+        revision: &Revision,
+    ) -> Result<()> {
+        // copy the missing files, then make them durable before they are
+        // referenced by a segments_N file; files copied by `update_nrt`
+        // were never synced
+        self.copy_files(source, &revision.files)?;
+        let names: HashSet<String> = revision.files.iter().map(|f| f.name.clone()).collect();
+        self.directory.sync(&names)?;
+
+        // write the segments file last, through a pending file renamed
+        // once durable
+        let pending_file_name =
+            file_name_from_generation(INDEX_FILE_PENDING_SEGMENTS, "", revision.generation as u64);
+        let _ = self.directory.delete_file(&pending_file_name);
+        {
+            let mut output = self
+                .directory
+                .create_output(&pending_file_name, &IOContext::Default)?;
+            write_segments(&mut output, &revision.segments_bytes)?;
+        }
+        let mut pending = HashSet::with_capacity(1);
+        pending.insert(pending_file_name.clone());
+        self.directory.sync(&pending)?;
+        self.directory
+            .rename(&pending_file_name, &revision.segments_file_name)?;
+        self.directory.sync_meta_data()?;
+
+        // older segments files would shadow a revision with a lower
+        // generation, eg after the primary index was rebuilt
+        for name in self.directory.list_all()? {
+            if name.starts_with(INDEX_FILE_SEGMENTS)
+                && name != INDEX_FILE_OLD_SEGMENT_GEN
+                && name != revision.segments_file_name
+            {
+                self.directory.delete_file(&name)?;
+            }
+        }
+
+        let mut commit_files = names;
+        commit_files.insert(revision.segments_file_name.clone());
+        state.generation = Some(revision.generation);
+        state.commit_files = commit_files;
+
+        if state.version.map_or(true, |v| v <= revision.version) {
+            let reader = StandardDirectoryReader::open(Arc::clone(&self.directory))?;
+            *self.reader.write()? = Some(Arc::new(reader));
+            state.version = Some(revision.version);
+            state.reader_files = state.commit_files.clone();
+        }
+        self.delete_unused_files(state)
+    }
+
+    fn install_nrt<S: Directory>(
+        &self,
+        state: &mut ReplicaState,
+        source: Arc<S>,
+        revision: &NrtRevision,
+    ) -> Result<()> {
+        self.copy_files(source, &revision.files)?;
+
+        let input = RAMIndexInput::new(
+            format!("NrtRevision(version={})", revision.version),
+            Arc::new(revision.infos_bytes.clone()),
+        );
+        let infos: SegmentInfos<D, C> =
+            SegmentInfos::read_from_input(&self.directory, Box::new(input), revision.generation)?;
+        let reader = StandardDirectoryReader::open_with_infos(Arc::clone(&self.directory), infos)?;
+        *self.reader.write()? = Some(Arc::new(reader));
+
+        state.version = Some(revision.version);
+        state.reader_files = revision.files.iter().map(|f| f.name.clone()).collect();
+        self.delete_unused_files(state)
+    }
+
+    /// Copies the files missing from the replica, or differing from the
+    /// primary's ones, and verifies the copies.
+    fn copy_files<S: Directory>(&self, source: Arc<S>, files: &[FileMetaData]) -> Result<()> {
+        for file in files {
+            if self.is_up_to_date(file) {
+                continue;
+            }
+            // stale file with the same name, eg from an interrupted copy
+            let _ = self.directory.delete_file(&file.name);
+            self.directory.copy_from(
+                Arc::clone(&source),
+                &file.name,
+                &file.name,
+                &IOContext::Default,
+            )?;
+            if let Err(e) = self.verify(file) {
+                let _ = self.directory.delete_file(&file.name);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the files referenced neither by the last commit nor by the
+    /// current reader.
+    fn delete_unused_files(&self, state: &ReplicaState) -> Result<()> {
+        for name in self.directory.list_all()? {
+            if name != WRITE_LOCK_NAME
+                && !state.commit_files.contains(&name)
+                && !state.reader_files.contains(&name)
+            {
+                if let Err(e) = self.directory.delete_file(&name) {
+                    warn!(
+                        "ReplicaNode: delete unused file '{}' failed by '{:?}'",
+                        name, e
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_up_to_date(&self, file: &FileMetaData) -> bool {
+        match self.directory.open_input(&file.name, &IOContext::READ) {
+            Ok(mut input) => {
+                input.len() as i64 == file.length
+                    && retrieve_checksum(input.as_mut()).ok() == Some(file.checksum)
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Checks the copy of `file` against the length and checksum published by
+    /// the primary, reading the whole file.
+    fn verify(&self, file: &FileMetaData) -> Result<()> {
+        let input = self.directory.open_input(&file.name, &IOContext::READ)?;
+        if input.len() as i64 != file.length {
+            bail!(CorruptIndex(format!(
+                "file '{}' has length {} after copy, expected {}",
+                file.name,
+                input.len(),
+                file.length
+            )));
+        }
+        let checksum = checksum_entire_file(input.as_ref())?;
+        if checksum != file.checksum {
+            bail!(CorruptIndex(format!(
+                "file '{}' checksum mismatch after copy: expected=0x{:X}, actual=0x{:X}",
+                file.name, file.checksum, checksum
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn write_segments(output: &mut impl IndexOutput, segments_bytes: &[u8]) -> Result<()> {
+    output.write_bytes(segments_bytes, 0, segments_bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType, Fieldable, IndexOptions, Term};
+    use core::index::merge::{SerialMergeScheduler, TieredMergePolicy};
+    use core::index::reader::IndexReader;
+    use core::index::replication::{DirectoryTransport, PrimaryNode};
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::store::directory::{FSDirectory, RAMDirectory};
+    use core::util::VariantValue;
+
+    type Primary = PrimaryNode<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+    type Replica = ReplicaNode<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    fn add_doc<D: Directory + Send + Sync + 'static>(
+        primary: &PrimaryNode<D, CodecEnum, SerialMergeScheduler, TieredMergePolicy>,
+        id: &str,
+    ) {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(Field::new(
+            "id".into(),
+            field_type,
+            Some(VariantValue::VString(id.to_string())),
+            None,
+        ))];
+        primary.writer().add_document(doc).unwrap();
+    }
+
+    #[test]
+    fn test_replicate_commits() {
+        let primary_dir = Arc::new(RAMDirectory::new());
+        let config = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(Arc::clone(&primary_dir), config).unwrap();
+        let primary = Primary::new(writer);
+        let transport = DirectoryTransport::new(Arc::clone(&primary_dir));
+
+        let replica = Replica::new(Arc::new(RAMDirectory::new())).unwrap();
+        assert!(!replica.update(&transport).unwrap());
+        assert!(replica.reader().unwrap().is_none());
+
+        add_doc(&primary, "1");
+        add_doc(&primary, "2");
+        let first = primary.commit_and_publish().unwrap();
+        assert!(replica.update(&transport).unwrap());
+        assert_eq!(replica.generation().unwrap(), Some(first.generation));
+        assert_eq!(replica.reader().unwrap().unwrap().num_docs(), 2);
+        assert!(!replica.update(&transport).unwrap());
+        assert_eq!(
+            primary.pinned_generations().unwrap(),
+            vec![first.generation]
+        );
+
+        // the published commit stays pinned while the writer moves on
+        add_doc(&primary, "3");
+        primary.writer().commit().unwrap();
+        primary.writer().force_merge(1, true).unwrap();
+        primary.writer().commit().unwrap();
+        for file in &first.files {
+            assert!(primary_dir.file_exists(&file.name).unwrap());
+        }
+
+        let old_reader = replica.reader().unwrap().unwrap();
+        let second = primary.publish().unwrap();
+        assert!(second.generation > first.generation);
+        assert_eq!(
+            primary.pinned_generations().unwrap(),
+            vec![second.generation]
+        );
+        assert!(replica.update(&transport).unwrap());
+        assert_eq!(replica.reader().unwrap().unwrap().num_docs(), 3);
+        assert_eq!(old_reader.num_docs(), 2);
+
+        // the replica only keeps the files of the installed revision
+        let mut expected: Vec<String> = second.files.iter().map(|f| f.name.clone()).collect();
+        expected.push(second.segments_file_name.clone());
+        expected.sort();
+        assert_eq!(replica.directory().list_all().unwrap(), expected);
+
+        primary.close().unwrap();
+        primary.writer().close().unwrap();
+    }
+
+    #[test]
+    fn test_replicate_commits_from_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary_dir = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let config = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(primary_dir, config).unwrap();
+        let primary =
+            PrimaryNode::<_, CodecEnum, SerialMergeScheduler, TieredMergePolicy>::new(writer);
+        let transport = DirectoryTransport::open(temp_dir.path()).unwrap();
+
+        let replica = Replica::new(Arc::new(RAMDirectory::new())).unwrap();
+        assert!(!replica.update(&transport).unwrap());
+
+        add_doc(&primary, "1");
+        add_doc(&primary, "2");
+        let first = primary.commit_and_publish().unwrap();
+        assert!(replica.update(&transport).unwrap());
+        assert_eq!(replica.generation().unwrap(), Some(first.generation));
+        assert_eq!(replica.reader().unwrap().unwrap().num_docs(), 2);
+
+        add_doc(&primary, "3");
+        let second = primary.commit_and_publish().unwrap();
+        assert!(replica.update(&transport).unwrap());
+        assert_eq!(replica.generation().unwrap(), Some(second.generation));
+        assert_eq!(replica.reader().unwrap().unwrap().num_docs(), 3);
+        assert!(!replica.update(&transport).unwrap());
+
+        // nothing is published once the primary is closed
+        primary.close().unwrap();
+        primary.writer().close().unwrap();
+        assert!(transport.checkout().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_replicate_nrt_segments() {
+        let primary_dir = Arc::new(RAMDirectory::new());
+        let config = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(Arc::clone(&primary_dir), config).unwrap();
+        let primary = Primary::new(writer);
+        let transport = DirectoryTransport::new(Arc::clone(&primary_dir));
+
+        let replica = Replica::new(Arc::new(RAMDirectory::new())).unwrap();
+        assert!(!replica.update_nrt(&transport).unwrap());
+
+        add_doc(&primary, "1");
+        add_doc(&primary, "2");
+        let first = primary.publish_nrt().unwrap();
+        assert!(replica.update_nrt(&transport).unwrap());
+        assert_eq!(replica.version().unwrap(), Some(first.version));
+        assert_eq!(replica.reader().unwrap().unwrap().num_docs(), 2);
+        assert!(!replica.update_nrt(&transport).unwrap());
+        // the replica searches the flushed segments without any commit
+        assert!(!index_exist(replica.directory().as_ref()).unwrap());
+        assert_eq!(replica.generation().unwrap(), None);
+
+        // deletes are written by the primary and shipped with the segments
+        primary
+            .writer()
+            .delete_documents_by_terms(vec![Term::new("id".into(), b"1".to_vec())])
+            .unwrap();
+        add_doc(&primary, "3");
+        let second = primary.publish_nrt().unwrap();
+        assert!(second.version > first.version);
+        assert!(replica.update_nrt(&transport).unwrap());
+        let reader = replica.reader().unwrap().unwrap();
+        assert_eq!(reader.num_docs(), 2);
+        assert_eq!(reader.max_doc(), 3);
+
+        // committing makes the replica durable
+        let commit = primary.commit_and_publish().unwrap();
+        assert!(replica.update(&transport).unwrap());
+        assert_eq!(replica.generation().unwrap(), Some(commit.generation));
+        assert!(index_exist(replica.directory().as_ref()).unwrap());
+        assert_eq!(replica.reader().unwrap().unwrap().num_docs(), 2);
+
+        primary.close().unwrap();
+        primary.writer().close().unwrap();
+    }
+}
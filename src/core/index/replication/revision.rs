@@ -0,0 +1,460 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::segment_infos::{file_name_from_generation, SegmentInfos};
+use core::codec::Codec;
+use core::codec::{check_footer, check_header, retrieve_checksum, write_footer, write_header};
+use core::store::directory::Directory;
+use core::store::io::{DataInput, DataOutput, IndexOutput, RAMOutputStream};
+use core::store::IOContext;
+use core::util::to_base36;
+use error::ErrorKind::IllegalState;
+use error::Result;
+
+use std::collections::HashSet;
+
+/// Prefix of the files a `PrimaryNode` publishes its revisions in, followed
+/// by the generation of the revision in base 36.
+pub const REVISION_FILE_PREFIX: &str = "revision";
+
+const REVISION_CODEC_NAME: &str = "Revision";
+const REVISION_VERSION_START: i32 = 0;
+const REVISION_VERSION_CURRENT: i32 = REVISION_VERSION_START;
+
+/// Prefix of the files a `PrimaryNode` publishes its near real-time revisions
+/// in, followed by the version of the revision in base 36.
+pub const NRT_REVISION_FILE_PREFIX: &str = "nrt_revision";
+
+const NRT_REVISION_CODEC_NAME: &str = "NrtRevision";
+const NRT_REVISION_VERSION_START: i32 = 0;
+const NRT_REVISION_VERSION_CURRENT: i32 = NRT_REVISION_VERSION_START;
+
+/// Describes one index file of a `Revision`, the checksum is the one
+/// recorded in the file's codec footer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileMetaData {
+    pub name: String,
+    pub length: i64,
+    pub checksum: i64,
+}
+
+/// A commit point published by a `PrimaryNode` for replicas to copy.
+///
+/// The `segments_N` file is carried inline so it stays available even once
+/// the primary's deletion policy removed it, and so replicas can write it
+/// last, after all the files it references have been copied.
+#[derive(Clone, Debug)]
+pub struct Revision {
+    /// generation of the `segments_N` file of the commit
+    pub generation: i64,
+    /// version of the `SegmentInfos` of the commit
+    pub version: i64,
+    pub segments_file_name: String,
+    /// content of the `segments_N` file
+    pub segments_bytes: Vec<u8>,
+    /// all the other files referenced by the commit, sorted by name
+    pub files: Vec<FileMetaData>,
+}
+
+impl Revision {
+    /// Builds the revision of the commit `infos` was read from. The files
+    /// must not be deleted meanwhile, eg because `infos` is pinned in the
+    /// `IndexWriter`'s deleter.
+    pub fn from_commit<D: Directory, C: Codec>(
+        directory: &D,
+        infos: &SegmentInfos<D, C>,
+    ) -> Result<Revision> {
+        let segments_file_name = match infos.segment_file_name() {
+            Some(name) => name,
+            None => bail!(IllegalState("segment infos was never committed".into())),
+        };
+        let mut input = directory.open_input(&segments_file_name, &IOContext::READ)?;
+        let length = input.len() as usize;
+        let mut segments_bytes = vec![0u8; length];
+        input.read_bytes(&mut segments_bytes, 0, length)?;
+
+        Ok(Revision {
+            generation: infos.generation,
+            version: infos.version,
+            segments_file_name,
+            segments_bytes,
+            files: file_meta_data(directory, infos.files(false))?,
+        })
+    }
+
+    /// Total size in bytes of the files of this revision.
+    pub fn size_in_bytes(&self) -> i64 {
+        self.segments_bytes.len() as i64 + self.files.iter().map(|f| f.length).sum::<i64>()
+    }
+
+    /// Name of the file the revision is published in.
+    pub fn file_name(&self) -> String {
+        file_name_from_generation(REVISION_FILE_PREFIX, "", self.generation as u64)
+    }
+
+    /// Writes the revision to its file in `directory`, readers never see a
+    /// partially written file.
+    pub fn write<D: Directory>(&self, directory: &D) -> Result<()> {
+        write_file(directory, &self.file_name(), |output| {
+            write_header(output, REVISION_CODEC_NAME, REVISION_VERSION_CURRENT)?;
+            output.write_long(self.generation)?;
+            output.write_long(self.version)?;
+            output.write_string(&self.segments_file_name)?;
+            write_byte_array(output, &self.segments_bytes)?;
+            write_file_meta_data(output, &self.files)
+        })
+    }
+
+    /// Reads a revision written by `Revision::write`.
+    pub fn read<D: Directory>(directory: &D, name: &str) -> Result<Revision> {
+        let mut input = directory.open_checksum_input(name, &IOContext::READ)?;
+        check_header(
+            &mut input,
+            REVISION_CODEC_NAME,
+            REVISION_VERSION_START,
+            REVISION_VERSION_CURRENT,
+        )?;
+        let generation = input.read_long()?;
+        let version = input.read_long()?;
+        let segments_file_name = input.read_string()?;
+        let segments_bytes = read_byte_array(&mut input)?;
+        let files = read_file_meta_data(&mut input)?;
+        check_footer(&mut input)?;
+        Ok(Revision {
+            generation,
+            version,
+            segments_file_name,
+            segments_bytes,
+            files,
+        })
+    }
+}
+
+/// Returns the generation of the given revision file, or None if `name` is
+/// not a revision file.
+pub fn revision_file_generation(name: &str) -> Option<i64> {
+    file_generation(name, REVISION_FILE_PREFIX)
+}
+
+/// Returns the revision file with the highest generation among `files`.
+pub fn latest_revision_file(files: &[String]) -> Option<&String> {
+    files
+        .iter()
+        .filter_map(|name| revision_file_generation(name).map(|generation| (generation, name)))
+        .max()
+        .map(|(_, name)| name)
+}
+
+/// The in-memory `SegmentInfos` of a near real-time reader published by a
+/// `PrimaryNode`, including segments flushed but not committed yet.
+///
+/// Nothing of it is durable: a replica installing it only copies the segment
+/// files and opens a reader on the deserialized infos, no `segments_N` file
+/// is written.
+#[derive(Clone, Debug)]
+pub struct NrtRevision {
+    /// generation of the last commit, the infos are serialized with it
+    pub generation: i64,
+    /// version of the `SegmentInfos`, increasing with every change
+    pub version: i64,
+    /// the `SegmentInfos` serialized by `SegmentInfos::write_output`
+    pub infos_bytes: Vec<u8>,
+    /// all the files referenced by the infos, sorted by name
+    pub files: Vec<FileMetaData>,
+}
+
+impl NrtRevision {
+    /// Serializes `infos` and describes its files, which must not be deleted
+    /// meanwhile, eg because a reader opened on `infos` is still open.
+    pub fn from_infos<D: Directory, C: Codec>(
+        directory: &D,
+        infos: &SegmentInfos<D, C>,
+    ) -> Result<NrtRevision> {
+        let mut output = RAMOutputStream::new(true);
+        infos.write_output(&mut output)?;
+        let mut infos_bytes = Vec::with_capacity(output.file_pointer() as usize);
+        output.write_to(&mut infos_bytes)?;
+
+        Ok(NrtRevision {
+            generation: infos.generation,
+            version: infos.version,
+            infos_bytes,
+            files: file_meta_data(directory, infos.files(false))?,
+        })
+    }
+
+    /// Name of the file the revision is published in.
+    pub fn file_name(&self) -> String {
+        format!(
+            "{}_{}",
+            NRT_REVISION_FILE_PREFIX,
+            to_base36(self.version as u64)
+        )
+    }
+
+    /// Writes the revision to its file in `directory`, readers never see a
+    /// partially written file.
+    pub fn write<D: Directory>(&self, directory: &D) -> Result<()> {
+        write_file(directory, &self.file_name(), |output| {
+            write_header(
+                output,
+                NRT_REVISION_CODEC_NAME,
+                NRT_REVISION_VERSION_CURRENT,
+            )?;
+            output.write_long(self.generation)?;
+            output.write_long(self.version)?;
+            write_byte_array(output, &self.infos_bytes)?;
+            write_file_meta_data(output, &self.files)
+        })
+    }
+
+    /// Reads a revision written by `NrtRevision::write`.
+    pub fn read<D: Directory>(directory: &D, name: &str) -> Result<NrtRevision> {
+        let mut input = directory.open_checksum_input(name, &IOContext::READ)?;
+        check_header(
+            &mut input,
+            NRT_REVISION_CODEC_NAME,
+            NRT_REVISION_VERSION_START,
+            NRT_REVISION_VERSION_CURRENT,
+        )?;
+        let generation = input.read_long()?;
+        let version = input.read_long()?;
+        let infos_bytes = read_byte_array(&mut input)?;
+        let files = read_file_meta_data(&mut input)?;
+        check_footer(&mut input)?;
+        Ok(NrtRevision {
+            generation,
+            version,
+            infos_bytes,
+            files,
+        })
+    }
+}
+
+/// Returns the version of the given near real-time revision file, or None
+/// if `name` is not such a file.
+pub fn nrt_revision_file_version(name: &str) -> Option<i64> {
+    file_generation(name, NRT_REVISION_FILE_PREFIX)
+}
+
+/// Returns the near real-time revision file with the highest version among
+/// `files`.
+pub fn latest_nrt_revision_file(files: &[String]) -> Option<&String> {
+    files
+        .iter()
+        .filter_map(|name| nrt_revision_file_version(name).map(|version| (version, name)))
+        .max()
+        .map(|(_, name)| name)
+}
+
+// parses the generation of a `{prefix}_{generation in base 36}` file name
+fn file_generation(name: &str, prefix: &str) -> Option<i64> {
+    if name.len() > prefix.len() + 1
+        && name.starts_with(prefix)
+        && name.as_bytes()[prefix.len()] == b'_'
+    {
+        i64::from_str_radix(&name[prefix.len() + 1..], 36).ok()
+    } else {
+        None
+    }
+}
+
+// writes a pending file, renamed to `name` once durable
+fn write_file<D, F>(directory: &D, name: &str, write: F) -> Result<()>
+where
+    D: Directory,
+    F: FnOnce(&mut D::IndexOutput) -> Result<()>,
+{
+    let pending_file_name = format!("pending_{}", name);
+    let _ = directory.delete_file(&pending_file_name);
+    {
+        let mut output = directory.create_output(&pending_file_name, &IOContext::Default)?;
+        write(&mut output)?;
+        write_footer(&mut output)?;
+    }
+    let mut pending = HashSet::with_capacity(1);
+    pending.insert(pending_file_name.clone());
+    directory.sync(&pending)?;
+    directory.rename(&pending_file_name, name)?;
+    directory.sync_meta_data()
+}
+
+fn write_byte_array(output: &mut impl DataOutput, bytes: &[u8]) -> Result<()> {
+    output.write_vint(bytes.len() as i32)?;
+    output.write_bytes(bytes, 0, bytes.len())
+}
+
+fn read_byte_array(input: &mut impl DataInput) -> Result<Vec<u8>> {
+    let length = input.read_vint()? as usize;
+    let mut bytes = vec![0u8; length];
+    input.read_bytes(&mut bytes, 0, length)?;
+    Ok(bytes)
+}
+
+fn write_file_meta_data(output: &mut impl DataOutput, files: &[FileMetaData]) -> Result<()> {
+    output.write_vint(files.len() as i32)?;
+    for file in files {
+        output.write_string(&file.name)?;
+        output.write_long(file.length)?;
+        output.write_long(file.checksum)?;
+    }
+    Ok(())
+}
+
+fn read_file_meta_data(input: &mut impl DataInput) -> Result<Vec<FileMetaData>> {
+    let count = input.read_vint()? as usize;
+    let mut files = Vec::with_capacity(count);
+    for _ in 0..count {
+        files.push(FileMetaData {
+            name: input.read_string()?,
+            length: input.read_long()?,
+            checksum: input.read_long()?,
+        });
+    }
+    Ok(files)
+}
+
+fn file_meta_data<D: Directory>(
+    directory: &D,
+    names: HashSet<String>,
+) -> Result<Vec<FileMetaData>> {
+    let mut names: Vec<String> = names.into_iter().collect();
+    names.sort();
+    let mut files = Vec::with_capacity(names.len());
+    for name in names {
+        let mut input = directory.open_input(&name, &IOContext::READ)?;
+        let length = input.len() as i64;
+        let checksum = retrieve_checksum(input.as_mut())?;
+        files.push(FileMetaData {
+            name,
+            length,
+            checksum,
+        });
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::directory::RAMDirectory;
+
+    #[test]
+    fn test_write_and_read_revision() {
+        let directory = RAMDirectory::new();
+        let revision = Revision {
+            generation: 37,
+            version: 5,
+            segments_file_name: "segments_11".into(),
+            segments_bytes: vec![1, 2, 3],
+            files: vec![
+                FileMetaData {
+                    name: "_0.cfe".into(),
+                    length: 10,
+                    checksum: 0x1234,
+                },
+                FileMetaData {
+                    name: "_0.cfs".into(),
+                    length: 200,
+                    checksum: 0x5678,
+                },
+            ],
+        };
+        revision.write(&directory).unwrap();
+        assert_eq!(revision.file_name(), "revision_11");
+        assert_eq!(
+            directory.list_all().unwrap(),
+            vec!["revision_11".to_string()]
+        );
+
+        let read = Revision::read(&directory, "revision_11").unwrap();
+        assert_eq!(read.generation, revision.generation);
+        assert_eq!(read.version, revision.version);
+        assert_eq!(read.segments_file_name, revision.segments_file_name);
+        assert_eq!(read.segments_bytes, revision.segments_bytes);
+        assert_eq!(read.files, revision.files);
+
+        // flip a byte of the file
+        let mut bytes = {
+            let mut input = directory
+                .open_input("revision_11", &IOContext::READ)
+                .unwrap();
+            let length = input.len() as usize;
+            let mut bytes = vec![0u8; length];
+            input.read_bytes(&mut bytes, 0, length).unwrap();
+            bytes
+        };
+        bytes[20] ^= 1;
+        directory.delete_file("revision_11").unwrap();
+        {
+            let mut output = directory
+                .create_output("revision_11", &IOContext::Default)
+                .unwrap();
+            output.write_bytes(&bytes, 0, bytes.len()).unwrap();
+        }
+        assert!(Revision::read(&directory, "revision_11").is_err());
+    }
+
+    #[test]
+    fn test_latest_revision_file() {
+        let files: Vec<String> = vec![
+            "_0.cfs",
+            "nrt_revision_z",
+            "pending_revision_z",
+            "revision_2",
+            "revision_a",
+            "revision_b.tmp",
+            "segments_3",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(
+            latest_revision_file(&files),
+            Some(&"revision_a".to_string())
+        );
+        assert!(latest_revision_file(&files[..3]).is_none());
+        assert_eq!(revision_file_generation("revision_a"), Some(10));
+        assert_eq!(revision_file_generation("revision"), None);
+        assert_eq!(revision_file_generation("revisions_1"), None);
+        assert_eq!(
+            latest_nrt_revision_file(&files),
+            Some(&"nrt_revision_z".to_string())
+        );
+        assert_eq!(nrt_revision_file_version("nrt_revision_z"), Some(35));
+    }
+
+    #[test]
+    fn test_write_and_read_nrt_revision() {
+        let directory = RAMDirectory::new();
+        let revision = NrtRevision {
+            generation: 3,
+            version: 72,
+            infos_bytes: vec![4, 5, 6, 7],
+            files: vec![FileMetaData {
+                name: "_1.cfs".into(),
+                length: 42,
+                checksum: 0x9abc,
+            }],
+        };
+        revision.write(&directory).unwrap();
+        assert_eq!(revision.file_name(), "nrt_revision_20");
+
+        let read = NrtRevision::read(&directory, "nrt_revision_20").unwrap();
+        assert_eq!(read.generation, revision.generation);
+        assert_eq!(read.version, revision.version);
+        assert_eq!(read.infos_bytes, revision.infos_bytes);
+        assert_eq!(read.files, revision.files);
+        assert!(Revision::read(&directory, "nrt_revision_20").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::index::replication::{latest_nrt_revision_file, latest_revision_file};
+use core::index::replication::{NrtRevision, Revision};
+use core::store::directory::{Directory, FSDirectory};
+use error::Result;
+
+use std::path::Path;
+use std::sync::Arc;
+
+/// How a `ReplicaNode` talks to the `PrimaryNode`.
+///
+/// A transport hands out the latest published revision and a `Directory`
+/// the files of the revision can be read from. The replica copies the files
+/// with `Directory::copy_from`, so a remote transport would typically expose
+/// a directory fetching the files over the network.
+pub trait ReplicationTransport {
+    type Source: Directory;
+
+    /// Checks out the latest revision published by the primary, or None if
+    /// nothing was published yet. The revision must be passed to `release`
+    /// once the replica is done copying it.
+    fn checkout(&self) -> Result<Option<Arc<Revision>>>;
+
+    /// The directory the files of the checked out revisions are read from.
+    fn source(&self) -> Arc<Self::Source>;
+
+    /// Releases a revision returned by `checkout`.
+    fn release(&self, revision: &Revision) -> Result<()>;
+}
+
+/// A `ReplicationTransport` also shipping the near real-time revisions of
+/// the primary, whose files are read from the same `source` directory.
+pub trait NrtReplicationTransport: ReplicationTransport {
+    /// Checks out the latest near real-time revision published by the
+    /// primary, or None if nothing was published yet. The revision must be
+    /// passed to `release_nrt` once the replica is done copying it.
+    fn checkout_nrt(&self) -> Result<Option<Arc<NrtRevision>>>;
+
+    /// Releases a revision returned by `checkout_nrt`.
+    fn release_nrt(&self, revision: &NrtRevision) -> Result<()>;
+}
+
+/// A `NrtReplicationTransport` reading the revisions a `PrimaryNode` publishes
+/// in its index directory, for replicas able to read that directory directly:
+/// from a local or shared filesystem, see `DirectoryTransport::open`.
+///
+/// The replicas don't pin the revisions they copy. The primary releases a
+/// revision once a newer one is published, and its files may be deleted
+/// then: the copy fails the checksum verification and the update has to be
+/// retried.
+pub struct DirectoryTransport<D: Directory> {
+    directory: Arc<D>,
+}
+
+impl<D: Directory> DirectoryTransport<D> {
+    pub fn new(directory: Arc<D>) -> Self {
+        DirectoryTransport { directory }
+    }
+}
+
+impl DirectoryTransport<FSDirectory> {
+    /// Opens a transport on the index directory of the primary at `path`.
+    pub fn open<T: AsRef<Path> + ?Sized>(path: &T) -> Result<Self> {
+        Ok(Self::new(Arc::new(FSDirectory::with_path(path)?)))
+    }
+}
+
+impl<D: Directory> ReplicationTransport for DirectoryTransport<D> {
+    type Source = D;
+
+    fn checkout(&self) -> Result<Option<Arc<Revision>>> {
+        read_latest(
+            self.directory.as_ref(),
+            latest_revision_file,
+            Revision::read,
+        )
+    }
+
+    fn source(&self) -> Arc<D> {
+        Arc::clone(&self.directory)
+    }
+
+    fn release(&self, _revision: &Revision) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<D: Directory> NrtReplicationTransport for DirectoryTransport<D> {
+    fn checkout_nrt(&self) -> Result<Option<Arc<NrtRevision>>> {
+        read_latest(
+            self.directory.as_ref(),
+            latest_nrt_revision_file,
+            NrtRevision::read,
+        )
+    }
+
+    fn release_nrt(&self, _revision: &NrtRevision) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn read_latest<D, T>(
+    directory: &D,
+    latest: fn(&[String]) -> Option<&String>,
+    read: fn(&D, &str) -> Result<T>,
+) -> Result<Option<Arc<T>>>
+where
+    D: Directory,
+{
+    loop {
+        let name = match latest(&directory.list_all()?) {
+            Some(name) => name.clone(),
+            None => return Ok(None),
+        };
+        match read(directory, &name) {
+            Ok(revision) => return Ok(Some(Arc::new(revision))),
+            Err(e) => {
+                // the revision may have been released meanwhile, retry with
+                // the latest one in that case
+                if latest(&directory.list_all()?) == Some(&name) {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
@@ -15,12 +15,12 @@ use core::codec::segment_infos::{SegmentCommitInfo, SegmentInfos};
 use core::codec::PostingIteratorFlags;
 use core::codec::{Codec, CodecPostingIterator, CodecTermIterator};
 use core::codec::{Fields, SeekStatus, TermIterator, Terms};
-use core::doc::{DocValuesType, Term};
+use core::doc::Term;
 use core::index::merge::MergePolicy;
 use core::index::reader::{IndexReader, LeafReader};
 use core::index::writer::{
     DocValuesUpdate, FieldTermIter, FieldTermIterator, MergedDocValuesUpdatesIterator,
-    NumericDocValuesUpdate, PrefixCodedTerms, PrefixCodedTermsBuilder,
+    PrefixCodedTerms, PrefixCodedTermsBuilder,
 };
 use core::index::writer::{ReaderPool, ReadersAndUpdates};
 use core::search::cache::{NoCacheQueryCache, QueryCache};
@@ -138,18 +138,7 @@ impl<C: Codec> BufferedUpdates<C> {
         let mut upd = update.clone();
         if docid_up_to < NO_MORE_DOCS {
             // segment private update
-            upd = match update.dv_type() {
-                DocValuesType::Numeric | DocValuesType::SortedNumeric => {
-                    Arc::new(NumericDocValuesUpdate::new(
-                        update.term(),
-                        update.field(),
-                        update.dv_type(),
-                        update.numeric(),
-                        Some(docid_up_to),
-                    ))
-                }
-                _ => unimplemented!(),
-            };
+            upd = update.with_docid_up_to(docid_up_to);
         }
         if let Some(m) = self.doc_values_updates.get_mut(&update.field()) {
             m.insert(update.term().bytes, upd);
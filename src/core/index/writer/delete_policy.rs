@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use core::index::writer::index_file_deleter::CommitPoint;
+use error::ErrorKind::IllegalArgument;
 use error::Result;
 
 /// Expert: policy for deletion of stale `IndexCommit index commits`.
@@ -43,7 +44,7 @@ use error::Result;
 /// Implementers of sub-classes should make sure that `#clone()`
 /// returns an independent instance able to work with any other `IndexWriter`
 /// or `Directory` instance.
-pub trait IndexDeletionPolicy {
+pub trait IndexDeletionPolicy: Send + Sync {
     /// This is called once when a writer is first
     /// instantiated to give the policy a chance to remove old
     /// commit points.
@@ -88,15 +89,19 @@ pub trait IndexDeletionPolicy {
     fn on_commit(&self, commits: Vec<&mut CommitPoint>) -> Result<()>;
 }
 
+/// An `IndexDeletionPolicy` implementation that
+/// keeps only the most recent commit and immediately removes
+/// all prior commits after a new commit is done.  This is
+/// the default deletion policy.
 #[derive(Default)]
 pub struct KeepOnlyLastCommitDeletionPolicy;
 
-impl KeepOnlyLastCommitDeletionPolicy {
-    pub fn on_init(&self, commits: Vec<&mut CommitPoint>) -> Result<()> {
+impl IndexDeletionPolicy for KeepOnlyLastCommitDeletionPolicy {
+    fn on_init(&self, commits: Vec<&mut CommitPoint>) -> Result<()> {
         self.on_commit(commits)
     }
 
-    pub fn on_commit(&self, mut commits: Vec<&mut CommitPoint>) -> Result<()> {
+    fn on_commit(&self, mut commits: Vec<&mut CommitPoint>) -> Result<()> {
         commits.pop();
         for commit in commits {
             commit.delete()?;
@@ -104,3 +109,37 @@ impl KeepOnlyLastCommitDeletionPolicy {
         Ok(())
     }
 }
+
+/// An `IndexDeletionPolicy` keeping the `num_to_keep` most recent
+/// commits and removing all older ones, so that readers or backups
+/// working on a slightly stale commit don't lose their files.
+pub struct KeepLastNCommitsDeletionPolicy {
+    num_to_keep: usize,
+}
+
+impl KeepLastNCommitsDeletionPolicy {
+    pub fn new(num_to_keep: usize) -> Result<Self> {
+        if num_to_keep == 0 {
+            bail!(IllegalArgument("num_to_keep must be at least 1".into()));
+        }
+        Ok(KeepLastNCommitsDeletionPolicy { num_to_keep })
+    }
+
+    pub fn num_to_keep(&self) -> usize {
+        self.num_to_keep
+    }
+}
+
+impl IndexDeletionPolicy for KeepLastNCommitsDeletionPolicy {
+    fn on_init(&self, commits: Vec<&mut CommitPoint>) -> Result<()> {
+        self.on_commit(commits)
+    }
+
+    fn on_commit(&self, commits: Vec<&mut CommitPoint>) -> Result<()> {
+        let num_to_delete = commits.len().saturating_sub(self.num_to_keep);
+        for commit in commits.into_iter().take(num_to_delete) {
+            commit.delete()?;
+        }
+        Ok(())
+    }
+}
@@ -16,6 +16,7 @@ use core::codec::doc_values::*;
 use core::codec::field_infos::{
     FieldInfo, FieldInfosBuilder, FieldInfosFormat, FieldInvertState, FieldNumbersRef,
 };
+use core::codec::knn_vectors::{KnnVectorValuesWriter, KnnVectorsFormat};
 use core::codec::norms::NormValuesWriter;
 use core::codec::norms::NormsFormat;
 use core::codec::points::PointValuesWriter;
@@ -27,7 +28,7 @@ use core::codec::segment_infos::{SegmentReadState, SegmentWriteState};
 use core::codec::stored_fields::StoredFieldsConsumer;
 use core::codec::term_vectors::TermVectorsConsumer;
 use core::codec::Codec;
-use core::doc::{DocValuesType, FieldType, Fieldable, IndexOptions};
+use core::doc::{DocValuesType, FieldType, Fieldable, IndexOptions, Term};
 use core::index::merge::MergePolicy;
 use core::index::writer::{index_writer, DocState, DocumentsWriterPerThread};
 use core::store::directory::Directory;
@@ -168,6 +169,35 @@ where
         Ok(())
     }
 
+    /// Writes all buffered knn vectors.
+    fn write_knn_vectors<DW: Directory>(
+        &mut self,
+        state: &SegmentWriteState<D, DW, C>,
+        sort_map: Option<&PackedLongDocMap>,
+    ) -> Result<()> {
+        let mut vectors_writer = None;
+        for per_field in &mut self.field_hash {
+            if let Some(ref mut values_writer) = per_field.knn_vector_values_writer {
+                if vectors_writer.is_none() {
+                    // lazy init
+                    vectors_writer = Some(
+                        state
+                            .segment_info
+                            .codec()
+                            .knn_vectors_format()
+                            .fields_writer(state)?,
+                    );
+                }
+                values_writer.flush(state, sort_map, vectors_writer.as_mut().unwrap())?;
+            }
+            per_field.knn_vector_values_writer = None;
+        }
+        if let Some(ref mut writer) = vectors_writer {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+
     /// Writes all buffered doc values (called from {@link #flush}).
     fn write_doc_values<DW: Directory>(
         &mut self,
@@ -316,6 +346,13 @@ where
             self.index_point(per_field.unwrap(), field, doc_state)?;
         }
 
+        if field.field_type().vector_dimension > 0 {
+            if per_field.is_none() {
+                per_field = Some(self.get_or_add_field(field.name(), field.field_type(), false)?);
+            }
+            self.index_knn_vector(per_field.unwrap(), field, doc_state)?;
+        }
+
         Ok(field_count)
     }
 
@@ -549,6 +586,38 @@ where
             )
     }
 
+    /// Called from process_document to index one field's knn vector
+    fn index_knn_vector(
+        &mut self,
+        field_idx: usize,
+        field: &impl Fieldable,
+        doc_state: &DocState,
+    ) -> Result<()> {
+        let per_field = &mut self.field_hash[field_idx];
+        // this setter will fail if the dimension or similarity was already set
+        // to something different:
+        per_field.field_info().set_knn_vector_attributes(
+            field.field_type().vector_dimension,
+            field.field_type().vector_similarity,
+        )?;
+
+        if per_field.knn_vector_values_writer.is_none() {
+            per_field.knn_vector_values_writer =
+                Some(KnnVectorValuesWriter::new(per_field.field_info())?);
+        }
+        match field.binary_value() {
+            Some(value) => per_field
+                .knn_vector_values_writer
+                .as_mut()
+                .unwrap()
+                .add_value(doc_state.doc_id, value),
+            None => bail!(IllegalArgument(format!(
+                "field '{}' has no knn vector value",
+                field.name()
+            ))),
+        }
+    }
+
     fn get_per_field_index(&mut self, name: &str) -> Option<usize> {
         for (idx, pf) in self.field_hash.iter().enumerate() {
             if pf.field_info().name.as_str() == name {
@@ -673,6 +742,7 @@ where
 
         self.write_doc_values(state, sort_map.as_ref().map(|m| m.as_ref()))?;
         self.write_points(state, sort_map.as_ref().map(|m| m.as_ref()))?;
+        self.write_knn_vectors(state, sort_map.as_ref().map(|m| m.as_ref()))?;
 
         // it's possible all docs hit non-aborting exceptions...
         self.stored_fields_consumer.finish(max_doc)?;
@@ -734,6 +804,18 @@ where
         res
     }
 
+    /// Returns true if a buffered document was indexed with the term.
+    pub fn contains_term(&self, term: &Term) -> bool {
+        let bytes = BytesRef::new(&term.bytes);
+        self.field_hash.iter().any(|pf| {
+            pf.field_info().name == term.field
+                && pf
+                    .term_hash_per_field
+                    .as_ref()
+                    .map_or(false, |tp| tp.base().contains_term(&bytes))
+        })
+    }
+
     pub fn need_flush(&self) -> bool {
         self.terms_hash.need_flush()
     }
@@ -749,6 +831,8 @@ pub struct PerField<T: TermsHashPerField> {
     doc_values_writer: Option<DocValuesWriterEnum>,
     // Non-null if this field ever had points in this segment:
     point_values_writer: Option<PointValuesWriter>,
+    // Non-null if this field ever had knn vectors in this segment:
+    knn_vector_values_writer: Option<KnnVectorValuesWriter>,
     /// We use this to know when a PerField is seen for the
     /// first time in the current document
     field_gen: i64,
@@ -771,6 +855,7 @@ impl<T: TermsHashPerField> PerField<T> {
             term_hash_per_field,
             doc_values_writer: None,
             point_values_writer: None,
+            knn_vector_values_writer: None,
             field_gen: -1,
             norms: None,
             invert,
@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::codec::doc_values::{BinaryDocValues, SortedNumericDocValues};
 use core::codec::{Codec, PackedLongDocMap, PostingIteratorFlags};
 use core::codec::{Fields, SorterDocMap, TermIterator, Terms};
 use core::doc::{DocValuesType, Term};
@@ -18,6 +19,7 @@ use core::index::reader::{LeafReader, SegmentReader};
 use core::search::DocIterator;
 use core::search::NO_MORE_DOCS;
 use core::store::directory::Directory;
+use error::ErrorKind::IllegalState;
 use error::Result;
 use std::cmp::Ordering;
 use std::collections::binary_heap::BinaryHeap;
@@ -55,14 +57,42 @@ impl NumericDocValuesUpdate {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct BinaryDocValuesUpdate {
+    term: Term,
+    field: String,
+    value: Vec<u8>,
+    docid_up_to: i32,
+}
+
+impl BinaryDocValuesUpdate {
+    pub fn new(term: Term, field: String, value: Vec<u8>, docid_up_to: Option<i32>) -> Self {
+        let docid_up_to = if let Some(v) = docid_up_to {
+            v
+        } else {
+            NO_MORE_DOCS
+        };
+        BinaryDocValuesUpdate {
+            term,
+            field,
+            value,
+            docid_up_to,
+        }
+    }
+}
+
 pub trait DocValuesUpdate {
     fn term(&self) -> Term;
     fn field(&self) -> String;
     fn dv_type(&self) -> DocValuesType;
-    fn numeric(&self) -> i64;
-    fn binary(&self) -> String;
+    /// The new value of a numeric update, an error for other updates.
+    fn numeric(&self) -> Result<i64>;
+    /// The new value of a binary update, an error for other updates.
+    fn binary(&self) -> Result<Vec<u8>>;
     fn docid_up_to(&self) -> i32;
     fn set_docid_up_to(&mut self, docid: i32);
+    /// Returns a copy of this update only applying to the docs before `docid`.
+    fn with_docid_up_to(&self, docid: i32) -> Arc<dyn DocValuesUpdate>;
 }
 
 impl DocValuesUpdate for NumericDocValuesUpdate {
@@ -78,12 +108,15 @@ impl DocValuesUpdate for NumericDocValuesUpdate {
         self.dv_type.clone()
     }
 
-    fn numeric(&self) -> i64 {
-        self.value
+    fn numeric(&self) -> Result<i64> {
+        Ok(self.value)
     }
 
-    fn binary(&self) -> String {
-        unimplemented!()
+    fn binary(&self) -> Result<Vec<u8>> {
+        bail!(IllegalState(format!(
+            "update of field [{}] is numeric, not binary",
+            self.field
+        )))
     }
 
     fn docid_up_to(&self) -> i32 {
@@ -93,6 +126,51 @@ impl DocValuesUpdate for NumericDocValuesUpdate {
     fn set_docid_up_to(&mut self, docid: i32) {
         self.docid_up_to = docid;
     }
+
+    fn with_docid_up_to(&self, docid: i32) -> Arc<dyn DocValuesUpdate> {
+        let mut update = self.clone();
+        update.docid_up_to = docid;
+        Arc::new(update)
+    }
+}
+
+impl DocValuesUpdate for BinaryDocValuesUpdate {
+    fn term(&self) -> Term {
+        self.term.clone()
+    }
+
+    fn field(&self) -> String {
+        self.field.clone()
+    }
+
+    fn dv_type(&self) -> DocValuesType {
+        DocValuesType::Binary
+    }
+
+    fn numeric(&self) -> Result<i64> {
+        bail!(IllegalState(format!(
+            "update of field [{}] is binary, not numeric",
+            self.field
+        )))
+    }
+
+    fn binary(&self) -> Result<Vec<u8>> {
+        Ok(self.value.clone())
+    }
+
+    fn docid_up_to(&self) -> i32 {
+        self.docid_up_to
+    }
+
+    fn set_docid_up_to(&mut self, docid: i32) {
+        self.docid_up_to = docid;
+    }
+
+    fn with_docid_up_to(&self, docid: i32) -> Arc<dyn DocValuesUpdate> {
+        let mut update = self.clone();
+        update.docid_up_to = docid;
+        Arc::new(update)
+    }
 }
 
 pub struct MergedDocValuesUpdatesIterator {
@@ -204,39 +282,72 @@ impl MergedDocValuesUpdatesIterator {
         }
     }
 
+    pub fn from_binary_updates(field: String, updates: Vec<(i32, Vec<u8>)>) -> Self {
+        Self {
+            dv_update: None,
+            del_gen: 0,
+            min_del_gen: 0,
+            max_del_gen: 0,
+            subs: Vec::new(),
+            heap: BinaryHeap::new(),
+            merged_updates: Some(Arc::new(DVUpdates::Binary(updates))),
+            field,
+            index: 0,
+        }
+    }
+
     pub fn is_merged_updates(&self) -> bool {
         self.merged_updates.is_some()
     }
 
-    pub fn next_dv_update(&mut self) -> (i32, i64) {
+    pub fn next_dv_update(&mut self) -> Result<(i32, i64)> {
+        let update = self.current_dv_update()?;
+        if update.0 != NO_MORE_DOCS {
+            self.index += 1;
+        }
+        Ok(update)
+    }
+
+    pub fn current_dv_update(&self) -> Result<(i32, i64)> {
         if let Some(updates) = &self.merged_updates {
             match updates.as_ref() {
                 DVUpdates::Numeric(upds) => {
                     if self.index < upds.len() {
-                        let update = upds[self.index];
-                        self.index += 1;
-                        return update;
+                        return Ok(upds[self.index]);
                     }
                 }
-                _ => unimplemented!(),
+                _ => bail!(IllegalState(format!(
+                    "updates of field [{}] are not numeric",
+                    self.field
+                ))),
             }
         }
-        (NO_MORE_DOCS, 0)
+        Ok((NO_MORE_DOCS, 0))
     }
 
-    pub fn current_dv_update(&self) -> (i32, i64) {
+    pub fn next_binary_dv_update(&mut self) -> Result<(i32, Vec<u8>)> {
+        let update = self.current_binary_dv_update()?;
+        if update.0 != NO_MORE_DOCS {
+            self.index += 1;
+        }
+        Ok(update)
+    }
+
+    pub fn current_binary_dv_update(&self) -> Result<(i32, Vec<u8>)> {
         if let Some(updates) = &self.merged_updates {
             match updates.as_ref() {
-                DVUpdates::Numeric(upds) => {
+                DVUpdates::Binary(upds) => {
                     if self.index < upds.len() {
-                        let update = upds[self.index];
-                        return update;
+                        return Ok(upds[self.index].clone());
                     }
                 }
-                _ => unimplemented!(),
+                _ => bail!(IllegalState(format!(
+                    "updates of field [{}] are not binary",
+                    self.field
+                ))),
             }
         }
-        (NO_MORE_DOCS, 0)
+        Ok((NO_MORE_DOCS, Vec::new()))
     }
 }
 
@@ -329,7 +440,7 @@ impl Eq for DocValuesUpdatesWrapper {}
 
 enum DVUpdates {
     Numeric(Vec<(i32, i64)>),
-    _Binary(Vec<(i32, String)>),
+    Binary(Vec<(i32, Vec<u8>)>),
     Iterator(MergedDocValuesUpdatesIterator),
 }
 
@@ -375,39 +486,60 @@ where
         })
     }
 
-    pub fn next_numeric(&mut self) -> (i32, i64) {
+    pub fn next_numeric(&mut self) -> Result<(i32, i64)> {
         match &mut self.updates {
             DVUpdates::Numeric(ndvs) => {
                 self.index += 1;
                 if self.index < ndvs.len() as i32 {
-                    return ndvs[self.index as usize];
+                    return Ok(ndvs[self.index as usize]);
                 }
-                (NO_MORE_DOCS, 0)
+                Ok((NO_MORE_DOCS, 0))
             }
-            DVUpdates::_Binary(_bdvs) => unimplemented!(),
+            DVUpdates::Binary(_) => bail!(IllegalState(
+                "binary doc values updates have no numeric values".into()
+            )),
             DVUpdates::Iterator(iterator) => {
                 self.doc_id += 1;
                 if self.doc_id >= LeafReader::max_doc(self.reader.as_ref()) {
-                    return (NO_MORE_DOCS, 0);
+                    return Ok((NO_MORE_DOCS, 0));
                 }
-                let (doc_id, value) = iterator.current_dv_update();
+                let (doc_id, value) = iterator.current_dv_update()?;
                 if self.doc_id == doc_id {
-                    iterator.next_dv_update();
-                    return (doc_id, value);
+                    iterator.next_dv_update()?;
+                    return Ok((doc_id, value));
                 }
-                let mut dvs = self
-                    .reader
-                    .get_sorted_numeric_doc_values(&iterator.field)
-                    .unwrap();
-                dvs.set_document(self.doc_id).unwrap_or(());
-                let value = dvs.value_at(0).unwrap_or(0);
-                (self.doc_id, value)
+                let mut dvs = Self::old_doc_values(&self.reader, &iterator.field)?;
+                let value = Self::old_value(&mut dvs, self.doc_id)?;
+                Ok((self.doc_id, value))
             }
         }
     }
 
-    pub fn next_binary(&mut self) -> (i32, String) {
-        unimplemented!()
+    pub fn next_binary(&mut self) -> Result<(i32, Vec<u8>)> {
+        match &mut self.updates {
+            DVUpdates::Binary(bdvs) => {
+                self.index += 1;
+                if self.index < bdvs.len() as i32 {
+                    return Ok(bdvs[self.index as usize].clone());
+                }
+                Ok((NO_MORE_DOCS, Vec::new()))
+            }
+            DVUpdates::Numeric(_) => bail!(IllegalState(
+                "numeric doc values updates have no binary values".into()
+            )),
+            DVUpdates::Iterator(iterator) => {
+                self.doc_id += 1;
+                if self.doc_id >= LeafReader::max_doc(self.reader.as_ref()) {
+                    return Ok((NO_MORE_DOCS, Vec::new()));
+                }
+                if iterator.current_binary_dv_update()?.0 == self.doc_id {
+                    return iterator.next_binary_dv_update();
+                }
+                let mut dvs = Self::old_binary_doc_values(&self.reader, &iterator.field)?;
+                let value = Self::old_binary_value(&mut dvs, self.doc_id)?;
+                Ok((self.doc_id, value))
+            }
+        }
     }
 
     fn prepare_data(
@@ -418,118 +550,188 @@ where
     ) -> Result<DVUpdates> {
         let mut up = iterator.next();
         let dv_type = up.as_ref().unwrap().dv_type();
-        match dv_type {
-            DocValuesType::Numeric | DocValuesType::SortedNumeric => {
-                let mut new_ndv = vec![];
-                let mut updates = vec![];
-                let mut field: Option<String> = None;
-                let mut field_terms = None;
-                loop {
-                    let term = up.as_ref().unwrap().term();
-                    if field_terms.is_none() {
-                        field_terms = reader.fields()?.terms(&term.field)?;
-                    }
-                    if field.is_none() {
-                        field = Some(up.as_ref().unwrap().field());
-                    }
+        let mut updates: Vec<(i32, Arc<dyn DocValuesUpdate>, u64)> = vec![];
+        let mut field: Option<String> = None;
+        let mut field_terms = None;
+        while let Some(update) = up {
+            let term = update.term();
+            if field_terms.is_none() {
+                field_terms = reader.fields()?.terms(&term.field)?;
+            }
+            if field.is_none() {
+                field = Some(update.field());
+            }
 
-                    if let Some(terms) = &field_terms {
-                        let mut it = terms.iterator()?;
-                        if let Ok(found) = it.seek_exact(&term.bytes) {
-                            if found {
-                                let mut doc_ids =
-                                    it.postings_with_flags(PostingIteratorFlags::NONE)?;
-                                loop {
-                                    let doc_id = doc_ids.next()?;
-                                    if doc_id == NO_MORE_DOCS {
-                                        break;
-                                    }
-                                    let mut old_id = doc_id;
-                                    if sort_map.is_some() {
-                                        // had been sorted when flush
-                                        old_id = sort_map.as_ref().unwrap().new_to_old(doc_id);
-                                    }
-                                    if old_id < up.as_ref().unwrap().docid_up_to() {
-                                        updates.push((
-                                            doc_id,
-                                            up.as_ref().unwrap().numeric(),
-                                            iterator.del_gen,
-                                        ));
-                                    }
-                                }
+            if let Some(terms) = &field_terms {
+                let mut it = terms.iterator()?;
+                if let Ok(found) = it.seek_exact(&term.bytes) {
+                    if found {
+                        let mut doc_ids = it.postings_with_flags(PostingIteratorFlags::NONE)?;
+                        loop {
+                            let doc_id = doc_ids.next()?;
+                            if doc_id == NO_MORE_DOCS {
+                                break;
                             }
-                        }
-                    }
-
-                    up = iterator.next();
-                    if up.is_none() {
-                        // sort doc_id & del_gen
-                        updates.sort_by(|a, b| {
-                            let res = a.0.cmp(&b.0);
-                            if res == Ordering::Equal {
-                                return b.2.cmp(&a.2);
+                            let mut old_id = doc_id;
+                            if sort_map.is_some() {
+                                // had been sorted when flush
+                                old_id = sort_map.as_ref().unwrap().new_to_old(doc_id);
+                            }
+                            if old_id < update.docid_up_to() {
+                                updates.push((doc_id, update.clone(), iterator.del_gen));
                             }
-                            res
-                        });
-                        // unique by doc_id
-                        updates.dedup_by(|a, b| a.0.eq(&b.0));
-                        if !include_old {
-                            return Ok(DVUpdates::Numeric(
-                                updates.iter().map(|(x, y, _)| (*x, *y)).collect(),
-                            ));
                         }
-                        break;
                     }
                 }
-                if !updates.is_empty() {
-                    // merge old & new doc values
-                    if let Some(field) = field {
-                        let mut old_ndv = reader.get_sorted_numeric_doc_values(&field)?;
-                        let mut i = 0;
-                        let mut it = updates.iter();
-                        loop {
-                            if let Some((doc_id, value, _)) = it.next() {
-                                let doc_id = *doc_id;
-                                let value = *value;
-                                if i < doc_id {
-                                    // old values
-                                    for id in i..doc_id {
-                                        // let old_value = old_ndv.get(id).unwrap();
-                                        old_ndv.set_document(id)?;
-                                        let old_value = old_ndv.value_at(0).unwrap_or(0);
-                                        new_ndv.push((id, old_value));
-                                        i += 1;
-                                    }
-                                }
-                                // new value
-                                new_ndv.push((doc_id, value));
-                                i += 1;
-                            } else if i > 0 {
-                                // old values
-                                while i < reader.max_docs() {
-                                    old_ndv.set_document(i)?;
-                                    let old_value = old_ndv.value_at(0).unwrap_or(0);
-                                    // let old_value = old_ndv.get(i).unwrap();
-                                    new_ndv.push((i, old_value));
-                                    i += 1;
-                                }
-                                break;
-                            } else {
-                                break;
-                            }
-                        }
+            }
+
+            up = iterator.next();
+        }
+        // sort doc_id & del_gen
+        updates.sort_by(|a, b| {
+            let res = a.0.cmp(&b.0);
+            if res == Ordering::Equal {
+                return b.2.cmp(&a.2);
+            }
+            res
+        });
+        // unique by doc_id
+        updates.dedup_by(|a, b| a.0.eq(&b.0));
+
+        match dv_type {
+            DocValuesType::Numeric | DocValuesType::SortedNumeric => {
+                let updates = updates
+                    .iter()
+                    .map(|(x, y, _)| Ok((*x, y.numeric()?)))
+                    .collect::<Result<Vec<(i32, i64)>>>()?;
+                if !include_old || updates.is_empty() {
+                    return Ok(DVUpdates::Numeric(updates));
+                }
+                // merge old & new doc values
+                let mut old_ndv = Self::old_doc_values(&reader, field.as_ref().unwrap())?;
+                let mut new_ndv = Vec::with_capacity(reader.max_docs() as usize);
+                let mut i = 0;
+                for (doc_id, value) in updates {
+                    // old values
+                    while i < doc_id {
+                        new_ndv.push((i, Self::old_value(&mut old_ndv, i)?));
+                        i += 1;
                     }
+                    // new value
+                    new_ndv.push((doc_id, value));
+                    i += 1;
+                }
+                // old values
+                while i < reader.max_docs() {
+                    new_ndv.push((i, Self::old_value(&mut old_ndv, i)?));
+                    i += 1;
                 }
                 Ok(DVUpdates::Numeric(new_ndv))
             }
-            _ => unimplemented!(),
+            DocValuesType::Binary => {
+                let updates = updates
+                    .iter()
+                    .map(|(x, y, _)| Ok((*x, y.binary()?)))
+                    .collect::<Result<Vec<(i32, Vec<u8>)>>>()?;
+                if !include_old || updates.is_empty() {
+                    return Ok(DVUpdates::Binary(updates));
+                }
+                // merge old & new doc values
+                let mut old_bdv = Self::old_binary_doc_values(&reader, field.as_ref().unwrap())?;
+                let mut new_bdv = Vec::with_capacity(reader.max_docs() as usize);
+                let mut i = 0;
+                for (doc_id, value) in updates {
+                    // old values
+                    while i < doc_id {
+                        new_bdv.push((i, Self::old_binary_value(&mut old_bdv, i)?));
+                        i += 1;
+                    }
+                    // new value
+                    new_bdv.push((doc_id, value));
+                    i += 1;
+                }
+                // old values
+                while i < reader.max_docs() {
+                    new_bdv.push((i, Self::old_binary_value(&mut old_bdv, i)?));
+                    i += 1;
+                }
+                Ok(DVUpdates::Binary(new_bdv))
+            }
+            _ => bail!(IllegalState(format!(
+                "doc values of type {:?} can't be updated",
+                dv_type
+            ))),
         }
     }
 
-    pub fn get_numeric_updates(&mut self) -> Vec<(i32, i64)> {
+    // The current values of `field`, or `None` if the segment has no values
+    // for it yet (e.g. the first soft delete of a segment).
+    fn old_doc_values(
+        reader: &SegmentReader<D, C>,
+        field: &str,
+    ) -> Result<Option<Box<dyn SortedNumericDocValues>>> {
+        match reader.field_info(field) {
+            Some(fi) if fi.doc_values_type != DocValuesType::Null => {
+                Ok(Some(reader.get_sorted_numeric_doc_values(field)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn old_value(
+        old_ndv: &mut Option<Box<dyn SortedNumericDocValues>>,
+        doc_id: i32,
+    ) -> Result<i64> {
+        match old_ndv {
+            Some(dvs) => {
+                dvs.set_document(doc_id)?;
+                Ok(dvs.value_at(0).unwrap_or(0))
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn old_binary_doc_values(
+        reader: &SegmentReader<D, C>,
+        field: &str,
+    ) -> Result<Option<Box<dyn BinaryDocValues>>> {
+        match reader.field_info(field) {
+            Some(fi) if fi.doc_values_type != DocValuesType::Null => {
+                Ok(Some(reader.get_binary_doc_values(field)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // an empty value means the document has no value for the field
+    fn old_binary_value(
+        old_bdv: &mut Option<Box<dyn BinaryDocValues>>,
+        doc_id: i32,
+    ) -> Result<Vec<u8>> {
+        match old_bdv {
+            Some(dvs) => dvs.get(doc_id),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn get_numeric_updates(&mut self) -> Result<Vec<(i32, i64)>> {
         match &self.updates {
-            DVUpdates::Numeric(updates) => updates.to_vec(),
-            _ => unimplemented!(),
+            DVUpdates::Numeric(updates) => Ok(updates.to_vec()),
+            _ => bail!(IllegalState("doc values updates are not numeric".into())),
+        }
+    }
+
+    pub fn get_binary_updates(&mut self) -> Result<Vec<(i32, Vec<u8>)>> {
+        match &self.updates {
+            DVUpdates::Binary(updates) => Ok(updates.to_vec()),
+            _ => bail!(IllegalState("doc values updates are not binary".into())),
+        }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        match &self.updates {
+            DVUpdates::Binary(_) => true,
+            _ => false,
         }
     }
 }
@@ -537,17 +739,41 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use core::doc::Term;
+    use core::codec::CodecEnum;
+    use core::doc::{
+        BinaryDocValuesField, Field, FieldType, Fieldable, IndexOptions, NumericDocValuesField,
+        Term,
+    };
+    use core::index::merge::{SerialMergeScheduler, TieredMergePolicy};
+    use core::index::reader::IndexReader;
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::store::directory::RAMDirectory;
+    use core::util::VariantValue;
     use std::sync::Arc;
 
     #[test]
     fn merged_dvu_iterator_from_updates() {
         let updates = vec![(1, 100), (4, 200), (9, 1)];
         let mut mit = MergedDocValuesUpdatesIterator::from_updates("".into(), updates);
-        assert_eq!(mit.next_dv_update(), (1, 100));
-        assert_ne!(mit.next_dv_update(), (4, 201));
-        assert_eq!(mit.next_dv_update(), (9, 1));
-        assert_eq!(mit.next_dv_update(), (NO_MORE_DOCS, 0));
+        assert_eq!(mit.next_dv_update().unwrap(), (1, 100));
+        assert_ne!(mit.next_dv_update().unwrap(), (4, 201));
+        assert_eq!(mit.next_dv_update().unwrap(), (9, 1));
+        assert_eq!(mit.next_dv_update().unwrap(), (NO_MORE_DOCS, 0));
+        assert!(mit.next_binary_dv_update().is_err());
+    }
+
+    #[test]
+    fn merged_dvu_iterator_from_binary_updates() {
+        let updates = vec![(2, b"a".to_vec()), (5, b"bc".to_vec())];
+        let mut mit = MergedDocValuesUpdatesIterator::from_binary_updates("".into(), updates);
+        assert_eq!(mit.current_binary_dv_update().unwrap(), (2, b"a".to_vec()));
+        assert_eq!(mit.next_binary_dv_update().unwrap(), (2, b"a".to_vec()));
+        assert_eq!(mit.next_binary_dv_update().unwrap(), (5, b"bc".to_vec()));
+        assert_eq!(
+            mit.next_binary_dv_update().unwrap(),
+            (NO_MORE_DOCS, Vec::new())
+        );
+        assert!(mit.current_dv_update().is_err());
     }
 
     #[test]
@@ -583,6 +809,125 @@ mod tests {
         run_data(it);
     }
 
+    fn new_writer() -> IndexWriter<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>
+    {
+        let config = Arc::new(IndexWriterConfig::default());
+        IndexWriter::new(Arc::new(RAMDirectory::new()), config).unwrap()
+    }
+
+    fn new_doc(id: &str, payload: &[u8], weight: i64) -> Vec<Box<dyn Fieldable>> {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        vec![
+            Box::new(Field::new(
+                "id".into(),
+                field_type,
+                Some(VariantValue::VString(id.into())),
+                None,
+            )),
+            Box::new(BinaryDocValuesField::new("payload", payload)),
+            Box::new(NumericDocValuesField::new("weight", weight)),
+        ]
+    }
+
+    fn id_term(id: &str) -> Term {
+        Term::new("id".into(), id.as_bytes().to_vec())
+    }
+
+    // the (payload, weight) of all the docs, in doc id order
+    fn doc_values<R: IndexReader>(reader: &R) -> Vec<(Vec<u8>, i64)> {
+        let mut values = vec![];
+        for leaf in reader.leaves() {
+            let mut payloads = leaf.reader.get_binary_doc_values("payload").unwrap();
+            let weights = leaf.reader.get_numeric_doc_values("weight").unwrap();
+            for doc in 0..leaf.reader.max_doc() {
+                values.push((payloads.get(doc).unwrap(), weights.get(doc).unwrap()));
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_binary_update_nrt_reopen() {
+        let writer = new_writer();
+        writer.add_document(new_doc("1", b"a", 1)).unwrap();
+        writer.add_document(new_doc("2", b"b", 2)).unwrap();
+        writer.commit().unwrap();
+        let reader = writer.get_reader(true, false).unwrap();
+
+        writer
+            .update_binary_doc_value(id_term("1"), "payload", b"z".to_vec())
+            .unwrap();
+        let reopened = writer.get_reader(true, false).unwrap();
+        assert_eq!(
+            doc_values(&reopened),
+            vec![(b"z".to_vec(), 1), (b"b".to_vec(), 2)]
+        );
+        // the previous reader still sees the old values
+        assert_eq!(
+            doc_values(&reader),
+            vec![(b"a".to_vec(), 1), (b"b".to_vec(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_atomic_multi_field_update() {
+        let writer = new_writer();
+        writer.add_document(new_doc("1", b"a", 1)).unwrap();
+        writer.add_document(new_doc("2", b"b", 2)).unwrap();
+        writer.commit().unwrap();
+
+        let updates: Vec<Box<dyn Fieldable>> = vec![
+            Box::new(BinaryDocValuesField::new("payload", b"y")),
+            Box::new(NumericDocValuesField::new("weight", 20)),
+        ];
+        let seq_no = writer.update_doc_values(id_term("2"), updates).unwrap();
+        // both fields are updated by a single operation
+        let next_seq_no = writer.add_document(new_doc("3", b"c", 3)).unwrap();
+        assert_eq!(next_seq_no, seq_no + 1);
+        writer.commit().unwrap();
+
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(
+            doc_values(&reader),
+            vec![(b"a".to_vec(), 1), (b"y".to_vec(), 20), (b"c".to_vec(), 3)]
+        );
+        // and written to a single doc values generation
+        let info = &reader.segment_infos().segments[0];
+        assert_eq!(info.doc_values_gen(), 1);
+        let leaves = reader.leaves();
+        let leaf = &leaves[0];
+        assert_eq!(leaf.reader.field_info("payload").unwrap().dv_gen, 1);
+        assert_eq!(leaf.reader.field_info("weight").unwrap().dv_gen, 1);
+    }
+
+    #[test]
+    fn test_updates_survive_merge() {
+        let writer = new_writer();
+        writer.add_document(new_doc("1", b"a", 1)).unwrap();
+        writer.add_document(new_doc("2", b"b", 2)).unwrap();
+        writer.commit().unwrap();
+        writer.add_document(new_doc("3", b"c", 3)).unwrap();
+        writer.commit().unwrap();
+
+        writer
+            .update_numeric_doc_value(id_term("1"), "weight", 10)
+            .unwrap();
+        writer
+            .update_binary_doc_value(id_term("3"), "payload", b"x".to_vec())
+            .unwrap();
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.leaves().len(), 1);
+        assert_eq!(
+            doc_values(&reader),
+            vec![(b"a".to_vec(), 10), (b"b".to_vec(), 2), (b"x".to_vec(), 3)]
+        );
+    }
+
     type V = Vec<Arc<dyn DocValuesUpdate>>;
     fn prepare_data() -> (V, V, V) {
         let id_field: String = "id".into();
@@ -631,7 +976,7 @@ mod tests {
                 "field: {}, id: {}, weight: {}",
                 update.field(),
                 std::str::from_utf8(&update.term().bytes).unwrap(),
-                update.numeric()
+                update.numeric().unwrap()
             );
             assert_eq!(update.field().as_str(), "weight");
             assert_eq!(
@@ -639,11 +984,11 @@ mod tests {
                 std::str::from_utf8(&update.term().bytes).unwrap()
             );
             if i == 2 || i == 7 {
-                assert_eq!(update.numeric(), i * 10);
+                assert_eq!(update.numeric().unwrap(), i * 10);
             } else if i % 3 == 0 || i == 5 {
-                assert_eq!(update.numeric(), i * 111);
+                assert_eq!(update.numeric().unwrap(), i * 111);
             } else {
-                assert_eq!(update.numeric(), i * 100);
+                assert_eq!(update.numeric().unwrap(), i * 100);
             }
             i += 1;
         }
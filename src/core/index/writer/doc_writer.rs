@@ -16,7 +16,7 @@ use core::codec::Codec;
 use core::doc::Fieldable;
 use core::doc::Term;
 use core::index::writer::{
-    DocValuesUpdate, DocumentsWriterDeleteQueue, DocumentsWriterFlushControl,
+    DocValuesUpdate, DocumentsDelete, DocumentsWriterDeleteQueue, DocumentsWriterFlushControl,
     DocumentsWriterFlushQueue, DocumentsWriterPerThread, DocumentsWriterPerThreadPool,
     FlushByCountsPolicy, IndexWriter, IndexWriterConfig, IndexWriterInner, ThreadState,
 };
@@ -198,7 +198,7 @@ where
     pub fn update_documents<F: Fieldable>(
         &self,
         docs: Vec<Vec<F>>,
-        del_term: Option<Term>,
+        del_term: Option<DocumentsDelete>,
     ) -> Result<(u64, bool)> {
         let mut has_event = self.pre_update()?;
 
@@ -229,7 +229,7 @@ where
         per_thread: &mut ThreadState<D, C, MS, MP>,
         docs: Vec<Vec<F>>,
         // analyzer: Analyzer,
-        del_term: Option<Term>,
+        del_term: Option<DocumentsDelete>,
     ) -> Result<u64> {
         let is_update = del_term.is_some();
 
@@ -274,10 +274,19 @@ where
     }
 
     pub fn update_doc_values(&self, update: Arc<dyn DocValuesUpdate>) -> Result<(u64, bool)> {
+        self.update_doc_values_atomically(vec![update])
+    }
+
+    /// All the given updates are buffered as one delete queue node, they get
+    /// the same sequence number and are applied in the same delete generation.
+    pub fn update_doc_values_atomically(
+        &self,
+        updates: Vec<Arc<dyn DocValuesUpdate>>,
+    ) -> Result<(u64, bool)> {
         debug_assert!(self.inited);
         let l = self.lock.lock().unwrap();
         let doc_writer_mut = unsafe { self.doc_writer_mut(&l) };
-        let seq_no = self.delete_queue.add_doc_values_update(update);
+        let seq_no = self.delete_queue.add_doc_values_updates(updates);
         doc_writer_mut.flush_control.do_on_delete();
 
         let applied = self.apply_all_deletes_local();
@@ -288,7 +297,7 @@ where
     pub fn update_document<F: Fieldable>(
         &self,
         doc: Vec<F>,
-        del_term: Option<Term>,
+        del_term: Option<DocumentsDelete>,
     ) -> Result<(u64, bool)> {
         let mut has_event = self.pre_update()?;
 
@@ -319,7 +328,7 @@ where
         per_thread: &mut ThreadState<D, C, MS, MP>,
         doc: Vec<F>,
         // analyzer: Analyzer,
-        del_term: Option<Term>,
+        del_term: Option<DocumentsDelete>,
     ) -> Result<u64> {
         let is_update = del_term.is_some();
 
@@ -698,6 +707,39 @@ where
         res
     }
 
+    /// Flushes the DWPT buffering a document with `term`, if any, and
+    /// publishes it along with the buffered deletes, so that the flushed
+    /// segments and their deletes are up to date for this term without
+    /// flushing all threads. Must be synced by IW fullFlushLock.
+    pub fn flush_thread_containing(&self, term: &Term) -> Result<bool> {
+        debug_assert!(self.inited);
+        self.ensure_open()?;
+
+        let mut flushing_dwpt = None;
+        for i in 0..self.per_thread_pool.active_thread_state_count() {
+            let per_thread = self.per_thread_pool.get_thread_state(i);
+            let guard = per_thread.lock.lock()?;
+            let per_thread_mut = per_thread.thread_state_mut(&guard);
+            if per_thread_mut.inited() && per_thread_mut.dwpt().contains_term(term) {
+                flushing_dwpt = self.flush_control.checkout_for_flush(per_thread_mut);
+                break;
+            }
+        }
+
+        let mut has_events = false;
+        if let Some(dwpt) = flushing_dwpt {
+            has_events = self.do_flush(dwpt)?;
+        }
+        // a concurrent flush may hold the term as well
+        self.flush_control.wait_for_flush()?;
+        if self.delete_queue.any_changes() {
+            self.ticket_queue.add_deletes(&self.delete_queue);
+        }
+        let index_writer = IndexWriter::with_inner(self.index_writer());
+        self.ticket_queue.force_purge(&index_writer)?;
+        Ok(has_events)
+    }
+
     pub fn any_changes(&self) -> bool {
         // changes are either in a DWPT or in the deleteQueue.
         // yet if we currently flush deletes and / or dwpt there
@@ -138,18 +138,29 @@ impl<C: Codec> DocumentsWriterDeleteQueue<C> {
         seq_no
     }
 
+    pub fn add_doc_values_updates(&self, updates: Vec<Arc<dyn DocValuesUpdate>>) -> u64 {
+        let node = Arc::new(DeleteListNode::new(DeleteNode::DocValuesUpdates(updates)));
+        let seq_no = self.add_node(node);
+        self.try_apply_global_slice();
+        seq_no
+    }
+
     /// invariant for document update
-    pub fn add_term_to_slice(&self, term: Term, slice: &mut DeleteSlice<C>) -> u64 {
-        let del_node = Arc::new(DeleteListNode::new(DeleteNode::Term(term)));
+    pub fn add_to_slice(&self, delete: DocumentsDelete, slice: &mut DeleteSlice<C>) -> u64 {
+        let data = match delete {
+            DocumentsDelete::Term(term) => DeleteNode::Term(term),
+            DocumentsDelete::DocValuesUpdates(updates) => DeleteNode::DocValuesUpdates(updates),
+        };
+        let del_node = Arc::new(DeleteListNode::new(data));
         let seq_no = self.add_node(del_node.clone());
         // this is an update request where the term is the updated documents
-        // delTerm. in that case we need to guarantee that this insert is atomic
-        // with regards to the given delete slice. This means if two threads try to
-        // update the same document with in turn the same delTerm one of them must
-        // win. By taking the node we have created for our del term as the new tail
-        // it is guaranteed that if another thread adds the same right after us we
-        // will apply this delete next time we update our slice and one of the two
-        // competing updates wins!
+        // delTerm (or the soft deletes replacing it). in that case we need to
+        // guarantee that this insert is atomic with regards to the given delete
+        // slice. This means if two threads try to update the same document with
+        // in turn the same delTerm one of them must win. By taking the node we have created for our
+        // del term as the new tail it is guaranteed that if another thread adds the same
+        // right after us we will apply this delete next time we update our slice and one of
+        // the two competing updates wins!
         slice.slice_tail = del_node;
         debug_assert!(!same_node(&slice.slice_head, &slice.slice_tail));
         self.try_apply_global_slice(); // TODO doing this each time is not necessary maybe
@@ -274,11 +285,20 @@ impl<C: Codec> DocumentsWriterDeleteQueue<C> {
     }
 }
 
+/// What an update of documents does to the documents already indexed:
+/// either hard delete the ones containing a term, or apply doc values
+/// updates to them (e.g. to mark them as soft deleted).
+pub enum DocumentsDelete {
+    Term(Term),
+    DocValuesUpdates(Vec<Arc<dyn DocValuesUpdate>>),
+}
+
 enum DeleteNode<C: Codec> {
     Term(Term),
     TermArray(Vec<Term>),
     QueryArray(Vec<Arc<dyn Query<C>>>),
     DocValuesUpdate(Arc<dyn DocValuesUpdate>),
+    DocValuesUpdates(Vec<Arc<dyn DocValuesUpdate>>),
     // used for sentinel head
     None,
 }
@@ -302,6 +322,11 @@ impl<C: Codec> DeleteNode<C> {
             DeleteNode::DocValuesUpdate(update) => {
                 buffered_deletes.add_doc_values_update(update.clone(), doc_id_upto);
             }
+            DeleteNode::DocValuesUpdates(updates) => {
+                for update in updates {
+                    buffered_deletes.add_doc_values_update(update.clone(), doc_id_upto);
+                }
+            }
             DeleteNode::None => {
                 unreachable!();
             }
@@ -17,7 +17,7 @@ use core::{
     codec::{Codec, LiveDocsFormat},
     doc::{Fieldable, Term},
     index::writer::{
-        BufferedUpdates, DeleteSlice, DocConsumer, DocumentsWriterDeleteQueue,
+        BufferedUpdates, DeleteSlice, DocConsumer, DocumentsDelete, DocumentsWriterDeleteQueue,
         FrozenBufferedUpdates, IndexWriterConfig, IndexWriterInner, INDEX_MAX_DOCS,
     },
     index::{merge::MergePolicy, merge::MergeScheduler},
@@ -87,6 +87,8 @@ pub struct DocumentsWriterPerThread<
     delete_slice: DeleteSlice<C>,
     pub byte_block_allocator: DirectTrackingAllocator,
     pub int_block_allocator: Box<dyn IntAllocator>,
+    // RAM used by the byte and int blocks of the in-memory segment
+    bytes_used: Arc<AtomicI64>,
     pending_num_docs: Arc<AtomicI64>,
     pub index_writer_config: Arc<IndexWriterConfig<C, MS, MP>>,
     // enable_test_points: bool,
@@ -137,6 +139,7 @@ where
         )?;
         let delete_slice = delete_queue.new_slice();
         let doc_state = DocState::new();
+        let bytes_used = Arc::new(AtomicI64::new(0));
         // doc_state.similarity = Some(index_writer_config.similarity());
         Ok(DocumentsWriterPerThread {
             directory,
@@ -149,8 +152,9 @@ where
             num_docs_in_ram: 0,
             delete_queue,
             delete_slice,
-            byte_block_allocator: DirectTrackingAllocator::new(),
-            int_block_allocator: Box::new(IntBlockAllocator::new()),
+            byte_block_allocator: DirectTrackingAllocator::with_counter(Arc::clone(&bytes_used)),
+            int_block_allocator: Box::new(IntBlockAllocator::new(Arc::clone(&bytes_used))),
+            bytes_used,
             pending_num_docs,
             index_writer_config,
             index_writer,
@@ -161,8 +165,10 @@ where
 
     pub fn init(&mut self, field_numbers: Arc<FieldNumbers>) {
         let field_infos = FieldInfosBuilder::new(FieldNumbersRef::new(field_numbers));
-        self.byte_block_allocator = DirectTrackingAllocator::new();
-        self.int_block_allocator = Box::new(IntBlockAllocator::new());
+        self.bytes_used = Arc::new(AtomicI64::new(0));
+        self.byte_block_allocator =
+            DirectTrackingAllocator::with_counter(Arc::clone(&self.bytes_used));
+        self.int_block_allocator = Box::new(IntBlockAllocator::new(Arc::clone(&self.bytes_used)));
 
         let consumer = DocConsumer::new(self, field_infos);
         self.consumer.write(consumer);
@@ -197,7 +203,7 @@ where
     pub fn update_document<F: Fieldable>(
         &mut self,
         mut doc: Vec<F>,
-        del_term: Option<Term>,
+        del_term: Option<DocumentsDelete>,
     ) -> Result<u64> {
         // debug_assert!(self.inited);
         self.reserve_one_doc()?;
@@ -231,7 +237,7 @@ where
     pub fn update_documents<F: Fieldable>(
         &mut self,
         docs: Vec<Vec<F>>,
-        del_term: Option<Term>,
+        del_term: Option<DocumentsDelete>,
     ) -> Result<u64> {
         // debug_assert!(self.inited);
         let mut doc_count = 0;
@@ -255,7 +261,7 @@ where
     fn do_update_documents<F: Fieldable>(
         &mut self,
         docs: Vec<Vec<F>>,
-        del_term: Option<Term>,
+        del_term: Option<DocumentsDelete>,
         doc_count: &mut i32,
         all_docs_indexed: &mut bool,
     ) -> Result<u64> {
@@ -294,7 +300,7 @@ where
         let seq_no = if let Some(del_term) = del_term {
             let seq = self
                 .delete_queue
-                .add_term_to_slice(del_term, &mut self.delete_slice);
+                .add_to_slice(del_term, &mut self.delete_slice);
             self.delete_slice.apply(
                 &mut self.pending_updates,
                 self.num_docs_in_ram as i32 - *doc_count,
@@ -330,7 +336,7 @@ where
         // confounding exception).
     }
 
-    fn finish_document(&mut self, del_term: Option<Term>) -> u64 {
+    fn finish_document(&mut self, del_term: Option<DocumentsDelete>) -> u64 {
         // here we actually finish the document in two steps:
         // 1. push the delete into the queue and update our slice
         // 2. increment the DWPT private document id.
@@ -342,7 +348,7 @@ where
         if let Some(del_term) = del_term {
             seq_no = self
                 .delete_queue
-                .add_term_to_slice(del_term, &mut self.delete_slice);
+                .add_to_slice(del_term, &mut self.delete_slice);
         } else {
             let (seq, apply) = self.delete_queue.update_slice(&mut self.delete_slice);
             seq_no = seq;
@@ -376,6 +382,18 @@ where
         Ok(frozen_updates)
     }
 
+    /// Returns true if one of the buffered documents was indexed with `term`.
+    pub fn contains_term(&self, term: &Term) -> bool {
+        debug_assert!(self.inited);
+        unsafe { self.consumer.get_ref().contains_term(term) }
+    }
+
+    /// Returns the RAM used by the postings of the in-memory segment, used
+    /// as the estimated size of the flushed segment.
+    pub fn bytes_used(&self) -> u64 {
+        self.bytes_used.load(Ordering::Acquire).max(0) as u64
+    }
+
     /// Flush all pending docs to a new segment
     pub fn flush(&mut self) -> Result<Option<FlushedSegment<D, C>>> {
         debug_assert!(self.inited);
@@ -383,7 +401,10 @@ where
         debug_assert!(self.delete_slice.is_empty());
 
         self.segment_info.max_doc = self.num_docs_in_ram as i32;
-        let ctx = IOContext::Flush(FlushInfo::new(self.num_docs_in_ram));
+        let ctx = IOContext::Flush(FlushInfo::with_segment_size(
+            self.num_docs_in_ram,
+            self.bytes_used(),
+        ));
 
         let mut flush_state = SegmentWriteState::new(
             Arc::clone(&self.directory),
@@ -504,7 +525,16 @@ where
     ) -> Result<()> {
         // set_diagnostics(&mut flushed_segment.segment_info.info, index_writer::SOURCE_FLUSH);
 
-        let flush_info = FlushInfo::new(flushed_segment.segment_info.info.max_doc() as u32);
+        // sum up the file lengths here rather than through `size_in_bytes`, which
+        // would cache a size that turns stale once the compound file is built
+        let segment_info = &flushed_segment.segment_info;
+        let segment_size: i64 = segment_info
+            .files()
+            .iter()
+            .filter_map(|name| segment_info.info.directory.file_length(name).ok())
+            .sum();
+        let flush_info =
+            FlushInfo::with_segment_size(segment_info.info.max_doc() as u32, segment_size as u64);
         let ctx = &IOContext::Flush(flush_info);
 
         if self.index_writer_config.use_compound_file {
@@ -858,12 +888,14 @@ where
 
 struct IntBlockAllocator {
     block_size: usize,
+    bytes_used: Arc<AtomicI64>,
 }
 
 impl IntBlockAllocator {
-    fn new() -> Self {
+    fn new(bytes_used: Arc<AtomicI64>) -> Self {
         IntBlockAllocator {
             block_size: INT_BLOCK_SIZE,
+            bytes_used,
         }
     }
 }
@@ -876,11 +908,13 @@ impl IntAllocator for IntBlockAllocator {
     fn recycle_int_blocks(&mut self, _blocks: &mut [Vec<i32>], _start: usize, _end: usize) {}
 
     fn int_block(&mut self) -> Vec<i32> {
+        self.bytes_used
+            .fetch_add((self.block_size * 4) as i64, Ordering::AcqRel);
         let b = vec![0; self.block_size];
         b
     }
 
     fn shallow_copy(&mut self) -> Box<dyn IntAllocator> {
-        Box::new(IntBlockAllocator::new())
+        Box::new(IntBlockAllocator::new(Arc::clone(&self.bytes_used)))
     }
 }
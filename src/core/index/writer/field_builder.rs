@@ -0,0 +1,291 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::doc::{Document, Fieldable};
+use core::util::VariantValue;
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Rebuilds the fields to index for one stored value of a field.
+///
+/// `IndexWriter::update_fields` only has the stored values of the document it
+/// updates, the builder registered for a field with
+/// `IndexWriterConfig::set_field_builder` turns each of them back into the
+/// fields (stored, indexed, doc values...) originally added for it.
+pub trait FieldBuilder: Send + Sync {
+    fn build(&self, field: &str, value: &VariantValue) -> Result<Vec<Box<dyn Fieldable>>>;
+}
+
+impl<F> FieldBuilder for F
+where
+    F: Fn(&str, &VariantValue) -> Result<Vec<Box<dyn Fieldable>>> + Send + Sync,
+{
+    fn build(&self, field: &str, value: &VariantValue) -> Result<Vec<Box<dyn Fieldable>>> {
+        (self)(field, value)
+    }
+}
+
+/// A change applied to the stored values of a document by
+/// `IndexWriter::update_fields`.
+#[derive(Debug, Clone)]
+pub enum FieldChange {
+    /// Replaces all the values of the field.
+    Set(String, VariantValue),
+    /// Appends a value to the field.
+    Add(String, VariantValue),
+    /// Removes all the values of the field, which must be stored.
+    Remove(String),
+}
+
+/// The stored values of a document grouped by field, in the order the fields
+/// were first stored.
+pub(crate) struct StoredValues {
+    fields: Vec<(String, Vec<VariantValue>)>,
+}
+
+impl StoredValues {
+    pub fn new(doc: Document) -> StoredValues {
+        let mut values = StoredValues { fields: vec![] };
+        for stored in doc.fields {
+            if let Some(value) = stored.field.field_data() {
+                values.values_mut(stored.field.name()).push(value.clone());
+            }
+        }
+        values
+    }
+
+    pub fn contains(&self, field: &str) -> bool {
+        self.fields.iter().any(|(name, _)| name == field)
+    }
+
+    fn values_mut(&mut self, field: &str) -> &mut Vec<VariantValue> {
+        let idx = match self.fields.iter().position(|(name, _)| name == field) {
+            Some(idx) => idx,
+            None => {
+                self.fields.push((field.to_string(), vec![]));
+                self.fields.len() - 1
+            }
+        };
+        &mut self.fields[idx].1
+    }
+
+    pub fn apply(&mut self, change: FieldChange) -> Result<()> {
+        match change {
+            FieldChange::Set(field, value) => {
+                let values = self.values_mut(&field);
+                values.clear();
+                values.push(value);
+            }
+            FieldChange::Add(field, value) => {
+                self.values_mut(&field).push(value);
+            }
+            FieldChange::Remove(field) => {
+                if !self.contains(&field) {
+                    bail!(IllegalArgument(format!(
+                        "field [{}] is not stored, it can't be removed",
+                        field
+                    )));
+                }
+                self.fields.retain(|(name, _)| *name != field);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the document's fields with the registered builders. Every
+    /// stored field must have a builder, builders of the fields missing from
+    /// the document are not used.
+    pub fn build(
+        &self,
+        builders: &HashMap<String, Arc<dyn FieldBuilder>>,
+    ) -> Result<Vec<Box<dyn Fieldable>>> {
+        let mut fields = vec![];
+        for (field, values) in &self.fields {
+            let builder = match builders.get(field) {
+                Some(builder) => builder,
+                None => bail!(IllegalArgument(format!(
+                    "no field builder registered for field [{}]",
+                    field
+                ))),
+            };
+            for value in values {
+                fields.extend(builder.build(field, value)?);
+            }
+        }
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::StoredField;
+
+    fn string_builder() -> Arc<dyn FieldBuilder> {
+        Arc::new(
+            |field: &str, value: &VariantValue| -> Result<Vec<Box<dyn Fieldable>>> {
+                let stored = StoredField::new(field, None, value.clone());
+                Ok(vec![Box::new(stored.field) as Box<dyn Fieldable>])
+            },
+        )
+    }
+
+    fn stored(field: &str, value: &str) -> StoredField {
+        StoredField::new(field, None, VariantValue::VString(value.to_string()))
+    }
+
+    #[test]
+    fn test_stored_values_changes() {
+        let doc = Document::new(vec![
+            stored("id", "1"),
+            stored("tag", "a"),
+            stored("tag", "b"),
+            stored("title", "old"),
+        ]);
+        let mut values = StoredValues::new(doc);
+        values
+            .apply(FieldChange::Set("title".into(), VariantValue::from("new")))
+            .unwrap();
+        values
+            .apply(FieldChange::Add("tag".into(), VariantValue::from("c")))
+            .unwrap();
+        values.apply(FieldChange::Remove("id".into())).unwrap();
+        assert!(values.apply(FieldChange::Remove("id".into())).is_err());
+
+        let mut builders = HashMap::new();
+        builders.insert("tag".to_string(), string_builder());
+        builders.insert("title".to_string(), string_builder());
+        let fields = values.build(&builders).unwrap();
+        let fields: Vec<(&str, &str)> = fields
+            .iter()
+            .map(|f| (f.name(), f.string_value().unwrap()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![("tag", "a"), ("tag", "b"), ("tag", "c"), ("title", "new")]
+        );
+
+        // fields without stored values are optional
+        builders.insert("body".to_string(), string_builder());
+        assert_eq!(values.build(&builders).unwrap().len(), 4);
+
+        // stored values need a builder
+        builders.remove("body");
+        builders.remove("tag");
+        assert!(values.build(&builders).is_err());
+    }
+
+    #[test]
+    fn test_update_fields() {
+        use core::doc::{DocumentStoredFieldVisitor, Field, FieldType, IndexOptions, Term};
+        use core::index::reader::IndexReader;
+        use core::index::writer::{IndexWriter, IndexWriterConfig};
+        use core::store::directory::RAMDirectory;
+
+        fn id_field(value: &VariantValue) -> Box<dyn Fieldable> {
+            let mut field_type = FieldType::default();
+            field_type.index_options = IndexOptions::Docs;
+            field_type.tokenized = false;
+            field_type.stored = true;
+            Box::new(Field::new(
+                "id".into(),
+                field_type,
+                Some(value.clone()),
+                None,
+            ))
+        }
+
+        let mut config = IndexWriterConfig::default();
+        config.set_field_builder(
+            "id",
+            Arc::new(
+                |_: &str, value: &VariantValue| -> Result<Vec<Box<dyn Fieldable>>> {
+                    Ok(vec![id_field(value)])
+                },
+            ),
+        );
+        config.set_field_builder("title", string_builder());
+        config.set_field_builder("tag", string_builder());
+        let writer = IndexWriter::new(Arc::new(RAMDirectory::new()), Arc::new(config)).unwrap();
+
+        let doc: Vec<Box<dyn Fieldable>> = vec![
+            id_field(&VariantValue::from("1")),
+            Box::new(stored("title", "first").field),
+            Box::new(stored("tag", "a").field),
+        ];
+        writer.add_document(doc).unwrap();
+        writer.commit().unwrap();
+        // the second document has no tag and is still buffered
+        let doc: Vec<Box<dyn Fieldable>> = vec![
+            id_field(&VariantValue::from("2")),
+            Box::new(stored("title", "second").field),
+        ];
+        writer.add_document(doc).unwrap();
+
+        let id_term = |id: &str| Term::new("id".into(), id.as_bytes().to_vec());
+        writer
+            .update_fields(
+                id_term("2"),
+                vec![FieldChange::Set("title".into(), VariantValue::from("new"))],
+            )
+            .unwrap();
+        writer
+            .update_fields(
+                id_term("1"),
+                vec![FieldChange::Add("tag".into(), VariantValue::from("b"))],
+            )
+            .unwrap();
+        assert!(writer.update_fields(id_term("3"), vec![]).is_err());
+
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.num_docs(), 2);
+        let mut docs = vec![];
+        for leaf in reader.leaves() {
+            let live_docs = leaf.reader.live_docs();
+            for doc in 0..leaf.reader.max_doc() {
+                if !live_docs.get(doc as usize).unwrap() {
+                    continue;
+                }
+                let mut visitor = DocumentStoredFieldVisitor::new(&[]);
+                leaf.reader.document(doc, &mut visitor).unwrap();
+                let fields: Vec<(String, String)> = visitor
+                    .document()
+                    .fields
+                    .iter()
+                    .map(|f| {
+                        let value = f.field.string_value().unwrap().to_string();
+                        (f.field.name().to_string(), value)
+                    })
+                    .collect();
+                docs.push(fields);
+            }
+        }
+        docs.sort();
+        let expected = |fields: &[(&str, &str)]| -> Vec<(String, String)> {
+            fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            docs,
+            vec![
+                expected(&[("id", "1"), ("tag", "a"), ("tag", "b"), ("title", "first")]),
+                expected(&[("id", "2"), ("title", "new")]),
+            ]
+        );
+    }
+}
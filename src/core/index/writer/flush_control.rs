@@ -271,6 +271,24 @@ impl<D: Directory + Send + Sync + 'static, C: Codec, MS: MergeScheduler, MP: Mer
         // while selecting that dwpt for flushing
     }
 
+    /// Marks the given thread state as pending and checks its DWPT out for
+    /// flushing, the caller must hold the thread state's lock.
+    pub fn checkout_for_flush(
+        &self,
+        per_thread: &mut ThreadState<D, C, MS, MP>,
+    ) -> Option<DocumentsWriterPerThread<D, C, MS, MP>> {
+        let l = self.lock.lock().unwrap();
+        let control_mut = unsafe { self.flush_control_mut(&l) };
+        if !per_thread.flush_pending() {
+            control_mut.set_flush_pending(per_thread, &l);
+        }
+        if per_thread.flush_pending() {
+            control_mut.internal_try_checkout_for_flush_no_lock(per_thread)
+        } else {
+            None
+        }
+    }
+
     fn try_checkout_for_flush(
         &self,
         per_thread: &ThreadState<D, C, MS, MP>,
@@ -17,7 +17,7 @@ use core::codec::segment_infos::{
     INDEX_FILE_OLD_SEGMENT_GEN, INDEX_FILE_PENDING_SEGMENTS, INDEX_FILE_SEGMENTS,
 };
 use core::codec::Codec;
-use core::index::writer::KeepOnlyLastCommitDeletionPolicy;
+use core::index::writer::IndexDeletionPolicy;
 use core::store::directory::{Directory, LockValidatingDirectoryWrapper};
 
 use regex::Regex;
@@ -76,7 +76,7 @@ pub struct IndexFileDeleter<D: Directory> {
     commits: Vec<CommitPoint>,
     /// Holds files we had inc_ref'd from the previous non-commit checkpoint:
     last_files: HashSet<String>,
-    policy: KeepOnlyLastCommitDeletionPolicy,
+    policy: Arc<dyn IndexDeletionPolicy>,
 
     delayed_dv_update_files: Arc<Mutex<Vec<(u64, Vec<String>)>>>,
     dv_pattern: Regex,
@@ -87,12 +87,15 @@ pub struct IndexFileDeleter<D: Directory> {
 }
 
 impl<D: Directory> IndexFileDeleter<D> {
-    pub fn new(directory: Arc<LockValidatingDirectoryWrapper<D>>) -> Self {
+    pub fn new(
+        directory: Arc<LockValidatingDirectoryWrapper<D>>,
+        policy: Arc<dyn IndexDeletionPolicy>,
+    ) -> Self {
         IndexFileDeleter {
             ref_counts: Arc::new(RwLock::new(HashMap::new())),
             commits: vec![],
             last_files: HashSet::new(),
-            policy: KeepOnlyLastCommitDeletionPolicy {},
+            policy,
             delayed_dv_update_files: Arc::new(Mutex::new(Vec::new())),
             dv_pattern: Regex::new(CODEC_UPDATE_DV_PATTERN).unwrap(),
             fnm_pattern: Regex::new(CODEC_UPDATE_FNM_PATTERN).unwrap(),
@@ -138,6 +141,7 @@ impl<D: Directory> IndexFileDeleter<D> {
                             sis.segment_file_name().unwrap_or("".to_string()),
                             sis.files(true),
                             sis.has_dv_updates(),
+                            sis.user_data.clone(),
                         );
                         self.commits.push(commit_point);
                         if sis.generation == segment_infos.generation {
@@ -163,6 +167,7 @@ impl<D: Directory> IndexFileDeleter<D> {
                     sis.segment_file_name().unwrap_or("".to_string()),
                     sis.files(true),
                     sis.has_dv_updates(),
+                    sis.user_data.clone(),
                 );
                 self.commits.push(commit_point);
                 current_commit_point_idx = Some(self.commits.len() - 1);
@@ -331,6 +336,7 @@ impl<D: Directory> IndexFileDeleter<D> {
                 segment_infos.segment_file_name().unwrap_or("".to_string()),
                 segment_infos.files(true),
                 segment_infos.has_dv_updates(),
+                segment_infos.user_data.clone(),
             );
             self.commits.push(p);
 
@@ -424,6 +430,20 @@ impl<D: Directory> IndexFileDeleter<D> {
         }
     }
 
+    /// Gives the deletion policy another chance to delete commit
+    /// points, eg after a snapshot has been released.
+    pub fn revisit_policy(&mut self) -> Result<()> {
+        debug_assert!(self.inited);
+        if !self.commits.is_empty() {
+            {
+                let commits: Vec<&mut CommitPoint> = self.commits.iter_mut().collect();
+                self.policy.on_commit(commits)?;
+            }
+            self.delete_commits()?;
+        }
+        Ok(())
+    }
+
     /// Remove the CommitPoints in the commitsToDelete List by
     /// DecRef'ing all files from each SegmentInfos.
     fn delete_commits(&mut self) -> Result<()> {
@@ -635,11 +655,13 @@ impl fmt::Debug for RefCount {
 /// Holds details for each commit point. This class is also passed to
 /// the deletion policy. Note: this class has a natural ordering that
 /// is inconsistent with equals.
+#[derive(Clone, Debug)]
 pub struct CommitPoint {
     generation: i64,
     segment_file_name: String,
     files: HashSet<String>,
     has_dv_updates: bool,
+    user_data: HashMap<String, String>,
     deleted: bool,
 }
 
@@ -649,12 +671,14 @@ impl CommitPoint {
         segment_file_name: String,
         files: HashSet<String>,
         has_dv_updates: bool,
+        user_data: HashMap<String, String>,
     ) -> Self {
         CommitPoint {
             generation,
             segment_file_name,
             files,
             has_dv_updates,
+            user_data,
             deleted: false,
         }
     }
@@ -664,6 +688,28 @@ impl CommitPoint {
         &self.segment_file_name
     }
 
+    /// Returns the generation (the _N in segments_N) for this commit point
+    pub fn generation(&self) -> i64 {
+        self.generation
+    }
+
+    /// Returns all index files referenced by this commit point, including
+    /// the `segments_N` file.
+    pub fn file_names(&self) -> &HashSet<String> {
+        &self.files
+    }
+
+    /// Returns true if this commit point has been deleted by the deletion policy.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    /// Reverts a `delete()` call; used by wrapping policies that must keep
+    /// a commit alive regardless of what the wrapped policy decided.
+    pub(crate) fn undelete(&mut self) {
+        self.deleted = false;
+    }
+
     /// Delete this commit point.  This only applies when using
     /// the commit point in the context of IndexWriter's
     /// IndexDeletionPolicy.
@@ -683,6 +729,12 @@ impl CommitPoint {
     pub fn has_dv_updates(&self) -> bool {
         self.has_dv_updates
     }
+
+    /// Returns user_data, previously passed to `IndexWriter::set_live_commit_data`
+    /// for this commit.
+    pub fn user_data(&self) -> &HashMap<String, String> {
+        &self.user_data
+    }
 }
 
 impl Ord for CommitPoint {
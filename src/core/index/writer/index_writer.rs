@@ -19,9 +19,9 @@ use core::codec::segment_infos::{
     file_name_from_generation, get_last_commit_segments_filename, SegmentCommitInfo, SegmentInfo,
     SegmentInfoFormat, SegmentInfos, SegmentWriteState, INDEX_FILE_PENDING_SEGMENTS,
 };
-use core::codec::{Codec, CompoundFormat, LiveDocsFormat, PackedLongDocMap};
+use core::codec::{Codec, CompoundFormat, LiveDocsFormat, PackedLongDocMap, PostingIteratorFlags};
 use core::doc::Term;
-use core::doc::{DocValuesType, Fieldable};
+use core::doc::{DocValuesType, Document, DocumentStoredFieldVisitor, Fieldable};
 use core::index::merge::MergeRateLimiter;
 use core::index::merge::MergeScheduler;
 use core::index::merge::SegmentMerger;
@@ -31,16 +31,23 @@ use core::index::merge::{OneMerge, OneMergeRunningInfo};
 use core::index::reader::index_exist;
 use core::index::reader::{LeafReader, SegmentReader, StandardDirectoryReader};
 use core::index::writer::{
-    BufferedUpdatesStream, DocumentsWriter, Event, FlushedSegment, FrozenBufferedUpdates,
-    IndexFileDeleter, IndexWriterConfig, MergedDocValuesUpdatesIterator, NewDocValuesIterator,
-    NumericDocValuesUpdate, OpenMode,
+    BinaryDocValuesUpdate, BufferedUpdatesStream, DocValuesUpdate, DocumentsDelete,
+    DocumentsWriter, Event, FieldChange, FlushedSegment, FrozenBufferedUpdates, IndexFileDeleter,
+    IndexWriterConfig, MergedDocValuesUpdatesIterator, NewDocValuesIterator,
+    NumericDocValuesUpdate, OpenMode, StoredValues,
 };
+use core::search::cache::{NoCacheQueryCache, QueryCache};
 use core::search::query::{MatchAllDocsQuery, Query};
-use core::store::directory::{Directory, LockValidatingDirectoryWrapper, TrackingDirectoryWrapper};
+use core::search::{DefaultIndexSearcher, DocIterator, SearchPlanBuilder};
+use core::store::directory::{
+    Directory, Lock, LockValidatingDirectoryWrapper, TrackingDirectoryWrapper, WRITE_LOCK_NAME,
+};
 use core::store::{FlushInfo, IOContext};
 use core::util::random_id;
 use core::util::to_base36;
-use core::util::{BitsRef, DerefWrapper, DocId, VERSION_LATEST};
+use core::util::{
+    BitSet, Bits, BitsRef, BytesRef, DerefWrapper, DocId, FixedBitSet, VERSION_LATEST,
+};
 
 use core::index::ErrorKind::MergeAborted;
 use error::ErrorKind::{AlreadyClosed, IllegalArgument, IllegalState, Index, RuntimeError};
@@ -55,7 +62,7 @@ use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
 use std::time::{Duration, SystemTime};
 
 use core::codec::doc_values::{
-    DocValuesWriter, NumericDocValuesWriter, SortedNumericDocValuesWriter,
+    BinaryDocValuesWriter, DocValuesWriter, NumericDocValuesWriter, SortedNumericDocValuesWriter,
 };
 use core::index::writer::dir_wrapper::RateLimitFilterDirectory;
 use core::search::NO_MORE_DOCS;
@@ -219,6 +226,9 @@ where
     MP: MergePolicy,
 {
     /// Constructs a new IndexWriter per the settings given in <code>conf</code>.
+    ///
+    /// The writer holds the directory's `write.lock` until it's closed, fails
+    /// with `LockObtainFailed` if another writer holds it.
     pub fn new(
         d: Arc<D>,
         conf: Arc<IndexWriterConfig<C, MS, MP>>,
@@ -392,7 +402,45 @@ where
     /// @throws CorruptIndexException if the index is corrupt
     /// @throws IOException if there is a low-level IO error
     pub fn update_document<F: Fieldable>(&self, doc: Vec<F>, term: Option<Term>) -> Result<u64> {
-        IndexWriterInner::update_document(self, doc, term)
+        IndexWriterInner::update_document(self, doc, term.map(DocumentsDelete::Term))
+    }
+
+    /// Updates some fields of the document containing `term`: its stored
+    /// values are loaded, flushing the buffered documents holding `term` if
+    /// needed, `changes` are applied to them, and the document is rebuilt with
+    /// the `FieldBuilder`s registered in the config and atomically replaces
+    /// the old one.
+    ///
+    /// Only stored fields survive the update, every stored field must have a
+    /// builder. `term` must match exactly one live document.
+    ///
+    /// *NOTE*: the read and the write are not atomic, concurrent updates of the
+    /// same document may overwrite each other's changes.
+    ///
+    /// @return The <a href="#sequence_number">sequence number</a>
+    /// for this operation
+    pub fn update_fields(&self, term: Term, changes: Vec<FieldChange>) -> Result<u64> {
+        IndexWriterInner::update_fields(self, term, changes)
+    }
+
+    /// Updates a document by first marking the document(s) containing
+    /// <code>term</code> as soft deleted and then adding the new document.
+    /// Like `update_document` the soft delete and the add are atomic as
+    /// seen by a reader on the same index.
+    ///
+    /// The soft deletes field must be configured with
+    /// `IndexWriterConfig::set_soft_deletes_field`. Soft deleted documents
+    /// are only hidden from readers wrapped by `SoftDeletesDirectoryReaderWrapper`.
+    ///
+    /// @return The <a href="#sequence_number">sequence number</a>
+    /// for this operation
+    pub fn soft_update_document<F: Fieldable>(&self, doc: Vec<F>, term: Term) -> Result<u64> {
+        let update = IndexWriterInner::soft_delete_update(self, term)?;
+        IndexWriterInner::update_document(
+            self,
+            doc,
+            Some(DocumentsDelete::DocValuesUpdates(vec![update])),
+        )
     }
 
     /// Atomically adds a block of documents with sequentially
@@ -454,7 +502,25 @@ where
         docs: Vec<Vec<F>>,
         term: Option<Term>,
     ) -> Result<u64> {
-        IndexWriterInner::update_documents(self, docs, term)
+        IndexWriterInner::update_documents(self, docs, term.map(DocumentsDelete::Term))
+    }
+
+    /// Atomically marks the documents containing <code>term</code> as soft
+    /// deleted and adds a block of documents with sequentially assigned
+    /// document IDs.
+    ///
+    /// See `#soft_update_document()` and `#update_documents()`.
+    pub fn soft_update_documents<F: Fieldable>(
+        &self,
+        docs: Vec<Vec<F>>,
+        term: Term,
+    ) -> Result<u64> {
+        let update = IndexWriterInner::soft_delete_update(self, term)?;
+        IndexWriterInner::update_documents(
+            self,
+            docs,
+            Some(DocumentsDelete::DocValuesUpdates(vec![update])),
+        )
     }
 
     /// Deletes the document(s) containing any of the
@@ -486,6 +552,20 @@ where
         IndexWriterInner::delete_documents_by_queries(self, queries)
     }
 
+    /// Marks the document(s) containing <code>term</code> as soft deleted by
+    /// setting the configured soft deletes field to 1.
+    ///
+    /// Soft deleted documents stay in the index until a merge drops the ones
+    /// not matching the soft deletes retention query. Until then they can be
+    /// recovered by setting the soft deletes field back to 0 with
+    /// `update_numeric_doc_value`.
+    ///
+    /// @return The <a href="#sequence_number">sequence number</a>
+    /// for this operation
+    pub fn soft_delete_documents(&self, term: Term) -> Result<u64> {
+        IndexWriterInner::soft_delete_documents(self, term)
+    }
+
     /// Delete all documents in the index.
     ///
     /// This method will drop all buffered documents and will remove all segments
@@ -521,6 +601,27 @@ where
         IndexWriterInner::update_numeric_doc_value(self, term, field, value)
     }
 
+    /// Updates a document's `BinaryDocValues` for `field` to the given `value`.
+    /// You can only update fields that already exist in the index, not add new
+    /// fields through this method.
+    ///
+    /// @return The <a href="#sequence_number">sequence number</a>
+    /// for this operation
+    pub fn update_binary_doc_value(&self, term: Term, field: &str, value: Vec<u8>) -> Result<u64> {
+        IndexWriterInner::update_binary_doc_value(self, term, field, value)
+    }
+
+    /// Updates the documents containing `term` with the doc values of all the
+    /// given fields, which may be numeric or binary doc values fields. All the
+    /// updates are applied atomically: a reader sees either none or all of
+    /// them.
+    ///
+    /// @return The <a href="#sequence_number">sequence number</a>
+    /// for this operation
+    pub fn update_doc_values<F: Fieldable>(&self, term: Term, updates: Vec<F>) -> Result<u64> {
+        IndexWriterInner::update_doc_values(self, term, updates)
+    }
+
     /// Forces merge policy to merge segments until there are
     /// max_num_segments. The actual merges to be
     /// executed are determined by the `MergePolicy`.
@@ -631,6 +732,41 @@ where
         IndexWriterInner::commit(self)
     }
 
+    /// Sets the iterator to provide the commit user data map at commit time.
+    ///
+    /// The data is recorded in the next `segments_N` written by `commit()` and can be
+    /// read back from `SegmentInfos::user_data`, `CommitPoint::user_data` or
+    /// `StandardDirectoryReader::user_data`. Calling this method marks the index as
+    /// changed, so the next `commit()` will write a new commit point even if no
+    /// documents were added.
+    pub fn set_live_commit_data<I>(&self, commit_user_data: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.writer
+            .set_live_commit_data(commit_user_data.into_iter().collect())
+    }
+
+    /// Returns the commit user data that will be written on the next commit, as set by
+    /// `set_live_commit_data`, or loaded from the commit this writer was opened on.
+    pub fn get_live_commit_data(&self) -> Result<HashMap<String, String>> {
+        let _l = self.writer.lock.lock()?;
+        Ok(self.writer.segment_infos.user_data.clone())
+    }
+
+    /// Expert: remove any index files that are no longer
+    /// used.
+    ///
+    /// `IndexWriter` normally deletes unused files itself,
+    /// during indexing.  However, a deletion policy such as
+    /// `SnapshotDeletionPolicy` may keep commits alive; once
+    /// a snapshot is released call this method to give the policy
+    /// another chance to delete the commit and remove its files
+    /// without waiting for the next commit.
+    pub fn delete_unused_files(&self) -> Result<()> {
+        self.writer.delete_unused_files()
+    }
+
     /// Moves all in-memory segments to the `Directory`, but does not commit
     /// (fsync) them (call {@link #commit} for that).
     pub fn flush(&self) -> Result<()> {
@@ -796,6 +932,8 @@ pub struct IndexWriterInner<
     directory_orig: Arc<D>,
     // wrapped with additional checks
     directory: Arc<LockValidatingDirectoryWrapper<D>>,
+    // held until the writer is closed, no other writer can open the index
+    write_lock: Arc<dyn Lock>,
 
     lock: Arc<Mutex<()>>,
     closed: AtomicBool,
@@ -906,7 +1044,12 @@ where
     ///           <code>OpenMode.APPEND</code> or if there is any other low-level
     ///           IO error
     fn new(d: Arc<D>, conf: Arc<IndexWriterConfig<C, MS, MP>>) -> Result<Self> {
-        let directory = Arc::new(LockValidatingDirectoryWrapper::new(Arc::clone(&d)));
+        // obtain the write lock first, before touching the index
+        let write_lock: Arc<dyn Lock> = Arc::from(d.obtain_lock(WRITE_LOCK_NAME)?);
+        let directory = Arc::new(LockValidatingDirectoryWrapper::new(
+            Arc::clone(&d),
+            Arc::clone(&write_lock),
+        ));
 
         let rate_limiters = Arc::new(ThreadLocal::default());
 
@@ -1001,7 +1144,7 @@ where
         // Default deleter (for backwards compatibility) is
         // KeepOnlyLastCommitDeleter:
 
-        let mut deleter = IndexFileDeleter::new(directory.clone());
+        let mut deleter = IndexFileDeleter::new(directory.clone(), conf.index_deletion_policy());
         let starting_commit_deleted =
             deleter.init(d.clone(), &files, &mut segment_infos, initial_index_exists)?;
 
@@ -1021,6 +1164,7 @@ where
             cond: Condvar::new(),
             directory_orig: d,
             directory,
+            write_lock,
             merge_directory,
             change_count,
             last_commit_change_count: AtomicU64::new(0),
@@ -1446,6 +1590,9 @@ where
             }
             self.closed.store(true, Ordering::Release);
             self.closing.store(false, Ordering::Release);
+            if let Err(e) = self.write_lock.close() {
+                warn!("IW - release write lock failed by '{:?}'", e);
+            }
 
             // so any "concurrently closing" threads wake up and see that the close has now
             // completed:
@@ -1589,6 +1736,21 @@ where
         self.segment_infos.changed();
     }
 
+    fn delete_unused_files(&self) -> Result<()> {
+        self.ensure_open(false)?;
+        let l = self.lock.lock()?;
+        let writer = unsafe { self.writer_mut(&l) };
+        writer.deleter.revisit_policy()
+    }
+
+    fn set_live_commit_data(&self, commit_user_data: HashMap<String, String>) -> Result<()> {
+        let l = self.lock.lock()?;
+        let writer = unsafe { self.writer_mut(&l) };
+        writer.segment_infos.user_data = commit_user_data;
+        writer.changed(&l);
+        Ok(())
+    }
+
     fn num_deleted_docs(&self, info: &SegmentCommitInfo<D, C>) -> u32 {
         // self.ensure_open(false);
         let mut del_count = info.del_count() as u32;
@@ -1835,9 +1997,6 @@ where
             self.segment_infos.changed();
         }
 
-        // TODO currently not support SegmentInfos#user_data
-        // if self.commit_user_data.is_some() {}
-
         // Must clone the segmentInfos while we still
         // hold fullFlushLock and while sync'd so that
         // no partial changes (eg a delete w/o
@@ -2225,7 +2384,7 @@ where
     fn update_documents<F: Fieldable>(
         index_writer: &IndexWriter<D, C, MS, MP>,
         docs: Vec<Vec<F>>,
-        term: Option<Term>,
+        term: Option<DocumentsDelete>,
     ) -> Result<u64> {
         index_writer.writer.ensure_open(true)?;
 
@@ -2286,7 +2445,7 @@ where
     fn update_document<F: Fieldable>(
         index_writer: &IndexWriter<D, C, MS, MP>,
         doc: Vec<F>,
-        term: Option<Term>,
+        term: Option<DocumentsDelete>,
     ) -> Result<u64> {
         index_writer.writer.ensure_open(true)?;
         let (seq_no, changed) = index_writer.writer.doc_writer.update_document(doc, term)?;
@@ -2297,6 +2456,94 @@ where
         Ok(seq_no)
     }
 
+    fn update_fields(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        term: Term,
+        changes: Vec<FieldChange>,
+    ) -> Result<u64> {
+        index_writer.writer.ensure_open(true)?;
+
+        let mut values = StoredValues::new(Self::stored_document(index_writer, &term)?);
+        for change in changes {
+            values.apply(change)?;
+        }
+        if !values.contains(term.field()) {
+            bail!(IllegalArgument(format!(
+                "field [{}] of the updated document is not stored",
+                term.field()
+            )));
+        }
+        let doc = values.build(index_writer.writer.config.field_builders())?;
+        Self::update_document(index_writer, doc, Some(DocumentsDelete::Term(term)))
+    }
+
+    // Loads the stored fields of the only live document containing `term`.
+    // Rather than opening a NRT reader, only the DWPT buffering the term is
+    // flushed and the term is looked up in the pooled segment readers.
+    fn stored_document(index_writer: &IndexWriter<D, C, MS, MP>, term: &Term) -> Result<Document> {
+        index_writer
+            .writer
+            .pool_readers
+            .store(true, Ordering::Release);
+        {
+            let _l = index_writer.writer.full_flush_lock.lock()?;
+            index_writer
+                .writer
+                .doc_writer
+                .flush_thread_containing(term)?;
+        }
+        Self::process_events(index_writer, false, true)?;
+
+        let l = index_writer.writer.lock.lock()?;
+        index_writer.writer.maybe_apply_deletes(true, &l)?;
+        let reader_pool = &index_writer.writer.reader_pool;
+        let mut segment_readers = Vec::with_capacity(index_writer.writer.segment_infos.len());
+        for info in &index_writer.writer.segment_infos.segments {
+            let rld = reader_pool.get_or_create(info)?;
+            let reader = rld.get_readonly_clone(&IOContext::READ);
+            reader_pool.release(&rld)?;
+            segment_readers.push(reader?);
+        }
+        drop(l);
+
+        let mut found = None;
+        for (i, segment_reader) in segment_readers.iter().enumerate() {
+            let live_docs = segment_reader.live_docs();
+            if let Some(mut postings) =
+                segment_reader.postings(term, PostingIteratorFlags::NONE as i32)?
+            {
+                loop {
+                    let doc = postings.next()?;
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    if !live_docs.get(doc as usize)? {
+                        continue;
+                    }
+                    if found.is_some() {
+                        bail!(IllegalArgument(format!(
+                            "more than one document contains term {:?}",
+                            term
+                        )));
+                    }
+                    found = Some((i, doc));
+                }
+            }
+        }
+
+        match found {
+            Some((i, doc)) => {
+                let mut visitor = DocumentStoredFieldVisitor::new(&[]);
+                segment_readers[i].document(doc, &mut visitor)?;
+                Ok(visitor.document())
+            }
+            None => bail!(IllegalArgument(format!(
+                "no document contains term {:?}",
+                term
+            ))),
+        }
+    }
+
     /// Updates a document's `NumericDocValues` for <code>field</code> to the
     /// given <code>value</code>. You can only update fields that already exist in
     /// the index, not add new fields through this method.
@@ -2324,16 +2571,142 @@ where
     ) -> Result<u64> {
         index_writer.writer.ensure_open(true)?;
 
-        let dv_type = index_writer
+        let update = Self::numeric_doc_values_update(index_writer, term, field, value)?;
+        let (seq, changed) = index_writer.writer.doc_writer.update_doc_values(update)?;
+        if changed {
+            Self::process_events(index_writer, true, false)?;
+        }
+        Ok(seq)
+    }
+
+    fn update_binary_doc_value(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        term: Term,
+        field: &str,
+        value: Vec<u8>,
+    ) -> Result<u64> {
+        index_writer.writer.ensure_open(true)?;
+
+        let update = Self::binary_doc_values_update(index_writer, term, field, value)?;
+        let (seq, changed) = index_writer.writer.doc_writer.update_doc_values(update)?;
+        if changed {
+            Self::process_events(index_writer, true, false)?;
+        }
+        Ok(seq)
+    }
+
+    fn update_doc_values<F: Fieldable>(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        term: Term,
+        fields: Vec<F>,
+    ) -> Result<u64> {
+        index_writer.writer.ensure_open(true)?;
+
+        if fields.is_empty() {
+            bail!(IllegalArgument("no doc values updates given".into()));
+        }
+        let mut updates = Vec::with_capacity(fields.len());
+        for field in &fields {
+            let update = match field.field_type().doc_values_type {
+                DocValuesType::Numeric | DocValuesType::SortedNumeric => {
+                    let value = match field.numeric_value() {
+                        Some(v) => v.long_value(),
+                        None => bail!(IllegalArgument(format!(
+                            "field [{}] has no numeric value",
+                            field.name()
+                        ))),
+                    };
+                    Self::numeric_doc_values_update(
+                        index_writer,
+                        term.clone(),
+                        field.name(),
+                        value,
+                    )?
+                }
+                DocValuesType::Binary => {
+                    let value = match field.binary_value() {
+                        Some(v) => v.to_vec(),
+                        None => bail!(IllegalArgument(format!(
+                            "field [{}] has no binary value",
+                            field.name()
+                        ))),
+                    };
+                    Self::binary_doc_values_update(index_writer, term.clone(), field.name(), value)?
+                }
+                _ => bail!(IllegalArgument(format!(
+                    "can only update NUMERIC or BINARY doc values fields, field [{}]",
+                    field.name()
+                ))),
+            };
+            updates.push(update);
+        }
+
+        let (seq, changed) = index_writer
             .writer
-            .global_field_numbers
-            .get_doc_values_type(field)?;
-        if dv_type.is_none()
-            || (dv_type != Some(DocValuesType::Numeric)
-                && dv_type != Some(DocValuesType::SortedNumeric))
-        {
+            .doc_writer
+            .update_doc_values_atomically(updates)?;
+        if changed {
+            Self::process_events(index_writer, true, false)?;
+        }
+        Ok(seq)
+    }
+
+    fn numeric_doc_values_update(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        term: Term,
+        field: &str,
+        value: i64,
+    ) -> Result<Arc<dyn DocValuesUpdate>> {
+        let dv_type = Self::check_doc_values_update_field(index_writer, field)?;
+        if dv_type != DocValuesType::Numeric && dv_type != DocValuesType::SortedNumeric {
+            bail!(IllegalArgument(format!("invalid field [{}]", field)));
+        }
+        Ok(Arc::new(NumericDocValuesUpdate::new(
+            term,
+            field.to_string(),
+            dv_type,
+            value,
+            None,
+        )))
+    }
+
+    fn binary_doc_values_update(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        term: Term,
+        field: &str,
+        value: Vec<u8>,
+    ) -> Result<Arc<dyn DocValuesUpdate>> {
+        let dv_type = Self::check_doc_values_update_field(index_writer, field)?;
+        if dv_type != DocValuesType::Binary {
             bail!(IllegalArgument(format!("invalid field [{}]", field)));
         }
+        if value.is_empty() {
+            bail!(IllegalArgument(format!(
+                "field [{}]: empty value not allowed",
+                field
+            )));
+        }
+        Ok(Arc::new(BinaryDocValuesUpdate::new(
+            term,
+            field.to_string(),
+            value,
+            None,
+        )))
+    }
+
+    // Returns the doc values type of an existing field that may be updated.
+    fn check_doc_values_update_field(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        field: &str,
+    ) -> Result<DocValuesType> {
+        let dv_type = match index_writer
+            .writer
+            .global_field_numbers
+            .get_doc_values_type(field)?
+        {
+            Some(dv_type) => dv_type,
+            None => bail!(IllegalArgument(format!("invalid field [{}]", field))),
+        };
 
         if let Some(sort_field) = index_writer.writer.config.index_sort() {
             let sort_field = sort_field.get_sort();
@@ -2346,10 +2719,42 @@ where
                 }
             }
         }
+        Ok(dv_type)
+    }
 
-        let (seq, changed) = index_writer.writer.doc_writer.update_doc_values(Arc::new(
-            NumericDocValuesUpdate::new(term, field.to_string(), dv_type.unwrap(), value, None),
-        ))?;
+    /// Returns the doc values update marking the documents containing `term`
+    /// as soft deleted.
+    fn soft_delete_update(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        term: Term,
+    ) -> Result<Arc<dyn DocValuesUpdate>> {
+        let field = match index_writer.writer.config.soft_deletes_field() {
+            Some(field) => field,
+            None => bail!(IllegalState("soft deletes field is not configured".into())),
+        };
+        // the soft deletes field doesn't have to be indexed before the first
+        // soft delete, register it so its updates aren't rejected
+        index_writer.writer.global_field_numbers.add_or_get(
+            field,
+            0,
+            DocValuesType::Numeric,
+            0,
+            0,
+        )?;
+        Ok(Arc::new(NumericDocValuesUpdate::new(
+            term,
+            field.to_string(),
+            DocValuesType::Numeric,
+            1,
+            None,
+        )))
+    }
+
+    fn soft_delete_documents(index_writer: &IndexWriter<D, C, MS, MP>, term: Term) -> Result<u64> {
+        index_writer.writer.ensure_open(true)?;
+
+        let update = Self::soft_delete_update(index_writer, term)?;
+        let (seq, changed) = index_writer.writer.doc_writer.update_doc_values(update)?;
         if changed {
             Self::process_events(index_writer, true, false)?;
         }
@@ -2941,6 +3346,10 @@ where
                     }
                 }
 
+                if let Some(field) = index_writer.writer.config.soft_deletes_field() {
+                    Self::drop_soft_deletes(index_writer, &rld, &reader, field)?;
+                }
+
                 live_docs = rld.readonly_live_docs();
                 del_count =
                     rld.pending_delete_count() as i32 + merge.segments[seg_upto].del_count();
@@ -3104,6 +3513,62 @@ where
         Ok(merge.info.as_ref().unwrap().info.max_doc)
     }
 
+    /// Hard deletes the soft deleted documents of a segment about to be merged
+    /// that don't match the soft deletes retention query, so the merge drops them.
+    fn drop_soft_deletes(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        rld: &ReadersAndUpdates<D, C, MS, MP>,
+        reader: &Arc<SegmentReader<D, C>>,
+        field: &str,
+    ) -> Result<()> {
+        match reader.field_info(field) {
+            Some(fi) if fi.doc_values_type == DocValuesType::Numeric => {}
+            _ => return Ok(()),
+        }
+
+        let retained = match index_writer.writer.config.soft_deletes_retention_query() {
+            Some(query) => {
+                let mut searcher = DefaultIndexSearcher::new(Arc::clone(reader), None);
+                let query_cache: Arc<dyn QueryCache<C>> = Arc::new(NoCacheQueryCache::new());
+                searcher.set_query_cache(query_cache);
+                let leaf = reader.leaf_context();
+                let weight = searcher.create_normalized_weight(query.as_ref(), false)?;
+                let mut retained = FixedBitSet::new(reader.max_doc() as usize);
+                if let Some(mut scorer) = weight.create_scorer(&leaf)? {
+                    loop {
+                        let doc = scorer.next()?;
+                        if doc == NO_MORE_DOCS {
+                            break;
+                        }
+                        retained.set(doc as usize);
+                    }
+                }
+                Some(retained)
+            }
+            None => None,
+        };
+
+        let values = reader.get_numeric_doc_values(field)?;
+        let live_docs = reader.live_docs();
+        let mut inited = false;
+        for doc in 0..reader.max_doc() {
+            if !live_docs.get(doc as usize)? || values.get(doc)? == 0 {
+                continue;
+            }
+            if let Some(ref retained) = retained {
+                if retained.get(doc as usize)? {
+                    continue;
+                }
+            }
+            if !inited {
+                rld.init_writable_live_docs()?;
+                inited = true;
+            }
+            rld.delete(doc)?;
+        }
+        Ok(())
+    }
+
     /// Does finishing for a merge, which is fast but holds the
     /// synchronized lock on IndexWriter instance.
     fn merge_finish(&mut self, _lock: &MutexGuard<()>, merge: &mut OneMerge<D, C>) {
@@ -3226,6 +3691,7 @@ where
         let mut holder = MergedDeletesAndUpdates::default();
         debug_assert_eq!(merge.segments.len(), merge_state.doc_maps.len());
         let mut dv_updates: HashMap<String, Vec<(i32, i64)>> = HashMap::new();
+        let mut binary_dv_updates: HashMap<String, Vec<(i32, Vec<u8>)>> = HashMap::new();
 
         for i in 0..merge.segments.len() {
             let info = &merge.segments[i];
@@ -3235,10 +3701,8 @@ where
             let rld = self.reader_pool.get(info.as_ref()).unwrap();
             let inner = rld.inner.lock()?;
 
-            let mut fields: Vec<&String> = Vec::with_capacity(inner.merging_dv_updates.len());
-            let mut field_updates: Vec<Vec<(i32, i64)>> =
-                Vec::with_capacity(inner.merging_dv_updates.len());
-            let mut indexes: Vec<usize> = Vec::with_capacity(inner.merging_dv_updates.len());
+            let mut numeric_updates: Vec<MergingFieldUpdates<i64>> = vec![];
+            let mut binary_updates: Vec<MergingFieldUpdates<Vec<u8>>> = vec![];
 
             // get all updates for fields
             for (field, updates) in &inner.merging_dv_updates {
@@ -3251,13 +3715,19 @@ where
                     false,
                 )?;
 
-                let updates = updates.get_numeric_updates();
-                if !updates.is_empty() {
-                    fields.push(field);
-                    field_updates.push(updates);
-                    indexes.push(0);
+                if updates.is_binary() {
+                    let updates = updates.get_binary_updates()?;
+                    if !updates.is_empty() {
+                        binary_updates.push(MergingFieldUpdates::new(field.clone(), updates));
+                    }
+                } else {
+                    let updates = updates.get_numeric_updates()?;
+                    if !updates.is_empty() {
+                        numeric_updates.push(MergingFieldUpdates::new(field.clone(), updates));
+                    }
                 }
             }
+            let has_updates = !numeric_updates.is_empty() || !binary_updates.is_empty();
 
             if !prev_live_docs.is_empty() {
                 debug_assert!(inner.live_docs.is_some());
@@ -3299,34 +3769,48 @@ where
                                 .as_ref()
                                 .unwrap()
                                 .delete(doc_id)?;
-                        } else if !fields.is_empty() {
+                        } else if has_updates {
                             self.maybe_apply_merged_dv_updates(
                                 merge,
                                 merge_state,
                                 &mut holder,
-                                &fields,
-                                &field_updates,
-                                &mut indexes,
+                                &mut numeric_updates,
                                 &mut dv_updates,
                                 i,
                                 j as i32,
                             )?;
+                            self.maybe_apply_merged_dv_updates(
+                                merge,
+                                merge_state,
+                                &mut holder,
+                                &mut binary_updates,
+                                &mut binary_dv_updates,
+                                i,
+                                j as i32,
+                            )?;
                         }
                     }
-                } else if !fields.is_empty() {
+                } else if has_updates {
                     for j in 0..max_doc as usize {
                         if prev_live_docs.get(j)? {
                             self.maybe_apply_merged_dv_updates(
                                 merge,
                                 merge_state,
                                 &mut holder,
-                                &fields,
-                                &field_updates,
-                                &mut indexes,
+                                &mut numeric_updates,
                                 &mut dv_updates,
                                 i,
                                 j as i32,
                             )?;
+                            self.maybe_apply_merged_dv_updates(
+                                merge,
+                                merge_state,
+                                &mut holder,
+                                &mut binary_updates,
+                                &mut binary_dv_updates,
+                                i,
+                                j as i32,
+                            )?;
                         }
                     }
                 }
@@ -3348,41 +3832,65 @@ where
                             .as_ref()
                             .unwrap()
                             .delete(doc_id)?;
-                    } else if !fields.is_empty() {
+                    } else if has_updates {
                         self.maybe_apply_merged_dv_updates(
                             merge,
                             merge_state,
                             &mut holder,
-                            &fields,
-                            &field_updates,
-                            &mut indexes,
+                            &mut numeric_updates,
                             &mut dv_updates,
                             i,
                             j as i32,
                         )?;
+                        self.maybe_apply_merged_dv_updates(
+                            merge,
+                            merge_state,
+                            &mut holder,
+                            &mut binary_updates,
+                            &mut binary_dv_updates,
+                            i,
+                            j as i32,
+                        )?;
                     }
                 }
-            } else if !fields.is_empty() {
+            } else if has_updates {
                 for j in 0..max_doc as usize {
                     self.maybe_apply_merged_dv_updates(
                         merge,
                         merge_state,
                         &mut holder,
-                        &fields,
-                        &field_updates,
-                        &mut indexes,
+                        &mut numeric_updates,
                         &mut dv_updates,
                         i,
                         j as i32,
                     )?;
+                    self.maybe_apply_merged_dv_updates(
+                        merge,
+                        merge_state,
+                        &mut holder,
+                        &mut binary_updates,
+                        &mut binary_dv_updates,
+                        i,
+                        j as i32,
+                    )?;
                 }
             }
         }
 
-        if !dv_updates.is_empty() {
+        if !dv_updates.is_empty() || !binary_dv_updates.is_empty() {
+            let mut merged_updates = Vec::with_capacity(dv_updates.len() + binary_dv_updates.len());
             for (field, mut updates) in dv_updates {
                 updates.sort_by(|a, b| a.0.cmp(&b.0));
                 let up = MergedDocValuesUpdatesIterator::from_updates(field.clone(), updates);
+                merged_updates.push((field, up));
+            }
+            for (field, mut updates) in binary_dv_updates {
+                updates.sort_by(|a, b| a.0.cmp(&b.0));
+                let up =
+                    MergedDocValuesUpdatesIterator::from_binary_updates(field.clone(), updates);
+                merged_updates.push((field, up));
+            }
+            for (field, up) in merged_updates {
                 if holder.merged_deletes_and_updates.is_none() {
                     holder.init(&self.reader_pool, merge, false)?;
                 }
@@ -3421,23 +3929,21 @@ where
         Ok(holder.merged_deletes_and_updates.take())
     }
 
-    fn maybe_apply_merged_dv_updates(
+    fn maybe_apply_merged_dv_updates<V: Clone>(
         &self,
         merge: &OneMerge<D, C>,
         merge_state: &MergeState<D, C>,
         holder: &mut MergedDeletesAndUpdates<D, C, MS, MP>,
-        fields: &Vec<&String>,
-        updates: &Vec<Vec<(i32, i64)>>,
-        indexes: &mut Vec<usize>,
-        out_updates: &mut HashMap<String, Vec<(i32, i64)>>,
+        updates: &mut [MergingFieldUpdates<V>],
+        out_updates: &mut HashMap<String, Vec<(i32, V)>>,
         segment: usize,
         doc_id: i32,
     ) -> Result<()> {
         let mut new_doc = -1;
-        for i in 0..fields.len() {
-            let idx = indexes[i];
-            if idx < updates[i].len() {
-                if doc_id == updates[i][idx].0 {
+        for field_updates in updates {
+            let idx = field_updates.index;
+            if idx < field_updates.updates.len() {
+                if doc_id == field_updates.updates[idx].0 {
                     if holder.merged_deletes_and_updates.is_none() {
                         holder.init(&self.reader_pool, merge, false)?;
                     }
@@ -3449,13 +3955,14 @@ where
                             .get(doc_id)
                             .unwrap();
                     }
-                    if let Some(upds) = out_updates.get_mut(fields[i]) {
-                        upds.push((new_doc, updates[i][idx].1));
+                    let value = field_updates.updates[idx].1.clone();
+                    if let Some(upds) = out_updates.get_mut(&field_updates.field) {
+                        upds.push((new_doc, value));
                     } else {
-                        let values = vec![(new_doc, updates[i][idx].1)];
-                        out_updates.insert(fields[i].clone(), values);
+                        let values = vec![(new_doc, value)];
+                        out_updates.insert(field_updates.field.clone(), values);
                     }
-                    indexes[i] += 1;
+                    field_updates.index += 1;
                 }
             }
         }
@@ -4404,7 +4911,11 @@ where
         // we write approximately that many bytes (based on Lucene46DVF):
         // HEADER + FOOTER: 40
         // 90 bytes per-field (over estimating long name and attributes map)
-        let infos_context = IOContext::Flush(FlushInfo::new(info.info.max_doc() as u32));
+        let estimated_infos_size = 40 + 90 * field_infos.len() as u64;
+        let infos_context = IOContext::Flush(FlushInfo::with_segment_size(
+            info.info.max_doc() as u32,
+            estimated_infos_size,
+        ));
         // separately also track which files were created for this gen
         let tracking_dir = TrackingDirectoryWrapper::new(dir.as_ref());
         infos_format.write(
@@ -4464,6 +4975,17 @@ where
                 fi.put_attribute(key.clone(), val.clone());
             }
         }
+        // updated fields this segment never indexed (e.g. the soft deletes
+        // field) are added, otherwise their updates would be dropped
+        for field in self.pending_dv_updates.keys() {
+            if builder.by_name.contains_key(field) {
+                continue;
+            }
+            let dv_type = builder.global_field_numbers.get_doc_values_type(field)?;
+            if let Some(dv_type) = dv_type {
+                builder.get_or_add(field)?.set_doc_values_type(dv_type)?;
+            }
+        }
         let mut field_infos = builder.finish()?;
 
         let codec = info.info.codec();
@@ -4519,30 +5041,45 @@ where
         dv_format: <C as Codec>::DVFmt,
     ) -> Result<HashMap<i32, HashSet<String>>> {
         let mut new_dv_files = HashMap::new();
-        for (field, updates) in &self.pending_dv_updates {
-            if self.reader.is_none() || infos.field_info_by_name(field).is_none() {
-                continue;
+        if self.reader.is_none() {
+            return Ok(new_dv_files);
+        }
+
+        // all the fields updated together are written to one generation, so
+        // an atomic update of several fields is published at once
+        let info = self.reader.as_ref().unwrap().si.clone();
+        let dv_gen = info.next_write_doc_values_gen();
+        let mut updated_fields = Vec::with_capacity(self.pending_dv_updates.len());
+        for field in self.pending_dv_updates.keys() {
+            if let Some(field_info) = infos.field_info_by_name(field) {
+                let field_info =
+                    unsafe { &mut *(field_info as *const FieldInfo as *mut FieldInfo) };
+                let old_dv_gen = field_info.set_doc_values_gen(dv_gen);
+                updated_fields.push((field_info, old_dv_gen));
             }
+        }
+        if updated_fields.is_empty() {
+            return Ok(new_dv_files);
+        }
+
+        let tracker = Arc::new(TrackingDirectoryWrapper::new(info.info.directory.as_ref()));
+        // step1 construct segment write state
+        let ctx = IOContext::Flush(FlushInfo::new(info.info.max_doc() as u32));
+        let fields = updated_fields.iter().map(|(fi, _)| (*fi).clone()).collect();
+        let state = SegmentWriteState::new(
+            tracker.clone(),
+            info.info.clone(),
+            FieldInfos::new(fields)?,
+            None,
+            ctx,
+            to_base36(dv_gen as u64),
+        );
+        // step2 get doc values consumer
+        let mut field_consumer = dv_format.fields_consumer(&state)?;
 
-            let info = self.reader.as_ref().unwrap().si.clone();
-            let tracker = Arc::new(TrackingDirectoryWrapper::new(info.info.directory.as_ref()));
-            let dv_gen = info.next_write_doc_values_gen();
-            // step1 construct segment write state
-            let ctx = IOContext::Flush(FlushInfo::new(info.info.max_doc() as u32));
-            let field_info = infos.field_info_by_name(field).unwrap();
-            let field_info = unsafe { &mut *(field_info as *const FieldInfo as *mut FieldInfo) };
-            let old_dv_gen = field_info.set_doc_values_gen(dv_gen);
-            let state = SegmentWriteState::new(
-                tracker.clone(),
-                info.info.clone(),
-                FieldInfos::new(vec![field_info.clone()])?,
-                None,
-                ctx,
-                to_base36(dv_gen as u64),
-            );
-            // step2 get doc values consumer
-            let mut field_consumer = dv_format.fields_consumer(&state)?;
-
+        let mut written_fields = vec![];
+        for (field_info, old_dv_gen) in updated_fields {
+            let updates = &self.pending_dv_updates[&field_info.name];
             let mut new_dv_updates_iter = if updates.len() == 1 && updates[0].is_merged_updates() {
                 NewDocValuesIterator::new(
                     self.reader().clone(),
@@ -4567,7 +5104,7 @@ where
                     let mut ndv_writer = NumericDocValuesWriter::new(field_info);
 
                     loop {
-                        let (doc_id, value) = new_dv_updates_iter.next_numeric();
+                        let (doc_id, value) = new_dv_updates_iter.next_numeric()?;
                         if doc_id == NO_MORE_DOCS {
                             break;
                         }
@@ -4588,7 +5125,7 @@ where
                     let mut ndv_writer = SortedNumericDocValuesWriter::new(field_info);
 
                     loop {
-                        let (doc_id, value) = new_dv_updates_iter.next_numeric();
+                        let (doc_id, value) = new_dv_updates_iter.next_numeric()?;
                         if doc_id == NO_MORE_DOCS {
                             break;
                         }
@@ -4604,20 +5141,70 @@ where
                         )?;
                     }
                 }
+                DocValuesType::Binary => {
+                    // step3 construct doc values writer, add data, and then flush to index
+                    let mut bdv_writer = BinaryDocValuesWriter::new(field_info)?;
+
+                    loop {
+                        let (doc_id, value) = new_dv_updates_iter.next_binary()?;
+                        if doc_id == NO_MORE_DOCS {
+                            break;
+                        }
+                        // documents without a value are left as holes
+                        if !value.is_empty() {
+                            bdv_writer.add_value(doc_id, &BytesRef::new(&value))?;
+                            doc_num += 1;
+                        }
+                    }
+                    if doc_num > 0 {
+                        bdv_writer.finish(doc_num);
+                        bdv_writer.flush(
+                            &state,
+                            None as Option<&PackedLongDocMap>,
+                            &mut field_consumer,
+                        )?;
+                    }
+                }
                 _ => unimplemented!(),
             };
 
             if doc_num > 0 {
-                info.advance_doc_values_gen();
-                new_dv_files.insert(field_info.number as i32, tracker.get_create_files());
+                written_fields.push(field_info.number as i32);
             } else {
                 field_info.set_doc_values_gen(old_dv_gen);
             }
         }
+        drop(field_consumer);
+
+        if !written_fields.is_empty() {
+            info.advance_doc_values_gen();
+            let files = tracker.get_create_files();
+            for number in written_fields {
+                new_dv_files.insert(number, files.clone());
+            }
+        }
         Ok(new_dv_files)
     }
 }
 
+// The doc values updates of one field of a merging segment, which are carried
+// over to the merged segment when the merge commits.
+struct MergingFieldUpdates<V> {
+    field: String,
+    updates: Vec<(i32, V)>,
+    index: usize,
+}
+
+impl<V> MergingFieldUpdates<V> {
+    fn new(field: String, updates: Vec<(i32, V)>) -> Self {
+        MergingFieldUpdates {
+            field,
+            updates,
+            index: 0,
+        }
+    }
+}
+
 struct MergedDeletesAndUpdates<
     D: Directory + Send + Sync + 'static,
     C: Codec,
@@ -15,9 +15,13 @@ use core::codec::{Codec, CodecEnum, Lucene62Codec};
 use core::index::merge::MergeScheduler;
 use core::index::merge::SerialMergeScheduler;
 use core::index::merge::{MergePolicy, TieredMergePolicy};
-use core::index::writer::KeepOnlyLastCommitDeletionPolicy;
-use core::search::sort_field::Sort;
+use core::index::writer::{FieldBuilder, IndexDeletionPolicy, KeepOnlyLastCommitDeletionPolicy};
+use core::search::query::Query;
+use core::search::sort_field::{Sort, SortField, SortFieldType};
+use error::ErrorKind::IllegalArgument;
+use error::Result;
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 /// Denotes a flush trigger is disabled.
@@ -70,6 +74,14 @@ pub struct IndexWriterConfig<C: Codec, MS: MergeScheduler, MP: MergePolicy> {
     pub open_mode: OpenMode,
     pub codec: Arc<C>,
     pub commit_on_close: bool,
+    /// Numeric doc values field marking documents as soft deleted.
+    pub soft_deletes_field: Option<String>,
+    /// Soft deleted documents matching this query survive merges.
+    pub soft_deletes_retention_query: Option<Arc<dyn Query<C>>>,
+    /// Rebuild the fields of documents updated by `IndexWriter::update_fields`.
+    pub field_builders: HashMap<String, Arc<dyn FieldBuilder>>,
+    /// Decides when old commit points are deleted from the index directory.
+    pub index_deletion_policy: Arc<dyn IndexDeletionPolicy>,
 }
 
 impl Default for IndexWriterConfig<CodecEnum, SerialMergeScheduler, TieredMergePolicy> {
@@ -95,6 +107,10 @@ impl<C: Codec, MS: MergeScheduler, MP: MergePolicy> IndexWriterConfig<C, MS, MP>
             open_mode: OpenMode::CreateOrAppend,
             codec,
             commit_on_close: true,
+            soft_deletes_field: None,
+            soft_deletes_retention_query: None,
+            field_builders: HashMap::new(),
+            index_deletion_policy: Arc::new(KeepOnlyLastCommitDeletionPolicy),
         }
     }
 
@@ -122,8 +138,88 @@ impl<C: Codec, MS: MergeScheduler, MP: MergePolicy> IndexWriterConfig<C, MS, MP>
         self.index_sort.as_ref()
     }
 
-    pub fn index_deletion_policy(&self) -> KeepOnlyLastCommitDeletionPolicy {
-        KeepOnlyLastCommitDeletionPolicy::default()
+    /// Sorts the documents of every flushed and merged segment by `sort`.
+    ///
+    /// Score, doc and distance sorts can't be used to sort an index.
+    pub fn set_index_sort(&mut self, sort: Sort) -> Result<()> {
+        for sort_field in sort.get_sort() {
+            let supported = match sort_field {
+                SortField::Simple(s) => match s.field_type() {
+                    SortFieldType::Score | SortFieldType::Doc | SortFieldType::Custom => false,
+                    _ => true,
+                },
+                SortField::LatLonDistance(_) => false,
+                _ => true,
+            };
+            if !supported {
+                bail!(IllegalArgument(format!(
+                    "invalid SortField type for index sort: {:?}",
+                    sort_field
+                )));
+            }
+            if self.soft_deletes_field() == Some(sort_field.field()) {
+                bail!(IllegalArgument(format!(
+                    "soft deletes field [{}] can't be an index sort field",
+                    sort_field.field()
+                )));
+            }
+        }
+        self.index_sort = Some(sort);
+        Ok(())
+    }
+
+    pub fn soft_deletes_field(&self) -> Option<&str> {
+        self.soft_deletes_field.as_ref().map(String::as_str)
+    }
+
+    /// Enables soft deletes: documents are deleted by `IndexWriter::soft_delete_documents`
+    /// and `IndexWriter::soft_update_document` by setting this numeric doc values
+    /// field to a non-zero value instead of being removed from the live docs.
+    pub fn set_soft_deletes_field(&mut self, field: &str) -> Result<()> {
+        if let Some(sort) = &self.index_sort {
+            if sort.get_sort().iter().any(|f| f.field() == field) {
+                bail!(IllegalArgument(format!(
+                    "soft deletes field [{}] can't be an index sort field",
+                    field
+                )));
+            }
+        }
+        self.soft_deletes_field = Some(field.to_string());
+        Ok(())
+    }
+
+    pub fn soft_deletes_retention_query(&self) -> Option<&Arc<dyn Query<C>>> {
+        self.soft_deletes_retention_query.as_ref()
+    }
+
+    /// Soft deleted documents matching `query` are kept when their segment is
+    /// merged, the others are dropped. Without a retention query merges drop
+    /// every soft deleted document.
+    pub fn set_soft_deletes_retention_query(&mut self, query: Arc<dyn Query<C>>) {
+        self.soft_deletes_retention_query = Some(query);
+    }
+
+    pub fn field_builders(&self) -> &HashMap<String, Arc<dyn FieldBuilder>> {
+        &self.field_builders
+    }
+
+    /// Registers the builder rebuilding `field` from its stored values when a
+    /// document is updated by `IndexWriter::update_fields`.
+    pub fn set_field_builder(&mut self, field: &str, builder: Arc<dyn FieldBuilder>) {
+        self.field_builders.insert(field.to_string(), builder);
+    }
+
+    pub fn index_deletion_policy(&self) -> Arc<dyn IndexDeletionPolicy> {
+        Arc::clone(&self.index_deletion_policy)
+    }
+
+    /// Sets the policy deciding when old commit points are deleted. The default
+    /// is `KeepOnlyLastCommitDeletionPolicy`.
+    ///
+    /// Keep a handle on the policy, eg a `SnapshotDeletionPolicy`, to interact
+    /// with it while the writer is open.
+    pub fn set_index_deletion_policy(&mut self, policy: Arc<dyn IndexDeletionPolicy>) {
+        self.index_deletion_policy = policy;
     }
 
     pub fn merge_scheduler(&self) -> MS {
@@ -59,6 +59,10 @@ mod index_writer_config;
 
 pub use self::index_writer_config::*;
 
+mod field_builder;
+
+pub use self::field_builder::*;
+
 mod doc_writer_per_thread;
 
 pub use self::doc_writer_per_thread::*;
@@ -67,6 +71,10 @@ mod prefix_code_terms;
 
 pub use self::prefix_code_terms::*;
 
+mod snapshot_deletion_policy;
+
+pub use self::snapshot_deletion_policy::*;
+
 pub mod doc_values_update;
 
 pub use self::doc_values_update::*;
@@ -0,0 +1,580 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::{check_footer, check_header, write_footer, write_header};
+use core::index::writer::{CommitPoint, IndexDeletionPolicy};
+use core::store::directory::Directory;
+use core::store::io::{DataInput, IndexOutput};
+use core::store::IOContext;
+use error::ErrorKind::{IllegalArgument, IllegalState};
+use error::Result;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// An `IndexDeletionPolicy` that wraps any other
+/// `IndexDeletionPolicy` and adds the ability to hold and later release
+/// snapshots of an index. While a snapshot is held, the `IndexWriter` will not
+/// remove any files associated with it even if the index is otherwise being
+/// actively, arbitrarily changed. Because we wrap another arbitrary
+/// `IndexDeletionPolicy`, this gives you the freedom to continue using
+/// whatever `IndexDeletionPolicy` you would normally want to use with your
+/// index.
+///
+/// This class maintains all snapshots in-memory, and so the information is not
+/// persisted and not protected against system failures. If persistence is
+/// important, you can use `PersistentSnapshotDeletionPolicy`.
+///
+/// Share the same `Arc` between the `IndexWriterConfig` and the code taking
+/// snapshots; after `release` call `IndexWriter::delete_unused_files` to
+/// remove the files of the released commit right away.
+pub struct SnapshotDeletionPolicy {
+    primary: Box<dyn IndexDeletionPolicy>,
+    state: Mutex<SnapshotState>,
+}
+
+#[derive(Default)]
+struct SnapshotState {
+    /// Records how many snapshots are held against each commit generation
+    ref_counts: HashMap<i64, usize>,
+    /// Used to map gen to CommitPoint.
+    index_commits: HashMap<i64, CommitPoint>,
+    /// Most recently committed `CommitPoint`.
+    last_commit: Option<CommitPoint>,
+    /// Used to detect misuse
+    inited: bool,
+}
+
+impl SnapshotDeletionPolicy {
+    pub fn new(primary: Box<dyn IndexDeletionPolicy>) -> Self {
+        SnapshotDeletionPolicy {
+            primary,
+            state: Mutex::new(SnapshotState::default()),
+        }
+    }
+
+    /// Snapshots the last commit and returns it. Once a commit is 'snapshotted,' it is protected
+    /// from deletion (as long as this `IndexDeletionPolicy` is used). The
+    /// snapshot can be removed by calling `release` followed
+    /// by a call to `IndexWriter::delete_unused_files`.
+    ///
+    /// NOTE: while the snapshot is held, the files it references will not
+    /// be deleted, which will consume additional disk space in your index. If you
+    /// take a snapshot at a particularly bad time (say just before you call
+    /// `IndexWriter::force_merge`) then in the worst case this could consume an extra 1X of
+    /// your total index size, until you release the snapshot.
+    pub fn snapshot(&self) -> Result<CommitPoint> {
+        let mut state = self.state.lock()?;
+        if !state.inited {
+            bail!(IllegalState(
+                "this instance is not being used by IndexWriter; be sure to use the instance \
+                 returned from IndexWriterConfig::index_deletion_policy"
+                    .into()
+            ));
+        }
+        let commit = match state.last_commit {
+            Some(ref c) => c.clone(),
+            None => {
+                // No commit yet, eg this is a new IndexWriter:
+                bail!(IllegalState("No index commit to snapshot".into()));
+            }
+        };
+        state.inc_ref(&commit);
+        Ok(commit)
+    }
+
+    /// Release a snapshotted commit.
+    pub fn release(&self, commit: &CommitPoint) -> Result<()> {
+        self.release_gen(commit.generation())
+    }
+
+    /// Release a snapshot by generation.
+    pub fn release_gen(&self, generation: i64) -> Result<()> {
+        self.state.lock()?.release_gen(generation)
+    }
+
+    /// Returns all `CommitPoint`s held by at least one snapshot.
+    pub fn snapshots(&self) -> Result<Vec<CommitPoint>> {
+        let state = self.state.lock()?;
+        let mut commits: Vec<CommitPoint> = state.index_commits.values().cloned().collect();
+        commits.sort();
+        Ok(commits)
+    }
+
+    /// Returns the total number of snapshots currently held.
+    pub fn snapshot_count(&self) -> Result<usize> {
+        Ok(self.state.lock()?.ref_counts.values().sum())
+    }
+
+    /// Retrieve a `CommitPoint` from its generation;
+    /// returns None if this `CommitPoint` is not currently
+    /// snapshotted
+    pub fn index_commit(&self, generation: i64) -> Result<Option<CommitPoint>> {
+        Ok(self.state.lock()?.index_commits.get(&generation).cloned())
+    }
+
+    /// Returns the snapshot ref counts by generation, used to persist them.
+    fn ref_counts(&self) -> Result<HashMap<i64, usize>> {
+        Ok(self.state.lock()?.ref_counts.clone())
+    }
+
+    /// Increments the ref count for `generation`, also for commits
+    /// not yet seen by this policy (eg snapshots loaded from disk).
+    fn inc_ref_gen(&self, generation: i64) -> Result<()> {
+        *self.state.lock()?.ref_counts.entry(generation).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Undoes a `release_gen`, restoring the ref count and the `CommitPoint`
+    /// which was mapped to `generation` before the release.
+    fn undo_release_gen(&self, generation: i64, commit: Option<CommitPoint>) -> Result<()> {
+        let mut state = self.state.lock()?;
+        *state.ref_counts.entry(generation).or_insert(0) += 1;
+        if let Some(commit) = commit {
+            state.index_commits.insert(generation, commit);
+        }
+        Ok(())
+    }
+
+    fn on_commits(&self, mut commits: Vec<&mut CommitPoint>, init: bool) -> Result<()> {
+        {
+            let wrapped: Vec<&mut CommitPoint> = commits.iter_mut().map(|c| &mut **c).collect();
+            if init {
+                self.primary.on_init(wrapped)?;
+            } else {
+                self.primary.on_commit(wrapped)?;
+            }
+        }
+        let mut state = self.state.lock()?;
+        for commit in &mut commits {
+            if state.ref_counts.contains_key(&commit.generation()) {
+                // snapshotted commits are never deleted
+                commit.undelete();
+                if init {
+                    state
+                        .index_commits
+                        .insert(commit.generation(), (**commit).clone());
+                }
+            }
+        }
+        state.last_commit = commits.last().map(|c| (**c).clone());
+        if init {
+            state.inited = true;
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotState {
+    fn inc_ref(&mut self, commit: &CommitPoint) {
+        let generation = commit.generation();
+        *self.ref_counts.entry(generation).or_insert(0) += 1;
+        self.index_commits.insert(generation, commit.clone());
+    }
+
+    fn release_gen(&mut self, generation: i64) -> Result<()> {
+        if !self.inited {
+            bail!(IllegalState(
+                "this instance is not being used by IndexWriter; be sure to use the instance \
+                 returned from IndexWriterConfig::index_deletion_policy"
+                    .into()
+            ));
+        }
+        let ref_count = match self.ref_counts.get_mut(&generation) {
+            Some(count) => {
+                debug_assert!(*count > 0);
+                *count -= 1;
+                *count
+            }
+            None => bail!(IllegalArgument(format!(
+                "commit gen={} is not currently snapshotted",
+                generation
+            ))),
+        };
+        if ref_count == 0 {
+            self.ref_counts.remove(&generation);
+            self.index_commits.remove(&generation);
+        }
+        Ok(())
+    }
+}
+
+impl IndexDeletionPolicy for SnapshotDeletionPolicy {
+    fn on_init(&self, commits: Vec<&mut CommitPoint>) -> Result<()> {
+        self.on_commits(commits, true)
+    }
+
+    fn on_commit(&self, commits: Vec<&mut CommitPoint>) -> Result<()> {
+        self.on_commits(commits, false)
+    }
+}
+
+/// Prefix used for the save file.
+pub const SNAPSHOTS_PREFIX: &str = "snapshots_";
+const SNAPSHOTS_CODEC: &str = "snapshots";
+const SNAPSHOTS_VERSION_START: i32 = 0;
+const SNAPSHOTS_VERSION_CURRENT: i32 = SNAPSHOTS_VERSION_START;
+
+/// A `SnapshotDeletionPolicy` which adds a persistence layer so that
+/// snapshots can be maintained across the life of an application. The snapshots
+/// are persisted in a `Directory` and are committed as soon as
+/// `snapshot` or `release` is called.
+///
+/// The snapshots are saved to `snapshots_N` files, which may live in the index
+/// directory itself since the `IndexWriter` never deletes them. Only the
+/// latest file is kept; older ones are removed after every successful save.
+pub struct PersistentSnapshotDeletionPolicy<D: Directory> {
+    snapshot: SnapshotDeletionPolicy,
+    dir: Arc<D>,
+    /// Generation of the next `snapshots_N` file to write.
+    next_write_gen: Mutex<u64>,
+}
+
+impl<D: Directory> PersistentSnapshotDeletionPolicy<D> {
+    /// Wraps `primary`, loading the snapshots previously persisted in `dir`,
+    /// if any. Those commits stay protected when the `IndexWriter` is opened.
+    pub fn new(primary: Box<dyn IndexDeletionPolicy>, dir: Arc<D>) -> Result<Self> {
+        let policy = PersistentSnapshotDeletionPolicy {
+            snapshot: SnapshotDeletionPolicy::new(primary),
+            dir,
+            next_write_gen: Mutex::new(0),
+        };
+        policy.load_prior_snapshots()?;
+        Ok(policy)
+    }
+
+    /// Snapshots the last commit and saves the snapshots to the directory
+    /// before returning it. See `SnapshotDeletionPolicy::snapshot`.
+    pub fn snapshot(&self) -> Result<CommitPoint> {
+        let commit = self.snapshot.snapshot()?;
+        if let Err(e) = self.persist() {
+            // roll back, the snapshot is not durable
+            let _ = self.snapshot.release(&commit);
+            return Err(e);
+        }
+        Ok(commit)
+    }
+
+    /// Deletes a snapshotted commit and saves the remaining snapshots to the
+    /// directory. See `SnapshotDeletionPolicy::release`.
+    pub fn release(&self, commit: &CommitPoint) -> Result<()> {
+        self.release_gen(commit.generation())
+    }
+
+    /// Deletes a snapshot by generation and saves the remaining snapshots.
+    pub fn release_gen(&self, generation: i64) -> Result<()> {
+        let commit = self.snapshot.index_commit(generation)?;
+        self.snapshot.release_gen(generation)?;
+        if let Err(e) = self.persist() {
+            // roll back, the release is not durable
+            let _ = self.snapshot.undo_release_gen(generation, commit);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn snapshots(&self) -> Result<Vec<CommitPoint>> {
+        self.snapshot.snapshots()
+    }
+
+    pub fn snapshot_count(&self) -> Result<usize> {
+        self.snapshot.snapshot_count()
+    }
+
+    pub fn index_commit(&self, generation: i64) -> Result<Option<CommitPoint>> {
+        self.snapshot.index_commit(generation)
+    }
+
+    /// Returns the file name the snapshots are currently
+    /// saved to, or None if no snapshots have been saved.
+    pub fn last_save_file(&self) -> Result<Option<String>> {
+        let next_write_gen = *self.next_write_gen.lock()?;
+        Ok(if next_write_gen == 0 {
+            None
+        } else {
+            Some(format!("{}{}", SNAPSHOTS_PREFIX, next_write_gen - 1))
+        })
+    }
+
+    fn persist(&self) -> Result<()> {
+        let mut next_write_gen = self.next_write_gen.lock()?;
+        let file_name = format!("{}{}", SNAPSHOTS_PREFIX, *next_write_gen);
+        let ref_counts = self.snapshot.ref_counts()?;
+
+        let res = (|| -> Result<()> {
+            let mut output = self.dir.create_output(&file_name, &IOContext::Default)?;
+            write_snapshots(&mut output, &ref_counts)?;
+            drop(output);
+            let mut names = HashSet::with_capacity(1);
+            names.insert(file_name.clone());
+            self.dir.sync(&names)?;
+            self.dir.sync_meta_data()
+        })();
+        if let Err(e) = res {
+            let _ = self.dir.delete_file(&file_name);
+            return Err(e);
+        }
+
+        if *next_write_gen > 0 {
+            let last_save_file = format!("{}{}", SNAPSHOTS_PREFIX, *next_write_gen - 1);
+            // the new file is durable, failing to remove the old one is harmless
+            let _ = self.dir.delete_file(&last_save_file);
+        }
+        *next_write_gen += 1;
+        Ok(())
+    }
+
+    /// Reads the snapshots information from the latest readable
+    /// `snapshots_N` file and deletes the others.
+    fn load_prior_snapshots(&self) -> Result<()> {
+        let mut files: Vec<(u64, String)> = vec![];
+        for name in self.dir.list_all()? {
+            if name.starts_with(SNAPSHOTS_PREFIX) {
+                if let Ok(gen) = name[SNAPSHOTS_PREFIX.len()..].parse::<u64>() {
+                    files.push((gen, name));
+                }
+            }
+        }
+        files.sort();
+
+        let mut loaded: Option<(u64, HashMap<i64, usize>)> = None;
+        let mut last_error = None;
+        for (gen, name) in files.iter().rev() {
+            match self.read_snapshots(name) {
+                Ok(ref_counts) => {
+                    loaded = Some((*gen, ref_counts));
+                    break;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match loaded {
+            Some((gen, ref_counts)) => {
+                for (generation, count) in ref_counts {
+                    for _ in 0..count {
+                        self.snapshot.inc_ref_gen(generation)?;
+                    }
+                }
+                *self.next_write_gen.lock()? = gen + 1;
+                for (g, name) in &files {
+                    if *g != gen {
+                        self.dir.delete_file(name)?;
+                    }
+                }
+                Ok(())
+            }
+            None => match last_error {
+                Some(e) => Err(e),
+                None => Ok(()),
+            },
+        }
+    }
+
+    fn read_snapshots(&self, name: &str) -> Result<HashMap<i64, usize>> {
+        let mut input = self.dir.open_checksum_input(name, &IOContext::READ)?;
+        check_header(
+            &mut input,
+            SNAPSHOTS_CODEC,
+            SNAPSHOTS_VERSION_START,
+            SNAPSHOTS_VERSION_START,
+        )?;
+        let count = input.read_vint()?;
+        let mut ref_counts = HashMap::with_capacity(count as usize);
+        for _ in 0..count {
+            let generation = input.read_vlong()?;
+            let ref_count = input.read_vint()?;
+            ref_counts.insert(generation, ref_count as usize);
+        }
+        check_footer(&mut input)?;
+        Ok(ref_counts)
+    }
+}
+
+fn write_snapshots(output: &mut impl IndexOutput, ref_counts: &HashMap<i64, usize>) -> Result<()> {
+    write_header(output, SNAPSHOTS_CODEC, SNAPSHOTS_VERSION_CURRENT)?;
+    output.write_vint(ref_counts.len() as i32)?;
+    for (generation, ref_count) in ref_counts {
+        output.write_vlong(*generation)?;
+        output.write_vint(*ref_count as i32)?;
+    }
+    write_footer(output)
+}
+
+impl<D: Directory + Send + Sync> IndexDeletionPolicy for PersistentSnapshotDeletionPolicy<D> {
+    fn on_init(&self, commits: Vec<&mut CommitPoint>) -> Result<()> {
+        self.snapshot.on_init(commits)
+    }
+
+    fn on_commit(&self, commits: Vec<&mut CommitPoint>) -> Result<()> {
+        self.snapshot.on_commit(commits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType, Fieldable, IndexOptions};
+    use core::index::merge::{SerialMergeScheduler, TieredMergePolicy};
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::index::writer::{KeepLastNCommitsDeletionPolicy, KeepOnlyLastCommitDeletionPolicy};
+    use core::store::directory::RAMDirectory;
+    use core::util::VariantValue;
+
+    type Writer = IndexWriter<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    fn new_writer(directory: &Arc<RAMDirectory>, policy: Arc<dyn IndexDeletionPolicy>) -> Writer {
+        let mut config = IndexWriterConfig::default();
+        config.set_index_deletion_policy(policy);
+        IndexWriter::new(Arc::clone(directory), Arc::new(config)).unwrap()
+    }
+
+    fn add_doc(writer: &Writer, id: &str) {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(Field::new(
+            "id".into(),
+            field_type,
+            Some(VariantValue::VString(id.to_string())),
+            None,
+        ))];
+        writer.add_document(doc).unwrap();
+    }
+
+    fn segments_files(directory: &RAMDirectory) -> Vec<String> {
+        directory
+            .list_all()
+            .unwrap()
+            .into_iter()
+            .filter(|name| name.starts_with("segments_"))
+            .collect()
+    }
+
+    #[test]
+    fn test_snapshot_deletion_policy() {
+        let directory = Arc::new(RAMDirectory::new());
+        let policy = Arc::new(SnapshotDeletionPolicy::new(Box::new(
+            KeepOnlyLastCommitDeletionPolicy,
+        )));
+        let writer = new_writer(
+            &directory,
+            Arc::clone(&policy) as Arc<dyn IndexDeletionPolicy>,
+        );
+        assert!(policy.snapshot().is_err());
+
+        add_doc(&writer, "1");
+        writer.commit().unwrap();
+        let commit = policy.snapshot().unwrap();
+        assert_eq!(policy.snapshot_count().unwrap(), 1);
+
+        add_doc(&writer, "2");
+        writer.commit().unwrap();
+        // the snapshotted commit survives the new commit
+        for file in commit.file_names() {
+            assert!(directory.file_exists(file).unwrap(), "{} deleted", file);
+        }
+        assert_eq!(segments_files(&directory).len(), 2);
+
+        // a rolled back release protects the commit again
+        policy.release(&commit).unwrap();
+        assert!(policy.index_commit(commit.generation()).unwrap().is_none());
+        policy
+            .undo_release_gen(commit.generation(), Some(commit.clone()))
+            .unwrap();
+        assert_eq!(policy.snapshot_count().unwrap(), 1);
+        assert!(policy.index_commit(commit.generation()).unwrap().is_some());
+        assert_eq!(policy.snapshots().unwrap().len(), 1);
+
+        policy.release(&commit).unwrap();
+        assert!(policy.release(&commit).is_err());
+        writer.delete_unused_files().unwrap();
+        assert_eq!(segments_files(&directory).len(), 1);
+        assert!(!directory.file_exists(commit.segments_file_name()).unwrap());
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_keep_last_n_commits() {
+        assert!(KeepLastNCommitsDeletionPolicy::new(0).is_err());
+
+        let directory = Arc::new(RAMDirectory::new());
+        let policy = Arc::new(KeepLastNCommitsDeletionPolicy::new(2).unwrap());
+        let writer = new_writer(&directory, policy);
+        for id in &["1", "2", "3", "4"] {
+            add_doc(&writer, id);
+            writer.commit().unwrap();
+        }
+        assert_eq!(segments_files(&directory).len(), 2);
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_persistent_snapshot_deletion_policy() {
+        let directory = Arc::new(RAMDirectory::new());
+        let generation = {
+            let policy = Arc::new(
+                PersistentSnapshotDeletionPolicy::new(
+                    Box::new(KeepOnlyLastCommitDeletionPolicy),
+                    Arc::clone(&directory),
+                )
+                .unwrap(),
+            );
+            let writer = new_writer(
+                &directory,
+                Arc::clone(&policy) as Arc<dyn IndexDeletionPolicy>,
+            );
+            add_doc(&writer, "1");
+            writer.commit().unwrap();
+            let commit = policy.snapshot().unwrap();
+            assert_eq!(
+                policy.last_save_file().unwrap(),
+                Some(format!("{}0", SNAPSHOTS_PREFIX))
+            );
+            writer.close().unwrap();
+            commit.generation()
+        };
+
+        // the snapshot is loaded again and still protects its commit
+        let policy = Arc::new(
+            PersistentSnapshotDeletionPolicy::new(
+                Box::new(KeepOnlyLastCommitDeletionPolicy),
+                Arc::clone(&directory),
+            )
+            .unwrap(),
+        );
+        assert_eq!(policy.snapshot_count().unwrap(), 1);
+        let writer = new_writer(
+            &directory,
+            Arc::clone(&policy) as Arc<dyn IndexDeletionPolicy>,
+        );
+        add_doc(&writer, "2");
+        writer.commit().unwrap();
+        let commit = policy.index_commit(generation).unwrap().unwrap();
+        assert!(directory.file_exists(commit.segments_file_name()).unwrap());
+
+        policy.release_gen(generation).unwrap();
+        assert_eq!(policy.snapshot_count().unwrap(), 0);
+        assert_eq!(
+            policy.last_save_file().unwrap(),
+            Some(format!("{}1", SNAPSHOTS_PREFIX))
+        );
+        assert!(!directory
+            .file_exists(&format!("{}0", SNAPSHOTS_PREFIX))
+            .unwrap());
+        writer.delete_unused_files().unwrap();
+        assert_eq!(segments_files(&directory).len(), 1);
+        writer.close().unwrap();
+    }
+}
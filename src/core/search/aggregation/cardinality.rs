@@ -0,0 +1,464 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+
+use fasthash::murmur3;
+
+use core::codec::doc_values::{BinaryDocValues, SortedDocValues, SortedSetDocValues, NO_MORE_ORDS};
+use core::codec::Codec;
+use core::doc::DocValuesType;
+use core::index::reader::SearchLeafReader;
+use core::search::aggregation::{mapped_bucket, AggregationResult};
+use core::search::facet::{LeafNumericValues, NumericValuesSource};
+use core::util::{BitsMut, DocId};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+pub const MIN_PRECISION: u8 = 4;
+pub const MAX_PRECISION: u8 = 18;
+pub const DEFAULT_PRECISION: u8 = 14;
+
+// empirical thresholds from the HyperLogLog++ paper, below which linear counting
+// is more accurate than the raw HyperLogLog estimate, indexed by `precision - 4`.
+const THRESHOLDS: [f64; 15] = [
+    10.0, 20.0, 40.0, 80.0, 220.0, 400.0, 900.0, 1800.0, 3100.0, 6500.0, 11500.0, 20000.0, 50000.0,
+    120_000.0, 350_000.0,
+];
+
+/// Hashes a value the same way for every segment, so that counters of different
+/// segments can be merged.
+#[inline]
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    murmur3::hash128(bytes) as u64
+}
+
+/// HyperLogLog++ cardinality estimator.
+///
+/// While the number of distinct hashes is small the exact hashes are kept
+/// (the "sparse" representation), which gives exact counts for low
+/// cardinalities. Once it grows past a threshold the counter switches to the
+/// `2^precision` registers of a classic HyperLogLog, using linear counting
+/// for the lower range of the estimates.
+#[derive(Clone, Debug)]
+pub struct HyperLogLogPlusPlus {
+    precision: u8,
+    sparse: Option<HashSet<u64>>,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLogPlusPlus {
+    pub fn new(precision: u8) -> Result<HyperLogLogPlusPlus> {
+        if precision < MIN_PRECISION || precision > MAX_PRECISION {
+            bail!(IllegalArgument(format!(
+                "precision must be in [{}, {}], got {}",
+                MIN_PRECISION, MAX_PRECISION, precision
+            )));
+        }
+        Ok(HyperLogLogPlusPlus {
+            precision,
+            sparse: Some(HashSet::new()),
+            registers: Vec::new(),
+        })
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    #[inline]
+    fn num_registers(&self) -> usize {
+        1 << self.precision
+    }
+
+    // keeping more hashes than this in the sparse set costs more memory than the
+    // dense registers
+    #[inline]
+    fn sparse_threshold(&self) -> usize {
+        self.num_registers() / 8
+    }
+
+    pub fn add_hash(&mut self, hash: u64) {
+        if let Some(ref mut sparse) = self.sparse {
+            sparse.insert(hash);
+            if sparse.len() <= self.sparse_threshold() {
+                return;
+            }
+        } else {
+            self.add_dense(hash);
+            return;
+        }
+        self.upgrade_to_dense();
+    }
+
+    fn upgrade_to_dense(&mut self) {
+        if let Some(sparse) = self.sparse.take() {
+            self.registers = vec![0u8; self.num_registers()];
+            for hash in sparse {
+                self.add_dense(hash);
+            }
+        }
+    }
+
+    #[inline]
+    fn add_dense(&mut self, hash: u64) {
+        let p = self.precision as u32;
+        let index = (hash >> (64 - p)) as usize;
+        // the remaining bits, with a sentinel bit to bound the run of zeros
+        let w = (hash << p) | (1u64 << (p - 1));
+        let rank = (w.leading_zeros() + 1) as u8;
+        if self.registers[index] < rank {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Merges the hashes seen by `other` into this counter. Both counters
+    /// must use the same precision.
+    pub fn merge(&mut self, other: &HyperLogLogPlusPlus) {
+        debug_assert_eq!(self.precision, other.precision);
+        match other.sparse {
+            Some(ref sparse) => {
+                for hash in sparse {
+                    self.add_hash(*hash);
+                }
+            }
+            None => {
+                self.upgrade_to_dense();
+                for (r, o) in self.registers.iter_mut().zip(&other.registers) {
+                    if *r < *o {
+                        *r = *o;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the estimated number of distinct hashes.
+    pub fn cardinality(&self) -> u64 {
+        if let Some(ref sparse) = self.sparse {
+            return sparse.len() as u64;
+        }
+
+        let m = self.num_registers() as f64;
+        let mut sum = 0f64;
+        let mut zeros = 0usize;
+        for r in &self.registers {
+            sum += 1.0 / (1u64 << *r) as f64;
+            if *r == 0 {
+                zeros += 1;
+            }
+        }
+
+        if zeros > 0 {
+            let linear_counting = m * (m / zeros as f64).ln();
+            if linear_counting <= THRESHOLDS[(self.precision - MIN_PRECISION) as usize] {
+                return linear_counting.round() as u64;
+            }
+        }
+        let alpha = match self.precision {
+            4 => 0.673,
+            5 => 0.697,
+            6 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        (alpha * m * m / sum).round() as u64
+    }
+}
+
+/// The doc values a `CardinalityAggregator` reads the distinct values from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardinalitySource {
+    /// ordinals of a `SortedSetDocValues` field, each ordinal is hashed once
+    /// per segment
+    SortedSet,
+    /// ordinals of a `SortedDocValues` field
+    Sorted,
+    /// hashed bytes of a `BinaryDocValues` field
+    Binary,
+    /// hashed values of a numeric field
+    Numeric(NumericValuesSource),
+}
+
+impl CardinalitySource {
+    fn doc_values_type(self) -> DocValuesType {
+        match self {
+            CardinalitySource::SortedSet => DocValuesType::SortedSet,
+            CardinalitySource::Sorted => DocValuesType::Sorted,
+            CardinalitySource::Binary => DocValuesType::Binary,
+            CardinalitySource::Numeric(s) => s.doc_values_type(),
+        }
+    }
+}
+
+// the hashes of the terms are cached by segment ordinal, only for the
+// ordinals seen as the dictionary may be much larger than the collected docs
+enum LeafCardinalityValues {
+    SortedSet {
+        values: Box<dyn SortedSetDocValues>,
+        ord_hashes: HashMap<i64, u64>,
+    },
+    Sorted {
+        values: Box<dyn SortedDocValues>,
+        ord_hashes: HashMap<i32, u64>,
+    },
+    Binary {
+        values: Box<dyn BinaryDocValues>,
+        docs_with_field: Box<dyn BitsMut>,
+    },
+    Numeric {
+        values: LeafNumericValues,
+        scratch: Vec<i64>,
+    },
+}
+
+impl LeafCardinalityValues {
+    fn open<C: Codec>(
+        reader: &SearchLeafReader<C>,
+        field: &str,
+        source: CardinalitySource,
+    ) -> Result<Option<LeafCardinalityValues>> {
+        if let CardinalitySource::Numeric(s) = source {
+            return Ok(LeafNumericValues::open(reader, field, s)?.map(|values| {
+                LeafCardinalityValues::Numeric {
+                    values,
+                    scratch: Vec::new(),
+                }
+            }));
+        }
+
+        let dv_type = match reader.field_info(field) {
+            Some(fi) if !fi.doc_values_type.null() => fi.doc_values_type,
+            _ => {
+                return Ok(None);
+            }
+        };
+        if dv_type != source.doc_values_type() {
+            bail!(IllegalArgument(format!(
+                "field '{}' was indexed with doc values type {:?}, but {:?} was requested",
+                field,
+                dv_type,
+                source.doc_values_type()
+            )));
+        }
+        let values = match source {
+            CardinalitySource::SortedSet => {
+                let values = reader.get_sorted_set_doc_values(field)?;
+                LeafCardinalityValues::SortedSet {
+                    values,
+                    ord_hashes: HashMap::new(),
+                }
+            }
+            CardinalitySource::Sorted => {
+                let values = reader.get_sorted_doc_values(field)?;
+                LeafCardinalityValues::Sorted {
+                    values,
+                    ord_hashes: HashMap::new(),
+                }
+            }
+            CardinalitySource::Binary => LeafCardinalityValues::Binary {
+                values: reader.get_binary_doc_values(field)?,
+                docs_with_field: reader.get_docs_with_field(field)?,
+            },
+            CardinalitySource::Numeric(_) => unreachable!(),
+        };
+        Ok(Some(values))
+    }
+
+    fn collect(&mut self, doc: DocId, counter: &mut HyperLogLogPlusPlus) -> Result<()> {
+        match self {
+            LeafCardinalityValues::SortedSet { values, ord_hashes } => {
+                values.set_document(doc)?;
+                loop {
+                    let ord = values.next_ord()?;
+                    if ord == NO_MORE_ORDS {
+                        break;
+                    }
+                    let hash = match ord_hashes.get(&ord) {
+                        Some(h) => *h,
+                        None => {
+                            let h = hash_bytes(&values.lookup_ord(ord)?);
+                            ord_hashes.insert(ord, h);
+                            h
+                        }
+                    };
+                    counter.add_hash(hash);
+                }
+            }
+            LeafCardinalityValues::Sorted { values, ord_hashes } => {
+                let ord = values.get_ord(doc)?;
+                if ord >= 0 {
+                    let hash = match ord_hashes.get(&ord) {
+                        Some(h) => *h,
+                        None => {
+                            let h = hash_bytes(&values.lookup_ord(ord)?);
+                            ord_hashes.insert(ord, h);
+                            h
+                        }
+                    };
+                    counter.add_hash(hash);
+                }
+            }
+            LeafCardinalityValues::Binary {
+                values,
+                docs_with_field,
+            } => {
+                if docs_with_field.get(doc as usize)? {
+                    counter.add_hash(hash_bytes(&values.get(doc)?));
+                }
+            }
+            LeafCardinalityValues::Numeric { values, scratch } => {
+                values.read(doc, scratch)?;
+                for v in scratch.iter() {
+                    counter.add_hash(hash_bytes(&v.to_be_bytes()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Approximate count of the distinct values of a field, computed with
+/// `HyperLogLogPlusPlus`.
+pub struct CardinalityAggregator {
+    field: String,
+    source: CardinalitySource,
+    precision: u8,
+    // counters by bucket ordinal
+    counters: Vec<HyperLogLogPlusPlus>,
+    values: Option<LeafCardinalityValues>,
+}
+
+impl CardinalityAggregator {
+    pub fn new(
+        field: String,
+        source: CardinalitySource,
+        precision: u8,
+    ) -> Result<CardinalityAggregator> {
+        // validate the precision eagerly
+        HyperLogLogPlusPlus::new(precision)?;
+        Ok(CardinalityAggregator {
+            field,
+            source,
+            precision,
+            counters: Vec::new(),
+            values: None,
+        })
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub(crate) fn set_next_reader<C: Codec>(&mut self, reader: &SearchLeafReader<C>) -> Result<()> {
+        self.values = LeafCardinalityValues::open(reader, &self.field, self.source)?;
+        Ok(())
+    }
+
+    pub(crate) fn new_leaf<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+    ) -> Result<CardinalityAggregator> {
+        Ok(CardinalityAggregator {
+            field: self.field.clone(),
+            source: self.source,
+            precision: self.precision,
+            counters: Vec::new(),
+            values: LeafCardinalityValues::open(reader, &self.field, self.source)?,
+        })
+    }
+
+    fn counter_mut(&mut self, bucket: usize) -> &mut HyperLogLogPlusPlus {
+        while self.counters.len() <= bucket {
+            self.counters
+                .push(HyperLogLogPlusPlus::new(self.precision).unwrap());
+        }
+        &mut self.counters[bucket]
+    }
+
+    pub(crate) fn collect(&mut self, doc: DocId, bucket: usize) -> Result<()> {
+        if self.values.is_some() {
+            // make sure the counter exists before borrowing the values
+            self.counter_mut(bucket);
+            let counter = &mut self.counters[bucket];
+            self.values.as_mut().unwrap().collect(doc, counter)?;
+        }
+        Ok(())
+    }
+
+    /// `bucket_mapping[i]` is the bucket of this aggregator the bucket `i` of
+    /// `other` is merged into.
+    pub(crate) fn merge(&mut self, other: CardinalityAggregator, bucket_mapping: &[usize]) {
+        for (i, counter) in other.counters.iter().enumerate() {
+            if let Some(bucket) = mapped_bucket(bucket_mapping, i) {
+                self.counter_mut(bucket).merge(counter);
+            }
+        }
+    }
+
+    /// Estimated number of distinct values for the given bucket.
+    pub fn cardinality(&self, bucket: usize) -> u64 {
+        self.counters.get(bucket).map_or(0, |c| c.cardinality())
+    }
+
+    pub fn result(&self, bucket: usize) -> AggregationResult {
+        AggregationResult::Value(Some(self.cardinality(bucket) as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_is_exact() {
+        let mut hll = HyperLogLogPlusPlus::new(14).unwrap();
+        for i in 0..1000u32 {
+            hll.add_hash(hash_bytes(&i.to_be_bytes()));
+            // duplicates are ignored
+            hll.add_hash(hash_bytes(&i.to_be_bytes()));
+        }
+        assert!(hll.sparse.is_some());
+        assert_eq!(hll.cardinality(), 1000);
+    }
+
+    #[test]
+    fn test_dense_estimate() {
+        let mut hll = HyperLogLogPlusPlus::new(14).unwrap();
+        let n = 100_000u32;
+        for i in 0..n {
+            hll.add_hash(hash_bytes(&i.to_be_bytes()));
+        }
+        assert!(hll.sparse.is_none());
+        let error = (hll.cardinality() as f64 - n as f64).abs() / n as f64;
+        assert!(error < 0.05, "relative error too large: {}", error);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = HyperLogLogPlusPlus::new(10).unwrap();
+        let mut b = HyperLogLogPlusPlus::new(10).unwrap();
+        for i in 0..5000u32 {
+            a.add_hash(hash_bytes(&i.to_be_bytes()));
+        }
+        for i in 2500..7500u32 {
+            b.add_hash(hash_bytes(&i.to_be_bytes()));
+        }
+        a.merge(&b);
+        let error = (a.cardinality() as f64 - 7500.0).abs() / 7500.0;
+        assert!(error < 0.1, "relative error too large: {}", error);
+
+        assert!(HyperLogLogPlusPlus::new(3).is_err());
+        assert!(HyperLogLogPlusPlus::new(19).is_err());
+    }
+}
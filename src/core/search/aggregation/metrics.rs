@@ -0,0 +1,276 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::f64;
+
+use core::codec::Codec;
+use core::index::reader::SearchLeafReader;
+use core::search::aggregation::{mapped_bucket, AggregationResult};
+use core::search::facet::{
+    decode_numeric_value, LeafNumericValues, NumericValuesSource, NumericValuesType,
+};
+use core::util::DocId;
+
+use error::Result;
+
+/// The metric a `StatsAggregator` reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsMetric {
+    Min,
+    Max,
+    Sum,
+    Avg,
+    ValueCount,
+    /// all of the above at once
+    Stats,
+}
+
+/// Statistics over a set of numeric values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Stats {
+    #[inline]
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn merge(&mut self, other: &Stats) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Returns `None` if there was no value.
+    pub fn min(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.min)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `None` if there was no value.
+    pub fn max(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.max)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `None` if there was no value.
+    pub fn avg(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.sum / self.count as f64)
+        } else {
+            None
+        }
+    }
+}
+
+/// Computes `Stats` over the numeric doc values of a field for all the
+/// collected documents, and reports the requested `StatsMetric`.
+///
+/// All the values of a multi valued field are taken into account, so
+/// `ValueCount` counts values rather than documents.
+pub struct StatsAggregator {
+    field: String,
+    source: NumericValuesSource,
+    value_type: NumericValuesType,
+    metric: StatsMetric,
+    // stats by bucket ordinal
+    stats: Vec<Stats>,
+    values: Option<LeafNumericValues>,
+    scratch: Vec<i64>,
+}
+
+impl StatsAggregator {
+    pub fn new(
+        field: String,
+        source: NumericValuesSource,
+        value_type: NumericValuesType,
+        metric: StatsMetric,
+    ) -> StatsAggregator {
+        StatsAggregator {
+            field,
+            source,
+            value_type,
+            metric,
+            stats: Vec::new(),
+            values: None,
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn metric(&self) -> StatsMetric {
+        self.metric
+    }
+
+    pub(crate) fn set_next_reader<C: Codec>(&mut self, reader: &SearchLeafReader<C>) -> Result<()> {
+        self.values = LeafNumericValues::open(reader, &self.field, self.source)?;
+        Ok(())
+    }
+
+    pub(crate) fn new_leaf<C: Codec>(&self, reader: &SearchLeafReader<C>) -> Result<StatsAggregator> {
+        let mut aggregator = StatsAggregator::new(
+            self.field.clone(),
+            self.source,
+            self.value_type,
+            self.metric,
+        );
+        aggregator.set_next_reader(reader)?;
+        Ok(aggregator)
+    }
+
+    pub(crate) fn collect(&mut self, doc: DocId, bucket: usize) -> Result<()> {
+        match self.values {
+            Some(ref mut values) => values.read(doc, &mut self.scratch)?,
+            None => {
+                return Ok(());
+            }
+        }
+        for i in 0..self.scratch.len() {
+            let value = decode_numeric_value(self.scratch[i], self.source, self.value_type);
+            self.add_value(bucket, value);
+        }
+        Ok(())
+    }
+
+    fn stats_mut(&mut self, bucket: usize) -> &mut Stats {
+        if self.stats.len() <= bucket {
+            self.stats.resize(bucket + 1, Stats::default());
+        }
+        &mut self.stats[bucket]
+    }
+
+    #[inline]
+    pub(crate) fn add_value(&mut self, bucket: usize, value: f64) {
+        self.stats_mut(bucket).add(value);
+    }
+
+    /// `bucket_mapping[i]` is the bucket of this aggregator the bucket `i` of
+    /// `other` is merged into.
+    pub(crate) fn merge(&mut self, other: StatsAggregator, bucket_mapping: &[usize]) {
+        for (i, stats) in other.stats.iter().enumerate() {
+            if let Some(bucket) = mapped_bucket(bucket_mapping, i) {
+                self.stats_mut(bucket).merge(stats);
+            }
+        }
+    }
+
+    /// Returns the stats of the given bucket.
+    pub fn stats(&self, bucket: usize) -> Stats {
+        self.stats.get(bucket).cloned().unwrap_or_default()
+    }
+
+    /// Returns the value of the configured metric for the given bucket, `None`
+    /// if the metric is undefined because there was no value.
+    ///
+    /// For `StatsMetric::Stats` this returns the average.
+    pub fn value(&self, bucket: usize) -> Option<f64> {
+        let stats = self.stats(bucket);
+        match self.metric {
+            StatsMetric::Min => stats.min(),
+            StatsMetric::Max => stats.max(),
+            StatsMetric::Sum => Some(stats.sum),
+            StatsMetric::ValueCount => Some(stats.count as f64),
+            StatsMetric::Avg | StatsMetric::Stats => stats.avg(),
+        }
+    }
+
+    pub fn result(&self, bucket: usize) -> AggregationResult {
+        match self.metric {
+            StatsMetric::Stats => AggregationResult::Stats(self.stats(bucket)),
+            _ => AggregationResult::Value(self.value(bucket)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.min(), None);
+        assert_eq!(stats.avg(), None);
+
+        for v in &[3.0, -1.0, 4.0] {
+            stats.add(*v);
+        }
+        let mut other = Stats::default();
+        other.add(10.0);
+        stats.merge(&other);
+
+        assert_eq!(stats.count, 4);
+        assert_eq!(stats.min(), Some(-1.0));
+        assert_eq!(stats.max(), Some(10.0));
+        assert_eq!(stats.sum, 16.0);
+        assert_eq!(stats.avg(), Some(4.0));
+    }
+
+    #[test]
+    fn test_merge_with_mapping() {
+        let mut a = StatsAggregator::new(
+            "price".into(),
+            NumericValuesSource::Numeric,
+            NumericValuesType::Long,
+            StatsMetric::Max,
+        );
+        let mut b = StatsAggregator::new(
+            "price".into(),
+            NumericValuesSource::Numeric,
+            NumericValuesType::Long,
+            StatsMetric::Max,
+        );
+        a.stats = vec![Stats::default(); 2];
+        a.stats[0].add(1.0);
+        a.stats[1].add(2.0);
+        b.stats = vec![Stats::default(); 2];
+        b.stats[0].add(5.0);
+        b.stats[1].add(7.0);
+
+        // bucket 0 of `b` is bucket 1 of `a`, bucket 1 of `b` is a new bucket
+        a.merge(b, &[1, 2]);
+        assert_eq!(a.value(0), Some(1.0));
+        assert_eq!(a.value(1), Some(5.0));
+        assert_eq!(a.value(2), Some(7.0));
+        assert_eq!(a.value(3), None);
+    }
+}
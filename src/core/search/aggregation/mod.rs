@@ -0,0 +1,614 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Aggregations compute metrics over the documents matching a query.
+//!
+//! An `Aggregator` collects documents into *buckets* identified by an
+//! ordinal. Top level aggregators only use the bucket `0`, bucket aggregators
+//! create one bucket per key and let their sub aggregators collect into it.
+//! `AggregationsCollector` runs a set of named aggregators as a
+//! `SearchCollector`, so it can be chained with a `TopDocsCollector` through
+//! `ChainedCollector` to get both the hits and the aggregations in one search.
+//!
+//! The results serialize to the JSON format of Elasticsearch aggregations.
+
+mod metrics;
+
+pub use self::metrics::*;
+
+mod cardinality;
+
+pub use self::cardinality::*;
+
+mod percentiles;
+
+pub use self::percentiles::*;
+
+mod terms;
+
+pub use self::terms::*;
+
+use std::mem;
+use std::result;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use core::codec::Codec;
+use core::index::reader::{LeafReaderContext, SearchLeafReader};
+use core::search::collector::{Collector, ParallelLeafCollector, SearchCollector};
+use core::search::scorer::Scorer;
+use core::util::DocId;
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use error::ErrorKind::{IllegalArgument, IllegalState};
+use error::{Result, ResultExt};
+
+/// The bucket top level aggregators collect into.
+pub const TOP_LEVEL_BUCKET: usize = 0;
+
+/// Mapped to the buckets of a partial aggregator that are dropped on merge.
+pub(crate) const DISCARDED_BUCKET: usize = usize::MAX;
+
+/// Returns the bucket the bucket `bucket` of a partial aggregator is merged
+/// into, `None` if it is dropped.
+#[inline]
+pub(crate) fn mapped_bucket(bucket_mapping: &[usize], bucket: usize) -> Option<usize> {
+    match bucket_mapping.get(bucket) {
+        Some(b) if *b != DISCARDED_BUCKET => Some(*b),
+        _ => None,
+    }
+}
+
+/// Result of an aggregation for a single bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AggregationResult {
+    /// single value metric, `None` if undefined (e.g. the min of no value)
+    Value(Option<f64>),
+    Stats(Stats),
+    /// `(percent, value)` pairs
+    Percentiles(Vec<(f64, Option<f64>)>),
+    Terms(TermsResult),
+}
+
+impl AggregationResult {
+    /// Returns the value of a metric result, `name` selecting the value of
+    /// multi value metrics: a stat name (`count`, `min`, `max`, `avg`, `sum`)
+    /// or a percent.
+    pub fn metric_value(&self, name: Option<&str>) -> Option<f64> {
+        match (self, name) {
+            (AggregationResult::Value(v), None) | (AggregationResult::Value(v), Some("value")) => {
+                *v
+            }
+            (AggregationResult::Stats(s), Some("count")) => Some(s.count as f64),
+            (AggregationResult::Stats(s), Some("min")) => s.min(),
+            (AggregationResult::Stats(s), Some("max")) => s.max(),
+            (AggregationResult::Stats(s), Some("sum")) => Some(s.sum),
+            (AggregationResult::Stats(s), Some("avg")) | (AggregationResult::Stats(s), None) => {
+                s.avg()
+            }
+            (AggregationResult::Percentiles(values), Some(percent)) => {
+                let percent: f64 = percent.parse().ok()?;
+                values
+                    .iter()
+                    .find(|(p, _)| *p == percent)
+                    .and_then(|(_, v)| *v)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for AggregationResult {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            AggregationResult::Value(v) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("value", v)?;
+                map.end()
+            }
+            AggregationResult::Stats(s) => {
+                let mut map = serializer.serialize_map(Some(5))?;
+                map.serialize_entry("count", &s.count)?;
+                map.serialize_entry("min", &s.min())?;
+                map.serialize_entry("max", &s.max())?;
+                map.serialize_entry("avg", &s.avg())?;
+                map.serialize_entry("sum", &s.sum)?;
+                map.end()
+            }
+            AggregationResult::Percentiles(values) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("values", &PercentileValues(values))?;
+                map.end()
+            }
+            AggregationResult::Terms(terms) => {
+                let mut map = serializer.serialize_map(Some(3))?;
+                // -1 when the error is unbounded, like Elasticsearch
+                let error = terms
+                    .doc_count_error_upper_bound
+                    .map(|e| e as i64)
+                    .unwrap_or(-1);
+                map.serialize_entry("doc_count_error_upper_bound", &error)?;
+                map.serialize_entry("sum_other_doc_count", &terms.sum_other_doc_count)?;
+                map.serialize_entry("buckets", &terms.buckets)?;
+                map.end()
+            }
+        }
+    }
+}
+
+// percents are keyed by their string representation, e.g. `"99.0"`
+struct PercentileValues<'a>(&'a [(f64, Option<f64>)]);
+
+impl<'a> Serialize for PercentileValues<'a> {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (percent, value) in self.0 {
+            map.serialize_entry(&format!("{:?}", percent), value)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for TermsBucket {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(2 + self.sub_aggregations.len()))?;
+        map.serialize_entry("key", &String::from_utf8_lossy(&self.key))?;
+        map.serialize_entry("doc_count", &self.doc_count)?;
+        for (name, result) in &self.sub_aggregations {
+            map.serialize_entry(name, result)?;
+        }
+        map.end()
+    }
+}
+
+pub enum Aggregator {
+    Stats(StatsAggregator),
+    Cardinality(CardinalityAggregator),
+    Percentiles(PercentilesAggregator),
+    Terms(Box<TermsAggregator>),
+}
+
+impl Aggregator {
+    pub(crate) fn set_next_reader<C: Codec>(&mut self, reader: &SearchLeafReader<C>) -> Result<()> {
+        match self {
+            Aggregator::Stats(a) => a.set_next_reader(reader),
+            Aggregator::Cardinality(a) => a.set_next_reader(reader),
+            Aggregator::Percentiles(a) => a.set_next_reader(reader),
+            Aggregator::Terms(a) => a.set_next_reader(reader),
+        }
+    }
+
+    /// Creates an empty aggregator with the same settings, positioned on
+    /// `reader`, used to collect a segment in parallel.
+    pub(crate) fn new_leaf<C: Codec>(&self, reader: &SearchLeafReader<C>) -> Result<Aggregator> {
+        let aggregator = match self {
+            Aggregator::Stats(a) => Aggregator::Stats(a.new_leaf(reader)?),
+            Aggregator::Cardinality(a) => Aggregator::Cardinality(a.new_leaf(reader)?),
+            Aggregator::Percentiles(a) => Aggregator::Percentiles(a.new_leaf(reader)?),
+            Aggregator::Terms(a) => Aggregator::Terms(Box::new(a.new_leaf(reader)?)),
+        };
+        Ok(aggregator)
+    }
+
+    #[inline]
+    pub(crate) fn collect(&mut self, doc: DocId, bucket: usize) -> Result<()> {
+        match self {
+            Aggregator::Stats(a) => a.collect(doc, bucket),
+            Aggregator::Cardinality(a) => a.collect(doc, bucket),
+            Aggregator::Percentiles(a) => a.collect(doc, bucket),
+            Aggregator::Terms(a) => a.collect(doc, bucket),
+        }
+    }
+
+    /// Merges the buckets of `other` into this aggregator, `bucket_mapping[i]`
+    /// being the bucket the bucket `i` of `other` is merged into.
+    pub(crate) fn merge(&mut self, other: Aggregator, bucket_mapping: &[usize]) -> Result<()> {
+        match (self, other) {
+            (Aggregator::Stats(a), Aggregator::Stats(b)) => a.merge(b, bucket_mapping),
+            (Aggregator::Cardinality(a), Aggregator::Cardinality(b)) => a.merge(b, bucket_mapping),
+            (Aggregator::Percentiles(a), Aggregator::Percentiles(b)) => a.merge(b, bucket_mapping),
+            (Aggregator::Terms(a), Aggregator::Terms(b)) => {
+                return a.merge(*b, bucket_mapping);
+            }
+            _ => bail!(IllegalState(
+                "can't merge aggregators of different types".into()
+            )),
+        }
+        Ok(())
+    }
+
+    pub fn result(&self, bucket: usize) -> AggregationResult {
+        match self {
+            Aggregator::Stats(a) => a.result(bucket),
+            Aggregator::Cardinality(a) => a.result(bucket),
+            Aggregator::Percentiles(a) => a.result(bucket),
+            Aggregator::Terms(a) => a.result(bucket),
+        }
+    }
+}
+
+impl From<StatsAggregator> for Aggregator {
+    fn from(a: StatsAggregator) -> Aggregator {
+        Aggregator::Stats(a)
+    }
+}
+
+impl From<CardinalityAggregator> for Aggregator {
+    fn from(a: CardinalityAggregator) -> Aggregator {
+        Aggregator::Cardinality(a)
+    }
+}
+
+impl From<PercentilesAggregator> for Aggregator {
+    fn from(a: PercentilesAggregator) -> Aggregator {
+        Aggregator::Percentiles(a)
+    }
+}
+
+impl From<TermsAggregator> for Aggregator {
+    fn from(a: TermsAggregator) -> Aggregator {
+        Aggregator::Terms(Box::new(a))
+    }
+}
+
+/// `Collector` running a set of named aggregations over all the matching
+/// documents.
+///
+/// ```rust, ignore
+/// let mut aggs = AggregationsCollector::default();
+/// aggs.add("max_price", StatsAggregator::new(
+///     "price".into(),
+///     NumericValuesSource::Numeric,
+///     NumericValuesType::Long,
+///     StatsMetric::Max,
+/// ))?;
+/// let mut top_docs = TopDocsCollector::new(10);
+/// {
+///     let mut collector = ChainedCollector::new(&mut top_docs, &mut aggs);
+///     searcher.search(&query, &mut collector)?;
+/// }
+/// let max_price = aggs.result("max_price");
+/// let json = serde_json::to_string(&aggs)?;
+/// ```
+#[derive(Default)]
+pub struct AggregationsCollector {
+    aggregations: Vec<(String, Aggregator)>,
+    channel: Option<(Sender<Vec<Aggregator>>, Receiver<Vec<Aggregator>>)>,
+}
+
+impl AggregationsCollector {
+    /// Adds an aggregation, `name` must be unique within this collector.
+    pub fn add<A: Into<Aggregator>>(&mut self, name: &str, aggregator: A) -> Result<()> {
+        if self.aggregations.iter().any(|(n, _)| n == name) {
+            bail!(IllegalArgument(format!(
+                "duplicate aggregation name '{}'",
+                name
+            )));
+        }
+        self.aggregations
+            .push((name.to_string(), aggregator.into()));
+        Ok(())
+    }
+
+    pub fn aggregator(&self, name: &str) -> Option<&Aggregator> {
+        self.aggregations
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, a)| a)
+    }
+
+    /// Returns the result of the aggregation named `name`.
+    pub fn result(&self, name: &str) -> Option<AggregationResult> {
+        self.aggregator(name).map(|a| a.result(TOP_LEVEL_BUCKET))
+    }
+
+    /// Returns the results of all the aggregations, in the order they were added.
+    pub fn results(&self) -> Vec<(String, AggregationResult)> {
+        self.aggregations
+            .iter()
+            .map(|(n, a)| (n.clone(), a.result(TOP_LEVEL_BUCKET)))
+            .collect()
+    }
+}
+
+/// Serializes the results of all the aggregations, keyed by name.
+impl Serialize for AggregationsCollector {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.aggregations.len()))?;
+        for (name, aggregator) in &self.aggregations {
+            map.serialize_entry(name, &aggregator.result(TOP_LEVEL_BUCKET))?;
+        }
+        map.end()
+    }
+}
+
+impl SearchCollector for AggregationsCollector {
+    type LC = AggregationsLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        for (_, aggregator) in &mut self.aggregations {
+            aggregator.set_next_reader(reader.reader)?;
+        }
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn init_parallel(&mut self) {
+        if self.channel.is_none() {
+            self.channel = Some(channel());
+        }
+    }
+
+    fn leaf_collector<C: Codec>(&self, reader: &LeafReaderContext<'_, C>) -> Result<Self::LC> {
+        let mut aggregators = Vec::with_capacity(self.aggregations.len());
+        for (_, aggregator) in &self.aggregations {
+            aggregators.push(aggregator.new_leaf(reader.reader)?);
+        }
+        Ok(AggregationsLeafCollector {
+            aggregators,
+            channel: self.channel.as_ref().unwrap().0.clone(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        // iff all the `weight.create_scorer(leaf_reader)` return None, the channel won't
+        // inited and thus stay None
+        if let Some((sender, receiver)) = self.channel.take() {
+            drop(sender);
+            while let Ok(aggregators) = receiver.recv() {
+                for ((_, aggregator), other) in self.aggregations.iter_mut().zip(aggregators) {
+                    aggregator.merge(other, &[TOP_LEVEL_BUCKET])?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Collector for AggregationsCollector {
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        for (_, aggregator) in &mut self.aggregations {
+            aggregator.collect(doc, TOP_LEVEL_BUCKET)?;
+        }
+        Ok(())
+    }
+}
+
+/// Segment collector of `AggregationsCollector` for parallel search.
+pub struct AggregationsLeafCollector {
+    aggregators: Vec<Aggregator>,
+    channel: Sender<Vec<Aggregator>>,
+}
+
+impl ParallelLeafCollector for AggregationsLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        let aggregators = mem::replace(&mut self.aggregators, Vec::new());
+        self.channel
+            .send(aggregators)
+            .chain_err(|| IllegalState("channel unexpected closed before search complete".into()))
+    }
+}
+
+impl Collector for AggregationsLeafCollector {
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        for aggregator in &mut self.aggregators {
+            aggregator.collect(doc, TOP_LEVEL_BUCKET)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+    use core::doc::{DocValuesType, Field, FieldType, Fieldable, NumericDocValuesField};
+    use core::index::reader::IndexReader;
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::search::facet::{NumericValuesSource, NumericValuesType};
+    use core::search::query::MatchAllDocsQuery;
+    use core::search::{DefaultIndexSearcher, IndexSearcher};
+    use core::store::directory::FSDirectory;
+    use core::util::VariantValue;
+    use serde_json;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn test_serialize_results() {
+        let mut stats = Stats::default();
+        stats.add(2.0);
+        stats.add(4.0);
+        let terms = AggregationResult::Terms(TermsResult {
+            doc_count_error_upper_bound: Some(0),
+            sum_other_doc_count: 1,
+            buckets: vec![TermsBucket {
+                key: b"rust".to_vec(),
+                doc_count: 2,
+                sub_aggregations: vec![
+                    ("price".into(), AggregationResult::Stats(stats)),
+                    ("max".into(), AggregationResult::Value(None)),
+                    (
+                        "latency".into(),
+                        AggregationResult::Percentiles(vec![(99.0, Some(1.5))]),
+                    ),
+                ],
+            }],
+        });
+        assert_eq!(
+            serde_json::to_string(&terms).unwrap(),
+            "{\"doc_count_error_upper_bound\":0,\"sum_other_doc_count\":1,\"buckets\":[\
+             {\"key\":\"rust\",\"doc_count\":2,\
+             \"price\":{\"count\":2,\"min\":2.0,\"max\":4.0,\"avg\":3.0,\"sum\":6.0},\
+             \"max\":{\"value\":null},\
+             \"latency\":{\"values\":{\"99.0\":1.5}}}]}"
+        );
+
+        let unbounded = AggregationResult::Terms(TermsResult {
+            doc_count_error_upper_bound: None,
+            sum_other_doc_count: 0,
+            buckets: vec![],
+        });
+        assert_eq!(
+            serde_json::to_string(&unbounded).unwrap(),
+            "{\"doc_count_error_upper_bound\":-1,\"sum_other_doc_count\":0,\"buckets\":[]}"
+        );
+    }
+
+    #[test]
+    fn test_metric_value() {
+        let mut stats = Stats::default();
+        stats.add(1.0);
+        stats.add(5.0);
+        let stats = AggregationResult::Stats(stats);
+        assert_eq!(stats.metric_value(Some("max")), Some(5.0));
+        assert_eq!(stats.metric_value(None), Some(3.0));
+        assert_eq!(stats.metric_value(Some("unknown")), None);
+
+        let percentiles = AggregationResult::Percentiles(vec![(50.0, Some(2.0))]);
+        assert_eq!(percentiles.metric_value(Some("50")), Some(2.0));
+        assert_eq!(percentiles.metric_value(Some("99")), None);
+    }
+
+    fn new_collector() -> AggregationsCollector {
+        let mut aggs = AggregationsCollector::default();
+        aggs.add(
+            "price_stats",
+            StatsAggregator::new(
+                "price".into(),
+                NumericValuesSource::Numeric,
+                NumericValuesType::Long,
+                StatsMetric::Stats,
+            ),
+        )
+        .unwrap();
+        let numeric = CardinalitySource::Numeric(NumericValuesSource::Numeric);
+        aggs.add(
+            "price_cardinality",
+            CardinalityAggregator::new("price".into(), numeric, 14).unwrap(),
+        )
+        .unwrap();
+        aggs.add(
+            "tag_cardinality",
+            CardinalityAggregator::new("tag".into(), CardinalitySource::SortedSet, 14).unwrap(),
+        )
+        .unwrap();
+        aggs.add(
+            "price_percentiles",
+            PercentilesAggregator::new(
+                "price".into(),
+                NumericValuesSource::Numeric,
+                NumericValuesType::Long,
+                vec![0.0, 50.0, 100.0],
+                100.0,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        aggs
+    }
+
+    fn check_results(aggs: &AggregationsCollector) {
+        let stats = Stats {
+            count: 30,
+            sum: 435.0,
+            min: 0.0,
+            max: 29.0,
+        };
+        assert_eq!(
+            aggs.result("price_stats"),
+            Some(AggregationResult::Stats(stats))
+        );
+        assert_eq!(
+            aggs.result("price_cardinality"),
+            Some(AggregationResult::Value(Some(30.0)))
+        );
+        assert_eq!(
+            aggs.result("tag_cardinality"),
+            Some(AggregationResult::Value(Some(7.0)))
+        );
+        let percentiles = aggs.result("price_percentiles").unwrap();
+        assert_eq!(percentiles.metric_value(Some("0")), Some(0.0));
+        assert_eq!(percentiles.metric_value(Some("100")), Some(29.0));
+        let median = percentiles.metric_value(Some("50")).unwrap();
+        assert!((median - 14.5).abs() <= 1.0, "median {}", median);
+    }
+
+    #[test]
+    fn test_collect_segments() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(IndexWriterConfig::default())).unwrap();
+        let tag_type = FieldType {
+            doc_values_type: DocValuesType::SortedSet,
+            ..FieldType::default()
+        };
+        for segment in 0..3 {
+            for i in segment * 10..(segment + 1) * 10 {
+                let tag = format!("tag{}", i % 7);
+                let doc: Vec<Box<dyn Fieldable>> = vec![
+                    Box::new(NumericDocValuesField::new("price", i)),
+                    Box::new(Field::new(
+                        "tag".into(),
+                        tag_type.clone(),
+                        Some(VariantValue::from(tag.as_bytes())),
+                        None,
+                    )),
+                ];
+                writer.add_document(doc).unwrap();
+            }
+            writer.commit().unwrap();
+        }
+        let reader = Arc::new(writer.get_reader(true, false).unwrap());
+        assert_eq!(reader.leaves().len(), 3);
+
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader), None);
+        let mut aggs = new_collector();
+        searcher.search(&MatchAllDocsQuery, &mut aggs).unwrap();
+        check_results(&aggs);
+
+        // each segment is collected by its own aggregators, merged at the end
+        let mut searcher = DefaultIndexSearcher::new(reader, None);
+        searcher.with_thread_pool(3);
+        let mut aggs = new_collector();
+        searcher
+            .search_parallel(&MatchAllDocsQuery, &mut aggs)
+            .unwrap();
+        check_results(&aggs);
+    }
+}
@@ -0,0 +1,377 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::f64;
+use std::f64::consts::PI;
+use std::mem;
+
+use core::codec::Codec;
+use core::index::reader::SearchLeafReader;
+use core::search::aggregation::{mapped_bucket, AggregationResult};
+use core::search::facet::{
+    decode_numeric_value, LeafNumericValues, NumericValuesSource, NumericValuesType,
+};
+use core::util::DocId;
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+pub const DEFAULT_COMPRESSION: f64 = 100.0;
+
+pub const DEFAULT_PERCENTS: [f64; 7] = [1.0, 5.0, 25.0, 50.0, 75.0, 95.0, 99.0];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Centroid {
+    mean: f64,
+    count: f64,
+}
+
+/// A merging t-digest, which summarizes a distribution of values in a bounded
+/// number of centroids and estimates its quantiles, with better accuracy
+/// near the tails of the distribution.
+///
+/// Incoming values are buffered and merged into the centroids once the
+/// buffer is full, sizing the centroids with the `k1` (arcsine) scale function.
+#[derive(Clone, Debug)]
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<f64>,
+    buffer_size: usize,
+    total_count: f64,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> Result<TDigest> {
+        if compression.is_nan() || compression < 1.0 {
+            bail!(IllegalArgument(format!(
+                "compression must be >= 1, got {}",
+                compression
+            )));
+        }
+        let buffer_size = (compression * 5.0).ceil() as usize;
+        Ok(TDigest {
+            compression,
+            centroids: Vec::new(),
+            buffer: Vec::with_capacity(buffer_size),
+            buffer_size,
+            total_count: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        })
+    }
+
+    pub fn compression(&self) -> f64 {
+        self.compression
+    }
+
+    /// Number of values added to this digest.
+    pub fn count(&self) -> u64 {
+        (self.total_count + self.buffer.len() as f64) as u64
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.buffer.push(value);
+        if self.buffer.len() >= self.buffer_size {
+            self.compress();
+        }
+    }
+
+    /// Merges all the values summarized by `other` into this digest.
+    pub fn merge(&mut self, other: &TDigest) {
+        self.buffer.extend_from_slice(&other.buffer);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.total_count += other.total_count;
+        self.centroids.extend_from_slice(&other.centroids);
+        self.compress();
+    }
+
+    #[inline]
+    fn k(&self, q: f64) -> f64 {
+        self.compression / (2.0 * PI) * (2.0 * q - 1.0).asin()
+    }
+
+    #[inline]
+    fn k_inv(&self, k: f64) -> f64 {
+        ((k * 2.0 * PI / self.compression).sin() + 1.0) / 2.0
+    }
+
+    /// Merges the buffered values into the centroids.
+    pub fn compress(&mut self) {
+        let mut centroids = mem::replace(&mut self.centroids, Vec::new());
+        for v in self.buffer.drain(..) {
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+            self.total_count += 1.0;
+            centroids.push(Centroid {
+                mean: v,
+                count: 1.0,
+            });
+        }
+        if centroids.is_empty() {
+            return;
+        }
+        centroids.sort_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap_or(Ordering::Equal));
+
+        let total = self.total_count;
+        let mut merged = Vec::with_capacity(centroids.len());
+        let mut iter = centroids.into_iter();
+        let mut current = iter.next().unwrap();
+        let mut weight_so_far = 0.0;
+        let mut limit = total * self.k_inv(self.k(0.0) + 1.0);
+        for next in iter {
+            let proposed = current.count + next.count;
+            if weight_so_far + proposed <= limit {
+                current.mean += (next.mean - current.mean) * next.count / proposed;
+                current.count = proposed;
+            } else {
+                weight_so_far += current.count;
+                merged.push(current);
+                limit = total * self.k_inv(self.k(weight_so_far / total) + 1.0);
+                current = next;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    /// Estimates the value at quantile `q`, with `q` in `[0, 1]`. Returns `NaN`
+    /// if the digest is empty.
+    ///
+    /// The digest must have been compressed since the last value was added.
+    pub fn quantile(&self, q: f64) -> f64 {
+        debug_assert!(self.buffer.is_empty());
+        let n = self.centroids.len();
+        if n == 0 {
+            return f64::NAN;
+        }
+        if n == 1 || q <= 0.0 {
+            return if n == 1 { self.centroids[0].mean } else { self.min };
+        }
+        if q >= 1.0 {
+            return self.max;
+        }
+
+        let index = q * self.total_count;
+        // each centroid is centered on the middle of the values it summarizes
+        let first = &self.centroids[0];
+        if index < first.count / 2.0 {
+            return self.min + (first.mean - self.min) * index / (first.count / 2.0);
+        }
+        let mut weight_so_far = first.count / 2.0;
+        for i in 0..n - 1 {
+            let (left, right) = (&self.centroids[i], &self.centroids[i + 1]);
+            let dw = (left.count + right.count) / 2.0;
+            if weight_so_far + dw > index {
+                let t = (index - weight_so_far) / dw;
+                return left.mean + t * (right.mean - left.mean);
+            }
+            weight_so_far += dw;
+        }
+        let last = &self.centroids[n - 1];
+        let t = (index - weight_so_far) / (last.count / 2.0);
+        last.mean + t.min(1.0) * (self.max - last.mean)
+    }
+}
+
+/// Approximate percentiles of the numeric values of a field, computed with a
+/// `TDigest`.
+pub struct PercentilesAggregator {
+    field: String,
+    source: NumericValuesSource,
+    value_type: NumericValuesType,
+    percents: Vec<f64>,
+    compression: f64,
+    // digests by bucket ordinal
+    digests: Vec<TDigest>,
+    values: Option<LeafNumericValues>,
+    scratch: Vec<i64>,
+}
+
+impl PercentilesAggregator {
+    pub fn new(
+        field: String,
+        source: NumericValuesSource,
+        value_type: NumericValuesType,
+        percents: Vec<f64>,
+        compression: f64,
+    ) -> Result<PercentilesAggregator> {
+        for p in &percents {
+            if p.is_nan() || *p < 0.0 || *p > 100.0 {
+                bail!(IllegalArgument(format!(
+                    "percent must be in [0, 100], got {}",
+                    p
+                )));
+            }
+        }
+        // validate the compression eagerly
+        TDigest::new(compression)?;
+        Ok(PercentilesAggregator {
+            field,
+            source,
+            value_type,
+            percents,
+            compression,
+            digests: Vec::new(),
+            values: None,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn percents(&self) -> &[f64] {
+        &self.percents
+    }
+
+    pub(crate) fn set_next_reader<C: Codec>(&mut self, reader: &SearchLeafReader<C>) -> Result<()> {
+        self.values = LeafNumericValues::open(reader, &self.field, self.source)?;
+        Ok(())
+    }
+
+    pub(crate) fn new_leaf<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+    ) -> Result<PercentilesAggregator> {
+        Ok(PercentilesAggregator {
+            field: self.field.clone(),
+            source: self.source,
+            value_type: self.value_type,
+            percents: self.percents.clone(),
+            compression: self.compression,
+            digests: Vec::new(),
+            values: LeafNumericValues::open(reader, &self.field, self.source)?,
+            scratch: Vec::new(),
+        })
+    }
+
+    fn digest_mut(&mut self, bucket: usize) -> &mut TDigest {
+        while self.digests.len() <= bucket {
+            self.digests.push(TDigest::new(self.compression).unwrap());
+        }
+        &mut self.digests[bucket]
+    }
+
+    pub(crate) fn collect(&mut self, doc: DocId, bucket: usize) -> Result<()> {
+        if let Some(ref mut values) = self.values {
+            values.read(doc, &mut self.scratch)?;
+            if self.scratch.is_empty() {
+                return Ok(());
+            }
+            while self.digests.len() <= bucket {
+                self.digests.push(TDigest::new(self.compression).unwrap());
+            }
+            let digest = &mut self.digests[bucket];
+            for raw in &self.scratch {
+                digest.add(decode_numeric_value(*raw, self.source, self.value_type));
+            }
+        }
+        Ok(())
+    }
+
+    /// `bucket_mapping[i]` is the bucket of this aggregator the bucket `i` of
+    /// `other` is merged into.
+    pub(crate) fn merge(&mut self, other: PercentilesAggregator, bucket_mapping: &[usize]) {
+        for (i, digest) in other.digests.iter().enumerate() {
+            if let Some(bucket) = mapped_bucket(bucket_mapping, i) {
+                self.digest_mut(bucket).merge(digest);
+            }
+        }
+    }
+
+    /// Returns the `(percent, value)` pairs for the given bucket, the value is
+    /// `None` if the bucket has no value.
+    pub fn percentiles(&self, bucket: usize) -> Vec<(f64, Option<f64>)> {
+        match self.digests.get(bucket) {
+            Some(digest) => {
+                let mut digest = digest.clone();
+                digest.compress();
+                self.percents
+                    .iter()
+                    .map(|p| {
+                        let v = digest.quantile(*p / 100.0);
+                        (*p, if v.is_nan() { None } else { Some(v) })
+                    })
+                    .collect()
+            }
+            None => self.percents.iter().map(|p| (*p, None)).collect(),
+        }
+    }
+
+    pub fn result(&self, bucket: usize) -> AggregationResult {
+        AggregationResult::Percentiles(self.percentiles(bucket))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform_quantiles() {
+        let mut digest = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        for i in 0..10_000 {
+            digest.add(i as f64);
+        }
+        digest.compress();
+        assert_eq!(digest.count(), 10_000);
+        assert!(digest.centroids.len() < 10_000);
+        assert_eq!(digest.quantile(0.0), 0.0);
+        assert_eq!(digest.quantile(1.0), 9999.0);
+        for q in &[0.01, 0.25, 0.5, 0.75, 0.99] {
+            let expected = q * 10_000.0;
+            let actual = digest.quantile(*q);
+            assert!(
+                (actual - expected).abs() < 100.0,
+                "quantile {} expected {} got {}",
+                q,
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        let mut b = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        for i in 0..5000 {
+            a.add(i as f64);
+            b.add((i + 5000) as f64);
+        }
+        a.merge(&b);
+        assert_eq!(a.count(), 10_000);
+        let median = a.quantile(0.5);
+        assert!((median - 5000.0).abs() < 100.0, "median {}", median);
+    }
+
+    #[test]
+    fn test_empty_and_single() {
+        let mut digest = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        digest.compress();
+        assert!(digest.quantile(0.5).is_nan());
+        digest.add(3.0);
+        digest.compress();
+        assert_eq!(digest.quantile(0.1), 3.0);
+        assert_eq!(digest.quantile(0.9), 3.0);
+    }
+}
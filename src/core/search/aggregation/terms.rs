@@ -0,0 +1,636 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem;
+
+use core::codec::doc_values::{SortedDocValues, SortedSetDocValues, NO_MORE_ORDS};
+use core::codec::Codec;
+use core::doc::DocValuesType;
+use core::index::reader::SearchLeafReader;
+use core::search::aggregation::{mapped_bucket, AggregationResult, Aggregator, DISCARDED_BUCKET};
+use core::util::DocId;
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+pub const DEFAULT_TERMS_SIZE: usize = 10;
+
+/// The doc values a `TermsAggregator` reads its bucket keys from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TermsSource {
+    Sorted,
+    SortedSet,
+}
+
+impl TermsSource {
+    fn doc_values_type(self) -> DocValuesType {
+        match self {
+            TermsSource::Sorted => DocValuesType::Sorted,
+            TermsSource::SortedSet => DocValuesType::SortedSet,
+        }
+    }
+}
+
+/// How the buckets of a `TermsAggregator` are ordered. Ties are broken by
+/// ascending key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TermsOrder {
+    Count {
+        asc: bool,
+    },
+    Key {
+        asc: bool,
+    },
+    /// by the value of a metric sub aggregation. `path` is the name of the sub
+    /// aggregation, optionally followed by a `.` and the name of the value for
+    /// multi value metrics, e.g. `price_stats.max` or `latency.99.0`.
+    ///
+    /// Buckets without a value are always sorted last.
+    Metric {
+        path: String,
+        asc: bool,
+    },
+}
+
+impl Default for TermsOrder {
+    fn default() -> TermsOrder {
+        TermsOrder::Count { asc: false }
+    }
+}
+
+/// A bucket of a `TermsResult`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermsBucket {
+    pub key: Vec<u8>,
+    pub doc_count: u64,
+    pub sub_aggregations: Vec<(String, AggregationResult)>,
+}
+
+/// Result of a `TermsAggregator` for a single bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermsResult {
+    /// upper bound of the doc count error of each returned bucket, not zero
+    /// only if some partial results were truncated to `shard_size` buckets.
+    /// `None` unless ordered by descending count, the error is unbounded for
+    /// the other orders
+    pub doc_count_error_upper_bound: Option<u64>,
+    /// number of documents in buckets that were not returned
+    pub sum_other_doc_count: u64,
+    pub buckets: Vec<TermsBucket>,
+}
+
+enum LeafOrdValues {
+    Sorted(Box<dyn SortedDocValues>),
+    SortedSet(Box<dyn SortedSetDocValues>),
+}
+
+impl LeafOrdValues {
+    fn open<C: Codec>(
+        reader: &SearchLeafReader<C>,
+        field: &str,
+        source: TermsSource,
+    ) -> Result<Option<LeafOrdValues>> {
+        let dv_type = match reader.field_info(field) {
+            Some(fi) if !fi.doc_values_type.null() => fi.doc_values_type,
+            _ => {
+                return Ok(None);
+            }
+        };
+        if dv_type != source.doc_values_type() {
+            bail!(IllegalArgument(format!(
+                "field '{}' was indexed with doc values type {:?}, but {:?} was requested",
+                field,
+                dv_type,
+                source.doc_values_type()
+            )));
+        }
+        let values = match source {
+            TermsSource::Sorted => LeafOrdValues::Sorted(reader.get_sorted_doc_values(field)?),
+            TermsSource::SortedSet => {
+                LeafOrdValues::SortedSet(reader.get_sorted_set_doc_values(field)?)
+            }
+        };
+        Ok(Some(values))
+    }
+
+    fn read_ords(&mut self, doc: DocId, ords: &mut Vec<i64>) -> Result<()> {
+        ords.clear();
+        match self {
+            LeafOrdValues::Sorted(dv) => {
+                let ord = dv.get_ord(doc)?;
+                if ord >= 0 {
+                    ords.push(ord as i64);
+                }
+            }
+            LeafOrdValues::SortedSet(dv) => {
+                dv.set_document(doc)?;
+                loop {
+                    let ord = dv.next_ord()?;
+                    if ord == NO_MORE_ORDS {
+                        break;
+                    }
+                    ords.push(ord);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup_ord(&mut self, ord: i64) -> Result<Vec<u8>> {
+        match self {
+            LeafOrdValues::Sorted(dv) => dv.lookup_ord(ord as i32),
+            LeafOrdValues::SortedSet(dv) => dv.lookup_ord(ord),
+        }
+    }
+}
+
+struct BucketState {
+    key: Vec<u8>,
+    doc_count: u64,
+}
+
+/// Bucket aggregation creating one bucket per distinct value of a `Sorted` or
+/// `SortedSet` doc values field, each bucket running its own instance of the
+/// sub aggregations.
+///
+/// Segment ordinals are resolved to their term once per segment and bucket,
+/// so that buckets are shared across segments. When collecting in parallel,
+/// each segment only contributes its top `shard_size` buckets, which may make
+/// the counts approximate for high cardinality fields.
+pub struct TermsAggregator {
+    field: String,
+    source: TermsSource,
+    size: usize,
+    shard_size: usize,
+    min_doc_count: u64,
+    order: TermsOrder,
+    sub_aggregations: Vec<(String, Aggregator)>,
+
+    buckets: Vec<BucketState>,
+    bucket_ords: HashMap<(usize, Vec<u8>), usize>,
+    buckets_by_parent: HashMap<usize, Vec<usize>>,
+    doc_count_errors: HashMap<usize, u64>,
+
+    values: Option<LeafOrdValues>,
+    // (parent bucket, segment ordinal) -> bucket
+    segment_bucket_ords: HashMap<(usize, i64), usize>,
+    scratch: Vec<i64>,
+}
+
+impl TermsAggregator {
+    pub fn new(field: String, source: TermsSource, size: usize) -> TermsAggregator {
+        TermsAggregator {
+            field,
+            source,
+            size,
+            shard_size: Self::default_shard_size(size),
+            min_doc_count: 1,
+            order: TermsOrder::default(),
+            sub_aggregations: Vec::new(),
+            buckets: Vec::new(),
+            bucket_ords: HashMap::new(),
+            buckets_by_parent: HashMap::new(),
+            doc_count_errors: HashMap::new(),
+            values: None,
+            segment_bucket_ords: HashMap::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    fn default_shard_size(size: usize) -> usize {
+        size + size / 2 + 10
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Sets the number of buckets each segment contributes in parallel
+    /// search, it can't be lower than `size`.
+    pub fn set_shard_size(&mut self, shard_size: usize) {
+        self.shard_size = shard_size.max(self.size);
+    }
+
+    /// Buckets with fewer documents are not returned. Only the terms of
+    /// collected documents are known, so `0` behaves like `1`.
+    pub fn set_min_doc_count(&mut self, min_doc_count: u64) {
+        self.min_doc_count = min_doc_count;
+    }
+
+    /// Sets the order of the buckets. When ordering by a metric, the sub
+    /// aggregation must have been added first.
+    pub fn set_order(&mut self, order: TermsOrder) -> Result<()> {
+        if let TermsOrder::Metric { ref path, .. } = order {
+            let name = path.split('.').next().unwrap();
+            match self.sub_aggregation(name) {
+                Some(Aggregator::Terms(_)) => bail!(IllegalArgument(format!(
+                    "can't order by '{}': not a metric aggregation",
+                    path
+                ))),
+                Some(_) => {}
+                None => bail!(IllegalArgument(format!(
+                    "can't order by '{}': unknown sub aggregation",
+                    path
+                ))),
+            }
+        }
+        self.order = order;
+        Ok(())
+    }
+
+    /// Adds a sub aggregation, run for each bucket; `name` must be unique
+    /// within this aggregator.
+    pub fn add_sub_aggregation<A: Into<Aggregator>>(
+        &mut self,
+        name: &str,
+        aggregator: A,
+    ) -> Result<()> {
+        if self.sub_aggregation(name).is_some() {
+            bail!(IllegalArgument(format!(
+                "duplicate aggregation name '{}'",
+                name
+            )));
+        }
+        self.sub_aggregations
+            .push((name.to_string(), aggregator.into()));
+        Ok(())
+    }
+
+    pub fn sub_aggregation(&self, name: &str) -> Option<&Aggregator> {
+        self.sub_aggregations
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, a)| a)
+    }
+
+    pub(crate) fn set_next_reader<C: Codec>(&mut self, reader: &SearchLeafReader<C>) -> Result<()> {
+        self.values = LeafOrdValues::open(reader, &self.field, self.source)?;
+        self.segment_bucket_ords.clear();
+        for (_, aggregator) in &mut self.sub_aggregations {
+            aggregator.set_next_reader(reader)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn new_leaf<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+    ) -> Result<TermsAggregator> {
+        let mut aggregator = TermsAggregator::new(self.field.clone(), self.source, self.size);
+        aggregator.shard_size = self.shard_size;
+        aggregator.min_doc_count = self.min_doc_count;
+        aggregator.order = self.order.clone();
+        for (name, sub) in &self.sub_aggregations {
+            aggregator
+                .sub_aggregations
+                .push((name.clone(), sub.new_leaf(reader)?));
+        }
+        aggregator.values = LeafOrdValues::open(reader, &self.field, self.source)?;
+        Ok(aggregator)
+    }
+
+    // returns the bucket for `key` under `parent`, creating it if needed
+    fn bucket_ord(&mut self, parent: usize, key: Vec<u8>) -> usize {
+        let entry = (parent, key);
+        if let Some(bucket) = self.bucket_ords.get(&entry) {
+            return *bucket;
+        }
+        let bucket = self.buckets.len();
+        self.buckets.push(BucketState {
+            key: entry.1.clone(),
+            doc_count: 0,
+        });
+        self.buckets_by_parent
+            .entry(parent)
+            .or_insert_with(Vec::new)
+            .push(bucket);
+        self.bucket_ords.insert(entry, bucket);
+        bucket
+    }
+
+    pub(crate) fn collect(&mut self, doc: DocId, parent: usize) -> Result<()> {
+        if self.values.is_none() {
+            return Ok(());
+        }
+        let mut ords = mem::replace(&mut self.scratch, Vec::new());
+        self.values.as_mut().unwrap().read_ords(doc, &mut ords)?;
+        for ord in &ords {
+            let bucket = match self.segment_bucket_ords.get(&(parent, *ord)) {
+                Some(bucket) => *bucket,
+                None => {
+                    let key = self.values.as_mut().unwrap().lookup_ord(*ord)?;
+                    let bucket = self.bucket_ord(parent, key);
+                    self.segment_bucket_ords.insert((parent, *ord), bucket);
+                    bucket
+                }
+            };
+            self.buckets[bucket].doc_count += 1;
+            for (_, aggregator) in &mut self.sub_aggregations {
+                aggregator.collect(doc, bucket)?;
+            }
+        }
+        self.scratch = ords;
+        Ok(())
+    }
+
+    /// Merges a partial aggregator collected on another segment, keeping only
+    /// its top `shard_size` buckets for each parent bucket.
+    pub(crate) fn merge(
+        &mut self,
+        mut other: TermsAggregator,
+        parent_mapping: &[usize],
+    ) -> Result<()> {
+        let mut mapping = vec![DISCARDED_BUCKET; other.buckets.len()];
+        let mut parents: Vec<usize> = other.buckets_by_parent.keys().cloned().collect();
+        parents.sort();
+        for parent in parents {
+            let new_parent = match mapped_bucket(parent_mapping, parent) {
+                Some(p) => p,
+                None => {
+                    continue;
+                }
+            };
+            let ords = other.sorted_buckets(parent);
+            let kept = ords.len().min(other.shard_size);
+            if self.bounds_doc_count_error() {
+                let mut error = other.doc_count_errors.get(&parent).cloned().unwrap_or(0);
+                if kept < ords.len() && kept > 0 {
+                    error += other.buckets[ords[kept - 1]].doc_count;
+                }
+                if error > 0 {
+                    *self.doc_count_errors.entry(new_parent).or_insert(0) += error;
+                }
+            }
+            for &i in &ords[..kept] {
+                let key = mem::replace(&mut other.buckets[i].key, Vec::new());
+                let bucket = self.bucket_ord(new_parent, key);
+                self.buckets[bucket].doc_count += other.buckets[i].doc_count;
+                mapping[i] = bucket;
+            }
+        }
+
+        let others = mem::replace(&mut other.sub_aggregations, Vec::new());
+        for ((_, aggregator), (_, other)) in self.sub_aggregations.iter_mut().zip(others) {
+            aggregator.merge(other, &mapping)?;
+        }
+        Ok(())
+    }
+
+    // a dropped term has at most the count of the last kept one only if the
+    // buckets are sorted by descending count
+    fn bounds_doc_count_error(&self) -> bool {
+        self.order == TermsOrder::Count { asc: false }
+    }
+
+    // returns the buckets of `parent` in the configured order
+    fn sorted_buckets(&self, parent: usize) -> Vec<usize> {
+        let ords = match self.buckets_by_parent.get(&parent) {
+            Some(ords) => ords,
+            None => {
+                return vec![];
+            }
+        };
+        let buckets = &self.buckets;
+        let by_key = |a: usize, b: usize| buckets[a].key.cmp(&buckets[b].key);
+        match self.order {
+            TermsOrder::Count { asc } => {
+                let mut ords = ords.clone();
+                ords.sort_by(|a, b| {
+                    let ord = buckets[*a].doc_count.cmp(&buckets[*b].doc_count);
+                    let ord = if asc { ord } else { ord.reverse() };
+                    ord.then_with(|| by_key(*a, *b))
+                });
+                ords
+            }
+            TermsOrder::Key { asc } => {
+                let mut ords = ords.clone();
+                ords.sort_by(|a, b| {
+                    let ord = by_key(*a, *b);
+                    if asc {
+                        ord
+                    } else {
+                        ord.reverse()
+                    }
+                });
+                ords
+            }
+            TermsOrder::Metric { ref path, asc } => {
+                let mut parts = path.splitn(2, '.');
+                let name = parts.next().unwrap();
+                let value_name = parts.next();
+                let aggregator = self.sub_aggregation(name);
+                let mut keyed: Vec<(usize, Option<f64>)> = ords
+                    .iter()
+                    .map(|o| {
+                        let value = aggregator
+                            .and_then(|a| a.result(*o).metric_value(value_name))
+                            .filter(|v| !v.is_nan());
+                        (*o, value)
+                    })
+                    .collect();
+                keyed.sort_by(|(a, va), (b, vb)| {
+                    let ord = match (va, vb) {
+                        (Some(x), Some(y)) => {
+                            let ord = x.partial_cmp(y).unwrap_or(Ordering::Equal);
+                            if asc {
+                                ord
+                            } else {
+                                ord.reverse()
+                            }
+                        }
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    };
+                    ord.then_with(|| by_key(*a, *b))
+                });
+                keyed.into_iter().map(|(o, _)| o).collect()
+            }
+        }
+    }
+
+    /// Returns the top `size` buckets created under the bucket `parent`.
+    pub fn terms_result(&self, parent: usize) -> TermsResult {
+        let ords = self.sorted_buckets(parent);
+        let total: u64 = ords.iter().map(|o| self.buckets[*o].doc_count).sum();
+        let buckets: Vec<TermsBucket> = ords
+            .into_iter()
+            .filter(|o| self.buckets[*o].doc_count >= self.min_doc_count)
+            .take(self.size)
+            .map(|o| TermsBucket {
+                key: self.buckets[o].key.clone(),
+                doc_count: self.buckets[o].doc_count,
+                sub_aggregations: self
+                    .sub_aggregations
+                    .iter()
+                    .map(|(n, a)| (n.clone(), a.result(o)))
+                    .collect(),
+            })
+            .collect();
+        let returned: u64 = buckets.iter().map(|b| b.doc_count).sum();
+        let doc_count_error_upper_bound = if self.bounds_doc_count_error() {
+            Some(self.doc_count_errors.get(&parent).cloned().unwrap_or(0))
+        } else {
+            None
+        };
+        TermsResult {
+            doc_count_error_upper_bound,
+            sum_other_doc_count: total - returned,
+            buckets,
+        }
+    }
+
+    pub fn result(&self, parent: usize) -> AggregationResult {
+        AggregationResult::Terms(self.terms_result(parent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::aggregation::{StatsAggregator, StatsMetric, TOP_LEVEL_BUCKET};
+    use core::search::facet::{NumericValuesSource, NumericValuesType};
+
+    fn add_bucket(agg: &mut TermsAggregator, parent: usize, key: &str, doc_count: u64) -> usize {
+        let bucket = agg.bucket_ord(parent, key.as_bytes().to_vec());
+        agg.buckets[bucket].doc_count += doc_count;
+        bucket
+    }
+
+    #[test]
+    fn test_order_and_size() {
+        let mut agg = TermsAggregator::new("tag".into(), TermsSource::SortedSet, 2);
+        add_bucket(&mut agg, TOP_LEVEL_BUCKET, "b", 3);
+        add_bucket(&mut agg, TOP_LEVEL_BUCKET, "a", 3);
+        add_bucket(&mut agg, TOP_LEVEL_BUCKET, "c", 5);
+        add_bucket(&mut agg, TOP_LEVEL_BUCKET, "d", 1);
+
+        let result = agg.terms_result(TOP_LEVEL_BUCKET);
+        let keys: Vec<&[u8]> = result.buckets.iter().map(|b| b.key.as_slice()).collect();
+        assert_eq!(keys, vec![b"c".as_ref(), b"a".as_ref()]);
+        assert_eq!(result.sum_other_doc_count, 4);
+
+        agg.set_order(TermsOrder::Key { asc: false }).unwrap();
+        agg.set_min_doc_count(2);
+        let result = agg.terms_result(TOP_LEVEL_BUCKET);
+        let keys: Vec<&[u8]> = result.buckets.iter().map(|b| b.key.as_slice()).collect();
+        assert_eq!(keys, vec![b"c".as_ref(), b"b".as_ref()]);
+    }
+
+    #[test]
+    fn test_order_by_metric() {
+        let mut agg = TermsAggregator::new("tag".into(), TermsSource::Sorted, 10);
+        agg.add_sub_aggregation(
+            "max_price",
+            StatsAggregator::new(
+                "price".into(),
+                NumericValuesSource::Numeric,
+                NumericValuesType::Long,
+                StatsMetric::Max,
+            ),
+        )
+        .unwrap();
+        assert!(agg
+            .set_order(TermsOrder::Metric {
+                path: "unknown".into(),
+                asc: true,
+            })
+            .is_err());
+        agg.set_order(TermsOrder::Metric {
+            path: "max_price".into(),
+            asc: true,
+        })
+        .unwrap();
+
+        let a = add_bucket(&mut agg, TOP_LEVEL_BUCKET, "a", 1);
+        let b = add_bucket(&mut agg, TOP_LEVEL_BUCKET, "b", 1);
+        add_bucket(&mut agg, TOP_LEVEL_BUCKET, "c", 1);
+        if let Aggregator::Stats(ref mut stats) = agg.sub_aggregations[0].1 {
+            let mut other = StatsAggregator::new(
+                "price".into(),
+                NumericValuesSource::Numeric,
+                NumericValuesType::Long,
+                StatsMetric::Max,
+            );
+            other.add_value(0, 10.0);
+            other.add_value(1, 5.0);
+            stats.merge(other, &[a, b]);
+        }
+
+        let result = agg.terms_result(TOP_LEVEL_BUCKET);
+        let keys: Vec<&[u8]> = result.buckets.iter().map(|b| b.key.as_slice()).collect();
+        // "c" has no value, so it is sorted last
+        assert_eq!(keys, vec![b"b".as_ref(), b"a".as_ref(), b"c".as_ref()]);
+        assert_eq!(
+            result.buckets[0].sub_aggregations[0],
+            ("max_price".to_string(), AggregationResult::Value(Some(5.0)))
+        );
+    }
+
+    #[test]
+    fn test_merge_with_shard_size() {
+        let mut agg = TermsAggregator::new("tag".into(), TermsSource::SortedSet, 1);
+        agg.set_shard_size(2);
+        add_bucket(&mut agg, TOP_LEVEL_BUCKET, "a", 4);
+
+        let mut other = TermsAggregator::new("tag".into(), TermsSource::SortedSet, 1);
+        other.set_shard_size(2);
+        add_bucket(&mut other, TOP_LEVEL_BUCKET, "a", 2);
+        add_bucket(&mut other, TOP_LEVEL_BUCKET, "b", 3);
+        add_bucket(&mut other, TOP_LEVEL_BUCKET, "c", 1);
+        agg.merge(other, &[TOP_LEVEL_BUCKET]).unwrap();
+
+        let result = agg.terms_result(TOP_LEVEL_BUCKET);
+        assert_eq!(result.buckets.len(), 1);
+        assert_eq!(result.buckets[0].key, b"a".to_vec());
+        assert_eq!(result.buckets[0].doc_count, 6);
+        // "c" was dropped from the partial result
+        assert_eq!(result.sum_other_doc_count, 3);
+        assert_eq!(result.doc_count_error_upper_bound, Some(2));
+    }
+
+    #[test]
+    fn test_doc_count_error_only_by_count_desc() {
+        let new_agg = |order: TermsOrder, counts: &[(&str, u64)]| {
+            let mut agg = TermsAggregator::new("tag".into(), TermsSource::SortedSet, 1);
+            agg.set_shard_size(1);
+            agg.set_order(order).unwrap();
+            for &(key, count) in counts {
+                add_bucket(&mut agg, TOP_LEVEL_BUCKET, key, count);
+            }
+            agg
+        };
+        for &asc in &[true, false] {
+            for order in vec![TermsOrder::Count { asc }, TermsOrder::Key { asc }] {
+                let mut agg = new_agg(order.clone(), &[("a", 4)]);
+                let other = new_agg(order.clone(), &[("a", 2), ("b", 3), ("c", 1)]);
+                agg.merge(other, &[TOP_LEVEL_BUCKET]).unwrap();
+                let error = agg
+                    .terms_result(TOP_LEVEL_BUCKET)
+                    .doc_count_error_upper_bound;
+                if order == (TermsOrder::Count { asc: false }) {
+                    // "a" and "c" were dropped, "b" was kept
+                    assert_eq!(error, Some(3));
+                } else {
+                    assert_eq!(error, None);
+                }
+            }
+        }
+    }
+}
@@ -15,6 +15,10 @@ mod top_docs;
 
 pub use self::top_docs::*;
 
+mod top_field;
+
+pub use self::top_field::*;
+
 mod early_terminating;
 
 pub use self::early_terminating::*;
@@ -0,0 +1,488 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::f32;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use core::codec::Codec;
+use core::index::reader::LeafReaderContext;
+use core::search::collector::{Collector, ParallelLeafCollector, SearchCollector};
+use core::search::scorer::Scorer;
+use core::search::sort_field::{
+    ComparatorValue, FieldComparator, FieldComparatorEnum, FieldDoc, ScoreDocHit, Sort,
+    SortFieldType, TopDocs, TopFieldDocs,
+};
+use core::util::DocId;
+use error::{ErrorKind::IllegalState, Result, ResultExt};
+
+/// A `Collector` that sorts the hits by the `SortField`s of a `Sort` and
+/// keeps the top `num_hits` ones.
+///
+/// Each hit is copied into a slot of the `FieldComparator`s, and the slots
+/// are kept in a heap with the least competitive hit on top, so that
+/// `FieldComparator::compare_bottom` can reject a document before it is
+/// copied. Ties are broken by ascending doc id.
+///
+/// The returned `TopDocs::Field` holds `FieldDoc`s whose `fields` are the
+/// sort values of each hit, in the order of the sort. The score of a hit is
+/// `NaN` unless the sort needs scores.
+///
+/// In parallel search each segment is collected into its own top `num_hits`,
+/// and those hits are merged by comparing their sort values with
+/// `FieldComparator::compare_values`.
+pub struct TopFieldCollector {
+    sort: Sort,
+    comparators: Vec<FieldComparatorEnum>,
+    // use score instead of doc as comparator value
+    score_comparators: Vec<bool>,
+    num_hits: usize,
+    // slots in heap order, the least competitive one first
+    heap: Vec<usize>,
+    // global doc id by slot
+    docs: Vec<DocId>,
+    scores: Vec<f32>,
+    needs_scores: bool,
+    total_hits: usize,
+    max_score: f32,
+    cur_doc_base: DocId,
+    // hits merged from the segments collected in parallel, sorted
+    merged: Vec<FieldDoc>,
+    channel: Option<(Sender<LeafTopFieldDocs>, Receiver<LeafTopFieldDocs>)>,
+}
+
+impl TopFieldCollector {
+    pub fn new(sort: Sort, num_hits: usize) -> TopFieldCollector {
+        let comparators: Vec<_> = sort
+            .get_sort()
+            .iter()
+            .map(|f| f.get_comparator(num_hits, f.missing_value()))
+            .collect();
+        let score_comparators = comparators
+            .iter()
+            .map(|c| c.get_type() == SortFieldType::Score)
+            .collect();
+        let needs_scores = sort.needs_scores();
+        TopFieldCollector {
+            sort,
+            comparators,
+            score_comparators,
+            num_hits,
+            heap: Vec::with_capacity(num_hits),
+            docs: Vec::with_capacity(num_hits),
+            scores: Vec::with_capacity(num_hits),
+            needs_scores,
+            total_hits: 0,
+            max_score: f32::NAN,
+            cur_doc_base: 0,
+            merged: vec![],
+            channel: None,
+        }
+    }
+
+    pub fn sort(&self) -> &Sort {
+        &self.sort
+    }
+
+    /// Returns `Less` if the hit of `slot1` ranks before the one of `slot2`.
+    fn compare_slots(&self, slot1: usize, slot2: usize) -> Ordering {
+        for (comparator, sort_field) in self.comparators.iter().zip(self.sort.get_sort()) {
+            let mut c = comparator.compare(slot1, slot2);
+            if sort_field.is_reverse() {
+                c = c.reverse();
+            }
+            if c != Ordering::Equal {
+                return c;
+            }
+        }
+        self.docs[slot1].cmp(&self.docs[slot2])
+    }
+
+    /// Same as `compare_slots` for hits whose sort values are already known.
+    fn compare_field_docs(&self, doc1: &FieldDoc, doc2: &FieldDoc) -> Ordering {
+        for (i, (comparator, sort_field)) in self
+            .comparators
+            .iter()
+            .zip(self.sort.get_sort())
+            .enumerate()
+        {
+            let mut c = comparator.compare_values(&doc1.fields[i], &doc2.fields[i]);
+            if sort_field.is_reverse() {
+                c = c.reverse();
+            }
+            if c != Ordering::Equal {
+                return c;
+            }
+        }
+        doc1.doc.cmp(&doc2.doc)
+    }
+
+    // the collected hits, sorted
+    fn field_docs(&self) -> Vec<FieldDoc> {
+        let mut slots = self.heap.clone();
+        slots.sort_by(|s1, s2| self.compare_slots(*s1, *s2));
+        slots
+            .into_iter()
+            .map(|slot| {
+                let fields = self.comparators.iter().map(|c| c.value(slot)).collect();
+                FieldDoc::new(self.docs[slot], self.scores[slot], fields)
+            })
+            .collect()
+    }
+
+    fn merge(&mut self, leaf: LeafTopFieldDocs) {
+        self.total_hits += leaf.total_hits;
+        if self.max_score.is_nan() || leaf.max_score > self.max_score {
+            self.max_score = leaf.max_score;
+        }
+        let mut merged = mem::replace(&mut self.merged, vec![]);
+        merged.extend(leaf.docs);
+        merged.sort_by(|d1, d2| self.compare_field_docs(d1, d2));
+        merged.truncate(self.num_hits);
+        self.merged = merged;
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.compare_slots(self.heap[i], self.heap[parent]) != Ordering::Greater {
+                break;
+            }
+            self.heap.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        let len = self.heap.len();
+        loop {
+            let mut worst = i;
+            for child in &[2 * i + 1, 2 * i + 2] {
+                if *child < len
+                    && self.compare_slots(self.heap[*child], self.heap[worst]) == Ordering::Greater
+                {
+                    worst = *child;
+                }
+            }
+            if worst == i {
+                break;
+            }
+            self.heap.swap(i, worst);
+            i = worst;
+        }
+    }
+
+    fn comparator_value(&self, i: usize, doc: DocId, score: f32) -> ComparatorValue {
+        if self.score_comparators[i] {
+            ComparatorValue::Score(score)
+        } else {
+            ComparatorValue::Doc(doc)
+        }
+    }
+
+    // whether the document ranks before the bottom of the full queue
+    fn is_competitive(&mut self, doc: DocId, score: f32) -> Result<bool> {
+        for i in 0..self.comparators.len() {
+            let value = self.comparator_value(i, doc, score);
+            let mut c = self.comparators[i].compare_bottom(value)?;
+            if self.sort.get_sort()[i].is_reverse() {
+                c = c.reverse();
+            }
+            match c {
+                Ordering::Less => return Ok(false),
+                Ordering::Greater => return Ok(true),
+                Ordering::Equal => {}
+            }
+        }
+        // documents are collected in doc id order, so the bottom wins the tie
+        Ok(false)
+    }
+
+    fn copy(&mut self, slot: usize, doc: DocId, score: f32) -> Result<()> {
+        for i in 0..self.comparators.len() {
+            let value = self.comparator_value(i, doc, score);
+            self.comparators[i].copy(slot, value)?;
+        }
+        Ok(())
+    }
+
+    fn update_bottom(&mut self) {
+        let bottom = self.heap[0];
+        for comparator in &mut self.comparators {
+            comparator.set_bottom(bottom);
+        }
+    }
+
+    /// Returns the top hits, sorted, as `TopDocs::Field`.
+    pub fn top_docs(&self) -> TopDocs {
+        let mut field_docs = self.field_docs();
+        if !self.merged.is_empty() {
+            field_docs.extend(self.merged.iter().cloned());
+            field_docs.sort_by(|d1, d2| self.compare_field_docs(d1, d2));
+            field_docs.truncate(self.num_hits);
+        }
+        let score_docs = field_docs.into_iter().map(ScoreDocHit::Field).collect();
+        TopDocs::Field(TopFieldDocs {
+            total_hits: self.total_hits,
+            score_docs,
+            max_score: self.max_score,
+            fields: self.sort.get_sort().to_vec(),
+        })
+    }
+}
+
+impl SearchCollector for TopFieldCollector {
+    type LC = TopFieldLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.cur_doc_base = reader.doc_base;
+        for comparator in &mut self.comparators {
+            comparator.get_information_from_reader(reader)?;
+        }
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn init_parallel(&mut self) {
+        if self.channel.is_none() {
+            self.channel = Some(channel());
+        }
+    }
+
+    fn leaf_collector<C: Codec>(
+        &self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<TopFieldLeafCollector> {
+        let mut collector = TopFieldCollector::new(self.sort.clone(), self.num_hits);
+        collector.set_next_reader(reader)?;
+        Ok(TopFieldLeafCollector {
+            collector,
+            channel: self.channel.as_ref().unwrap().0.clone(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        // iff all the `weight.create_scorer(leaf_reader)` return None, the channel won't
+        // inited and thus stay None
+        if let Some((sender, receiver)) = self.channel.take() {
+            drop(sender);
+            while let Ok(leaf) = receiver.recv() {
+                self.merge(leaf);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Collector for TopFieldCollector {
+    fn needs_scores(&self) -> bool {
+        self.needs_scores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        self.total_hits += 1;
+        if self.num_hits == 0 {
+            return Ok(());
+        }
+        let score = if self.needs_scores {
+            let score = scorer.score()?;
+            if self.max_score.is_nan() || score > self.max_score {
+                self.max_score = score;
+            }
+            score
+        } else {
+            f32::NAN
+        };
+
+        let slot = if self.heap.len() < self.num_hits {
+            let slot = self.heap.len();
+            self.docs.push(0);
+            self.scores.push(f32::NAN);
+            slot
+        } else if self.is_competitive(doc, score)? {
+            self.heap[0]
+        } else {
+            return Ok(());
+        };
+
+        self.copy(slot, doc, score)?;
+        self.docs[slot] = self.cur_doc_base + doc;
+        self.scores[slot] = score;
+        if slot < self.heap.len() {
+            // replaced the bottom
+            self.sift_down(0);
+        } else {
+            self.heap.push(slot);
+            let last = self.heap.len() - 1;
+            self.sift_up(last);
+        }
+        if self.heap.len() == self.num_hits {
+            self.update_bottom();
+        }
+        Ok(())
+    }
+}
+
+struct LeafTopFieldDocs {
+    // sorted
+    docs: Vec<FieldDoc>,
+    total_hits: usize,
+    max_score: f32,
+}
+
+/// Segment collector of `TopFieldCollector` for parallel search.
+pub struct TopFieldLeafCollector {
+    collector: TopFieldCollector,
+    channel: Sender<LeafTopFieldDocs>,
+}
+
+impl ParallelLeafCollector for TopFieldLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        let top_docs = LeafTopFieldDocs {
+            docs: self.collector.field_docs(),
+            total_hits: self.collector.total_hits,
+            max_score: self.collector.max_score,
+        };
+        self.channel
+            .send(top_docs)
+            .chain_err(|| IllegalState("channel unexpected closed before search complete".into()))
+    }
+}
+
+impl Collector for TopFieldLeafCollector {
+    fn needs_scores(&self) -> bool {
+        self.collector.needs_scores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        self.collector.collect(doc, scorer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::tests::*;
+
+    use core::index::reader::IndexReader;
+    use core::index::tests::*;
+    use core::search::sort_field::{SimpleSortField, SortField};
+    use core::search::*;
+    use core::util::VariantValue;
+
+    fn collect_all(collector: &mut TopFieldCollector, docs: Vec<DocId>) {
+        let mut scorer = create_mock_scorer(docs);
+        let leaf_reader = MockLeafReader::new(0);
+        let index_reader = MockIndexReader::new(vec![leaf_reader]);
+        let leaf_reader_context = index_reader.leaves();
+        collector.set_next_reader(&leaf_reader_context[0]).unwrap();
+        loop {
+            let doc = scorer.next().unwrap();
+            if doc == NO_MORE_DOCS {
+                break;
+            }
+            collector.collect(doc, &mut scorer).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sort_by_doc_reverse() {
+        let sort = Sort::new(vec![SortField::Simple(SimpleSortField::new(
+            String::new(),
+            SortFieldType::Doc,
+            true,
+        ))]);
+        let mut collector = TopFieldCollector::new(sort, 3);
+        assert!(!collector.needs_scores());
+        collect_all(&mut collector, vec![1, 2, 3, 4, 5]);
+
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 5);
+        let docs: Vec<DocId> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        assert_eq!(docs, vec![5, 4, 3]);
+        match top_docs.score_docs()[1] {
+            ScoreDocHit::Field(ref field_doc) => {
+                assert_eq!(field_doc.fields, vec![VariantValue::Int(4)]);
+                assert!(field_doc.score.is_nan());
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_sort_by_score() {
+        let mut collector = TopFieldCollector::new(Sort::new(vec![SortField::new_score()]), 2);
+        assert!(collector.needs_scores());
+        collect_all(&mut collector, vec![2, 7, 3, 9]);
+
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 4);
+        let docs: Vec<DocId> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        assert_eq!(docs, vec![9, 7]);
+        match top_docs {
+            TopDocs::Field(ref field_docs) => assert_eq!(field_docs.max_score, 9.0),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_parallel_collect() {
+        let index_reader =
+            MockIndexReader::new(vec![MockLeafReader::new(10), MockLeafReader::new(10)]);
+        let leaf_reader_context = index_reader.leaves();
+        let segment_docs = vec![vec![2, 7, 3, 9], vec![1, 8]];
+
+        let sort = Sort::new(vec![
+            SortField::new_score(),
+            SortField::Simple(SimpleSortField::new(
+                String::new(),
+                SortFieldType::Doc,
+                false,
+            )),
+        ]);
+        let mut collector = TopFieldCollector::new(sort, 3);
+        assert!(collector.support_parallel());
+        collector.init_parallel();
+        for (ctx, docs) in leaf_reader_context.iter().zip(segment_docs) {
+            let mut leaf_collector = collector.leaf_collector(ctx).unwrap();
+            let mut scorer = create_mock_scorer(docs);
+            loop {
+                let doc = scorer.next().unwrap();
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                leaf_collector.collect(doc, &mut scorer).unwrap();
+            }
+            leaf_collector.finish_leaf().unwrap();
+        }
+        collector.finish_parallel().unwrap();
+
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 6);
+        let docs: Vec<DocId> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        assert_eq!(docs, vec![9, 18, 7]);
+        match top_docs.score_docs()[1] {
+            ScoreDocHit::Field(ref field_doc) => {
+                assert_eq!(
+                    field_doc.fields,
+                    vec![VariantValue::Float(8.0), VariantValue::Int(18)]
+                );
+            }
+            _ => unreachable!(),
+        }
+    }
+}
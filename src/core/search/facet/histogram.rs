@@ -0,0 +1,482 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::i64;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use core::codec::Codec;
+use core::index::reader::LeafReaderContext;
+use core::search::collector::{Collector, ParallelLeafCollector, SearchCollector};
+use core::search::facet::{
+    decode_numeric_value, LeafNumericValues, NumericValuesSource, NumericValuesType,
+};
+use core::search::scorer::Scorer;
+use core::util::DocId;
+
+use error::ErrorKind::{IllegalArgument, IllegalState};
+use error::{Result, ResultExt};
+
+/// Maximum number of buckets `HistogramCollector::buckets` may return when
+/// empty buckets are filled in.
+pub const MAX_HISTOGRAM_BUCKETS: i64 = 65_536;
+
+/// A single bucket of a histogram, holding the documents whose value is in
+/// `[key, key + interval)`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HistogramBucket {
+    pub key: f64,
+    pub doc_count: i64,
+}
+
+/// Parameters shared by the main and the per segment histogram collectors.
+#[derive(Clone, Copy, Debug)]
+struct HistogramSpec {
+    source: NumericValuesSource,
+    value_type: NumericValuesType,
+    interval: f64,
+    offset: f64,
+}
+
+impl HistogramSpec {
+    /// Returns the bucket of the given value, or `None` if the value is NaN
+    /// or infinite, or if its bucket doesn't fit in an `i64`.
+    #[inline]
+    fn bucket_ord(&self, raw: i64) -> Option<i64> {
+        let value = decode_numeric_value(raw, self.source, self.value_type);
+        let ord = ((value - self.offset) / self.interval).floor();
+        // `i64::MAX as f64` rounds up to 2^63, which is out of range already
+        if ord.is_finite() && ord >= i64::MIN as f64 && ord < i64::MAX as f64 {
+            Some(ord as i64)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn bucket_key(&self, ord: i64) -> f64 {
+        ord as f64 * self.interval + self.offset
+    }
+}
+
+struct HistogramCounter {
+    spec: HistogramSpec,
+    // bucket ord -> doc count
+    counts: BTreeMap<i64, i64>,
+    values: Option<LeafNumericValues>,
+    scratch: Vec<i64>,
+}
+
+impl HistogramCounter {
+    fn new(spec: HistogramSpec, values: Option<LeafNumericValues>) -> HistogramCounter {
+        HistogramCounter {
+            spec,
+            counts: BTreeMap::new(),
+            values,
+            scratch: Vec::new(),
+        }
+    }
+
+    fn collect(&mut self, doc: DocId) -> Result<()> {
+        if let Some(ref mut values) = self.values {
+            values.read(doc, &mut self.scratch)?;
+            count_values(&self.spec, &mut self.counts, &self.scratch);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, counts: BTreeMap<i64, i64>) {
+        for (ord, count) in counts {
+            *self.counts.entry(ord).or_insert(0) += count;
+        }
+    }
+}
+
+// values are sorted, so a doc with several values in the same bucket
+// only has to be compared with the previous bucket to be counted once.
+fn count_values(spec: &HistogramSpec, counts: &mut BTreeMap<i64, i64>, values: &[i64]) {
+    let mut last_ord = None;
+    for raw in values {
+        if let Some(ord) = spec.bucket_ord(*raw) {
+            if last_ord != Some(ord) {
+                *counts.entry(ord).or_insert(0) += 1;
+                last_ord = Some(ord);
+            }
+        }
+    }
+}
+
+/// `Collector` that builds a fixed-interval histogram over the numeric doc
+/// values of a field, e.g. for price buckets or date histograms (with
+/// millisecond timestamps and a day/hour interval).
+///
+/// Each value `v` falls into the bucket with key
+/// `floor((v - offset) / interval) * interval + offset`. A document is counted
+/// at most once per bucket.
+pub struct HistogramCollector {
+    field: String,
+    min_doc_count: i64,
+    counter: HistogramCounter,
+    channel: Option<(Sender<BTreeMap<i64, i64>>, Receiver<BTreeMap<i64, i64>>)>,
+}
+
+impl HistogramCollector {
+    pub fn new(
+        field: String,
+        source: NumericValuesSource,
+        value_type: NumericValuesType,
+        interval: f64,
+        offset: f64,
+        min_doc_count: i64,
+    ) -> Result<HistogramCollector> {
+        if interval.is_nan() || interval <= 0.0 || interval.is_infinite() {
+            bail!(IllegalArgument(format!(
+                "histogram interval must be a positive number, got {}",
+                interval
+            )));
+        }
+        if !offset.is_finite() {
+            bail!(IllegalArgument(format!(
+                "histogram offset must be a finite number, got {}",
+                offset
+            )));
+        }
+        if min_doc_count < 0 {
+            bail!(IllegalArgument(format!(
+                "min_doc_count must be >= 0, got {}",
+                min_doc_count
+            )));
+        }
+        let spec = HistogramSpec {
+            source,
+            value_type,
+            interval,
+            offset,
+        };
+        Ok(HistogramCollector {
+            field,
+            min_doc_count,
+            counter: HistogramCounter::new(spec, None),
+            channel: None,
+        })
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Returns the buckets ordered by key.
+    ///
+    /// If `min_doc_count` is `0`, empty buckets between the first and the last
+    /// non-empty bucket are returned as well, an error is returned if there
+    /// would be more than `MAX_HISTOGRAM_BUCKETS` of them.
+    pub fn buckets(&self) -> Result<Vec<HistogramBucket>> {
+        let spec = &self.counter.spec;
+        let counts = &self.counter.counts;
+        if self.min_doc_count == 0 {
+            let (first, last) = match (counts.keys().next(), counts.keys().next_back()) {
+                (Some(first), Some(last)) => (*first, *last),
+                _ => {
+                    return Ok(vec![]);
+                }
+            };
+            match last.checked_sub(first) {
+                Some(span) if span < MAX_HISTOGRAM_BUCKETS => {}
+                _ => bail!(IllegalArgument(format!(
+                    "histogram on field '{}' would have more than {} buckets, use a larger \
+                     interval or a positive min_doc_count",
+                    self.field, MAX_HISTOGRAM_BUCKETS
+                ))),
+            }
+            Ok((first..=last)
+                .map(|ord| HistogramBucket {
+                    key: spec.bucket_key(ord),
+                    doc_count: counts.get(&ord).cloned().unwrap_or(0),
+                })
+                .collect())
+        } else {
+            Ok(counts
+                .iter()
+                .filter(|(_, count)| **count >= self.min_doc_count)
+                .map(|(ord, count)| HistogramBucket {
+                    key: spec.bucket_key(*ord),
+                    doc_count: *count,
+                })
+                .collect())
+        }
+    }
+}
+
+impl SearchCollector for HistogramCollector {
+    type LC = HistogramLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.counter.values =
+            LeafNumericValues::open(reader.reader, &self.field, self.counter.spec.source)?;
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn init_parallel(&mut self) {
+        if self.channel.is_none() {
+            self.channel = Some(channel());
+        }
+    }
+
+    fn leaf_collector<C: Codec>(&self, reader: &LeafReaderContext<'_, C>) -> Result<Self::LC> {
+        let values = LeafNumericValues::open(reader.reader, &self.field, self.counter.spec.source)?;
+        Ok(HistogramLeafCollector {
+            counter: HistogramCounter::new(self.counter.spec, values),
+            channel: self.channel.as_ref().unwrap().0.clone(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        // iff all the `weight.create_scorer(leaf_reader)` return None, the channel won't
+        // inited and thus stay None
+        if let Some((sender, receiver)) = self.channel.take() {
+            drop(sender);
+            while let Ok(counts) = receiver.recv() {
+                self.counter.merge(counts);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Collector for HistogramCollector {
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        self.counter.collect(doc)
+    }
+}
+
+/// Segment collector of `HistogramCollector` for parallel search.
+pub struct HistogramLeafCollector {
+    counter: HistogramCounter,
+    channel: Sender<BTreeMap<i64, i64>>,
+}
+
+impl ParallelLeafCollector for HistogramLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        let counts = mem::replace(&mut self.counter.counts, BTreeMap::new());
+        self.channel
+            .send(counts)
+            .chain_err(|| IllegalState("channel unexpected closed before search complete".into()))
+    }
+}
+
+impl Collector for HistogramLeafCollector {
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        self.counter.collect(doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    use core::doc::NumericDocValuesField;
+    use core::index::reader::IndexReader;
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::search::query::MatchAllDocsQuery;
+    use core::search::{DefaultIndexSearcher, IndexSearcher};
+    use core::store::directory::FSDirectory;
+
+    use std::f64;
+    use std::sync::Arc;
+
+    fn long_collector(interval: f64, offset: f64, min_doc_count: i64) -> HistogramCollector {
+        HistogramCollector::new(
+            "price".into(),
+            NumericValuesSource::SortedNumeric,
+            NumericValuesType::Long,
+            interval,
+            offset,
+            min_doc_count,
+        )
+        .unwrap()
+    }
+
+    fn add_values(counter: &mut HistogramCounter, values: &[i64]) {
+        count_values(&counter.spec, &mut counter.counts, values);
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut collector = long_collector(10.0, 0.0, 1);
+        add_values(&mut collector.counter, &[1, 5]);
+        add_values(&mut collector.counter, &[12]);
+        add_values(&mut collector.counter, &[-3]);
+        add_values(&mut collector.counter, &[35]);
+
+        let buckets = collector.buckets().unwrap();
+        let keys: Vec<f64> = buckets.iter().map(|b| b.key).collect();
+        let counts: Vec<i64> = buckets.iter().map(|b| b.doc_count).collect();
+        assert_eq!(keys, vec![-10.0, 0.0, 10.0, 30.0]);
+        assert_eq!(counts, vec![1, 1, 1, 1]);
+
+        let mut collector = long_collector(10.0, 5.0, 0);
+        add_values(&mut collector.counter, &[5, 14]);
+        add_values(&mut collector.counter, &[34]);
+        let buckets = collector.buckets().unwrap();
+        let keys: Vec<f64> = buckets.iter().map(|b| b.key).collect();
+        let counts: Vec<i64> = buckets.iter().map(|b| b.doc_count).collect();
+        assert_eq!(keys, vec![5.0, 15.0, 25.0]);
+        assert_eq!(counts, vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn test_histogram_merge() {
+        let mut collector = long_collector(1.0, 0.0, 1);
+        add_values(&mut collector.counter, &[1, 2]);
+        let mut other = BTreeMap::new();
+        other.insert(2, 3);
+        other.insert(4, 1);
+        collector.counter.merge(other);
+
+        let counts: Vec<(f64, i64)> = collector
+            .buckets()
+            .unwrap()
+            .iter()
+            .map(|b| (b.key, b.doc_count))
+            .collect();
+        assert_eq!(counts, vec![(1.0, 1), (2.0, 4), (4.0, 1)]);
+    }
+
+    #[test]
+    fn test_histogram_too_many_buckets() {
+        let mut collector = long_collector(1.0, 0.0, 0);
+        add_values(&mut collector.counter, &[0]);
+        add_values(&mut collector.counter, &[MAX_HISTOGRAM_BUCKETS - 1]);
+        assert_eq!(
+            collector.buckets().unwrap().len() as i64,
+            MAX_HISTOGRAM_BUCKETS
+        );
+
+        add_values(&mut collector.counter, &[MAX_HISTOGRAM_BUCKETS]);
+        assert!(collector.buckets().is_err());
+        collector.min_doc_count = 1;
+        assert_eq!(collector.buckets().unwrap().len(), 3);
+
+        let mut collector = long_collector(1.0, 0.0, 0);
+        add_values(&mut collector.counter, &[i64::MIN, 0]);
+        assert!(collector.buckets().is_err());
+    }
+
+    #[test]
+    fn test_histogram_out_of_range_values() {
+        let spec = HistogramSpec {
+            source: NumericValuesSource::Numeric,
+            value_type: NumericValuesType::Double,
+            interval: 1.0,
+            offset: 0.0,
+        };
+        let ord = |value: f64| spec.bucket_ord(value.to_bits() as i64);
+        assert_eq!(ord(-2.5), Some(-3));
+        assert_eq!(ord(::std::f64::NAN), None);
+        assert_eq!(ord(::std::f64::INFINITY), None);
+        assert_eq!(ord(::std::f64::NEG_INFINITY), None);
+        assert_eq!(ord(1e300), None);
+        assert_eq!(ord(-1e300), None);
+        assert_eq!(ord(i64::MAX as f64), None);
+        assert_eq!(ord(i64::MIN as f64), Some(i64::MIN));
+
+        let spec = HistogramSpec {
+            interval: 1e-300,
+            ..spec
+        };
+        assert_eq!(spec.bucket_ord(1e10f64.to_bits() as i64), None);
+    }
+
+    #[test]
+    fn test_histogram_search() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(IndexWriterConfig::default())).unwrap();
+        let segments = [
+            vec![1.5, 12.0, 15.0, f64::INFINITY],
+            vec![3.0, -4.0, 1e300],
+            vec![f64::NAN, 25.0, 31.0],
+        ];
+        for values in &segments {
+            for value in values {
+                let price = NumericDocValuesField::new("price", value.to_bits() as i64);
+                writer.add_document(vec![price]).unwrap();
+            }
+            writer.commit().unwrap();
+        }
+        let reader = Arc::new(writer.get_reader(true, false).unwrap());
+        assert_eq!(reader.leaves().len(), 3);
+
+        let new_collector = || {
+            HistogramCollector::new(
+                "price".into(),
+                NumericValuesSource::Numeric,
+                NumericValuesType::Double,
+                10.0,
+                0.0,
+                0,
+            )
+            .unwrap()
+        };
+        let expected = vec![(-10.0, 1), (0.0, 2), (10.0, 2), (20.0, 1), (30.0, 1)];
+        let counts = |collector: &HistogramCollector| -> Vec<(f64, i64)> {
+            collector
+                .buckets()
+                .unwrap()
+                .iter()
+                .map(|b| (b.key, b.doc_count))
+                .collect()
+        };
+
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader), None);
+        let mut collector = new_collector();
+        searcher.search(&MatchAllDocsQuery, &mut collector).unwrap();
+        assert_eq!(counts(&collector), expected);
+
+        let mut searcher = DefaultIndexSearcher::new(reader, None);
+        searcher.with_thread_pool(3);
+        let mut collector = new_collector();
+        searcher
+            .search_parallel(&MatchAllDocsQuery, &mut collector)
+            .unwrap();
+        assert_eq!(counts(&collector), expected);
+    }
+
+    #[test]
+    fn test_invalid_interval() {
+        assert!(HistogramCollector::new(
+            "price".into(),
+            NumericValuesSource::Numeric,
+            NumericValuesType::Double,
+            0.0,
+            0.0,
+            1,
+        )
+        .is_err());
+    }
+}
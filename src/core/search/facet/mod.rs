@@ -0,0 +1,61 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod numeric_values;
+
+pub use self::numeric_values::*;
+
+mod range_facet_counts;
+
+pub use self::range_facet_counts::*;
+
+mod histogram;
+
+pub use self::histogram::*;
+
+/// Single label and its value, usually contained in a `FacetResult`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LabelAndValue {
+    /// Facet's label.
+    pub label: String,
+    /// Value associated with this label.
+    pub value: i64,
+}
+
+impl LabelAndValue {
+    pub fn new(label: String, value: i64) -> LabelAndValue {
+        LabelAndValue { label, value }
+    }
+}
+
+/// Counts for a single facet field, returned by the facet collectors.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FacetResult {
+    /// Field name the facet was computed on.
+    pub field: String,
+    /// Total number of documents that had at least one value within any
+    /// of the requested ranges.
+    pub value: i64,
+    /// Child counts, in the order the ranges were requested.
+    pub label_values: Vec<LabelAndValue>,
+}
+
+impl FacetResult {
+    pub fn new(field: String, value: i64, label_values: Vec<LabelAndValue>) -> FacetResult {
+        FacetResult {
+            field,
+            value,
+            label_values,
+        }
+    }
+}
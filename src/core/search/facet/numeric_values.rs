@@ -0,0 +1,137 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::doc_values::{NumericDocValues, SortedNumericDocValues};
+use core::codec::Codec;
+use core::doc::DocValuesType;
+use core::index::reader::SearchLeafReader;
+use core::util::{sortable_long2double, BitsMut, DocId};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+/// The doc values type a numeric facet or aggregation reads its values from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumericValuesSource {
+    /// single valued field, indexed with `NumericDocValuesField`
+    /// or `DoubleDocValuesField`
+    Numeric,
+    /// multi valued field, indexed with `SortedNumericDocValuesField`
+    SortedNumeric,
+}
+
+impl NumericValuesSource {
+    pub fn doc_values_type(self) -> DocValuesType {
+        match self {
+            NumericValuesSource::Numeric => DocValuesType::Numeric,
+            NumericValuesSource::SortedNumeric => DocValuesType::SortedNumeric,
+        }
+    }
+}
+
+/// How the raw `i64` doc values of a field should be interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumericValuesType {
+    Long,
+    Double,
+}
+
+/// Decodes a raw doc value into a `f64`.
+///
+/// `DoubleDocValuesField` stores the raw bits of the double, while doubles in
+/// a `SortedNumericDocValuesField` are expected to be encoded as sortable longs.
+pub fn decode_numeric_value(
+    raw: i64,
+    source: NumericValuesSource,
+    value_type: NumericValuesType,
+) -> f64 {
+    match (value_type, source) {
+        (NumericValuesType::Long, _) => raw as f64,
+        (NumericValuesType::Double, NumericValuesSource::Numeric) => f64::from_bits(raw as u64),
+        (NumericValuesType::Double, NumericValuesSource::SortedNumeric) => {
+            sortable_long2double(raw)
+        }
+    }
+}
+
+/// Per segment view over the numeric values of a field, hiding the difference
+/// between single and multi valued doc values.
+pub enum LeafNumericValues {
+    Numeric {
+        values: Box<dyn NumericDocValues>,
+        docs_with_field: Box<dyn BitsMut>,
+    },
+    SortedNumeric(Box<dyn SortedNumericDocValues>),
+}
+
+impl LeafNumericValues {
+    /// Returns `None` if the field has no doc values in this segment.
+    pub fn open<C: Codec>(
+        reader: &SearchLeafReader<C>,
+        field: &str,
+        source: NumericValuesSource,
+    ) -> Result<Option<LeafNumericValues>> {
+        let dv_type = match reader.field_info(field) {
+            Some(fi) => fi.doc_values_type,
+            None => {
+                return Ok(None);
+            }
+        };
+        if dv_type.null() {
+            return Ok(None);
+        }
+        if dv_type != source.doc_values_type() {
+            bail!(IllegalArgument(format!(
+                "field '{}' was indexed with doc values type {:?}, but {:?} was requested",
+                field,
+                dv_type,
+                source.doc_values_type()
+            )));
+        }
+
+        let values = match source {
+            NumericValuesSource::Numeric => LeafNumericValues::Numeric {
+                values: reader.get_numeric_doc_values(field)?,
+                docs_with_field: reader.get_docs_with_field(field)?,
+            },
+            NumericValuesSource::SortedNumeric => {
+                LeafNumericValues::SortedNumeric(reader.get_sorted_numeric_doc_values(field)?)
+            }
+        };
+        Ok(Some(values))
+    }
+
+    /// Reads all the raw values of `doc` into `values`, which is cleared first.
+    ///
+    /// Values of a multi valued field are returned in ascending order.
+    pub fn read(&mut self, doc: DocId, values: &mut Vec<i64>) -> Result<()> {
+        values.clear();
+        match self {
+            LeafNumericValues::Numeric {
+                values: dv,
+                docs_with_field,
+            } => {
+                if docs_with_field.get(doc as usize)? {
+                    values.push(dv.get_mut(doc)?);
+                }
+            }
+            LeafNumericValues::SortedNumeric(dv) => {
+                dv.set_document(doc)?;
+                for i in 0..dv.count() {
+                    values.push(dv.value_at(i)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
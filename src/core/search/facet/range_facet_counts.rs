@@ -0,0 +1,560 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::i64;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use core::codec::Codec;
+use core::index::reader::LeafReaderContext;
+use core::search::collector::{Collector, ParallelLeafCollector, SearchCollector};
+use core::search::facet::{FacetResult, LabelAndValue, LeafNumericValues, NumericValuesSource};
+use core::search::scorer::Scorer;
+use core::util::{double2sortable_long, DocId};
+
+use error::ErrorKind::{IllegalArgument, IllegalState};
+use error::{Result, ResultExt};
+
+/// Represents a range over long values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LongRange {
+    pub label: String,
+    pub min: i64,
+    pub min_inclusive: bool,
+    pub max: i64,
+    pub max_inclusive: bool,
+    // inclusive bounds after normalization
+    lower: i64,
+    upper: i64,
+}
+
+impl LongRange {
+    pub fn new(
+        label: String,
+        min: i64,
+        min_inclusive: bool,
+        max: i64,
+        max_inclusive: bool,
+    ) -> Result<LongRange> {
+        let (lower, upper) = normalize_bounds(&label, min, min_inclusive, max, max_inclusive)?;
+        Ok(LongRange {
+            label,
+            min,
+            min_inclusive,
+            max,
+            max_inclusive,
+            lower,
+            upper,
+        })
+    }
+
+    /// True if this range accepts the provided value.
+    pub fn accept(&self, value: i64) -> bool {
+        value >= self.lower && value <= self.upper
+    }
+}
+
+/// Represents a range over double values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DoubleRange {
+    pub label: String,
+    pub min: f64,
+    pub min_inclusive: bool,
+    pub max: f64,
+    pub max_inclusive: bool,
+    // inclusive bounds in the sortable long space
+    lower: i64,
+    upper: i64,
+}
+
+impl DoubleRange {
+    pub fn new(
+        label: String,
+        min: f64,
+        min_inclusive: bool,
+        max: f64,
+        max_inclusive: bool,
+    ) -> Result<DoubleRange> {
+        if min.is_nan() || max.is_nan() {
+            bail!(IllegalArgument(format!(
+                "range '{}': min and max cannot be NaN",
+                label
+            )));
+        }
+        // sortable longs keep the order of doubles and two adjacent doubles map to
+        // two adjacent longs, so exclusive bounds can be normalized in the long space.
+        let (lower, upper) = normalize_bounds(
+            &label,
+            double2sortable_long(min),
+            min_inclusive,
+            double2sortable_long(max),
+            max_inclusive,
+        )?;
+        Ok(DoubleRange {
+            label,
+            min,
+            min_inclusive,
+            max,
+            max_inclusive,
+            lower,
+            upper,
+        })
+    }
+
+    /// True if this range accepts the provided value.
+    pub fn accept(&self, value: f64) -> bool {
+        let v = double2sortable_long(value);
+        v >= self.lower && v <= self.upper
+    }
+}
+
+fn normalize_bounds(
+    label: &str,
+    min: i64,
+    min_inclusive: bool,
+    max: i64,
+    max_inclusive: bool,
+) -> Result<(i64, i64)> {
+    let lower = if min_inclusive {
+        Some(min)
+    } else if min != i64::MAX {
+        Some(min + 1)
+    } else {
+        None
+    };
+    let upper = if max_inclusive {
+        Some(max)
+    } else if max != i64::MIN {
+        Some(max - 1)
+    } else {
+        None
+    };
+    match (lower, upper) {
+        (Some(l), Some(u)) if l <= u => Ok((l, u)),
+        _ => bail!(IllegalArgument(format!(
+            "range '{}' cannot match any value",
+            label
+        ))),
+    }
+}
+
+/// Counts the number of documents falling into each of a set of ranges.
+///
+/// A document is counted at most once per range, even if several of its
+/// values fall into it.
+struct RangeCounter {
+    bounds: Arc<Vec<(i64, i64)>>,
+    counts: Vec<i64>,
+    total_count: i64,
+}
+
+impl RangeCounter {
+    fn new(bounds: Arc<Vec<(i64, i64)>>) -> RangeCounter {
+        let counts = vec![0; bounds.len()];
+        RangeCounter {
+            bounds,
+            counts,
+            total_count: 0,
+        }
+    }
+
+    /// `values` must be sorted ascending.
+    fn add_doc(&mut self, values: &[i64]) {
+        if values.is_empty() {
+            return;
+        }
+        let mut matched = false;
+        for (i, &(lower, upper)) in self.bounds.iter().enumerate() {
+            // first value that is `>= lower`
+            let idx = match values.binary_search(&lower) {
+                Ok(idx) | Err(idx) => idx,
+            };
+            if idx < values.len() && values[idx] <= upper {
+                self.counts[i] += 1;
+                matched = true;
+            }
+        }
+        if matched {
+            self.total_count += 1;
+        }
+    }
+
+    fn merge(&mut self, counts: &[i64], total_count: i64) {
+        debug_assert_eq!(self.counts.len(), counts.len());
+        for (c, other) in self.counts.iter_mut().zip(counts) {
+            *c += *other;
+        }
+        self.total_count += total_count;
+    }
+}
+
+struct LeafRangeCounts {
+    counts: Vec<i64>,
+    total_count: i64,
+}
+
+/// Shared implementation of `LongRangeFacetCounts` and `DoubleRangeFacetCounts`.
+struct RangeFacetCountsBase {
+    field: String,
+    source: NumericValuesSource,
+    // maps a raw doc value into the long space the range bounds live in
+    value_mapper: fn(i64) -> i64,
+    counter: RangeCounter,
+    values: Option<LeafNumericValues>,
+    scratch: Vec<i64>,
+    channel: Option<(Sender<LeafRangeCounts>, Receiver<LeafRangeCounts>)>,
+}
+
+impl RangeFacetCountsBase {
+    fn new(
+        field: String,
+        source: NumericValuesSource,
+        value_mapper: fn(i64) -> i64,
+        bounds: Vec<(i64, i64)>,
+    ) -> RangeFacetCountsBase {
+        RangeFacetCountsBase {
+            field,
+            source,
+            value_mapper,
+            counter: RangeCounter::new(Arc::new(bounds)),
+            values: None,
+            scratch: Vec::new(),
+            channel: None,
+        }
+    }
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.values = LeafNumericValues::open(reader.reader, &self.field, self.source)?;
+        Ok(())
+    }
+
+    fn init_parallel(&mut self) {
+        if self.channel.is_none() {
+            self.channel = Some(channel());
+        }
+    }
+
+    fn leaf_collector<C: Codec>(
+        &self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<RangeFacetLeafCollector> {
+        let values = LeafNumericValues::open(reader.reader, &self.field, self.source)?;
+        Ok(RangeFacetLeafCollector {
+            counter: RangeCounter::new(Arc::clone(&self.counter.bounds)),
+            value_mapper: self.value_mapper,
+            values,
+            scratch: Vec::new(),
+            channel: self.channel.as_ref().unwrap().0.clone(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        // iff all the `weight.create_scorer(leaf_reader)` return None, the channel won't
+        // inited and thus stay None
+        if let Some((sender, receiver)) = self.channel.take() {
+            drop(sender);
+            while let Ok(leaf) = receiver.recv() {
+                self.counter.merge(&leaf.counts, leaf.total_count);
+            }
+        }
+        Ok(())
+    }
+
+    fn collect(&mut self, doc: DocId) -> Result<()> {
+        collect_doc(
+            &mut self.values,
+            &mut self.scratch,
+            self.value_mapper,
+            &mut self.counter,
+            doc,
+        )
+    }
+}
+
+fn collect_doc(
+    values: &mut Option<LeafNumericValues>,
+    scratch: &mut Vec<i64>,
+    value_mapper: fn(i64) -> i64,
+    counter: &mut RangeCounter,
+    doc: DocId,
+) -> Result<()> {
+    if let Some(values) = values {
+        values.read(doc, scratch)?;
+        // the mapping only changes single valued doubles, so multi valued
+        // fields stay sorted
+        for v in scratch.iter_mut() {
+            *v = value_mapper(*v);
+        }
+        counter.add_doc(scratch);
+    }
+    Ok(())
+}
+
+fn identity(v: i64) -> i64 {
+    v
+}
+
+fn raw_double_to_sortable(v: i64) -> i64 {
+    double2sortable_long(f64::from_bits(v as u64))
+}
+
+/// `Collector` that counts, for each of the provided `LongRange`s, the number
+/// of matching documents with a value of `field` within that range.
+///
+/// Values are read from `NumericDocValues` or `SortedNumericDocValues` in a
+/// single pass per segment, and per segment counts are merged back when used
+/// with `IndexSearcher::search_parallel`.
+pub struct LongRangeFacetCounts {
+    ranges: Vec<LongRange>,
+    base: RangeFacetCountsBase,
+}
+
+impl LongRangeFacetCounts {
+    pub fn new(
+        field: String,
+        source: NumericValuesSource,
+        ranges: Vec<LongRange>,
+    ) -> LongRangeFacetCounts {
+        let bounds = ranges.iter().map(|r| (r.lower, r.upper)).collect();
+        let base = RangeFacetCountsBase::new(field, source, identity, bounds);
+        LongRangeFacetCounts { ranges, base }
+    }
+
+    /// Returns the count of each range, in the order the ranges were provided.
+    pub fn facet_result(&self) -> FacetResult {
+        let label_values = self
+            .ranges
+            .iter()
+            .zip(&self.base.counter.counts)
+            .map(|(r, c)| LabelAndValue::new(r.label.clone(), *c))
+            .collect();
+        FacetResult::new(
+            self.base.field.clone(),
+            self.base.counter.total_count,
+            label_values,
+        )
+    }
+}
+
+impl SearchCollector for LongRangeFacetCounts {
+    type LC = RangeFacetLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.base.set_next_reader(reader)
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn init_parallel(&mut self) {
+        self.base.init_parallel()
+    }
+
+    fn leaf_collector<C: Codec>(&self, reader: &LeafReaderContext<'_, C>) -> Result<Self::LC> {
+        self.base.leaf_collector(reader)
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        self.base.finish_parallel()
+    }
+}
+
+impl Collector for LongRangeFacetCounts {
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        self.base.collect(doc)
+    }
+}
+
+/// `Collector` that counts, for each of the provided `DoubleRange`s, the number
+/// of matching documents with a value of `field` within that range.
+///
+/// Single valued fields are expected to be indexed with `DoubleDocValuesField`,
+/// multi valued fields with the sortable long encoding of the doubles.
+pub struct DoubleRangeFacetCounts {
+    ranges: Vec<DoubleRange>,
+    base: RangeFacetCountsBase,
+}
+
+impl DoubleRangeFacetCounts {
+    pub fn new(
+        field: String,
+        source: NumericValuesSource,
+        ranges: Vec<DoubleRange>,
+    ) -> DoubleRangeFacetCounts {
+        let bounds = ranges.iter().map(|r| (r.lower, r.upper)).collect();
+        let value_mapper: fn(i64) -> i64 = match source {
+            NumericValuesSource::Numeric => raw_double_to_sortable,
+            NumericValuesSource::SortedNumeric => identity,
+        };
+        let base = RangeFacetCountsBase::new(field, source, value_mapper, bounds);
+        DoubleRangeFacetCounts { ranges, base }
+    }
+
+    /// Returns the count of each range, in the order the ranges were provided.
+    pub fn facet_result(&self) -> FacetResult {
+        let label_values = self
+            .ranges
+            .iter()
+            .zip(&self.base.counter.counts)
+            .map(|(r, c)| LabelAndValue::new(r.label.clone(), *c))
+            .collect();
+        FacetResult::new(
+            self.base.field.clone(),
+            self.base.counter.total_count,
+            label_values,
+        )
+    }
+}
+
+impl SearchCollector for DoubleRangeFacetCounts {
+    type LC = RangeFacetLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.base.set_next_reader(reader)
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn init_parallel(&mut self) {
+        self.base.init_parallel()
+    }
+
+    fn leaf_collector<C: Codec>(&self, reader: &LeafReaderContext<'_, C>) -> Result<Self::LC> {
+        self.base.leaf_collector(reader)
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        self.base.finish_parallel()
+    }
+}
+
+impl Collector for DoubleRangeFacetCounts {
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        self.base.collect(doc)
+    }
+}
+
+/// Segment collector of `LongRangeFacetCounts` and `DoubleRangeFacetCounts`
+/// for parallel search.
+pub struct RangeFacetLeafCollector {
+    counter: RangeCounter,
+    value_mapper: fn(i64) -> i64,
+    values: Option<LeafNumericValues>,
+    scratch: Vec<i64>,
+    channel: Sender<LeafRangeCounts>,
+}
+
+impl ParallelLeafCollector for RangeFacetLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        let counts = LeafRangeCounts {
+            counts: self.counter.counts.clone(),
+            total_count: self.counter.total_count,
+        };
+        self.channel
+            .send(counts)
+            .chain_err(|| IllegalState("channel unexpected closed before search complete".into()))
+    }
+}
+
+impl Collector for RangeFacetLeafCollector {
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        collect_doc(
+            &mut self.values,
+            &mut self.scratch,
+            self.value_mapper,
+            &mut self.counter,
+            doc,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_range_bounds() {
+        let r = LongRange::new("r".into(), 0, true, 10, false).unwrap();
+        assert!(r.accept(0));
+        assert!(r.accept(9));
+        assert!(!r.accept(10));
+        assert!(!r.accept(-1));
+
+        let r = LongRange::new("r".into(), 0, false, 10, true).unwrap();
+        assert!(!r.accept(0));
+        assert!(r.accept(10));
+
+        assert!(LongRange::new("r".into(), 5, false, 5, true).is_err());
+        assert!(LongRange::new("r".into(), i64::MAX, false, i64::MAX, true).is_err());
+    }
+
+    #[test]
+    fn test_double_range_bounds() {
+        let r = DoubleRange::new("r".into(), 0.0, true, 1.0, false).unwrap();
+        assert!(r.accept(0.0));
+        assert!(r.accept(0.999_999));
+        assert!(!r.accept(1.0));
+        assert!(!r.accept(-0.5));
+
+        assert!(DoubleRange::new("r".into(), 1.0, true, 0.5, true).is_err());
+        assert!(DoubleRange::new("r".into(), ::std::f64::NAN, true, 0.5, true).is_err());
+    }
+
+    #[test]
+    fn test_range_counter() {
+        let bounds = vec![(0, 9), (5, 14), (100, 200)];
+        let mut counter = RangeCounter::new(Arc::new(bounds));
+
+        counter.add_doc(&[1]);
+        counter.add_doc(&[6]);
+        // multiple values in the same range only count once
+        counter.add_doc(&[6, 7, 8]);
+        counter.add_doc(&[50]);
+        counter.add_doc(&[]);
+        counter.add_doc(&[3, 150]);
+
+        assert_eq!(counter.counts, vec![4, 2, 1]);
+        assert_eq!(counter.total_count, 4);
+
+        let mut other = RangeCounter::new(Arc::clone(&counter.bounds));
+        other.add_doc(&[120]);
+        counter.merge(&other.counts, other.total_count);
+        assert_eq!(counter.counts, vec![4, 2, 2]);
+        assert_eq!(counter.total_count, 5);
+    }
+
+    #[test]
+    fn test_double_value_mapping() {
+        let bounds = DoubleRange::new("r".into(), 1.5, true, 2.5, true).unwrap();
+        let mut counter = RangeCounter::new(Arc::new(vec![(bounds.lower, bounds.upper)]));
+        for v in &[1.0f64, 1.5, 2.0, 3.0] {
+            counter.add_doc(&[raw_double_to_sortable(v.to_bits() as i64)]);
+        }
+        assert_eq!(counter.counts, vec![2]);
+    }
+}
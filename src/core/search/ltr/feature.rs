@@ -0,0 +1,453 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use core::codec::Codec;
+use core::doc::Term;
+use core::index::reader::{IndexReader, LeafReaderContext};
+use core::search::facet::{
+    decode_numeric_value, LeafNumericValues, NumericValuesSource, NumericValuesType,
+};
+use core::search::query::{BooleanQuery, Query, TermQuery, Weight};
+use core::search::scorer::Scorer;
+use core::search::searcher::{IndexSearcher, SearchPlanBuilder};
+use core::search::sort_field::ScoreDocHit;
+use core::search::DocIterator;
+use core::util::{DocId, VariantValue};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+enum FeatureSource<C: Codec> {
+    Query(Box<dyn Query<C>>),
+    DocValue {
+        field: String,
+        source: NumericValuesSource,
+        value_type: NumericValuesType,
+        missing: f32,
+    },
+}
+
+/// A named signal computed for each document to rank.
+pub struct Feature<C: Codec> {
+    name: String,
+    source: FeatureSource<C>,
+}
+
+impl<C: Codec> Feature<C> {
+    /// The score of `query` for the document, 0 if it does not match.
+    pub fn query(name: &str, query: Box<dyn Query<C>>) -> Feature<C> {
+        Feature {
+            name: name.to_string(),
+            source: FeatureSource::Query(query),
+        }
+    }
+
+    /// The BM25 score of the given terms in `field`, that is the sum of the
+    /// scores of the term sub-queries which match the document.
+    pub fn terms_bm25(name: &str, field: &str, terms: &[&str]) -> Result<Feature<C>> {
+        if terms.is_empty() {
+            bail!(IllegalArgument(format!(
+                "feature '{}' needs at least one term",
+                name
+            )));
+        }
+        let shoulds = terms
+            .iter()
+            .map(|t| {
+                let term = Term::new(field.to_string(), t.as_bytes().to_vec());
+                Box::new(TermQuery::new(term, 1.0, None)) as Box<dyn Query<C>>
+            })
+            .collect();
+        let query = BooleanQuery::build(vec![], shoulds, vec![], vec![], 0)?;
+        Ok(Self::query(name, query))
+    }
+
+    /// The numeric doc value of `field`, or `missing` if the document has
+    /// none. The smallest value of a multi valued field is used.
+    pub fn doc_value(
+        name: &str,
+        field: &str,
+        source: NumericValuesSource,
+        value_type: NumericValuesType,
+        missing: f32,
+    ) -> Feature<C> {
+        Feature {
+            name: name.to_string(),
+            source: FeatureSource::DocValue {
+                field: field.to_string(),
+                source,
+                value_type,
+                missing,
+            },
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<C: Codec> fmt::Display for Feature<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.source {
+            FeatureSource::Query(ref query) => write!(f, "{}: {}", &self.name, query),
+            FeatureSource::DocValue { ref field, .. } => {
+                write!(f, "{}: doc_value({})", &self.name, field)
+            }
+        }
+    }
+}
+
+/// An ordered list of features. The position of a feature in the set is its
+/// index in the feature vectors given to a `RankModel`.
+pub struct FeatureSet<C: Codec> {
+    name: String,
+    features: Vec<Feature<C>>,
+    ordinals: HashMap<String, usize>,
+}
+
+impl<C: Codec> FeatureSet<C> {
+    pub fn new(name: &str) -> FeatureSet<C> {
+        FeatureSet {
+            name: name.to_string(),
+            features: vec![],
+            ordinals: HashMap::new(),
+        }
+    }
+
+    /// Returns an error if a feature with the same name was already added.
+    pub fn add(&mut self, feature: Feature<C>) -> Result<()> {
+        if self.ordinals.contains_key(feature.name()) {
+            bail!(IllegalArgument(format!(
+                "feature '{}' is already in feature set '{}'",
+                feature.name(),
+                &self.name
+            )));
+        }
+        self.ordinals
+            .insert(feature.name().to_string(), self.features.len());
+        self.features.push(feature);
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    pub fn features(&self) -> &[Feature<C>] {
+        &self.features
+    }
+
+    pub fn feature_names(&self) -> Vec<&str> {
+        self.features.iter().map(|f| f.name()).collect()
+    }
+
+    /// The index of the feature in the feature vectors.
+    pub fn ordinal(&self, name: &str) -> Option<usize> {
+        self.ordinals.get(name).cloned()
+    }
+
+    /// Returns the features of `values` as a map from feature name, the
+    /// format of `Rescorer::rescore_features`.
+    pub fn to_map(&self, values: &[f32]) -> HashMap<String, VariantValue> {
+        debug_assert_eq!(values.len(), self.features.len());
+        self.features
+            .iter()
+            .zip(values.iter())
+            .map(|(f, v)| (f.name.clone(), VariantValue::Float(*v)))
+            .collect()
+    }
+
+    pub fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+    ) -> Result<FeatureSetWeight<C>> {
+        let mut weights = Vec::with_capacity(self.features.len());
+        for feature in &self.features {
+            let weight = match feature.source {
+                FeatureSource::Query(ref query) => {
+                    FeatureWeight::Query(searcher.create_normalized_weight(query.as_ref(), true)?)
+                }
+                FeatureSource::DocValue {
+                    ref field,
+                    source,
+                    value_type,
+                    missing,
+                } => FeatureWeight::DocValue {
+                    field: field.clone(),
+                    source,
+                    value_type,
+                    missing,
+                },
+            };
+            weights.push(weight);
+        }
+        Ok(FeatureSetWeight { weights })
+    }
+
+    /// Computes the feature vectors of the hits, in the order of `hits`.
+    ///
+    /// This is how features are logged to build a training set, see
+    /// `format_ranklib` for a format most learning to rank tools read.
+    pub fn extract<IS: IndexSearcher<C>>(
+        &self,
+        searcher: &IS,
+        hits: &[ScoreDocHit],
+    ) -> Result<Vec<Vec<f32>>> {
+        let weight = self.create_weight(searcher)?;
+        let leaves = searcher.reader().leaves();
+
+        // extractors only move forward, visit the hits by doc
+        let mut order: Vec<usize> = (0..hits.len()).collect();
+        order.sort_by_key(|&i| hits[i].doc_id());
+
+        let mut vectors = vec![vec![]; hits.len()];
+        let mut leaf_idx = 0;
+        let mut extractor: Option<LeafFeatureExtractor> = None;
+        for i in order {
+            let doc = hits[i].doc_id();
+            while leaf_idx < leaves.len()
+                && doc >= leaves[leaf_idx].doc_base + leaves[leaf_idx].reader.max_doc()
+            {
+                leaf_idx += 1;
+                extractor = None;
+            }
+            if leaf_idx == leaves.len() {
+                bail!(IllegalArgument(format!("doc {} is out of bounds", doc)));
+            }
+            if extractor.is_none() {
+                extractor = Some(weight.extractor(&leaves[leaf_idx])?);
+            }
+            let mut values = vec![0f32; self.features.len()];
+            extractor
+                .as_mut()
+                .unwrap()
+                .extract(doc - leaves[leaf_idx].doc_base, &mut values)?;
+            vectors[i] = values;
+        }
+        Ok(vectors)
+    }
+}
+
+impl<C: Codec> fmt::Display for FeatureSet<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FeatureSet(name: {}, features: [", &self.name)?;
+        for (i, feature) in self.features.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", feature)?;
+        }
+        write!(f, "])")
+    }
+}
+
+enum FeatureWeight<C: Codec> {
+    Query(Box<dyn Weight<C>>),
+    DocValue {
+        field: String,
+        source: NumericValuesSource,
+        value_type: NumericValuesType,
+        missing: f32,
+    },
+}
+
+/// The features of a `FeatureSet` prepared for an `IndexSearcher`.
+pub struct FeatureSetWeight<C: Codec> {
+    weights: Vec<FeatureWeight<C>>,
+}
+
+impl<C: Codec> FeatureSetWeight<C> {
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    pub fn extractor(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<LeafFeatureExtractor> {
+        let mut extractors = Vec::with_capacity(self.weights.len());
+        for weight in &self.weights {
+            let extractor = match weight {
+                FeatureWeight::Query(weight) => {
+                    LeafFeature::Query(weight.create_scorer(leaf_reader_ctx)?)
+                }
+                FeatureWeight::DocValue {
+                    field,
+                    source,
+                    value_type,
+                    missing,
+                } => LeafFeature::DocValue {
+                    values: LeafNumericValues::open(leaf_reader_ctx.reader, field, *source)?,
+                    source: *source,
+                    value_type: *value_type,
+                    missing: *missing,
+                },
+            };
+            extractors.push(extractor);
+        }
+        Ok(LeafFeatureExtractor {
+            features: extractors,
+            raw_values: vec![],
+            doc: -1,
+        })
+    }
+}
+
+enum LeafFeature {
+    // None if the query matches no doc of the segment
+    Query(Option<Box<dyn Scorer>>),
+    DocValue {
+        values: Option<LeafNumericValues>,
+        source: NumericValuesSource,
+        value_type: NumericValuesType,
+        missing: f32,
+    },
+}
+
+/// Computes the feature vectors of the docs of one segment.
+pub struct LeafFeatureExtractor {
+    features: Vec<LeafFeature>,
+    raw_values: Vec<i64>,
+    doc: DocId,
+}
+
+impl LeafFeatureExtractor {
+    /// Writes the features of `doc` into `values`.
+    ///
+    /// Docs must be extracted in increasing order, the same doc may be
+    /// extracted several times in a row.
+    pub fn extract(&mut self, doc: DocId, values: &mut [f32]) -> Result<()> {
+        debug_assert_eq!(values.len(), self.features.len());
+        if doc < self.doc {
+            bail!(IllegalArgument(format!(
+                "docs must be extracted in order, got {} after {}",
+                doc, self.doc
+            )));
+        }
+        self.doc = doc;
+        for (feature, value) in self.features.iter_mut().zip(values.iter_mut()) {
+            *value = match feature {
+                LeafFeature::Query(Some(scorer)) => {
+                    let mut current = scorer.doc_id();
+                    if current < doc {
+                        current = scorer.advance(doc)?;
+                    }
+                    if current == doc {
+                        scorer.score()?
+                    } else {
+                        0f32
+                    }
+                }
+                LeafFeature::Query(None) => 0f32,
+                LeafFeature::DocValue {
+                    values: Some(dv),
+                    source,
+                    value_type,
+                    missing,
+                } => {
+                    dv.read(doc, &mut self.raw_values)?;
+                    match self.raw_values.first() {
+                        Some(&raw) => decode_numeric_value(raw, *source, *value_type) as f32,
+                        None => *missing,
+                    }
+                }
+                LeafFeature::DocValue {
+                    values: None,
+                    missing,
+                    ..
+                } => *missing,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Formats a feature vector as a line of the RankLib / SVMrank training
+/// format: `<label> qid:<qid> 1:<v1> 2:<v2> ... # <comment>`.
+///
+/// Feature ids start at 1, in the order of the `FeatureSet`.
+pub fn format_ranklib(label: f32, qid: &str, features: &[f32], comment: Option<&str>) -> String {
+    let mut line = format!("{} qid:{}", label, qid);
+    for (i, v) in features.iter().enumerate() {
+        line.push_str(&format!(" {}:{}", i + 1, v));
+    }
+    if let Some(comment) = comment {
+        line.push_str(" # ");
+        line.push_str(comment);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::tests::TestCodec;
+    use core::search::query::MatchAllDocsQuery;
+
+    #[test]
+    fn test_feature_set() {
+        let mut feature_set: FeatureSet<TestCodec> = FeatureSet::new("products");
+        feature_set
+            .add(Feature::terms_bm25("title_bm25", "title", &["red", "shoes"]).unwrap())
+            .unwrap();
+        feature_set
+            .add(Feature::doc_value(
+                "popularity",
+                "popularity",
+                NumericValuesSource::Numeric,
+                NumericValuesType::Long,
+                0.0,
+            ))
+            .unwrap();
+        let duplicate = Feature::query("popularity", Box::new(MatchAllDocsQuery));
+        assert!(feature_set.add(duplicate).is_err());
+        assert!(Feature::<TestCodec>::terms_bm25("empty", "title", &[]).is_err());
+
+        assert_eq!(feature_set.len(), 2);
+        assert_eq!(
+            feature_set.feature_names(),
+            vec!["title_bm25", "popularity"]
+        );
+        assert_eq!(feature_set.ordinal("popularity"), Some(1));
+        assert_eq!(feature_set.ordinal("price"), None);
+
+        let map = feature_set.to_map(&[1.5, 3.0]);
+        assert_eq!(map["title_bm25"], VariantValue::Float(1.5));
+        assert_eq!(map["popularity"], VariantValue::Float(3.0));
+    }
+
+    #[test]
+    fn test_format_ranklib() {
+        assert_eq!(
+            format_ranklib(2.0, "q1", &[0.5, 3.0], Some("doc 7")),
+            "2 qid:q1 1:0.5 2:3 # doc 7"
+        );
+        assert_eq!(format_ranklib(0.0, "q2", &[], None), "0 qid:q2");
+    }
+}
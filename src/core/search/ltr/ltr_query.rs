@@ -0,0 +1,458 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use core::codec::Codec;
+use core::index::reader::LeafReaderContext;
+use core::search::explanation::Explanation;
+use core::search::ltr::{FeatureSet, FeatureSetWeight, LeafFeatureExtractor, RankModel};
+use core::search::query::{AllDocsIterator, Query, TermQuery, Weight};
+use core::search::scorer::{FeatureResult, Scorer};
+use core::search::searcher::SearchPlanBuilder;
+use core::search::DocIterator;
+use core::util::{DocId, VariantValue};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+pub const LTR: &str = "ltr";
+
+/// Scores documents with a `RankModel` applied to the features of a
+/// `FeatureSet`.
+///
+/// It matches all documents and computing the features of a document is
+/// costly, so it is meant to rescore the top hits of a cheaper query with
+/// `QueryRescorer`:
+///
+/// ```rust,ignore
+/// let query = LtrQuery::new(Arc::new(feature_set), Arc::new(model))?;
+/// let request = RescoreRequest::new(
+///     Box::new(query), 0.0, 1.0, RescoreMode::Total, 100, false);
+/// QueryRescorer.rescore(&searcher, &request, &mut top_docs)?;
+/// ```
+///
+/// `QueryRescorer::rescore_features` returns the feature values of the hits
+/// keyed by feature name, to log them for training.
+pub struct LtrQuery<C: Codec> {
+    feature_set: Arc<FeatureSet<C>>,
+    model: Arc<dyn RankModel>,
+}
+
+impl<C: Codec> LtrQuery<C> {
+    /// Returns an error if the model was not built for the feature set.
+    pub fn new(feature_set: Arc<FeatureSet<C>>, model: Arc<dyn RankModel>) -> Result<LtrQuery<C>> {
+        if model.num_features() != feature_set.len() {
+            bail!(IllegalArgument(format!(
+                "model expects {} features, but feature set '{}' has {}",
+                model.num_features(),
+                feature_set.name(),
+                feature_set.len()
+            )));
+        }
+        Ok(LtrQuery { feature_set, model })
+    }
+
+    pub fn feature_set(&self) -> &FeatureSet<C> {
+        &self.feature_set
+    }
+
+    pub fn model(&self) -> &dyn RankModel {
+        self.model.as_ref()
+    }
+}
+
+impl<C: Codec> Query<C> for LtrQuery<C> {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let names = self
+            .feature_set
+            .feature_names()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        Ok(Box::new(LtrWeight {
+            features: self.feature_set.create_weight(searcher)?,
+            names: Arc::new(names),
+            model: Arc::clone(&self.model),
+            weight: 1f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<C: Codec> fmt::Display for LtrQuery<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LtrQuery(feature_set: {}, model: {})",
+            &self.feature_set, &self.model
+        )
+    }
+}
+
+struct LtrWeight<C: Codec> {
+    features: FeatureSetWeight<C>,
+    names: Arc<Vec<String>>,
+    model: Arc<dyn RankModel>,
+    weight: f32,
+    norm: f32,
+}
+
+impl<C: Codec> LtrWeight<C> {
+    fn ltr_scorer(&self, leaf_reader_ctx: &LeafReaderContext<'_, C>) -> Result<LtrScorer> {
+        Ok(LtrScorer {
+            iterator: AllDocsIterator::new(leaf_reader_ctx.reader.max_doc()),
+            extractor: self.features.extractor(leaf_reader_ctx)?,
+            values: vec![0f32; self.features.len()],
+            extracted_doc: -1,
+            names: Arc::clone(&self.names),
+            model: Arc::clone(&self.model),
+            weight: self.weight,
+        })
+    }
+}
+
+impl<C: Codec> Weight<C> for LtrWeight<C> {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        Ok(Some(Box::new(self.ltr_scorer(leaf_reader_ctx)?)))
+    }
+
+    fn query_type(&self) -> &'static str {
+        LTR
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    fn needs_scores(&self) -> bool {
+        true
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        let mut scorer = self.ltr_scorer(reader)?;
+        scorer.advance(doc)?;
+        let score = scorer.score()?;
+        let features = self
+            .names
+            .iter()
+            .zip(scorer.values.iter())
+            .map(|(name, v)| Explanation::new(true, *v, name.clone(), vec![]))
+            .collect();
+        Ok(Explanation::new(
+            true,
+            score,
+            format!("{}, model: {}, of features:", self, &self.model),
+            features,
+        ))
+    }
+}
+
+impl<C: Codec> fmt::Display for LtrWeight<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LtrWeight(features: {:?}, weight: {})",
+            &self.names, self.weight
+        )
+    }
+}
+
+struct LtrScorer {
+    iterator: AllDocsIterator,
+    extractor: LeafFeatureExtractor,
+    values: Vec<f32>,
+    // the doc `values` were extracted for
+    extracted_doc: DocId,
+    names: Arc<Vec<String>>,
+    model: Arc<dyn RankModel>,
+    weight: f32,
+}
+
+impl LtrScorer {
+    fn extract(&mut self) -> Result<()> {
+        let doc = self.iterator.doc_id();
+        if doc != self.extracted_doc {
+            self.extractor.extract(doc, &mut self.values)?;
+            self.extracted_doc = doc;
+        }
+        Ok(())
+    }
+}
+
+impl Scorer for LtrScorer {
+    fn score(&mut self) -> Result<f32> {
+        self.extract()?;
+        Ok(self.model.score(&self.values) * self.weight)
+    }
+
+    fn score_feature(&mut self) -> Result<Vec<FeatureResult>> {
+        self.extract()?;
+        let features: HashMap<String, VariantValue> = self
+            .names
+            .iter()
+            .zip(self.values.iter())
+            .map(|(name, v)| (name.clone(), VariantValue::Float(*v)))
+            .collect();
+        Ok(vec![FeatureResult::new(features)])
+    }
+}
+
+impl DocIterator for LtrScorer {
+    fn doc_id(&self) -> DocId {
+        self.iterator.doc_id()
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        self.iterator.next()
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        self.iterator.advance(target)
+    }
+
+    fn cost(&self) -> usize {
+        self.iterator.cost()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+    use core::analysis::WhitespaceTokenizer;
+    use core::codec::tests::TestCodec;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType, Fieldable, IndexOptions, NumericDocValuesField, Term};
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::search::collector::TopDocsCollector;
+    use core::search::facet::{NumericValuesSource, NumericValuesType};
+    use core::search::ltr::{Feature, LinearModel, TreeEnsembleModel};
+    use core::search::query::{BooleanQuery, MatchAllDocsQuery};
+    use core::search::scorer::{QueryRescorer, RescoreMode, RescoreRequest, Rescorer};
+    use core::search::sort_field::{ScoreDoc, ScoreDocHit};
+    use core::search::{DefaultIndexSearcher, IndexSearcher};
+    use core::store::directory::FSDirectory;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_ltr_query_new() {
+        let mut feature_set: FeatureSet<TestCodec> = FeatureSet::new("test");
+        feature_set
+            .add(Feature::query("all", Box::new(MatchAllDocsQuery)))
+            .unwrap();
+        let feature_set = Arc::new(feature_set);
+
+        let model = Arc::new(LinearModel::new(vec![2.0], 1.0));
+        let query = LtrQuery::new(Arc::clone(&feature_set), model).unwrap();
+        assert_eq!(query.feature_set().len(), 1);
+        assert!((query.model().score(&[0.5]) - 2.0).abs() < 1e-6);
+
+        let model = Arc::new(LinearModel::new(vec![2.0, 1.0], 1.0));
+        assert!(LtrQuery::new(feature_set, model).is_err());
+    }
+
+    fn title_field(text: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        let reader = Cursor::new(text.as_bytes().to_vec());
+        let token_stream = WhitespaceTokenizer::new(Box::new(reader));
+        Field::new(
+            "title".into(),
+            field_type,
+            None,
+            Some(Box::new(token_stream)),
+        )
+    }
+
+    fn term_query(field: &str, text: &str) -> TermQuery {
+        TermQuery::new(Term::new(field.into(), text.as_bytes().to_vec()), 1.0, None)
+    }
+
+    fn doc_scores<IS: IndexSearcher<CodecEnum>>(
+        searcher: &IS,
+        query: &dyn Query<CodecEnum>,
+    ) -> HashMap<DocId, f32> {
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query, &mut collector).unwrap();
+        collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|hit| (hit.doc_id(), hit.score()))
+            .collect()
+    }
+
+    fn rescore<IS: IndexSearcher<CodecEnum>>(
+        searcher: &IS,
+        query: LtrQuery<CodecEnum>,
+    ) -> Vec<(DocId, f32)> {
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(&MatchAllDocsQuery, &mut collector).unwrap();
+        let mut top_docs = collector.top_docs();
+        let request = RescoreRequest::new(Box::new(query), 0.0, 1.0, RescoreMode::Total, 10, false);
+        QueryRescorer
+            .rescore(searcher, &request, &mut top_docs)
+            .unwrap();
+        top_docs
+            .score_docs()
+            .iter()
+            .map(|hit| (hit.doc_id(), hit.score()))
+            .collect()
+    }
+
+    #[test]
+    fn test_ltr_rescore() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(IndexWriterConfig::default())).unwrap();
+        // two segments, doc 2 has no popularity
+        let segments = [
+            vec![
+                ("red shoes", Some(10)),
+                ("blue shoes", Some(50)),
+                ("red hat", None),
+            ],
+            vec![("red red shoes", Some(5)), ("green socks", Some(100))],
+        ];
+        for docs in &segments {
+            for &(title, popularity) in docs {
+                let mut doc: Vec<Box<dyn Fieldable>> = vec![Box::new(title_field(title))];
+                if let Some(popularity) = popularity {
+                    doc.push(Box::new(NumericDocValuesField::new(
+                        "popularity",
+                        popularity,
+                    )));
+                }
+                writer.add_document(doc).unwrap();
+            }
+            writer.commit().unwrap();
+        }
+        let reader = writer.get_reader(true, false).unwrap();
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader), None);
+
+        let mut feature_set: FeatureSet<CodecEnum> = FeatureSet::new("products");
+        feature_set
+            .add(Feature::terms_bm25("title_bm25", "title", &["red", "shoes"]).unwrap())
+            .unwrap();
+        feature_set
+            .add(Feature::doc_value(
+                "popularity",
+                "popularity",
+                NumericValuesSource::Numeric,
+                NumericValuesType::Long,
+                -1.0,
+            ))
+            .unwrap();
+        feature_set
+            .add(Feature::query(
+                "is_red",
+                Box::new(term_query("title", "red")),
+            ))
+            .unwrap();
+        let feature_set = Arc::new(feature_set);
+
+        // out of order, across segments and with doc 0 twice
+        let hits: Vec<ScoreDocHit> = [4, 0, 3, 0, 2]
+            .iter()
+            .map(|&doc| ScoreDocHit::Score(ScoreDoc::new(doc, 1.0)))
+            .collect();
+        let vectors = feature_set.extract(&searcher, &hits).unwrap();
+        assert_eq!(vectors.len(), hits.len());
+        assert_eq!(vectors[1], vectors[3]);
+
+        let popularity: Vec<f32> = vectors.iter().map(|v| v[1]).collect();
+        assert_eq!(popularity, vec![100.0, 10.0, 5.0, 10.0, -1.0]);
+
+        // the query features are the scores of the queries searched alone
+        let shoulds = vec![
+            Box::new(term_query("title", "red")) as Box<dyn Query<CodecEnum>>,
+            Box::new(term_query("title", "shoes")),
+        ];
+        let bm25_query = BooleanQuery::build(vec![], shoulds, vec![], vec![], 0).unwrap();
+        let bm25 = doc_scores(&searcher, bm25_query.as_ref());
+        let is_red = doc_scores(&searcher, &term_query("title", "red"));
+        for (hit, vector) in hits.iter().zip(vectors.iter()) {
+            let doc = hit.doc_id();
+            let expected_bm25 = bm25.get(&doc).cloned().unwrap_or(0.0);
+            let expected_is_red = is_red.get(&doc).cloned().unwrap_or(0.0);
+            assert!((vector[0] - expected_bm25).abs() < 1e-6);
+            assert!((vector[2] - expected_is_red).abs() < 1e-6);
+        }
+        assert!(vectors[1][0] > 0.0 && vectors[4][0] > 0.0);
+        assert_eq!(vectors[0][0], 0.0);
+        assert_eq!(vectors[0][2], 0.0);
+        assert!(vectors[2][2] > 0.0);
+
+        // ranks by popularity
+        let linear = Arc::new(LinearModel::new(vec![0.0, 1.0, 0.0], 0.0));
+        let query = LtrQuery::new(Arc::clone(&feature_set), linear).unwrap();
+        assert_eq!(
+            rescore(&searcher, query),
+            vec![(4, 100.0), (1, 50.0), (0, 10.0), (3, 5.0), (2, -1.0)]
+        );
+
+        // red titles first, then by popularity, a missing popularity lowers the score
+        let json = r#"[
+            {"nodeid": 0, "split": "is_red", "split_condition": 0.0001,
+                "yes": 1, "no": 2, "missing": 1, "children": [
+                {"nodeid": 1, "split": "popularity", "split_condition": 75.0,
+                    "yes": 3, "no": 4, "missing": 3, "children": [
+                    {"nodeid": 3, "leaf": 0.5},
+                    {"nodeid": 4, "leaf": 1.5}
+                ]},
+                {"nodeid": 2, "split": "popularity", "split_condition": 8.0,
+                    "yes": 5, "no": 6, "missing": 5, "children": [
+                    {"nodeid": 5, "leaf": 2.0},
+                    {"nodeid": 6, "leaf": 4.0}
+                ]}
+            ]},
+            {"nodeid": 0, "split": "popularity", "split_condition": 0.0,
+                "yes": 1, "no": 2, "missing": 1, "children": [
+                {"nodeid": 1, "leaf": -1.0},
+                {"nodeid": 2, "leaf": 0.0}
+            ]}
+        ]"#;
+        let tree = Arc::new(TreeEnsembleModel::from_xgboost_json(json, &feature_set, 0.0).unwrap());
+        let query = LtrQuery::new(Arc::clone(&feature_set), tree).unwrap();
+        assert_eq!(
+            rescore(&searcher, query),
+            vec![(0, 4.0), (3, 2.0), (4, 1.5), (2, 1.0), (1, 0.5)]
+        );
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Learning to rank: rescoring the top hits with a model trained offline.
+//!
+//! A `FeatureSet` lists the signals a model is trained on: query scores, the
+//! BM25 score of terms and numeric doc values. `FeatureSet::extract` and
+//! `QueryRescorer::rescore_features` log their values for the hits of a
+//! search, `format_ranklib` writes them in the format training tools read.
+//!
+//! The trained model, a `LinearModel` or a gradient boosted
+//! `TreeEnsembleModel` dumped by XGBoost or LightGBM, is run by an `LtrQuery`
+//! given to `QueryRescorer` to rerank the top hits.
+
+mod feature;
+
+pub use self::feature::*;
+
+mod model;
+
+pub use self::model::*;
+
+mod tree_ensemble;
+
+pub use self::tree_ensemble::*;
+
+mod ltr_query;
+
+pub use self::ltr_query::*;
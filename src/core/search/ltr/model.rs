@@ -0,0 +1,144 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+
+use serde_json::Value;
+
+use core::codec::Codec;
+use core::search::ltr::FeatureSet;
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+/// A ranking function trained offline, which scores a document from its
+/// feature vector.
+///
+/// The features are indexed in the order of the `FeatureSet` the model was
+/// loaded with.
+pub trait RankModel: fmt::Display + Send + Sync {
+    fn score(&self, features: &[f32]) -> f32;
+
+    /// The number of features the model expects.
+    fn num_features(&self) -> usize;
+}
+
+/// Scores documents with a weighted sum of their features.
+pub struct LinearModel {
+    weights: Vec<f32>,
+    bias: f32,
+}
+
+impl LinearModel {
+    pub fn new(weights: Vec<f32>, bias: f32) -> LinearModel {
+        LinearModel { weights, bias }
+    }
+
+    /// Loads a model with the weights keyed by feature name:
+    ///
+    /// ```json
+    /// {"bias": 0.5, "weights": {"title_bm25": 1.2, "popularity": 0.01}}
+    /// ```
+    ///
+    /// Features without a weight are ignored by the model, weights of
+    /// unknown features are an error.
+    pub fn from_json<C: Codec>(json: &str, feature_set: &FeatureSet<C>) -> Result<LinearModel> {
+        let value: Value = ::serde_json::from_str(json)?;
+        let bias = match value.get("bias") {
+            Some(bias) => json_f32(bias, "bias")?,
+            None => 0f32,
+        };
+        let mut weights = vec![0f32; feature_set.len()];
+        match value.get("weights").and_then(Value::as_object) {
+            Some(map) => {
+                for (name, weight) in map {
+                    match feature_set.ordinal(name) {
+                        Some(ord) => weights[ord] = json_f32(weight, name)?,
+                        None => bail!(IllegalArgument(format!(
+                            "unknown feature '{}' in linear model",
+                            name
+                        ))),
+                    }
+                }
+            }
+            None => bail!(IllegalArgument(
+                "linear model must have an object of 'weights'".into()
+            )),
+        }
+        Ok(LinearModel { weights, bias })
+    }
+}
+
+impl RankModel for LinearModel {
+    fn score(&self, features: &[f32]) -> f32 {
+        debug_assert_eq!(features.len(), self.weights.len());
+        self.weights
+            .iter()
+            .zip(features.iter())
+            .fold(self.bias, |acc, (w, f)| acc + w * f)
+    }
+
+    fn num_features(&self) -> usize {
+        self.weights.len()
+    }
+}
+
+impl fmt::Display for LinearModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LinearModel(weights: {:?}, bias: {})",
+            &self.weights, self.bias
+        )
+    }
+}
+
+pub(crate) fn json_f32(value: &Value, key: &str) -> Result<f32> {
+    match value.as_f64() {
+        Some(v) => Ok(v as f32),
+        None => bail!(IllegalArgument(format!(
+            "'{}' must be a number, got {}",
+            key, value
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::tests::TestCodec;
+    use core::search::ltr::Feature;
+    use core::search::query::MatchAllDocsQuery;
+
+    fn feature_set() -> FeatureSet<TestCodec> {
+        let mut feature_set = FeatureSet::new("test");
+        for name in &["a", "b", "c"] {
+            feature_set
+                .add(Feature::query(name, Box::new(MatchAllDocsQuery)))
+                .unwrap();
+        }
+        feature_set
+    }
+
+    #[test]
+    fn test_linear_model() {
+        let json = r#"{"bias": 0.5, "weights": {"a": 2.0, "c": -1.0}}"#;
+        let model = LinearModel::from_json(json, &feature_set()).unwrap();
+        assert_eq!(model.num_features(), 3);
+        assert!((model.score(&[1.0, 10.0, 3.0]) - (-0.5)).abs() < 1e-6);
+
+        let unknown = r#"{"weights": {"d": 1.0}}"#;
+        assert!(LinearModel::from_json(unknown, &feature_set()).is_err());
+        assert!(LinearModel::from_json(r#"{"bias": 1.0}"#, &feature_set()).is_err());
+    }
+}
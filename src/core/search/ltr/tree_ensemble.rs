@@ -0,0 +1,429 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+
+use serde_json::Value;
+
+use core::codec::Codec;
+use core::search::ltr::{json_f32, FeatureSet, RankModel};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+#[derive(Debug, Clone)]
+enum TreeNode {
+    Split {
+        feature: usize,
+        threshold: f32,
+        // whether values equal to the threshold go left
+        inclusive: bool,
+        // where NaN values go
+        default_left: bool,
+        left: usize,
+        right: usize,
+    },
+    Leaf(f32),
+}
+
+/// A regression tree, flattened with the root at 0.
+#[derive(Debug, Clone)]
+struct RegressionTree {
+    nodes: Vec<TreeNode>,
+}
+
+impl RegressionTree {
+    fn score(&self, features: &[f32]) -> f32 {
+        let mut idx = 0;
+        loop {
+            match self.nodes[idx] {
+                TreeNode::Leaf(value) => return value,
+                TreeNode::Split {
+                    feature,
+                    threshold,
+                    inclusive,
+                    default_left,
+                    left,
+                    right,
+                } => {
+                    let value = features[feature];
+                    let go_left = if value.is_nan() {
+                        default_left
+                    } else if inclusive {
+                        value <= threshold
+                    } else {
+                        value < threshold
+                    };
+                    idx = if go_left { left } else { right };
+                }
+            }
+        }
+    }
+}
+
+/// A gradient boosted tree ensemble, the score is the sum of the leaf values
+/// of all trees plus a base score.
+///
+/// The raw score is used, no objective transformation (e.g. sigmoid) is
+/// applied: it does not change the ranking.
+pub struct TreeEnsembleModel {
+    trees: Vec<RegressionTree>,
+    base_score: f32,
+    num_features: usize,
+}
+
+impl TreeEnsembleModel {
+    /// Loads a model dumped by XGBoost with `Booster.get_dump(dump_format='json')`,
+    /// the trees being the elements of a JSON array.
+    ///
+    /// Splits go to `yes` when the value is lower than `split_condition`. The
+    /// `split` features are resolved by name in the feature set, the `f<N>`
+    /// names of models trained without feature names are the N-th feature.
+    /// `base_score` is not part of the dump and must be given, it is 0.5 in
+    /// XGBoost by default.
+    pub fn from_xgboost_json<C: Codec>(
+        json: &str,
+        feature_set: &FeatureSet<C>,
+        base_score: f32,
+    ) -> Result<TreeEnsembleModel> {
+        let value: Value = ::serde_json::from_str(json)?;
+        let roots = match value.as_array() {
+            Some(roots) => roots,
+            None => bail!(IllegalArgument(
+                "xgboost model must be an array of trees".into()
+            )),
+        };
+        let mut trees = Vec::with_capacity(roots.len());
+        for root in roots {
+            let mut nodes = vec![];
+            Self::parse_xgboost_node(root, feature_set, &mut nodes)?;
+            trees.push(RegressionTree { nodes });
+        }
+        Ok(TreeEnsembleModel {
+            trees,
+            base_score,
+            num_features: feature_set.len(),
+        })
+    }
+
+    fn parse_xgboost_node<C: Codec>(
+        node: &Value,
+        feature_set: &FeatureSet<C>,
+        nodes: &mut Vec<TreeNode>,
+    ) -> Result<usize> {
+        let idx = nodes.len();
+        if let Some(leaf) = node.get("leaf") {
+            nodes.push(TreeNode::Leaf(json_f32(leaf, "leaf")?));
+            return Ok(idx);
+        }
+
+        let feature = match node.get("split").and_then(Value::as_str) {
+            Some(name) => resolve_feature(name, "f", feature_set)?,
+            None => bail!(IllegalArgument(format!(
+                "xgboost node without leaf or split: {}",
+                node
+            ))),
+        };
+        let threshold = json_f32(field(node, "split_condition")?, "split_condition")?;
+        let yes = json_u64(field(node, "yes")?, "yes")?;
+        let no = json_u64(field(node, "no")?, "no")?;
+        let missing = match node.get("missing") {
+            Some(missing) => json_u64(missing, "missing")?,
+            None => yes,
+        };
+        let children = match node.get("children").and_then(Value::as_array) {
+            Some(children) => children,
+            None => bail!(IllegalArgument(format!(
+                "xgboost split without children: {}",
+                node
+            ))),
+        };
+
+        // the children are pushed after their parent, fix the links later
+        nodes.push(TreeNode::Leaf(0f32));
+        let left = Self::parse_xgboost_node(xgboost_child(children, yes)?, feature_set, nodes)?;
+        let right = Self::parse_xgboost_node(xgboost_child(children, no)?, feature_set, nodes)?;
+        nodes[idx] = TreeNode::Split {
+            feature,
+            threshold,
+            inclusive: false,
+            default_left: missing == yes,
+            left,
+            right,
+        };
+        Ok(idx)
+    }
+
+    /// Loads a model dumped by LightGBM with `Booster.dump_model()`.
+    ///
+    /// Splits go to the left child when the value is lower than or equal to
+    /// `threshold`. The features are resolved by their name in
+    /// `feature_names`, the default `Column_<N>` names are the N-th feature.
+    /// Only numerical `<=` splits are supported.
+    pub fn from_lightgbm_json<C: Codec>(
+        json: &str,
+        feature_set: &FeatureSet<C>,
+    ) -> Result<TreeEnsembleModel> {
+        let value: Value = ::serde_json::from_str(json)?;
+        let mut features = vec![];
+        if let Some(names) = value.get("feature_names").and_then(Value::as_array) {
+            for name in names {
+                match name.as_str() {
+                    Some(name) => features.push(resolve_feature(name, "Column_", feature_set)?),
+                    None => bail!(IllegalArgument(format!(
+                        "feature name must be a string, got {}",
+                        name
+                    ))),
+                }
+            }
+        }
+        let tree_info = match value.get("tree_info").and_then(Value::as_array) {
+            Some(tree_info) => tree_info,
+            None => bail!(IllegalArgument(
+                "lightgbm model must have an array of 'tree_info'".into()
+            )),
+        };
+        let mut trees = Vec::with_capacity(tree_info.len());
+        for info in tree_info {
+            let mut nodes = vec![];
+            let root = field(info, "tree_structure")?;
+            Self::parse_lightgbm_node(root, &features, feature_set, &mut nodes)?;
+            trees.push(RegressionTree { nodes });
+        }
+        Ok(TreeEnsembleModel {
+            trees,
+            base_score: 0f32,
+            num_features: feature_set.len(),
+        })
+    }
+
+    fn parse_lightgbm_node<C: Codec>(
+        node: &Value,
+        features: &[usize],
+        feature_set: &FeatureSet<C>,
+        nodes: &mut Vec<TreeNode>,
+    ) -> Result<usize> {
+        let idx = nodes.len();
+        if let Some(leaf) = node.get("leaf_value") {
+            nodes.push(TreeNode::Leaf(json_f32(leaf, "leaf_value")?));
+            return Ok(idx);
+        }
+
+        let split_feature = json_u64(field(node, "split_feature")?, "split_feature")? as usize;
+        let feature = if features.is_empty() {
+            if split_feature >= feature_set.len() {
+                bail!(IllegalArgument(format!(
+                    "split feature {} is out of the feature set",
+                    split_feature
+                )));
+            }
+            split_feature
+        } else if split_feature < features.len() {
+            features[split_feature]
+        } else {
+            bail!(IllegalArgument(format!(
+                "split feature {} is out of feature_names",
+                split_feature
+            )));
+        };
+        match node.get("decision_type").and_then(Value::as_str) {
+            None | Some("<=") => {}
+            Some(other) => bail!(IllegalArgument(format!(
+                "unsupported lightgbm decision type '{}'",
+                other
+            ))),
+        }
+        let threshold = json_f32(field(node, "threshold")?, "threshold")?;
+        let default_left = node
+            .get("default_left")
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+
+        nodes.push(TreeNode::Leaf(0f32));
+        let left =
+            Self::parse_lightgbm_node(field(node, "left_child")?, features, feature_set, nodes)?;
+        let right =
+            Self::parse_lightgbm_node(field(node, "right_child")?, features, feature_set, nodes)?;
+        nodes[idx] = TreeNode::Split {
+            feature,
+            threshold,
+            inclusive: true,
+            default_left,
+            left,
+            right,
+        };
+        Ok(idx)
+    }
+
+    pub fn num_trees(&self) -> usize {
+        self.trees.len()
+    }
+}
+
+impl RankModel for TreeEnsembleModel {
+    fn score(&self, features: &[f32]) -> f32 {
+        debug_assert_eq!(features.len(), self.num_features);
+        self.trees
+            .iter()
+            .fold(self.base_score, |acc, t| acc + t.score(features))
+    }
+
+    fn num_features(&self) -> usize {
+        self.num_features
+    }
+}
+
+impl fmt::Display for TreeEnsembleModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "TreeEnsembleModel(trees: {}, base_score: {})",
+            self.trees.len(),
+            self.base_score
+        )
+    }
+}
+
+fn field<'a>(node: &'a Value, key: &str) -> Result<&'a Value> {
+    match node.get(key) {
+        Some(v) => Ok(v),
+        None => bail!(IllegalArgument(format!(
+            "missing '{}' in tree node {}",
+            key, node
+        ))),
+    }
+}
+
+fn xgboost_child(children: &[Value], id: u64) -> Result<&Value> {
+    match children
+        .iter()
+        .find(|c| c.get("nodeid").and_then(Value::as_u64) == Some(id))
+    {
+        Some(c) => Ok(c),
+        None => bail!(IllegalArgument(format!(
+            "xgboost node {} not found in children",
+            id
+        ))),
+    }
+}
+
+fn json_u64(value: &Value, key: &str) -> Result<u64> {
+    match value.as_u64() {
+        Some(v) => Ok(v),
+        None => bail!(IllegalArgument(format!(
+            "'{}' must be a non negative integer, got {}",
+            key, value
+        ))),
+    }
+}
+
+// resolves a feature by name, or by index for the default `<prefix><N>` names
+fn resolve_feature<C: Codec>(
+    name: &str,
+    default_prefix: &str,
+    feature_set: &FeatureSet<C>,
+) -> Result<usize> {
+    if let Some(ord) = feature_set.ordinal(name) {
+        return Ok(ord);
+    }
+    if name.starts_with(default_prefix) {
+        if let Ok(ord) = name[default_prefix.len()..].parse::<usize>() {
+            if ord < feature_set.len() {
+                return Ok(ord);
+            }
+        }
+    }
+    bail!(IllegalArgument(format!(
+        "unknown feature '{}' in feature set '{}'",
+        name,
+        feature_set.name()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::tests::TestCodec;
+    use core::search::ltr::Feature;
+    use core::search::query::MatchAllDocsQuery;
+
+    fn feature_set() -> FeatureSet<TestCodec> {
+        let mut feature_set = FeatureSet::new("test");
+        for name in &["bm25", "popularity"] {
+            feature_set
+                .add(Feature::query(name, Box::new(MatchAllDocsQuery)))
+                .unwrap();
+        }
+        feature_set
+    }
+
+    #[test]
+    fn test_xgboost_model() {
+        let json = r#"[
+            {"nodeid": 0, "depth": 0, "split": "bm25", "split_condition": 2.0,
+             "yes": 1, "no": 2, "missing": 2, "children": [
+                {"nodeid": 1, "leaf": -0.5},
+                {"nodeid": 2, "depth": 1, "split": "f1", "split_condition": 10.0,
+                 "yes": 3, "no": 4, "missing": 3, "children": [
+                    {"nodeid": 3, "leaf": 0.25},
+                    {"nodeid": 4, "leaf": 1.0}
+                ]}
+            ]},
+            {"nodeid": 0, "leaf": 0.125}
+        ]"#;
+        let model = TreeEnsembleModel::from_xgboost_json(json, &feature_set(), 0.5).unwrap();
+        assert_eq!(model.num_trees(), 2);
+        assert!((model.score(&[1.0, 100.0]) - 0.125).abs() < 1e-6);
+        // threshold is exclusive
+        assert!((model.score(&[2.0, 5.0]) - 0.875).abs() < 1e-6);
+        assert!((model.score(&[3.0, 10.0]) - 1.625).abs() < 1e-6);
+        // NaN follows missing
+        assert!((model.score(&[::std::f32::NAN, ::std::f32::NAN]) - 0.875).abs() < 1e-6);
+
+        let unknown = r#"[{"nodeid": 0, "split": "price", "split_condition": 1.0,
+            "yes": 1, "no": 2, "children": [{"nodeid": 1, "leaf": 0}, {"nodeid": 2, "leaf": 1}]}]"#;
+        assert!(TreeEnsembleModel::from_xgboost_json(unknown, &feature_set(), 0.0).is_err());
+    }
+
+    #[test]
+    fn test_lightgbm_model() {
+        let json = r#"{
+            "feature_names": ["popularity", "bm25"],
+            "tree_info": [
+                {"tree_index": 0, "tree_structure": {
+                    "split_feature": 1, "threshold": 2.0, "decision_type": "<=",
+                    "default_left": false,
+                    "left_child": {"leaf_value": -1.0},
+                    "right_child": {
+                        "split_feature": 0, "threshold": 10.0, "decision_type": "<=",
+                        "default_left": true,
+                        "left_child": {"leaf_value": 0.5},
+                        "right_child": {"leaf_value": 2.0}
+                    }
+                }},
+                {"tree_index": 1, "tree_structure": {"leaf_value": 0.25}}
+            ]
+        }"#;
+        let model = TreeEnsembleModel::from_lightgbm_json(json, &feature_set()).unwrap();
+        assert_eq!(model.num_trees(), 2);
+        // threshold is inclusive
+        assert!((model.score(&[2.0, 100.0]) - (-0.75)).abs() < 1e-6);
+        assert!((model.score(&[3.0, 10.0]) - 0.75).abs() < 1e-6);
+        assert!((model.score(&[3.0, 11.0]) - 2.25).abs() < 1e-6);
+
+        let categorical = r#"{"tree_info": [{"tree_structure": {
+            "split_feature": 0, "threshold": "1||2", "decision_type": "==",
+            "left_child": {"leaf_value": 0}, "right_child": {"leaf_value": 1}}}]}"#;
+        assert!(TreeEnsembleModel::from_lightgbm_json(categorical, &feature_set()).is_err());
+    }
+}
@@ -11,8 +11,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod aggregation;
 pub mod cache;
 pub mod collector;
+pub mod facet;
+pub mod ltr;
 pub mod query;
 pub mod scorer;
 pub mod similarity;
@@ -34,6 +37,10 @@ mod search_manager;
 
 pub use self::search_manager::*;
 
+mod nearest_points;
+
+pub use self::nearest_points::*;
+
 use std::i32;
 
 use core::util::DocId;
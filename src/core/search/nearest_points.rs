@@ -0,0 +1,188 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::points::{Lucene60PointsReader, PointValues};
+use core::index::reader::IndexReader;
+use core::util::bkd::{NearestPoint, PointDistance};
+
+use error::{ErrorKind, Result};
+
+/// Finds the `k` points of `field` closest to the target of `distance` over
+/// all the segments of `reader`, sorted by increasing distance (ties are broken
+/// by doc id). Deleted docs are skipped and `NearestPoint::doc` is the doc id
+/// in `reader`.
+///
+/// Each segment contributes its own `k` nearest points, which are merged into
+/// the global `k` nearest.
+pub fn nearest_points<R: IndexReader + ?Sized>(
+    reader: &R,
+    field: &str,
+    distance: &impl PointDistance,
+    k: usize,
+) -> Result<Vec<NearestPoint>> {
+    let mut hits = vec![];
+    for leaf in reader.leaves() {
+        let points = match leaf.reader.point_values() {
+            Some(points) => points,
+            None => continue,
+        };
+        let points = match PointValues::as_any(&*points).downcast_ref::<Lucene60PointsReader>() {
+            Some(points) => points,
+            None => bail!(ErrorKind::IllegalArgument(format!(
+                "nearest points of field '{}' need a Lucene60PointsReader",
+                field
+            ))),
+        };
+        let bkd_reader = match points.bkd_reader(field)? {
+            Some(bkd_reader) => bkd_reader,
+            None => continue,
+        };
+        let segment_hits = if leaf.reader.num_docs() < leaf.reader.max_doc() {
+            let live_docs = leaf.reader.live_docs();
+            bkd_reader.nearest(distance, k, Some(live_docs.as_ref()))?
+        } else {
+            bkd_reader.nearest(distance, k, None)?
+        };
+        for mut hit in segment_hits {
+            hit.doc += leaf.doc_base;
+            hits.push(hit);
+        }
+    }
+    hits.sort_by(NearestPoint::cmp_by_distance);
+    hits.truncate(k);
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    use core::doc::{Field, FieldType, Fieldable, IndexOptions, Term};
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::search::query::IntPoint;
+    use core::store::directory::FSDirectory;
+    use core::util::bkd::EuclideanDistance;
+    use core::util::{DocId, VariantValue};
+
+    use std::sync::Arc;
+
+    fn new_doc(point: [i32; 2], deleted: bool) -> Vec<Box<dyn Fieldable>> {
+        let point_type = FieldType {
+            tokenized: false,
+            dimension_count: 2,
+            dimension_num_bytes: 4,
+            ..FieldType::default()
+        };
+        let mut group_type = FieldType::default();
+        group_type.index_options = IndexOptions::Docs;
+        group_type.tokenized = false;
+        group_type.omit_norms = true;
+        let group = if deleted { "deleted" } else { "live" };
+        vec![
+            Box::new(Field::new_bytes(
+                "point".into(),
+                IntPoint::pack(&point),
+                point_type,
+            )),
+            Box::new(Field::new(
+                "group".into(),
+                group_type,
+                Some(VariantValue::VString(group.into())),
+                None,
+            )),
+        ]
+    }
+
+    // the k nearest (doc, distance) of the live points, by brute force
+    fn brute_force(points: &[([i32; 2], bool)], target: [i32; 2], k: usize) -> Vec<(DocId, f64)> {
+        let mut hits: Vec<(DocId, f64)> = points
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, deleted))| !*deleted)
+            .map(|(doc, (point, _))| {
+                let dx = f64::from(point[0]) - f64::from(target[0]);
+                let dy = f64::from(point[1]) - f64::from(target[1]);
+                (doc as DocId, (dx * dx + dy * dy).sqrt())
+            })
+            .collect();
+        hits.sort_by(|h1, h2| h1.1.partial_cmp(&h2.1).unwrap().then(h1.0.cmp(&h2.0)));
+        hits.truncate(k);
+        hits
+    }
+
+    #[test]
+    fn test_nearest_points() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(IndexWriterConfig::default())).unwrap();
+
+        // enough points for the first segment to have inner nodes, on a small
+        // grid so that there are ties
+        let mut seed = 17u32;
+        let mut points = vec![];
+        for i in 0..3000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let x = (seed >> 8) as i32 % 200 - 100;
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let y = (seed >> 8) as i32 % 200 - 100;
+            points.push(([x, y], i % 7 == 0));
+        }
+        for (point, deleted) in &points[..2500] {
+            writer.add_document(new_doc(*point, *deleted)).unwrap();
+        }
+        writer.commit().unwrap();
+        for (point, deleted) in &points[2500..] {
+            writer.add_document(new_doc(*point, *deleted)).unwrap();
+        }
+        writer.commit().unwrap();
+
+        let targets = [[0, 0], [-100, 99], [37, -12], [1000, 1000]];
+        let check = |points: &[([i32; 2], bool)],
+                     reader: &dyn Fn(usize, [i32; 2]) -> Vec<NearestPoint>| {
+            for target in &targets {
+                for k in &[1, 10, 100, 5000] {
+                    let expected = brute_force(points, *target, *k);
+                    let hits = reader(*k, *target);
+                    assert_eq!(hits.len(), expected.len());
+                    for (hit, (doc, distance)) in hits.iter().zip(expected) {
+                        assert_eq!(hit.doc, doc);
+                        assert!((hit.distance - distance).abs() < 1e-9);
+                    }
+                }
+            }
+        };
+
+        // without deletions
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.leaves().len(), 2);
+        let all: Vec<([i32; 2], bool)> = points.iter().map(|(p, _)| (*p, false)).collect();
+        check(&all, &|k, target| {
+            let distance = EuclideanDistance::new(&IntPoint::pack(&target), 2).unwrap();
+            nearest_points(&reader, "point", &distance, k).unwrap()
+        });
+
+        // deleted docs are skipped
+        writer
+            .delete_documents_by_terms(vec![Term::new("group".into(), b"deleted".to_vec())])
+            .unwrap();
+        writer.commit().unwrap();
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.leaves().len(), 2);
+        check(&points, &|k, target| {
+            let distance = EuclideanDistance::new(&IntPoint::pack(&target), 2).unwrap();
+            nearest_points(&reader, "point", &distance, k).unwrap()
+        });
+    }
+}
@@ -0,0 +1,431 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::knn_vectors::KnnVectorsReader;
+use core::codec::Codec;
+use core::index::reader::LeafReaderContext;
+use core::search::explanation::Explanation;
+use core::search::query::{Query, TermQuery, Weight};
+use core::search::scorer::Scorer;
+use core::search::searcher::SearchPlanBuilder;
+use core::search::{DocIterator, NO_MORE_DOCS};
+use core::util::{BitSet, Bits, DocId, FixedBitSet};
+
+use error::{ErrorKind, Result};
+
+use std::cmp::Ordering;
+use std::fmt;
+
+pub const KNN_VECTOR: &str = "knn_vector";
+
+/// Finds the `k` documents whose vector is the most similar to a target vector.
+///
+/// The search is approximate, it walks the HNSW graph of each segment. The
+/// nearest docs of all the segments are gathered when the weight is created,
+/// and only the global `k` best of them match, so the query never matches more
+/// than `k` docs. The score of a doc is the similarity of its vector to the
+/// target, as defined by the `VectorSimilarity` of the field.
+///
+/// Deleted docs never match. If a filter is given, only docs matching it are
+/// returned, which may trigger an exact scan of the segment when it is very
+/// selective.
+pub struct KnnVectorQuery<C: Codec> {
+    field: String,
+    target: Vec<f32>,
+    k: usize,
+    filter: Option<Box<dyn Query<C>>>,
+}
+
+impl<C: Codec> KnnVectorQuery<C> {
+    pub fn new(field: &str, target: Vec<f32>, k: usize) -> Result<KnnVectorQuery<C>> {
+        Self::build(field, target, k, None)
+    }
+
+    pub fn with_filter(
+        field: &str,
+        target: Vec<f32>,
+        k: usize,
+        filter: Box<dyn Query<C>>,
+    ) -> Result<KnnVectorQuery<C>> {
+        Self::build(field, target, k, Some(filter))
+    }
+
+    fn build(
+        field: &str,
+        target: Vec<f32>,
+        k: usize,
+        filter: Option<Box<dyn Query<C>>>,
+    ) -> Result<KnnVectorQuery<C>> {
+        if k == 0 {
+            bail!(ErrorKind::IllegalArgument("k must be at least 1".into()));
+        }
+        if target.is_empty() || target.iter().any(|v| !v.is_finite()) {
+            bail!(ErrorKind::IllegalArgument(
+                "target vector must be non empty with finite values".into()
+            ));
+        }
+        Ok(KnnVectorQuery {
+            field: field.to_string(),
+            target,
+            k,
+            filter,
+        })
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn target(&self) -> &[f32] {
+        &self.target
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    // the live docs matching the filter, None if no doc matches
+    fn accept_docs(
+        &self,
+        filter: &dyn Weight<C>,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<FixedBitSet>> {
+        let leaf_reader = leaf_reader_ctx.reader;
+        let mut scorer = match filter.create_scorer(leaf_reader_ctx)? {
+            Some(scorer) => scorer,
+            None => return Ok(None),
+        };
+        let live_docs = leaf_reader.live_docs();
+        let mut accept_docs = FixedBitSet::new(leaf_reader.max_doc() as usize);
+        let mut matched = false;
+        loop {
+            let doc = scorer.next()?;
+            if doc == NO_MORE_DOCS {
+                break;
+            }
+            if live_docs.get(doc as usize)? {
+                accept_docs.set(doc as usize);
+                matched = true;
+            }
+        }
+        Ok(if matched { Some(accept_docs) } else { None })
+    }
+
+    // the `k` nearest docs of one segment, best first
+    fn search_leaf(
+        &self,
+        filter: Option<&dyn Weight<C>>,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Vec<(DocId, f32)>> {
+        let leaf_reader = leaf_reader_ctx.reader;
+        let vectors = match leaf_reader.knn_vectors() {
+            Some(vectors) => vectors,
+            None => return Ok(vec![]),
+        };
+        match leaf_reader.field_info(&self.field) {
+            Some(field_info) if field_info.knn_vector_dimension() > 0 => {}
+            _ => return Ok(vec![]),
+        }
+
+        if let Some(filter) = filter {
+            match self.accept_docs(filter, leaf_reader_ctx)? {
+                Some(accept_docs) => vectors.search(
+                    &self.field,
+                    &self.target,
+                    self.k,
+                    Some(&accept_docs as &dyn Bits),
+                ),
+                None => Ok(vec![]),
+            }
+        } else if leaf_reader.num_docs() < leaf_reader.max_doc() {
+            let live_docs = leaf_reader.live_docs();
+            vectors.search(&self.field, &self.target, self.k, Some(live_docs.as_ref()))
+        } else {
+            vectors.search(&self.field, &self.target, self.k, None)
+        }
+    }
+}
+
+impl<C: Codec> Query<C> for KnnVectorQuery<C> {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let filter = match self.filter {
+            Some(ref filter) => Some(searcher.create_weight(filter.as_ref(), false)?),
+            None => None,
+        };
+
+        // (leaf ord, global doc, score) of the nearest docs of every segment
+        let leaves = searcher.leaves();
+        let mut hits = vec![];
+        for leaf in &leaves {
+            for (doc, score) in self.search_leaf(filter.as_ref().map(|f| f.as_ref()), leaf)? {
+                hits.push((leaf.ord, leaf.doc_base + doc, score));
+            }
+        }
+        // keep the global top k, ties are broken by ascending doc id
+        hits.sort_by(|h1, h2| {
+            h2.2.partial_cmp(&h1.2)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| h1.1.cmp(&h2.1))
+        });
+        hits.truncate(self.k);
+
+        let mut leaf_docs = vec![vec![]; leaves.len()];
+        for (ord, doc, score) in hits {
+            leaf_docs[ord].push((doc - leaves[ord].doc_base, score));
+        }
+        Ok(Box::new(KnnVectorWeight {
+            field: self.field.clone(),
+            target: self.target.clone(),
+            k: self.k,
+            leaf_docs,
+            weight: 1f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self
+    }
+}
+
+impl<C: Codec> fmt::Display for KnnVectorQuery<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "KnnVectorQuery(field: {}, target: {:?}, k: {}",
+            &self.field, &self.target, self.k
+        )?;
+        if let Some(ref filter) = self.filter {
+            write!(f, ", filter: {}", filter)?;
+        }
+        write!(f, ")")
+    }
+}
+
+struct KnnVectorWeight {
+    field: String,
+    target: Vec<f32>,
+    k: usize,
+    // the docs of each segment within the global top k, by leaf ord
+    leaf_docs: Vec<Vec<(DocId, f32)>>,
+    weight: f32,
+    norm: f32,
+}
+
+impl<C: Codec> Weight<C> for KnnVectorWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        match self.leaf_docs.get(leaf_reader_ctx.ord) {
+            Some(docs) if !docs.is_empty() => Ok(Some(Box::new(KnnVectorScorer::new(
+                docs.clone(),
+                self.weight,
+            )))),
+            _ => Ok(None),
+        }
+    }
+
+    fn query_type(&self) -> &'static str {
+        KNN_VECTOR
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    fn needs_scores(&self) -> bool {
+        true
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        if let Some(mut scorer) = self.create_scorer(reader)? {
+            if scorer.advance(doc)? == doc {
+                let score = scorer.score()?;
+                return Ok(Explanation::new(
+                    true,
+                    score,
+                    format!("{} in {}, vector similarity to target", self, doc),
+                    vec![],
+                ));
+            }
+        }
+        Ok(Explanation::new(
+            false,
+            0.0f32,
+            format!(
+                "{} is not within the {} nearest docs of {}",
+                doc, self.k, self
+            ),
+            vec![],
+        ))
+    }
+}
+
+impl fmt::Display for KnnVectorWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "KnnVectorWeight(field: {}, target: {:?}, k: {})",
+            &self.field, &self.target, self.k
+        )
+    }
+}
+
+/// Iterates over the nearest docs found in one segment.
+struct KnnVectorScorer {
+    // sorted by doc
+    docs: Vec<DocId>,
+    scores: Vec<f32>,
+    // position of the current doc in `docs`
+    index: usize,
+    doc: DocId,
+}
+
+impl KnnVectorScorer {
+    fn new(mut results: Vec<(DocId, f32)>, boost: f32) -> KnnVectorScorer {
+        results.sort_by_key(|r| r.0);
+        let (docs, scores) = results.into_iter().map(|(d, s)| (d, s * boost)).unzip();
+        KnnVectorScorer {
+            docs,
+            scores,
+            index: 0,
+            doc: -1,
+        }
+    }
+}
+
+impl Scorer for KnnVectorScorer {
+    fn score(&mut self) -> Result<f32> {
+        debug_assert!(self.doc >= 0 && self.doc != NO_MORE_DOCS);
+        Ok(self.scores[self.index])
+    }
+}
+
+impl DocIterator for KnnVectorScorer {
+    fn doc_id(&self) -> DocId {
+        self.doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        if self.doc == NO_MORE_DOCS {
+            return Ok(NO_MORE_DOCS);
+        }
+        let target = self.doc + 1;
+        self.advance(target)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        let start = if self.doc < 0 { 0 } else { self.index };
+        self.index = match self.docs[start..].binary_search(&target) {
+            Ok(i) | Err(i) => start + i,
+        };
+        self.doc = if self.index < self.docs.len() {
+            self.docs[self.index]
+        } else {
+            NO_MORE_DOCS
+        };
+        Ok(self.doc)
+    }
+
+    fn cost(&self) -> usize {
+        self.docs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+    use core::codec::tests::TestCodec;
+    use core::doc::{Fieldable, KnnVectorField};
+    use core::index::reader::IndexReader;
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::search::collector::TopDocsCollector;
+    use core::search::{DefaultIndexSearcher, IndexSearcher};
+    use core::store::directory::FSDirectory;
+    use core::util::VectorSimilarity;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn test_knn_vector_query_new() {
+        let query: KnnVectorQuery<TestCodec> =
+            KnnVectorQuery::new("vec", vec![1.0, 0.0], 10).unwrap();
+        assert_eq!(query.field(), "vec");
+        assert_eq!(query.k(), 10);
+        assert_eq!(query.target(), &[1.0, 0.0]);
+
+        assert!(KnnVectorQuery::<TestCodec>::new("vec", vec![1.0], 0).is_err());
+        assert!(KnnVectorQuery::<TestCodec>::new("vec", vec![], 1).is_err());
+        assert!(KnnVectorQuery::<TestCodec>::new("vec", vec![::std::f32::NAN], 1).is_err());
+    }
+
+    #[test]
+    fn test_knn_vector_scorer() {
+        let mut scorer = KnnVectorScorer::new(vec![(7, 0.5), (2, 0.9), (4, 0.7)], 2.0);
+        assert_eq!(scorer.cost(), 3);
+        assert_eq!(scorer.next().unwrap(), 2);
+        assert!((scorer.score().unwrap() - 1.8).abs() < 1e-6);
+        assert_eq!(scorer.advance(5).unwrap(), 7);
+        assert!((scorer.score().unwrap() - 1.0).abs() < 1e-6);
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+    }
+
+    #[test]
+    fn test_knn_vector_query_global_top_k() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(IndexWriterConfig::default())).unwrap();
+        // 4 segments of 5 docs, the doc `i` has the vector [i, 1]
+        for segment in 0..4 {
+            for i in 0..5 {
+                let x = (segment * 5 + i) as f32;
+                let field = KnnVectorField::new("vec", &[x, 1.0], VectorSimilarity::Euclidean);
+                let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(field.unwrap())];
+                writer.add_document(doc).unwrap();
+            }
+            writer.commit().unwrap();
+        }
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.leaves().len(), 4);
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader), None);
+
+        let query = KnnVectorQuery::new("vec", vec![7.2, 1.0], 3).unwrap();
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(&query, &mut collector).unwrap();
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 3);
+        let docs: Vec<DocId> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        assert_eq!(docs, vec![7, 8, 6]);
+        assert_eq!(searcher.count(&query).unwrap(), 3);
+
+        assert!(searcher.explain(&query, 8).unwrap().is_match());
+        assert!(!searcher.explain(&query, 9).unwrap().is_match());
+    }
+}
@@ -0,0 +1,743 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+
+use core::analysis::TokenStream;
+use core::codec::points::{IntersectVisitor, PointValues, Relation};
+use core::codec::Codec;
+use core::doc::{DocValuesType, Field, FieldType, Fieldable, IndexOptions};
+use core::index::reader::{LeafReader, LeafReaderContext};
+use core::search::explanation::Explanation;
+use core::search::query::{AllDocsIterator, PointDocIterEnum, Query, TermQuery, Weight};
+use core::search::scorer::{ConstantScoreScorer, Scorer};
+use core::search::searcher::SearchPlanBuilder;
+use core::search::{DocIdSet, DocIterator, EmptyDocIterator};
+use core::util::geo::{
+    check_latitude, check_longitude, decode_latitude, decode_longitude,
+    distance_query_sort_key, encode_latitude, encode_latitude_ceil, encode_longitude,
+    encode_longitude_ceil, haversin_sort_key, relate_box_to_circle, Polygon, Polygon2D,
+    Rectangle,
+};
+use core::util::{
+    int2sortable_bytes, sortable_bytes2int, DocId, DocIdSetBuilder, DocIdSetEnum, Numeric,
+    VariantValue, VectorSimilarity,
+};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+const LAT_LON_POINT_FIELD_TYPE: FieldType = FieldType {
+    stored: false,
+    tokenized: false,
+    store_term_vectors: false,
+    store_term_vector_offsets: false,
+    store_term_vector_positions: false,
+    store_term_vector_payloads: false,
+    omit_norms: false,
+    index_options: IndexOptions::Null,
+    doc_values_type: DocValuesType::Null,
+    dimension_count: 2,
+    dimension_num_bytes: 4,
+    vector_dimension: 0,
+    vector_similarity: VectorSimilarity::Euclidean,
+};
+
+/// An indexed location, for fast geo filtering.
+///
+/// The latitude and longitude are quantized to 32 bits each, see
+/// `geo::encode_latitude`, and indexed as a two dimensional point. A document
+/// may have several points, it matches if any of them matches.
+///
+/// This field only supports filtering, use `LatLonDocValuesField` to sort by
+/// distance.
+pub struct LatLonPoint {
+    field: Field,
+}
+
+impl LatLonPoint {
+    /// Returns an error if the latitude or longitude is out of bounds.
+    pub fn new(name: &str, latitude: f64, longitude: f64) -> Result<LatLonPoint> {
+        Ok(LatLonPoint {
+            field: Field::new(
+                String::from(name),
+                LAT_LON_POINT_FIELD_TYPE,
+                Some(VariantValue::Binary(LatLonPoint::pack(latitude, longitude)?)),
+                None,
+            ),
+        })
+    }
+
+    /// Encodes a point into the 8 bytes of its indexed value.
+    pub fn pack(latitude: f64, longitude: f64) -> Result<Vec<u8>> {
+        check_latitude(latitude)?;
+        check_longitude(longitude)?;
+        let mut packed = vec![0u8; 8];
+        int2sortable_bytes(encode_latitude(latitude), &mut packed[..4]);
+        int2sortable_bytes(encode_longitude(longitude), &mut packed[4..]);
+        Ok(packed)
+    }
+
+    /// Decodes an indexed value to the quantized `(latitude, longitude)`.
+    pub fn unpack(packed: &[u8]) -> (f64, f64) {
+        (
+            decode_latitude(sortable_bytes2int(&packed[..4])),
+            decode_longitude(sortable_bytes2int(&packed[4..8])),
+        )
+    }
+
+    /// Returns the quantized latitude.
+    pub fn latitude(&self) -> f64 {
+        LatLonPoint::unpack(self.field.binary_value().unwrap()).0
+    }
+
+    /// Returns the quantized longitude.
+    pub fn longitude(&self) -> f64 {
+        LatLonPoint::unpack(self.field.binary_value().unwrap()).1
+    }
+
+    /// Creates a query for the points within a box, bounds included.
+    ///
+    /// `min_longitude > max_longitude` means the box crosses the dateline.
+    pub fn new_box_query<C: Codec>(
+        field: String,
+        min_latitude: f64,
+        max_latitude: f64,
+        min_longitude: f64,
+        max_longitude: f64,
+    ) -> Result<Box<dyn Query<C>>> {
+        let rectangle = Rectangle::new(min_latitude, max_latitude, min_longitude, max_longitude)?;
+        Ok(Box::new(LatLonPointQuery {
+            field,
+            shape: LatLonShape::Box(rectangle),
+        }))
+    }
+
+    /// Creates a query for the points within `radius_meters` of the center,
+    /// using the haversine distance.
+    pub fn new_distance_query<C: Codec>(
+        field: String,
+        latitude: f64,
+        longitude: f64,
+        radius_meters: f64,
+    ) -> Result<Box<dyn Query<C>>> {
+        check_latitude(latitude)?;
+        check_longitude(longitude)?;
+        if !radius_meters.is_finite() || radius_meters < 0.0 {
+            bail!(IllegalArgument(format!(
+                "radius_meters: '{}' is invalid",
+                radius_meters
+            )));
+        }
+        Ok(Box::new(LatLonPointQuery {
+            field,
+            shape: LatLonShape::Distance {
+                latitude,
+                longitude,
+                radius_meters,
+            },
+        }))
+    }
+
+    /// Creates a query for the points within one of the polygons, and not in
+    /// one of their holes.
+    pub fn new_polygon_query<C: Codec>(
+        field: String,
+        polygons: Vec<Polygon>,
+    ) -> Result<Box<dyn Query<C>>> {
+        if polygons.is_empty() {
+            bail!(IllegalArgument("polygons must not be empty".into()));
+        }
+        Ok(Box::new(LatLonPointQuery {
+            field,
+            shape: LatLonShape::Polygon(polygons),
+        }))
+    }
+}
+
+impl Fieldable for LatLonPoint {
+    fn name(&self) -> &str {
+        self.field.name()
+    }
+
+    fn field_type(&self) -> &FieldType {
+        self.field.field_type()
+    }
+
+    fn boost(&self) -> f32 {
+        self.field.boost()
+    }
+
+    fn field_data(&self) -> Option<&VariantValue> {
+        self.field.field_data()
+    }
+
+    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
+        self.field.token_stream()
+    }
+
+    fn binary_value(&self) -> Option<&[u8]> {
+        self.field.binary_value()
+    }
+
+    fn string_value(&self) -> Option<&str> {
+        None
+    }
+
+    fn numeric_value(&self) -> Option<Numeric> {
+        None
+    }
+}
+
+enum LatLonShape {
+    Box(Rectangle),
+    Distance {
+        latitude: f64,
+        longitude: f64,
+        radius_meters: f64,
+    },
+    Polygon(Vec<Polygon>),
+}
+
+impl fmt::Display for LatLonShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LatLonShape::Box(rectangle) => write!(f, "box: {}", rectangle),
+            LatLonShape::Distance {
+                latitude,
+                longitude,
+                radius_meters,
+            } => write!(
+                f,
+                "latitude: {}, longitude: {}, radius: {}m",
+                latitude, longitude, radius_meters
+            ),
+            LatLonShape::Polygon(polygons) => {
+                write!(f, "polygons: [")?;
+                for (i, polygon) in polygons.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", polygon)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Matches the documents with a `LatLonPoint` within a box, a distance of a
+/// point or polygons, see the `LatLonPoint::new_*_query` methods.
+pub struct LatLonPointQuery {
+    field: String,
+    shape: LatLonShape,
+}
+
+pub const LAT_LON_POINT: &str = "lat_lon_point";
+
+impl LatLonPointQuery {
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    fn create_matcher(&self) -> Result<(EncodedBox, LatLonMatcher)> {
+        match self.shape {
+            LatLonShape::Box(ref r) => Ok((EncodedBox::from_box(r), LatLonMatcher::Box)),
+            LatLonShape::Distance {
+                latitude,
+                longitude,
+                radius_meters,
+            } => {
+                let bbox = Rectangle::from_point_distance(latitude, longitude, radius_meters)?;
+                // the exact filtering is done on the sort key, so the box only
+                // needs to be a superset
+                let min_lat = encode_latitude(bbox.min_lat);
+                let max_lat = encode_latitude(bbox.max_lat);
+                let lon_ranges = if bbox.crosses_dateline() {
+                    vec![
+                        (i32::min_value(), encode_longitude(bbox.max_lon)),
+                        (encode_longitude(bbox.min_lon), i32::max_value()),
+                    ]
+                } else {
+                    vec![(encode_longitude(bbox.min_lon), encode_longitude(bbox.max_lon))]
+                };
+                Ok((
+                    EncodedBox {
+                        min_lat,
+                        max_lat,
+                        lon_ranges,
+                    },
+                    LatLonMatcher::Distance {
+                        latitude,
+                        longitude,
+                        sort_key: distance_query_sort_key(radius_meters),
+                        axis_lat: Rectangle::axis_lat(latitude, radius_meters),
+                    },
+                ))
+            }
+            LatLonShape::Polygon(ref polygons) => {
+                let polygon2d = Polygon2D::create(polygons)?;
+                let bounds = EncodedBox {
+                    min_lat: encode_latitude_ceil(polygon2d.min_lat()),
+                    max_lat: encode_latitude(polygon2d.max_lat()),
+                    lon_ranges: vec![(
+                        encode_longitude_ceil(polygon2d.min_lon()),
+                        encode_longitude(polygon2d.max_lon()),
+                    )],
+                };
+                Ok((bounds, LatLonMatcher::Polygon(polygon2d)))
+            }
+        }
+    }
+}
+
+impl<C: Codec> Query<C> for LatLonPointQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let (bounds, matcher) = self.create_matcher()?;
+        Ok(Box::new(LatLonPointWeight {
+            field: self.field.clone(),
+            description: self.shape.to_string(),
+            bounds,
+            matcher,
+            weight: 0f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self
+    }
+}
+
+impl fmt::Display for LatLonPointQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LatLonPointQuery(field: {}, {})", &self.field, &self.shape)
+    }
+}
+
+/// A box on the encoded values, the longitude is split in two ranges when the
+/// box crosses the dateline.
+struct EncodedBox {
+    min_lat: i32,
+    max_lat: i32,
+    lon_ranges: Vec<(i32, i32)>,
+}
+
+impl EncodedBox {
+    fn from_box(r: &Rectangle) -> EncodedBox {
+        // exact values of 90 and 180 can't be represented by the encoding, so
+        // a box with these minimums can't match anything
+        if r.min_lat == 90.0 {
+            return EncodedBox {
+                min_lat: 0,
+                max_lat: 0,
+                lon_ranges: vec![],
+            };
+        }
+        let (min_lon, max_lon) = if r.min_lon == 180.0 && r.max_lon < r.min_lon {
+            (-180.0, r.max_lon)
+        } else {
+            (r.min_lon, r.max_lon)
+        };
+        let lon_ranges = if min_lon == 180.0 {
+            vec![]
+        } else if max_lon < min_lon {
+            vec![
+                (i32::min_value(), encode_longitude(max_lon)),
+                (encode_longitude_ceil(min_lon), i32::max_value()),
+            ]
+        } else {
+            vec![(encode_longitude_ceil(min_lon), encode_longitude(max_lon))]
+        };
+        EncodedBox {
+            min_lat: encode_latitude_ceil(r.min_lat),
+            max_lat: encode_latitude(r.max_lat),
+            lon_ranges,
+        }
+    }
+
+    fn contains(&self, lat: i32, lon: i32) -> bool {
+        lat >= self.min_lat
+            && lat <= self.max_lat
+            && self
+                .lon_ranges
+                .iter()
+                .any(|&(min, max)| lon >= min && lon <= max)
+    }
+
+    fn relate(&self, min_lat: i32, max_lat: i32, min_lon: i32, max_lon: i32) -> Relation {
+        if max_lat < self.min_lat || min_lat > self.max_lat {
+            return Relation::CellOutsideQuery;
+        }
+        let mut overlaps = false;
+        let mut lon_within = false;
+        for &(min, max) in &self.lon_ranges {
+            if max_lon < min || min_lon > max {
+                continue;
+            }
+            overlaps = true;
+            lon_within |= min_lon >= min && max_lon <= max;
+        }
+        if !overlaps {
+            Relation::CellOutsideQuery
+        } else if lon_within && min_lat >= self.min_lat && max_lat <= self.max_lat {
+            Relation::CellInsideQuery
+        } else {
+            Relation::CellCrossesQuery
+        }
+    }
+}
+
+/// The exact check of a shape, after the `EncodedBox` one.
+enum LatLonMatcher {
+    // the box is exact
+    Box,
+    Distance {
+        latitude: f64,
+        longitude: f64,
+        sort_key: f64,
+        axis_lat: f64,
+    },
+    Polygon(Polygon2D),
+}
+
+struct LatLonPointWeight {
+    field: String,
+    description: String,
+    bounds: EncodedBox,
+    matcher: LatLonMatcher,
+    weight: f32,
+    norm: f32,
+}
+
+impl LatLonPointWeight {
+    fn matches(&self, packed_value: &[u8]) -> bool {
+        let lat = sortable_bytes2int(&packed_value[..4]);
+        let lon = sortable_bytes2int(&packed_value[4..8]);
+        if !self.bounds.contains(lat, lon) {
+            return false;
+        }
+        match self.matcher {
+            LatLonMatcher::Box => true,
+            LatLonMatcher::Distance {
+                latitude,
+                longitude,
+                sort_key,
+                ..
+            } => {
+                haversin_sort_key(
+                    latitude,
+                    longitude,
+                    decode_latitude(lat),
+                    decode_longitude(lon),
+                ) <= sort_key
+            }
+            LatLonMatcher::Polygon(ref polygon) => {
+                polygon.contains(decode_latitude(lat), decode_longitude(lon))
+            }
+        }
+    }
+
+    fn relate(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+        let min_lat = sortable_bytes2int(&min_packed_value[..4]);
+        let min_lon = sortable_bytes2int(&min_packed_value[4..8]);
+        let max_lat = sortable_bytes2int(&max_packed_value[..4]);
+        let max_lon = sortable_bytes2int(&max_packed_value[4..8]);
+        let relation = self.bounds.relate(min_lat, max_lat, min_lon, max_lon);
+        if relation == Relation::CellOutsideQuery {
+            return relation;
+        }
+        match self.matcher {
+            LatLonMatcher::Box => relation,
+            LatLonMatcher::Distance {
+                latitude,
+                longitude,
+                sort_key,
+                axis_lat,
+            } => relate_box_to_circle(
+                decode_latitude(min_lat),
+                decode_latitude(max_lat),
+                decode_longitude(min_lon),
+                decode_longitude(max_lon),
+                latitude,
+                longitude,
+                sort_key,
+                axis_lat,
+            ),
+            LatLonMatcher::Polygon(ref polygon) => polygon.relate(
+                decode_latitude(min_lat),
+                decode_latitude(max_lat),
+                decode_longitude(min_lon),
+                decode_longitude(max_lon),
+            ),
+        }
+    }
+
+    fn build_matching_doc_set<R: LeafReader + ?Sized>(
+        &self,
+        reader: &R,
+        values: &impl PointValues,
+    ) -> Result<DocIdSetEnum> {
+        let mut result = DocIdSetBuilder::from_values(reader.max_doc(), values, &self.field)?;
+        {
+            let mut visitor = LatLonPointIntersectVisitor {
+                doc_id_set_builder: &mut result,
+                weight: self,
+            };
+            values.intersect(&self.field, &mut visitor)?;
+        }
+        Ok(result.build())
+    }
+}
+
+impl<C: Codec> Weight<C> for LatLonPointWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let leaf_reader = leaf_reader_ctx.reader;
+        let values = match leaf_reader.point_values() {
+            Some(values) => values,
+            None => {
+                return Ok(None);
+            }
+        };
+        let field_info = match leaf_reader.field_info(&self.field) {
+            Some(field_info) => field_info,
+            None => {
+                return Ok(None);
+            }
+        };
+        if field_info.point_dimension_count != 2 || field_info.point_num_bytes != 4 {
+            bail!(IllegalArgument(format!(
+                "field '{}' was indexed with num_dims={} and bytes_per_dim={}, but LatLonPoint \
+                 has num_dims=2 and bytes_per_dim=4",
+                &self.field, field_info.point_dimension_count, field_info.point_num_bytes
+            )));
+        }
+
+        let all_docs_match = values.doc_count(&self.field)? == leaf_reader.max_doc()
+            && self.relate(
+                &values.min_packed_value(&self.field)?,
+                &values.max_packed_value(&self.field)?,
+            ) == Relation::CellInsideQuery;
+        let iterator = if all_docs_match {
+            PointDocIterEnum::All(AllDocsIterator::new(leaf_reader.max_doc()))
+        } else if let Some(iter) = self
+            .build_matching_doc_set(leaf_reader, &values)?
+            .iterator()?
+        {
+            PointDocIterEnum::DocSet(iter)
+        } else {
+            PointDocIterEnum::None(EmptyDocIterator::default())
+        };
+        let cost = iterator.cost();
+        Ok(Some(Box::new(ConstantScoreScorer::new(
+            self.weight,
+            iterator,
+            cost,
+        ))))
+    }
+
+    fn query_type(&self) -> &'static str {
+        LAT_LON_POINT
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, _reader: &LeafReaderContext<'_, C>, _doc: DocId) -> Result<Explanation> {
+        unimplemented!()
+    }
+}
+
+impl fmt::Display for LatLonPointWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LatLonPointWeight(field: {}, {})",
+            &self.field, &self.description
+        )
+    }
+}
+
+struct LatLonPointIntersectVisitor<'a> {
+    doc_id_set_builder: &'a mut DocIdSetBuilder,
+    weight: &'a LatLonPointWeight,
+}
+
+impl<'a> IntersectVisitor for LatLonPointIntersectVisitor<'a> {
+    fn visit(&mut self, doc_id: DocId) -> Result<()> {
+        self.doc_id_set_builder.add_doc(doc_id);
+        Ok(())
+    }
+
+    fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
+        if self.weight.matches(packed_value) {
+            self.doc_id_set_builder.add_doc(doc_id);
+        }
+        Ok(())
+    }
+
+    fn compare(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+        self.weight.relate(min_packed_value, max_packed_value)
+    }
+
+    fn grow(&mut self, count: usize) {
+        self.doc_id_set_builder.grow(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::tests::TestCodec;
+    use core::util::geo::haversin_meters;
+
+    fn weight(query: Box<dyn Query<TestCodec>>) -> LatLonPointWeight {
+        let query = query.as_any().downcast_ref::<LatLonPointQuery>().unwrap();
+        let (bounds, matcher) = query.create_matcher().unwrap();
+        LatLonPointWeight {
+            field: query.field.clone(),
+            description: String::new(),
+            bounds,
+            matcher,
+            weight: 0f32,
+            norm: 1f32,
+        }
+    }
+
+    fn pack_cell(min_lat: f64, max_lat: f64, min_lon: f64, max_lon: f64) -> (Vec<u8>, Vec<u8>) {
+        (
+            LatLonPoint::pack(min_lat, min_lon).unwrap(),
+            LatLonPoint::pack(max_lat, max_lon).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_lat_lon_point() {
+        let point = LatLonPoint::new("location", 40.7143528, -74.0059731).unwrap();
+        assert_eq!(point.field_type().dimension_count, 2);
+        assert_eq!(point.binary_value().unwrap().len(), 8);
+        assert!((point.latitude() - 40.7143528).abs() < 1e-7);
+        assert!((point.longitude() + 74.0059731).abs() < 1e-7);
+        assert!(LatLonPoint::new("location", 90.5, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_box_query() {
+        let w = weight(
+            LatLonPoint::new_box_query("location".into(), 10.0, 20.0, 30.0, 40.0).unwrap(),
+        );
+        assert!(w.matches(&LatLonPoint::pack(15.0, 35.0).unwrap()));
+        assert!(w.matches(&LatLonPoint::pack(10.0, 40.0).unwrap()));
+        assert!(!w.matches(&LatLonPoint::pack(9.9, 35.0).unwrap()));
+        assert!(!w.matches(&LatLonPoint::pack(15.0, 41.0).unwrap()));
+
+        let (min, max) = pack_cell(11.0, 12.0, 31.0, 32.0);
+        assert_eq!(w.relate(&min, &max), Relation::CellInsideQuery);
+        let (min, max) = pack_cell(5.0, 12.0, 31.0, 32.0);
+        assert_eq!(w.relate(&min, &max), Relation::CellCrossesQuery);
+        let (min, max) = pack_cell(21.0, 22.0, 31.0, 32.0);
+        assert_eq!(w.relate(&min, &max), Relation::CellOutsideQuery);
+
+        // across the dateline
+        let w = weight(
+            LatLonPoint::new_box_query("location".into(), -10.0, 10.0, 170.0, -170.0).unwrap(),
+        );
+        assert!(w.matches(&LatLonPoint::pack(0.0, 175.0).unwrap()));
+        assert!(w.matches(&LatLonPoint::pack(0.0, -175.0).unwrap()));
+        assert!(!w.matches(&LatLonPoint::pack(0.0, 0.0).unwrap()));
+        let (min, max) = pack_cell(-1.0, 1.0, -10.0, 10.0);
+        assert_eq!(w.relate(&min, &max), Relation::CellOutsideQuery);
+
+        let w = weight(
+            LatLonPoint::new_box_query("location".into(), 90.0, 90.0, 0.0, 10.0).unwrap(),
+        );
+        assert!(!w.matches(&LatLonPoint::pack(90.0, 5.0).unwrap()));
+    }
+
+    #[test]
+    fn test_distance_query() {
+        let (lat, lon) = (40.7143528, -74.0059731);
+        let w = weight(
+            LatLonPoint::new_distance_query("location".into(), lat, lon, 10_000.0).unwrap(),
+        );
+        let docs = [(40.7, -74.0), (40.78, -73.97), (40.8, -74.0), (40.5, -74.3)];
+        for &(doc_lat, doc_lon) in &docs {
+            let packed = LatLonPoint::pack(doc_lat, doc_lon).unwrap();
+            let (q_lat, q_lon) = LatLonPoint::unpack(&packed);
+            let expected = haversin_meters(lat, lon, q_lat, q_lon) <= 10_000.0;
+            assert_eq!(w.matches(&packed), expected, "{} {}", doc_lat, doc_lon);
+        }
+
+        let (min, max) = pack_cell(40.71, 40.72, -74.01, -74.0);
+        assert_eq!(w.relate(&min, &max), Relation::CellInsideQuery);
+        let (min, max) = pack_cell(41.0, 42.0, -74.01, -74.0);
+        assert_eq!(w.relate(&min, &max), Relation::CellOutsideQuery);
+        let (min, max) = pack_cell(40.0, 41.0, -75.0, -73.0);
+        assert_eq!(w.relate(&min, &max), Relation::CellCrossesQuery);
+
+        assert!(
+            LatLonPoint::new_distance_query::<TestCodec>("location".into(), 0.0, 0.0, -1.0)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_polygon_query() {
+        let hole = Polygon::new(
+            vec![2.0, 2.0, 3.0, 3.0, 2.0],
+            vec![2.0, 3.0, 3.0, 2.0, 2.0],
+            vec![],
+        )
+        .unwrap();
+        let polygon = Polygon::new(
+            vec![0.0, 0.0, 5.0, 5.0, 0.0],
+            vec![0.0, 5.0, 5.0, 0.0, 0.0],
+            vec![hole],
+        )
+        .unwrap();
+        let w = weight(LatLonPoint::new_polygon_query("location".into(), vec![polygon]).unwrap());
+        assert!(w.matches(&LatLonPoint::pack(1.0, 1.0).unwrap()));
+        assert!(!w.matches(&LatLonPoint::pack(2.5, 2.5).unwrap()));
+        assert!(!w.matches(&LatLonPoint::pack(6.0, 1.0).unwrap()));
+
+        let (min, max) = pack_cell(0.5, 1.5, 0.5, 1.5);
+        assert_eq!(w.relate(&min, &max), Relation::CellInsideQuery);
+        let (min, max) = pack_cell(2.2, 2.8, 2.2, 2.8);
+        assert_eq!(w.relate(&min, &max), Relation::CellOutsideQuery);
+        let (min, max) = pack_cell(4.0, 6.0, 4.0, 6.0);
+        assert_eq!(w.relate(&min, &max), Relation::CellCrossesQuery);
+
+        assert!(LatLonPoint::new_polygon_query::<TestCodec>("location".into(), vec![]).is_err());
+    }
+}
@@ -38,6 +38,22 @@ mod point_range_query;
 
 pub use self::point_range_query::*;
 
+mod lat_lon_point_query;
+
+pub use self::lat_lon_point_query::*;
+
+mod point_in_set_query;
+
+pub use self::point_in_set_query::*;
+
+mod knn_vector_query;
+
+pub use self::knn_vector_query::*;
+
+mod vector_score_query;
+
+pub use self::vector_score_query::*;
+
 mod query_string;
 
 pub use self::query_string::*;
@@ -95,6 +111,11 @@ use std::hash::{Hash, Hasher};
 /// * [`BoostQuery`]
 /// * [`PhraseQuery`]
 /// * [`PointRangeQuery`](point_range/struct.PointRangeQuery.html)
+/// * [`RangeFieldQuery`](struct.RangeFieldQuery.html)
+/// * [`LatLonPointQuery`](struct.LatLonPointQuery.html)
+/// * [`PointInSetQuery`](struct.PointInSetQuery.html)
+/// * [`KnnVectorQuery`](struct.KnnVectorQuery.html)
+/// * [`VectorScoreQuery`](struct.VectorScoreQuery.html)
 /// * [`ConstantScoreQuery`](match_all/struct.ConstantScoreQuery.html)
 /// * [`DisjunctionMaxQuery`](disjunction/struct.DisjunctionMaxQuery.html)
 /// * [`MatchAllDocsQuery`](match_all/struct.MatchAllDocsQuery.html)
@@ -0,0 +1,381 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use error::{ErrorKind, Result};
+use std::fmt;
+
+use core::codec::points::{IntersectVisitor, PointValues, Relation};
+use core::codec::Codec;
+use core::index::reader::{LeafReader, LeafReaderContext};
+use core::search::explanation::Explanation;
+use core::search::query::{PointDocIterEnum, PointValueType, Query, TermQuery, Weight};
+use core::search::scorer::{ConstantScoreScorer, Scorer};
+use core::search::searcher::SearchPlanBuilder;
+use core::search::{DocIdSet, DocIterator, EmptyDocIterator};
+use core::util::{DocId, DocIdSetBuilder, DocIdSetEnum};
+
+/// Abstract query class to find all documents whose single or multi-dimensional point values,
+/// previously indexed with e.g. `IntPoint`, is contained in the specified set.
+///
+/// The points are kept sorted in packed byte order, so a single BKD traversal is enough: a cell
+/// is pruned as soon as no point of the set falls within its bounds.
+pub struct PointInSetQuery {
+    field: String,
+    num_dims: usize,
+    bytes_per_dim: usize,
+    // sorted and de-duplicated packed points
+    sorted_packed_points: Vec<Vec<u8>>,
+    value_type: PointValueType,
+}
+
+impl PointInSetQuery {
+    pub fn new(
+        field: String,
+        num_dims: usize,
+        bytes_per_dim: usize,
+        mut packed_points: Vec<Vec<u8>>,
+        value_type: PointValueType,
+    ) -> Result<PointInSetQuery> {
+        assert!(!field.is_empty());
+        if num_dims == 0 || bytes_per_dim == 0 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "num_dims and bytes_per_dim must be > 0, got num_dims={}, bytes_per_dim={}",
+                num_dims, bytes_per_dim
+            )));
+        }
+        let packed_bytes_length = num_dims * bytes_per_dim;
+        for point in &packed_points {
+            if point.len() != packed_bytes_length {
+                bail!(ErrorKind::IllegalArgument(format!(
+                    "packed point length should be {} but got {}",
+                    packed_bytes_length,
+                    point.len()
+                )));
+            }
+        }
+        packed_points.sort();
+        packed_points.dedup();
+
+        Ok(PointInSetQuery {
+            field,
+            num_dims,
+            bytes_per_dim,
+            sorted_packed_points: packed_points,
+            value_type,
+        })
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn packed_points(&self) -> &[Vec<u8>] {
+        &self.sorted_packed_points
+    }
+}
+
+pub const POINT_IN_SET: &str = "point_in_set";
+
+impl<C: Codec> Query<C> for PointInSetQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(PointInSetWeight {
+            field: self.field.clone(),
+            num_dims: self.num_dims,
+            bytes_per_dim: self.bytes_per_dim,
+            sorted_packed_points: self.sorted_packed_points.clone(),
+            value_type: self.value_type,
+            weight: 0f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self
+    }
+}
+
+impl fmt::Display for PointInSetQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let points: Vec<String> = self
+            .sorted_packed_points
+            .iter()
+            .map(|p| self.value_type.format_bytes(p, self.bytes_per_dim))
+            .collect();
+        write!(
+            f,
+            "PointInSetQuery(field: {}, type: {}, num_dims: {}, bytes_per_dim: {}, points: [{}])",
+            &self.field,
+            &self.value_type,
+            self.num_dims,
+            self.bytes_per_dim,
+            points.join(", ")
+        )
+    }
+}
+
+struct PointInSetWeight {
+    field: String,
+    num_dims: usize,
+    bytes_per_dim: usize,
+    sorted_packed_points: Vec<Vec<u8>>,
+    value_type: PointValueType,
+    weight: f32,
+    norm: f32,
+}
+
+impl PointInSetWeight {
+    fn matches(&self, packed_value: &[u8]) -> bool {
+        self.sorted_packed_points
+            .binary_search_by(|p| p.as_slice().cmp(packed_value))
+            .is_ok()
+    }
+
+    fn relate(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+        // a point inside the cell is lexicographically between the cell's min and max, so only
+        // this slice of the sorted set has to be checked dimension by dimension
+        let start = match self
+            .sorted_packed_points
+            .binary_search_by(|p| p.as_slice().cmp(min_packed_value))
+        {
+            Ok(i) | Err(i) => i,
+        };
+        let bytes = self.bytes_per_dim;
+        for point in &self.sorted_packed_points[start..] {
+            if point.as_slice() > max_packed_value {
+                break;
+            }
+            let mut inside = true;
+            for dim in 0..self.num_dims {
+                let offset = dim * bytes;
+                let end = offset + bytes;
+                if point[offset..end] < min_packed_value[offset..end]
+                    || point[offset..end] > max_packed_value[offset..end]
+                {
+                    inside = false;
+                    break;
+                }
+            }
+            if inside {
+                return if min_packed_value == max_packed_value {
+                    // every point of this cell is equal to the matched one
+                    Relation::CellInsideQuery
+                } else {
+                    Relation::CellCrossesQuery
+                };
+            }
+        }
+        Relation::CellOutsideQuery
+    }
+
+    fn build_matching_doc_set<R: LeafReader + ?Sized>(
+        &self,
+        reader: &R,
+        values: &impl PointValues,
+    ) -> Result<DocIdSetEnum> {
+        let mut result = DocIdSetBuilder::from_values(reader.max_doc(), values, &self.field)?;
+        {
+            let mut visitor = PointInSetIntersectVisitor {
+                doc_id_set_builder: &mut result,
+                weight: self,
+            };
+            values.intersect(&self.field, &mut visitor)?;
+        }
+
+        Ok(result.build())
+    }
+}
+
+impl<C: Codec> Weight<C> for PointInSetWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        if self.sorted_packed_points.is_empty() {
+            return Ok(None);
+        }
+        let leaf_reader = leaf_reader_ctx.reader;
+        if let Some(ref values) = leaf_reader.point_values() {
+            if let Some(field_info) = leaf_reader.field_info(&self.field) {
+                if field_info.point_dimension_count != self.num_dims as u32 {
+                    bail!(ErrorKind::IllegalArgument(format!(
+                        "field '{}' was indexed with num_dims={} but this query has num_dims={}",
+                        &self.field, field_info.point_dimension_count, self.num_dims
+                    )));
+                }
+                if self.bytes_per_dim as u32 != field_info.point_num_bytes {
+                    bail!(ErrorKind::IllegalArgument(format!(
+                        "field '{}' was indexed with bytes_per_dim={} but this query has \
+                         bytes_per_dim={}",
+                        &self.field, field_info.point_num_bytes, self.bytes_per_dim
+                    )));
+                }
+
+                let iterator = if let Some(iter) = self
+                    .build_matching_doc_set(leaf_reader, values)?
+                    .iterator()?
+                {
+                    PointDocIterEnum::DocSet(iter)
+                } else {
+                    PointDocIterEnum::None(EmptyDocIterator::default())
+                };
+                let cost = iterator.cost();
+                return Ok(Some(Box::new(ConstantScoreScorer::new(
+                    self.weight,
+                    iterator,
+                    cost,
+                ))));
+            }
+        }
+        Ok(None)
+    }
+
+    fn query_type(&self) -> &'static str {
+        POINT_IN_SET
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        if let Some(mut scorer) = self.create_scorer(reader)? {
+            if scorer.advance(doc)? == doc {
+                return Ok(Explanation::new(
+                    true,
+                    self.weight,
+                    format!("{}, constant score", self),
+                    vec![],
+                ));
+            }
+        }
+        Ok(Explanation::new(
+            false,
+            0f32,
+            format!("{}, query did not match", self),
+            vec![],
+        ))
+    }
+}
+
+impl fmt::Display for PointInSetWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PointInSetWeight(field: {}, type: {}, num_dims: {}, bytes_per_dim: {}, points: {})",
+            &self.field,
+            &self.value_type,
+            self.num_dims,
+            self.bytes_per_dim,
+            self.sorted_packed_points.len()
+        )
+    }
+}
+
+struct PointInSetIntersectVisitor<'a> {
+    doc_id_set_builder: &'a mut DocIdSetBuilder,
+    weight: &'a PointInSetWeight,
+}
+
+impl<'a> IntersectVisitor for PointInSetIntersectVisitor<'a> {
+    fn visit(&mut self, doc_id: DocId) -> Result<()> {
+        self.doc_id_set_builder.add_doc(doc_id);
+        Ok(())
+    }
+
+    fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
+        if self.weight.matches(packed_value) {
+            self.doc_id_set_builder.add_doc(doc_id);
+        }
+        Ok(())
+    }
+
+    fn compare(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+        self.weight.relate(min_packed_value, max_packed_value)
+    }
+
+    fn grow(&mut self, count: usize) {
+        self.doc_id_set_builder.grow(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::tests::TestCodec;
+    use core::search::query::{IntPoint, LongPoint};
+
+    fn weight(query: Box<dyn Query<TestCodec>>) -> PointInSetWeight {
+        let query = query.as_any().downcast_ref::<PointInSetQuery>().unwrap();
+        PointInSetWeight {
+            field: query.field.clone(),
+            num_dims: query.num_dims,
+            bytes_per_dim: query.bytes_per_dim,
+            sorted_packed_points: query.sorted_packed_points.clone(),
+            value_type: query.value_type,
+            weight: 0f32,
+            norm: 1f32,
+        }
+    }
+
+    #[test]
+    fn test_int_set_query() {
+        let w = weight(IntPoint::new_set_query("f".into(), &[17, -3, 42, 17, 1000]).unwrap());
+        assert_eq!(w.sorted_packed_points.len(), 4);
+        assert!(w.matches(&IntPoint::pack(&[-3])));
+        assert!(w.matches(&IntPoint::pack(&[1000])));
+        assert!(!w.matches(&IntPoint::pack(&[18])));
+
+        let cell = |min: i32, max: i32| w.relate(&IntPoint::pack(&[min]), &IntPoint::pack(&[max]));
+        assert_eq!(cell(18, 41), Relation::CellOutsideQuery);
+        assert_eq!(cell(0, 20), Relation::CellCrossesQuery);
+        assert_eq!(cell(42, 42), Relation::CellInsideQuery);
+        assert_eq!(cell(1001, i32::max_value()), Relation::CellOutsideQuery);
+        assert_eq!(cell(i32::min_value(), -4), Relation::CellOutsideQuery);
+    }
+
+    #[test]
+    fn test_multi_dim_set_query() {
+        let points = vec![LongPoint::pack(&[1, 10]), LongPoint::pack(&[5, 2])];
+        let query = PointInSetQuery::new("f".into(), 2, 8, points, PointValueType::Long).unwrap();
+        let w = weight(Box::new(query));
+        assert!(w.matches(&LongPoint::pack(&[5, 2])));
+        assert!(!w.matches(&LongPoint::pack(&[5, 10])));
+
+        // (5, 2) is lexicographically inside [(0, 3), (6, 9)] but outside on the second dim
+        let min = LongPoint::pack(&[0, 3]);
+        let max = LongPoint::pack(&[6, 9]);
+        assert_eq!(w.relate(&min, &max), Relation::CellOutsideQuery);
+        let max = LongPoint::pack(&[6, 10]);
+        assert_eq!(w.relate(&min, &max), Relation::CellCrossesQuery);
+
+        let invalid = vec![vec![0u8; 4]];
+        assert!(PointInSetQuery::new("f".into(), 2, 8, invalid, PointValueType::Long).is_err());
+    }
+}
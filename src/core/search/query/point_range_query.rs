@@ -12,13 +12,15 @@
 // limitations under the License.
 
 use error::{ErrorKind, Result};
+use std::cmp::Ordering;
 use std::fmt;
 
 use core::codec::points::{IntersectVisitor, PointValues, Relation};
 use core::codec::Codec;
+use core::doc::{Field, FieldType};
 use core::index::reader::{LeafReader, LeafReaderContext};
 use core::search::explanation::Explanation;
-use core::search::query::{AllDocsIterator, Query, TermQuery, Weight};
+use core::search::query::{AllDocsIterator, PointInSetQuery, Query, TermQuery, Weight};
 use core::search::scorer::{ConstantScoreScorer, Scorer};
 use core::search::searcher::SearchPlanBuilder;
 use core::search::{DocIdSet, DocIterator, EmptyDocIterator};
@@ -265,6 +267,18 @@ impl IntPoint {
             PointValueType::Integer,
         )?))
     }
+
+    /// Create a query matching any of the specified 1D values.
+    pub fn new_set_query<C: Codec>(field: String, values: &[i32]) -> Result<Box<dyn Query<C>>> {
+        let points = values.iter().map(|v| IntPoint::pack(&[*v])).collect();
+        Ok(Box::new(PointInSetQuery::new(
+            field,
+            1,
+            4,
+            points,
+            PointValueType::Integer,
+        )?))
+    }
 }
 
 pub struct LongPoint;
@@ -312,6 +326,283 @@ impl LongPoint {
             PointValueType::Long,
         )?))
     }
+
+    /// Create a query matching any of the specified 1D values.
+    pub fn new_set_query<C: Codec>(field: String, values: &[i64]) -> Result<Box<dyn Query<C>>> {
+        let points = values.iter().map(|v| LongPoint::pack(&[*v])).collect();
+        Ok(Box::new(PointInSetQuery::new(
+            field,
+            1,
+            8,
+            points,
+            PointValueType::Long,
+        )?))
+    }
+}
+
+/// An indexed integer range field.
+///
+/// A range of `N` dimensions (at most 4) is indexed as a point of `2 * N` dimensions: all the
+/// minimums followed by all the maximums. It is typically used for time windows or price ranges
+/// which can then be queried with `intersects`, `contains`, `within` or `crosses` relations.
+pub struct IntRange;
+
+impl IntRange {
+    /// Creates a field indexing the range `[min, max]`, bounds included.
+    pub fn new_field(name: &str, min: &[i32], max: &[i32]) -> Result<Field> {
+        range_field(name, min, max)
+    }
+
+    pub fn pack(min: &[i32], max: &[i32]) -> Result<Vec<u8>> {
+        pack_range(min, max)
+    }
+
+    /// Matches the indexed ranges intersecting the query range.
+    pub fn new_intersects_query<C: Codec>(
+        field: String,
+        min: &[i32],
+        max: &[i32],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Intersects)
+    }
+
+    /// Matches the indexed ranges containing the query range.
+    pub fn new_contains_query<C: Codec>(
+        field: String,
+        min: &[i32],
+        max: &[i32],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Contains)
+    }
+
+    /// Matches the indexed ranges within the query range.
+    pub fn new_within_query<C: Codec>(
+        field: String,
+        min: &[i32],
+        max: &[i32],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Within)
+    }
+
+    /// Matches the indexed ranges intersecting but not within the query range.
+    pub fn new_crosses_query<C: Codec>(
+        field: String,
+        min: &[i32],
+        max: &[i32],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Crosses)
+    }
+}
+
+/// An indexed long range field, see `IntRange`.
+pub struct LongRange;
+
+impl LongRange {
+    /// Creates a field indexing the range `[min, max]`, bounds included.
+    pub fn new_field(name: &str, min: &[i64], max: &[i64]) -> Result<Field> {
+        range_field(name, min, max)
+    }
+
+    pub fn pack(min: &[i64], max: &[i64]) -> Result<Vec<u8>> {
+        pack_range(min, max)
+    }
+
+    /// Matches the indexed ranges intersecting the query range.
+    pub fn new_intersects_query<C: Codec>(
+        field: String,
+        min: &[i64],
+        max: &[i64],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Intersects)
+    }
+
+    /// Matches the indexed ranges containing the query range.
+    pub fn new_contains_query<C: Codec>(
+        field: String,
+        min: &[i64],
+        max: &[i64],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Contains)
+    }
+
+    /// Matches the indexed ranges within the query range.
+    pub fn new_within_query<C: Codec>(
+        field: String,
+        min: &[i64],
+        max: &[i64],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Within)
+    }
+
+    /// Matches the indexed ranges intersecting but not within the query range.
+    pub fn new_crosses_query<C: Codec>(
+        field: String,
+        min: &[i64],
+        max: &[i64],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Crosses)
+    }
+}
+
+/// An indexed double range field, see `IntRange`.
+pub struct DoubleRange;
+
+impl DoubleRange {
+    /// Creates a field indexing the range `[min, max]`, bounds included.
+    pub fn new_field(name: &str, min: &[f64], max: &[f64]) -> Result<Field> {
+        range_field(name, min, max)
+    }
+
+    pub fn pack(min: &[f64], max: &[f64]) -> Result<Vec<u8>> {
+        pack_range(min, max)
+    }
+
+    /// Matches the indexed ranges intersecting the query range.
+    pub fn new_intersects_query<C: Codec>(
+        field: String,
+        min: &[f64],
+        max: &[f64],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Intersects)
+    }
+
+    /// Matches the indexed ranges containing the query range.
+    pub fn new_contains_query<C: Codec>(
+        field: String,
+        min: &[f64],
+        max: &[f64],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Contains)
+    }
+
+    /// Matches the indexed ranges within the query range.
+    pub fn new_within_query<C: Codec>(
+        field: String,
+        min: &[f64],
+        max: &[f64],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Within)
+    }
+
+    /// Matches the indexed ranges intersecting but not within the query range.
+    pub fn new_crosses_query<C: Codec>(
+        field: String,
+        min: &[f64],
+        max: &[f64],
+    ) -> Result<Box<dyn Query<C>>> {
+        range_query(field, min, max, RangeQueryType::Crosses)
+    }
+}
+
+/// The encoding of the bounds of a range field in its point dimensions.
+trait RangeBound: Copy + PartialOrd + fmt::Display {
+    const BYTES_PER_DIM: usize;
+    const VALUE_TYPE: PointValueType;
+
+    fn encode(value: Self, dest: &mut [u8]);
+}
+
+impl RangeBound for i32 {
+    const BYTES_PER_DIM: usize = 4;
+    const VALUE_TYPE: PointValueType = PointValueType::Integer;
+
+    fn encode(value: i32, dest: &mut [u8]) {
+        IntPoint::encode_dimension(value, dest)
+    }
+}
+
+impl RangeBound for i64 {
+    const BYTES_PER_DIM: usize = 8;
+    const VALUE_TYPE: PointValueType = PointValueType::Long;
+
+    fn encode(value: i64, dest: &mut [u8]) {
+        LongPoint::encode_dimension(value, dest)
+    }
+}
+
+impl RangeBound for f64 {
+    const BYTES_PER_DIM: usize = 8;
+    const VALUE_TYPE: PointValueType = PointValueType::Double;
+
+    fn encode(value: f64, dest: &mut [u8]) {
+        DoublePoint::encode_dimension(value, dest)
+    }
+}
+
+fn range_field<T: RangeBound>(name: &str, min: &[T], max: &[T]) -> Result<Field> {
+    let packed = pack_range(min, max)?;
+    Ok(Field::new_bytes(
+        String::from(name),
+        packed,
+        range_field_type(min.len(), T::BYTES_PER_DIM as u32),
+    ))
+}
+
+// packs all the minimums followed by all the maximums
+fn pack_range<T: RangeBound>(min: &[T], max: &[T]) -> Result<Vec<u8>> {
+    check_range_dims(min.len(), max.len())?;
+    for dim in 0..min.len() {
+        match min[dim].partial_cmp(&max[dim]) {
+            Some(Ordering::Greater) => bail!(ErrorKind::IllegalArgument(format!(
+                "min value ({}) is greater than max value ({}) for dim {}",
+                min[dim], max[dim], dim
+            ))),
+            Some(_) => {}
+            None => bail!(ErrorKind::IllegalArgument(format!(
+                "invalid NaN bound for dim {}",
+                dim
+            ))),
+        }
+    }
+    let bytes = T::BYTES_PER_DIM;
+    let mut packed = vec![0u8; 2 * min.len() * bytes];
+    for (i, value) in min.iter().chain(max.iter()).enumerate() {
+        T::encode(*value, &mut packed[i * bytes..(i + 1) * bytes]);
+    }
+    Ok(packed)
+}
+
+fn range_query<C: Codec, T: RangeBound>(
+    field: String,
+    min: &[T],
+    max: &[T],
+    query_type: RangeQueryType,
+) -> Result<Box<dyn Query<C>>> {
+    Ok(Box::new(RangeFieldQuery::new(
+        field,
+        pack_range(min, max)?,
+        min.len(),
+        query_type,
+        T::VALUE_TYPE,
+    )?))
+}
+
+/// Maximum number of dimensions of a range field, each one takes two point dimensions.
+pub const RANGE_MAX_DIMENSIONS: usize = 4;
+
+fn check_range_dims(min_dims: usize, max_dims: usize) -> Result<()> {
+    if min_dims == 0 || min_dims > RANGE_MAX_DIMENSIONS {
+        bail!(ErrorKind::IllegalArgument(format!(
+            "range fields support 1 to {} dimensions, got {}",
+            RANGE_MAX_DIMENSIONS, min_dims
+        )));
+    }
+    if min_dims != max_dims {
+        bail!(ErrorKind::IllegalArgument(format!(
+            "min has {} dimensions but max has {}",
+            min_dims, max_dims
+        )));
+    }
+    Ok(())
+}
+
+fn range_field_type(num_dims: usize, bytes_per_dim: u32) -> FieldType {
+    FieldType {
+        tokenized: false,
+        dimension_count: 2 * num_dims as u32,
+        dimension_num_bytes: bytes_per_dim,
+        ..FieldType::default()
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -577,8 +868,23 @@ impl<C: Codec> Weight<C> for PointRangeWeight {
         false
     }
 
-    fn explain(&self, _reader: &LeafReaderContext<'_, C>, _doc: DocId) -> Result<Explanation> {
-        unimplemented!()
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        if let Some(mut scorer) = self.create_scorer(reader)? {
+            if scorer.advance(doc)? == doc {
+                return Ok(Explanation::new(
+                    true,
+                    self.weight,
+                    format!("{}, constant score", self),
+                    vec![],
+                ));
+            }
+        }
+        Ok(Explanation::new(
+            false,
+            0f32,
+            format!("{}, query did not match", self),
+            vec![],
+        ))
     }
 }
 
@@ -646,12 +952,11 @@ impl<'a> IntersectVisitor for PointRangeIntersectVisitor<'a> {
             let offset = dim * bytes;
             let end = offset + bytes;
             if min_packed_value[offset..end] > self.weight.upper_point[offset..end]
-                || max_packed_value[offset..end] < self.weight.lower_point[offset..]
+                || max_packed_value[offset..end] < self.weight.lower_point[offset..end]
             {
                 return Relation::CellOutsideQuery;
             }
 
-            let end = offset + bytes;
             crosses |= min_packed_value[offset..end] < self.weight.lower_point[offset..end]
                 || max_packed_value[offset..end] > self.weight.upper_point[offset..end];
         }
@@ -668,7 +973,406 @@ impl<'a> IntersectVisitor for PointRangeIntersectVisitor<'a> {
     }
 }
 
-enum PointDocIterEnum {
+/// The relation between the indexed ranges and the query range of a `RangeFieldQuery`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RangeQueryType {
+    /// the indexed range intersects the query range
+    Intersects,
+    /// the indexed range contains the query range
+    Contains,
+    /// the indexed range is within the query range
+    Within,
+    /// the indexed range intersects the query range but is not within it
+    Crosses,
+}
+
+impl RangeQueryType {
+    // relation of a cell to the query on one dimension, `cell_min`/`cell_max` are the bounds of
+    // the indexed minimums and maximums, i.e. `[min_lo, min_hi]` and `[max_lo, max_hi]`
+    fn compare_dim(
+        self,
+        query_min: &[u8],
+        query_max: &[u8],
+        cell_min: (&[u8], &[u8]),
+        cell_max: (&[u8], &[u8]),
+    ) -> Relation {
+        let (min_lo, min_hi) = cell_min;
+        let (max_lo, max_hi) = cell_max;
+        let (outside, inside) = match self {
+            RangeQueryType::Intersects => (
+                min_lo > query_max || max_hi < query_min,
+                min_hi <= query_max && max_lo >= query_min,
+            ),
+            RangeQueryType::Contains => (
+                min_lo > query_min || max_hi < query_max,
+                min_hi <= query_min && max_lo >= query_max,
+            ),
+            RangeQueryType::Within => (
+                min_hi < query_min || max_lo > query_max,
+                min_lo >= query_min && max_hi <= query_max,
+            ),
+            RangeQueryType::Crosses => unreachable!(),
+        };
+        if outside {
+            Relation::CellOutsideQuery
+        } else if inside {
+            Relation::CellInsideQuery
+        } else {
+            Relation::CellCrossesQuery
+        }
+    }
+
+    fn matches_dim(self, query_min: &[u8], query_max: &[u8], min: &[u8], max: &[u8]) -> bool {
+        match self {
+            RangeQueryType::Intersects => min <= query_max && max >= query_min,
+            RangeQueryType::Contains => min <= query_min && max >= query_max,
+            RangeQueryType::Within => min >= query_min && max <= query_max,
+            RangeQueryType::Crosses => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for RangeQueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match *self {
+            RangeQueryType::Intersects => "intersects",
+            RangeQueryType::Contains => "contains",
+            RangeQueryType::Within => "within",
+            RangeQueryType::Crosses => "crosses",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// Queries the ranges indexed by `IntRange`, `LongRange` or `DoubleRange` which relate to the
+/// query range as specified by `RangeQueryType`.
+pub struct RangeFieldQuery {
+    field: String,
+    // number of range dimensions, the field has twice as many point dimensions
+    num_dims: usize,
+    bytes_per_dim: usize,
+    // query minimums followed by query maximums, like the indexed value
+    ranges: Vec<u8>,
+    query_type: RangeQueryType,
+    value_type: PointValueType,
+}
+
+pub const RANGE_FIELD: &str = "range_field";
+
+impl RangeFieldQuery {
+    pub fn new(
+        field: String,
+        ranges: Vec<u8>,
+        num_dims: usize,
+        query_type: RangeQueryType,
+        value_type: PointValueType,
+    ) -> Result<RangeFieldQuery> {
+        assert!(!field.is_empty());
+        check_range_dims(num_dims, num_dims)?;
+        if ranges.is_empty() || ranges.len() % (2 * num_dims) != 0 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "ranges of length {} is not a fixed multiple of 2 * num_dims",
+                ranges.len()
+            )));
+        }
+        let bytes_per_dim = ranges.len() / (2 * num_dims);
+        Ok(RangeFieldQuery {
+            field,
+            num_dims,
+            bytes_per_dim,
+            ranges,
+            query_type,
+            value_type,
+        })
+    }
+
+    pub fn query_type(&self) -> RangeQueryType {
+        self.query_type
+    }
+}
+
+impl<C: Codec> Query<C> for RangeFieldQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(RangeFieldWeight {
+            field: self.field.clone(),
+            num_dims: self.num_dims,
+            bytes_per_dim: self.bytes_per_dim,
+            ranges: self.ranges.clone(),
+            query_type: self.query_type,
+            value_type: self.value_type,
+            weight: 0f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self
+    }
+}
+
+impl fmt::Display for RangeFieldQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let half = self.num_dims * self.bytes_per_dim;
+        write!(
+            f,
+            "RangeFieldQuery(field: {}, type: {}, relation: {}, num_dims: {}, min: {}, max: {})",
+            &self.field,
+            &self.value_type,
+            &self.query_type,
+            self.num_dims,
+            self.value_type
+                .format_bytes(&self.ranges[..half], self.bytes_per_dim),
+            self.value_type
+                .format_bytes(&self.ranges[half..], self.bytes_per_dim),
+        )
+    }
+}
+
+struct RangeFieldWeight {
+    field: String,
+    num_dims: usize,
+    bytes_per_dim: usize,
+    ranges: Vec<u8>,
+    query_type: RangeQueryType,
+    value_type: PointValueType,
+    weight: f32,
+    norm: f32,
+}
+
+impl RangeFieldWeight {
+    // (min, max) bounds of `dim` in a packed range
+    fn dim_bounds<'a>(&self, packed: &'a [u8], dim: usize) -> (&'a [u8], &'a [u8]) {
+        let offset = dim * self.bytes_per_dim;
+        let max_offset = offset + self.num_dims * self.bytes_per_dim;
+        (
+            &packed[offset..offset + self.bytes_per_dim],
+            &packed[max_offset..max_offset + self.bytes_per_dim],
+        )
+    }
+
+    fn matches_type(&self, query_type: RangeQueryType, packed_value: &[u8]) -> bool {
+        (0..self.num_dims).all(|dim| {
+            let (query_min, query_max) = self.dim_bounds(&self.ranges, dim);
+            let (min, max) = self.dim_bounds(packed_value, dim);
+            query_type.matches_dim(query_min, query_max, min, max)
+        })
+    }
+
+    fn matches(&self, packed_value: &[u8]) -> bool {
+        if self.query_type == RangeQueryType::Crosses {
+            self.matches_type(RangeQueryType::Intersects, packed_value)
+                && !self.matches_type(RangeQueryType::Within, packed_value)
+        } else {
+            self.matches_type(self.query_type, packed_value)
+        }
+    }
+
+    fn relate_type(
+        &self,
+        query_type: RangeQueryType,
+        min_packed_value: &[u8],
+        max_packed_value: &[u8],
+    ) -> Relation {
+        let mut inside = true;
+        for dim in 0..self.num_dims {
+            let (query_min, query_max) = self.dim_bounds(&self.ranges, dim);
+            let (min_lo, max_lo) = self.dim_bounds(min_packed_value, dim);
+            let (min_hi, max_hi) = self.dim_bounds(max_packed_value, dim);
+            match query_type.compare_dim(query_min, query_max, (min_lo, min_hi), (max_lo, max_hi)) {
+                Relation::CellOutsideQuery => return Relation::CellOutsideQuery,
+                Relation::CellCrossesQuery => inside = false,
+                Relation::CellInsideQuery => {}
+            }
+        }
+        if inside {
+            Relation::CellInsideQuery
+        } else {
+            Relation::CellCrossesQuery
+        }
+    }
+
+    fn relate(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+        if self.query_type != RangeQueryType::Crosses {
+            return self.relate_type(self.query_type, min_packed_value, max_packed_value);
+        }
+        let intersects = self.relate_type(
+            RangeQueryType::Intersects,
+            min_packed_value,
+            max_packed_value,
+        );
+        if intersects == Relation::CellOutsideQuery {
+            return Relation::CellOutsideQuery;
+        }
+        match self.relate_type(RangeQueryType::Within, min_packed_value, max_packed_value) {
+            // every range of the cell is within the query, none of them crosses it
+            Relation::CellInsideQuery => Relation::CellOutsideQuery,
+            Relation::CellOutsideQuery if intersects == Relation::CellInsideQuery => {
+                Relation::CellInsideQuery
+            }
+            _ => Relation::CellCrossesQuery,
+        }
+    }
+
+    fn build_matching_doc_set<R: LeafReader + ?Sized>(
+        &self,
+        reader: &R,
+        values: &impl PointValues,
+    ) -> Result<DocIdSetEnum> {
+        let mut result = DocIdSetBuilder::from_values(reader.max_doc(), values, &self.field)?;
+        {
+            let mut visitor = RangeFieldIntersectVisitor {
+                doc_id_set_builder: &mut result,
+                weight: self,
+            };
+            values.intersect(&self.field, &mut visitor)?;
+        }
+
+        Ok(result.build())
+    }
+}
+
+impl<C: Codec> Weight<C> for RangeFieldWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let leaf_reader = leaf_reader_ctx.reader;
+        if let Some(ref values) = leaf_reader.point_values() {
+            if let Some(field_info) = leaf_reader.field_info(&self.field) {
+                if field_info.point_dimension_count != 2 * self.num_dims as u32 {
+                    bail!(ErrorKind::IllegalArgument(format!(
+                        "field '{}' was indexed with num_dims={} but this query has num_dims={}",
+                        &self.field,
+                        field_info.point_dimension_count / 2,
+                        self.num_dims
+                    )));
+                }
+                if self.bytes_per_dim as u32 != field_info.point_num_bytes {
+                    bail!(ErrorKind::IllegalArgument(format!(
+                        "field '{}' was indexed with bytes_per_dim={} but this query has \
+                         bytes_per_dim={}",
+                        &self.field, field_info.point_num_bytes, self.bytes_per_dim
+                    )));
+                }
+
+                let all_docs_match = values.doc_count(&self.field)? == leaf_reader.max_doc()
+                    && self.relate(
+                        &values.min_packed_value(&self.field)?,
+                        &values.max_packed_value(&self.field)?,
+                    ) == Relation::CellInsideQuery;
+
+                let iterator = if all_docs_match {
+                    PointDocIterEnum::All(AllDocsIterator::new(leaf_reader.max_doc()))
+                } else if let Some(iter) = self
+                    .build_matching_doc_set(leaf_reader, values)?
+                    .iterator()?
+                {
+                    PointDocIterEnum::DocSet(iter)
+                } else {
+                    PointDocIterEnum::None(EmptyDocIterator::default())
+                };
+                let cost = iterator.cost();
+                return Ok(Some(Box::new(ConstantScoreScorer::new(
+                    self.weight,
+                    iterator,
+                    cost,
+                ))));
+            }
+        }
+        Ok(None)
+    }
+
+    fn query_type(&self) -> &'static str {
+        RANGE_FIELD
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        if let Some(mut scorer) = self.create_scorer(reader)? {
+            if scorer.advance(doc)? == doc {
+                return Ok(Explanation::new(
+                    true,
+                    self.weight,
+                    format!("{}, constant score", self),
+                    vec![],
+                ));
+            }
+        }
+        Ok(Explanation::new(
+            false,
+            0f32,
+            format!("{}, query did not match", self),
+            vec![],
+        ))
+    }
+}
+
+impl fmt::Display for RangeFieldWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let half = self.num_dims * self.bytes_per_dim;
+        write!(
+            f,
+            "RangeFieldWeight(field: {}, type: {}, relation: {}, num_dims: {}, min: {}, max: {})",
+            &self.field,
+            &self.value_type,
+            &self.query_type,
+            self.num_dims,
+            self.value_type
+                .format_bytes(&self.ranges[..half], self.bytes_per_dim),
+            self.value_type
+                .format_bytes(&self.ranges[half..], self.bytes_per_dim),
+        )
+    }
+}
+
+struct RangeFieldIntersectVisitor<'a> {
+    doc_id_set_builder: &'a mut DocIdSetBuilder,
+    weight: &'a RangeFieldWeight,
+}
+
+impl<'a> IntersectVisitor for RangeFieldIntersectVisitor<'a> {
+    fn visit(&mut self, doc_id: DocId) -> Result<()> {
+        self.doc_id_set_builder.add_doc(doc_id);
+        Ok(())
+    }
+
+    fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
+        if self.weight.matches(packed_value) {
+            self.doc_id_set_builder.add_doc(doc_id);
+        }
+        Ok(())
+    }
+
+    fn compare(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+        self.weight.relate(min_packed_value, max_packed_value)
+    }
+
+    fn grow(&mut self, count: usize) {
+        self.doc_id_set_builder.grow(count)
+    }
+}
+
+pub(crate) enum PointDocIterEnum {
     DocSet(DocIdSetDocIterEnum),
     All(AllDocsIterator),
     None(EmptyDocIterator),
@@ -747,3 +1451,165 @@ impl DocIterator for PointDocIterEnum {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+    use core::codec::tests::TestCodec;
+    use core::doc::Fieldable;
+    use core::index::writer::{IndexWriter, IndexWriterConfig};
+    use core::search::{DefaultIndexSearcher, IndexSearcher};
+    use core::store::directory::FSDirectory;
+
+    use std::sync::Arc;
+
+    fn weight(query: Box<dyn Query<TestCodec>>) -> RangeFieldWeight {
+        let query = query.as_any().downcast_ref::<RangeFieldQuery>().unwrap();
+        RangeFieldWeight {
+            field: query.field.clone(),
+            num_dims: query.num_dims,
+            bytes_per_dim: query.bytes_per_dim,
+            ranges: query.ranges.clone(),
+            query_type: query.query_type,
+            value_type: query.value_type,
+            weight: 0f32,
+            norm: 1f32,
+        }
+    }
+
+    fn int_range(min: i32, max: i32) -> Vec<u8> {
+        IntRange::pack(&[min], &[max]).unwrap()
+    }
+
+    #[test]
+    fn test_range_field() {
+        let field = IntRange::new_field("window", &[1, 10], &[5, 20]).unwrap();
+        assert_eq!(field.field_type().dimension_count, 4);
+        assert_eq!(field.field_type().dimension_num_bytes, 4);
+        assert!(IntRange::pack(&[5], &[1]).is_err());
+        assert!(LongRange::pack(&[1, 2], &[3]).is_err());
+        assert!(DoubleRange::pack(&[::std::f64::NAN], &[1.0]).is_err());
+        assert!(DoubleRange::pack(&[0.0; 5], &[1.0; 5]).is_err());
+    }
+
+    #[test]
+    fn test_range_matches() {
+        let intersects = weight(IntRange::new_intersects_query("f".into(), &[10], &[20]).unwrap());
+        let contains = weight(IntRange::new_contains_query("f".into(), &[10], &[20]).unwrap());
+        let within = weight(IntRange::new_within_query("f".into(), &[10], &[20]).unwrap());
+        let crosses = weight(IntRange::new_crosses_query("f".into(), &[10], &[20]).unwrap());
+
+        // (range, intersects, contains, within, crosses)
+        let cases = [
+            ((0, 5), false, false, false, false),
+            ((0, 10), true, false, false, true),
+            ((12, 18), true, false, true, false),
+            ((10, 20), true, true, true, false),
+            ((5, 25), true, true, false, true),
+            ((15, 30), true, false, false, true),
+            ((21, 30), false, false, false, false),
+        ];
+        for &((min, max), i, c, w, x) in &cases {
+            let packed = int_range(min, max);
+            assert_eq!(
+                intersects.matches(&packed),
+                i,
+                "intersects {:?}",
+                (min, max)
+            );
+            assert_eq!(contains.matches(&packed), c, "contains {:?}", (min, max));
+            assert_eq!(within.matches(&packed), w, "within {:?}", (min, max));
+            assert_eq!(crosses.matches(&packed), x, "crosses {:?}", (min, max));
+        }
+
+        let query = LongRange::new_intersects_query("f".into(), &[0, 0], &[10, 10]).unwrap();
+        let two_dims = weight(query);
+        assert!(two_dims.matches(&LongRange::pack(&[5, 5], &[15, 15]).unwrap()));
+        assert!(!two_dims.matches(&LongRange::pack(&[5, 11], &[15, 15]).unwrap()));
+    }
+
+    #[test]
+    fn test_range_relate() {
+        // cells bound the indexed minimums and maximums: [min_lo, max_lo] - [min_hi, max_hi]
+        let cell = |min_lo: i32, max_lo: i32, min_hi: i32, max_hi: i32| {
+            let mut min = IntPoint::pack(&[min_lo]);
+            min.extend(IntPoint::pack(&[max_lo]));
+            let mut max = IntPoint::pack(&[min_hi]);
+            max.extend(IntPoint::pack(&[max_hi]));
+            (min, max)
+        };
+
+        let intersects = weight(IntRange::new_intersects_query("f".into(), &[10], &[20]).unwrap());
+        let (min, max) = cell(0, 12, 8, 30);
+        assert_eq!(intersects.relate(&min, &max), Relation::CellInsideQuery);
+        let (min, max) = cell(21, 25, 30, 40);
+        assert_eq!(intersects.relate(&min, &max), Relation::CellOutsideQuery);
+        let (min, max) = cell(0, 5, 15, 30);
+        assert_eq!(intersects.relate(&min, &max), Relation::CellCrossesQuery);
+
+        let within = weight(IntRange::new_within_query("f".into(), &[10], &[20]).unwrap());
+        let (min, max) = cell(10, 11, 15, 20);
+        assert_eq!(within.relate(&min, &max), Relation::CellInsideQuery);
+        let (min, max) = cell(0, 21, 5, 30);
+        assert_eq!(within.relate(&min, &max), Relation::CellOutsideQuery);
+
+        let crosses = weight(IntRange::new_crosses_query("f".into(), &[10], &[20]).unwrap());
+        let (min, max) = cell(10, 11, 15, 20);
+        assert_eq!(crosses.relate(&min, &max), Relation::CellOutsideQuery);
+        let (min, max) = cell(0, 21, 5, 30);
+        assert_eq!(crosses.relate(&min, &max), Relation::CellInsideQuery);
+        let (min, max) = cell(0, 12, 12, 30);
+        assert_eq!(crosses.relate(&min, &max), Relation::CellCrossesQuery);
+
+        let contains = weight(IntRange::new_contains_query("f".into(), &[10], &[20]).unwrap());
+        let (min, max) = cell(0, 20, 10, 30);
+        assert_eq!(contains.relate(&min, &max), Relation::CellInsideQuery);
+        let (min, max) = cell(11, 20, 15, 30);
+        assert_eq!(contains.relate(&min, &max), Relation::CellOutsideQuery);
+    }
+
+    #[test]
+    fn test_explain() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(directory, Arc::new(IndexWriterConfig::default())).unwrap();
+        let point_type = FieldType {
+            tokenized: false,
+            dimension_count: 1,
+            dimension_num_bytes: 4,
+            ..FieldType::default()
+        };
+        for &(point, min, max) in &[(1, 1, 5), (15, 10, 30), (25, 40, 50)] {
+            let doc: Vec<Box<dyn Fieldable>> = vec![
+                Box::new(Field::new_bytes(
+                    "point".into(),
+                    IntPoint::pack(&[point]),
+                    point_type.clone(),
+                )),
+                Box::new(IntRange::new_field("window", &[min], &[max]).unwrap()),
+            ];
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        let reader = writer.get_reader(true, false).unwrap();
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader), None);
+
+        let queries = vec![
+            IntPoint::new_range_query("point".into(), 10, 20).unwrap(),
+            IntPoint::new_set_query("point".into(), &[15, 30]).unwrap(),
+            IntRange::new_intersects_query("window".into(), &[20], &[35]).unwrap(),
+        ];
+        for query in &queries {
+            let explanation = searcher.explain(query.as_ref(), 1).unwrap();
+            assert!(explanation.is_match());
+            assert!(explanation.value() > 0f32);
+            for &doc in &[0, 2] {
+                let explanation = searcher.explain(query.as_ref(), doc).unwrap();
+                assert!(!explanation.is_match());
+                assert_eq!(explanation.value(), 0f32);
+            }
+        }
+    }
+}
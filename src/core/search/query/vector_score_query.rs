@@ -0,0 +1,450 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::doc_values::{BinaryDocValues, EmptyBinaryDocValues};
+use core::codec::Codec;
+use core::doc::decode_vector_into;
+use core::index::reader::LeafReaderContext;
+use core::search::explanation::Explanation;
+use core::search::query::{Query, TermQuery, Weight};
+use core::search::scorer::Scorer;
+use core::search::searcher::SearchPlanBuilder;
+use core::search::DocIterator;
+use core::util::{dot_product, squared_distance, DocId, VectorSimilarity};
+
+use error::{ErrorKind, Result};
+
+use std::any::Any;
+use std::fmt;
+
+pub const VECTOR_SCORE: &str = "vector_score";
+
+/// Scores the docs matching a query by the exact similarity of their vector
+/// to a target vector.
+///
+/// The vectors are read from a binary doc values field, encoded with
+/// `encode_vector`, so no vector index is needed:
+///
+/// ```rust,ignore
+/// doc.push(Box::new(BinaryDocValuesField::new("vec", &encode_vector(&vector))));
+/// ```
+///
+/// The score of a doc is the `VectorSimilarity` of its vector to the target,
+/// the score of the wrapped query is ignored and docs without a vector score
+/// 0. As every matching doc is scored it is meant to rerank a small window of
+/// hits, as the query of a `RescoreRequest` given to `QueryRescorer`, or to
+/// score a selective query.
+pub struct VectorScoreQuery<C: Codec> {
+    query: Box<dyn Query<C>>,
+    field: String,
+    target: Vec<f32>,
+    similarity: VectorSimilarity,
+}
+
+impl<C: Codec> VectorScoreQuery<C> {
+    pub fn new(
+        query: Box<dyn Query<C>>,
+        field: &str,
+        target: Vec<f32>,
+        similarity: VectorSimilarity,
+    ) -> Result<VectorScoreQuery<C>> {
+        if target.is_empty() || target.iter().any(|v| !v.is_finite()) {
+            bail!(ErrorKind::IllegalArgument(
+                "target vector must be non empty with finite values".into()
+            ));
+        }
+        Ok(VectorScoreQuery {
+            query,
+            field: field.to_string(),
+            target,
+            similarity,
+        })
+    }
+
+    pub fn query(&self) -> &dyn Query<C> {
+        self.query.as_ref()
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn target(&self) -> &[f32] {
+        &self.target
+    }
+
+    pub fn similarity(&self) -> VectorSimilarity {
+        self.similarity
+    }
+}
+
+impl<C: Codec> Query<C> for VectorScoreQuery<C> {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(VectorScoreWeight {
+            weight: searcher.create_weight(self.query.as_ref(), false)?,
+            field: self.field.clone(),
+            target: self.target.clone(),
+            similarity: self.similarity,
+            boost: 1f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        self.query.extract_terms()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<C: Codec> fmt::Display for VectorScoreQuery<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "VectorScoreQuery(query: {}, field: {}, target: {:?}, similarity: {})",
+            &self.query, &self.field, &self.target, self.similarity
+        )
+    }
+}
+
+struct VectorScoreWeight<C: Codec> {
+    weight: Box<dyn Weight<C>>,
+    field: String,
+    target: Vec<f32>,
+    similarity: VectorSimilarity,
+    boost: f32,
+    norm: f32,
+}
+
+impl<C: Codec> VectorScoreWeight<C> {
+    fn vector_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<VectorScoreScorer>> {
+        let scorer = match self.weight.create_scorer(leaf_reader_ctx)? {
+            Some(scorer) => scorer,
+            None => return Ok(None),
+        };
+        // segments without the field score all their docs 0
+        let doc_values = if leaf_reader_ctx.reader.field_info(&self.field).is_some() {
+            leaf_reader_ctx.reader.get_binary_doc_values(&self.field)?
+        } else {
+            Box::new(EmptyBinaryDocValues)
+        };
+        Ok(Some(VectorScoreScorer::new(
+            scorer,
+            doc_values,
+            VectorScoreFunction::new(&self.target, self.similarity),
+            self.boost,
+        )))
+    }
+}
+
+impl<C: Codec> Weight<C> for VectorScoreWeight<C> {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        Ok(self
+            .vector_scorer(leaf_reader_ctx)?
+            .map(|s| Box::new(s) as Box<dyn Scorer>))
+    }
+
+    fn query_type(&self) -> &'static str {
+        VECTOR_SCORE
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.boost = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.boost * self.boost
+    }
+
+    fn needs_scores(&self) -> bool {
+        true
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        if let Some(mut scorer) = self.vector_scorer(reader)? {
+            if scorer.advance(doc)? == doc {
+                let score = scorer.score()?;
+                return Ok(Explanation::new(
+                    true,
+                    score,
+                    format!("{}, product of:", self),
+                    vec![
+                        Explanation::new(
+                            true,
+                            score / self.boost,
+                            format!("{} similarity of field '{}'", self.similarity, self.field),
+                            vec![],
+                        ),
+                        Explanation::new(true, self.boost, "boost".to_string(), vec![]),
+                    ],
+                ));
+            }
+        }
+        Ok(Explanation::new(
+            false,
+            0f32,
+            format!("{}, query did not match", self),
+            vec![],
+        ))
+    }
+}
+
+impl<C: Codec> fmt::Display for VectorScoreWeight<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "VectorScoreWeight(weight: {}, field: {}, similarity: {}, boost: {})",
+            &self.weight, &self.field, self.similarity, self.boost
+        )
+    }
+}
+
+/// Computes the similarity of vectors to the target, with the same scores as
+/// `VectorSimilarity::compare`. The norm of the target is only computed once.
+struct VectorScoreFunction {
+    target: Vec<f32>,
+    target_norm: f32,
+    similarity: VectorSimilarity,
+}
+
+impl VectorScoreFunction {
+    fn new(target: &[f32], similarity: VectorSimilarity) -> VectorScoreFunction {
+        VectorScoreFunction {
+            target: target.to_vec(),
+            target_norm: dot_product(target, target).sqrt(),
+            similarity,
+        }
+    }
+
+    fn dimension(&self) -> usize {
+        self.target.len()
+    }
+
+    fn score(&self, vector: &[f32]) -> f32 {
+        debug_assert_eq!(vector.len(), self.target.len());
+        match self.similarity {
+            VectorSimilarity::Euclidean => 1.0 / (1.0 + squared_distance(&self.target, vector)),
+            VectorSimilarity::DotProduct => {
+                (1.0 + dot_product(&self.target, vector)).max(0.0) / 2.0
+            }
+            VectorSimilarity::Cosine => {
+                let dot = dot_product(&self.target, vector);
+                let norm = self.target_norm * dot_product(vector, vector).sqrt();
+                let cosine = if norm == 0.0 { 0.0 } else { dot / norm };
+                (1.0 + cosine) / 2.0
+            }
+        }
+    }
+}
+
+/// Scores the docs of the wrapped scorer by the similarity of their vector.
+struct VectorScoreScorer {
+    scorer: Box<dyn Scorer>,
+    doc_values: Box<dyn BinaryDocValues>,
+    function: VectorScoreFunction,
+    // decoded vector of the current doc, reused across docs
+    vector: Vec<f32>,
+    boost: f32,
+}
+
+impl VectorScoreScorer {
+    fn new(
+        scorer: Box<dyn Scorer>,
+        doc_values: Box<dyn BinaryDocValues>,
+        function: VectorScoreFunction,
+        boost: f32,
+    ) -> VectorScoreScorer {
+        let vector = vec![0f32; function.dimension()];
+        VectorScoreScorer {
+            scorer,
+            doc_values,
+            function,
+            vector,
+            boost,
+        }
+    }
+}
+
+impl Scorer for VectorScoreScorer {
+    fn score(&mut self) -> Result<f32> {
+        let doc = self.scorer.doc_id();
+        let bytes = self.doc_values.get(doc)?;
+        if bytes.is_empty() {
+            return Ok(0f32);
+        }
+        if bytes.len() != self.vector.len() * 4 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "vector of doc {} has {} bytes, expected dimension {}",
+                doc,
+                bytes.len(),
+                self.vector.len()
+            )));
+        }
+        decode_vector_into(&bytes, &mut self.vector);
+        Ok(self.function.score(&self.vector) * self.boost)
+    }
+}
+
+impl DocIterator for VectorScoreScorer {
+    fn doc_id(&self) -> DocId {
+        self.scorer.doc_id()
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        self.scorer.next()
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        self.scorer.advance(target)
+    }
+
+    fn cost(&self) -> usize {
+        self.scorer.cost()
+    }
+
+    fn matches(&mut self) -> Result<bool> {
+        self.scorer.matches()
+    }
+
+    fn approximate_next(&mut self) -> Result<DocId> {
+        self.scorer.approximate_next()
+    }
+
+    fn approximate_advance(&mut self, target: DocId) -> Result<DocId> {
+        self.scorer.approximate_advance(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::tests::TestCodec;
+    use core::doc::encode_vector;
+    use core::search::query::MatchAllDocsQuery;
+    use core::search::NO_MORE_DOCS;
+
+    struct MockBinaryDocValues {
+        values: Vec<Vec<u8>>,
+    }
+
+    impl BinaryDocValues for MockBinaryDocValues {
+        fn get(&mut self, doc_id: DocId) -> Result<Vec<u8>> {
+            Ok(self.values[doc_id as usize].clone())
+        }
+    }
+
+    struct MockScorer {
+        doc: DocId,
+        max_doc: DocId,
+    }
+
+    impl Scorer for MockScorer {
+        fn score(&mut self) -> Result<f32> {
+            Ok(1f32)
+        }
+    }
+
+    impl DocIterator for MockScorer {
+        fn doc_id(&self) -> DocId {
+            self.doc
+        }
+
+        fn next(&mut self) -> Result<DocId> {
+            let target = self.doc + 1;
+            self.advance(target)
+        }
+
+        fn advance(&mut self, target: DocId) -> Result<DocId> {
+            self.doc = if target < self.max_doc {
+                target
+            } else {
+                NO_MORE_DOCS
+            };
+            Ok(self.doc)
+        }
+
+        fn cost(&self) -> usize {
+            self.max_doc as usize
+        }
+    }
+
+    #[test]
+    fn test_vector_score_function() {
+        let target = [1.0f32, 2.0, 3.0, 4.0, 5.0];
+        let vectors = [
+            [5.0f32, 4.0, 3.0, 2.0, 1.0],
+            [0.0f32; 5],
+            [-1.0f32, 0.5, 0.0, 2.0, -3.0],
+        ];
+        for sim in &[
+            VectorSimilarity::Euclidean,
+            VectorSimilarity::DotProduct,
+            VectorSimilarity::Cosine,
+        ] {
+            let function = VectorScoreFunction::new(&target, *sim);
+            for v in &vectors {
+                assert!((function.score(v) - sim.compare(&target, v)).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vector_score_scorer() {
+        let values = vec![
+            encode_vector(&[1.0, 0.0]),
+            vec![],
+            encode_vector(&[0.0, 1.0]),
+            encode_vector(&[1.0, 0.0, 0.0]),
+        ];
+        let mut scorer = VectorScoreScorer::new(
+            Box::new(MockScorer {
+                doc: -1,
+                max_doc: 4,
+            }),
+            Box::new(MockBinaryDocValues { values }),
+            VectorScoreFunction::new(&[1.0, 0.0], VectorSimilarity::Cosine),
+            2.0,
+        );
+        assert_eq!(scorer.next().unwrap(), 0);
+        assert!((scorer.score().unwrap() - 2.0).abs() < 1e-6);
+        assert_eq!(scorer.next().unwrap(), 1);
+        assert_eq!(scorer.score().unwrap(), 0.0);
+        assert_eq!(scorer.next().unwrap(), 2);
+        assert!((scorer.score().unwrap() - 1.0).abs() < 1e-6);
+        // dimension mismatch
+        assert_eq!(scorer.next().unwrap(), 3);
+        assert!(scorer.score().is_err());
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+
+        let query = VectorScoreQuery::<TestCodec>::new(
+            Box::new(MatchAllDocsQuery),
+            "vec",
+            vec![::std::f32::INFINITY],
+            VectorSimilarity::Cosine,
+        );
+        assert!(query.is_err());
+    }
+}
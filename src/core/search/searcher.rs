@@ -209,6 +209,9 @@ pub trait SearchPlanBuilder<C: Codec> {
     /// max doc of the reader in searcher, same as IndexSearcher::reader()::max_doc()
     fn max_doc(&self) -> i32;
 
+    /// leaves of the reader in searcher, same as IndexSearcher::reader()::leaves()
+    fn leaves(&self) -> Vec<LeafReaderContext<'_, C>>;
+
     /// Creates a `Weight` for the given query, potentially adding caching
     /// if possible and configured.
     fn create_weight(&self, query: &dyn Query<C>, needs_scores: bool)
@@ -685,6 +688,10 @@ where
         self.reader.max_doc()
     }
 
+    fn leaves(&self) -> Vec<LeafReaderContext<'_, C>> {
+        self.reader.leaves()
+    }
+
     /// Creates a {@link Weight} for the given query, potentially adding caching
     /// if possible and configured.
     fn create_weight(
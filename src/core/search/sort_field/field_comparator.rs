@@ -11,14 +11,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::codec::doc_values::NumericDocValues;
+use core::codec::doc_values::{NumericDocValues, SortedNumericDocValues};
+use core::doc::DocValuesType;
 use core::index::reader::{LeafReaderContext, SearchLeafReader};
-use core::search::sort_field::{SortFieldType, SortedWrapperDocValuesSource};
+use core::search::sort_field::{
+    SortFieldType, SortedSetSelector, SortedSetSelectorType, SortedWrapperDocValuesSource,
+};
+use core::util::geo::{decode_lat_lon, haversin_meters_from_sort_key, haversin_sort_key};
 use core::util::{BitsMut, DocId, VariantValue};
 use error::Result;
 
 use core::codec::Codec;
 use std::cmp::Ordering;
+use std::f64;
 use std::fmt;
 
 #[derive(Copy, Clone, Debug)]
@@ -121,11 +126,17 @@ impl PartialOrd for ComparatorValue {
 ///   update internal state of the comparator, for example retrieving new values from DocValues.
 /// * `value()` Return the sort value stored in the specified slot.  This is only called at the end
 ///   of the search, in order to populate `FieldDoc::fields` when returning the top results.
+/// * `compare_values()` Compare two values returned by `value()`, used to merge the top results
+///   collected from different segments in parallel.
 pub trait FieldComparator: fmt::Display {
     fn compare(&self, slot1: usize, slot2: usize) -> Ordering;
 
     fn value(&self, slot: usize) -> VariantValue;
 
+    fn compare_values(&self, v1: &VariantValue, v2: &VariantValue) -> Ordering {
+        v1.cmp(v2)
+    }
+
     fn set_bottom(&mut self, slot: usize);
 
     fn compare_bottom(&mut self, value: ComparatorValue) -> Result<Ordering>;
@@ -145,6 +156,8 @@ pub enum FieldComparatorEnum {
     Doc(DocComparator),
     NumericDV(NumericDocValuesComparator<DefaultDocValuesSource>),
     SortedNumericDV(NumericDocValuesComparator<SortedWrapperDocValuesSource>),
+    SortedSetDV(SortedSetDocValuesComparator),
+    LatLonDistance(LatLonDistanceComparator),
 }
 
 impl FieldComparator for FieldComparatorEnum {
@@ -154,6 +167,8 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.compare(slot1, slot2),
             FieldComparatorEnum::NumericDV(c) => c.compare(slot1, slot2),
             FieldComparatorEnum::SortedNumericDV(c) => c.compare(slot1, slot2),
+            FieldComparatorEnum::SortedSetDV(c) => c.compare(slot1, slot2),
+            FieldComparatorEnum::LatLonDistance(c) => c.compare(slot1, slot2),
         }
     }
 
@@ -163,6 +178,19 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.value(slot),
             FieldComparatorEnum::NumericDV(c) => c.value(slot),
             FieldComparatorEnum::SortedNumericDV(c) => c.value(slot),
+            FieldComparatorEnum::SortedSetDV(c) => c.value(slot),
+            FieldComparatorEnum::LatLonDistance(c) => c.value(slot),
+        }
+    }
+
+    fn compare_values(&self, v1: &VariantValue, v2: &VariantValue) -> Ordering {
+        match self {
+            FieldComparatorEnum::Score(c) => c.compare_values(v1, v2),
+            FieldComparatorEnum::Doc(c) => c.compare_values(v1, v2),
+            FieldComparatorEnum::NumericDV(c) => c.compare_values(v1, v2),
+            FieldComparatorEnum::SortedNumericDV(c) => c.compare_values(v1, v2),
+            FieldComparatorEnum::SortedSetDV(c) => c.compare_values(v1, v2),
+            FieldComparatorEnum::LatLonDistance(c) => c.compare_values(v1, v2),
         }
     }
 
@@ -172,6 +200,8 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.set_bottom(slot),
             FieldComparatorEnum::NumericDV(c) => c.set_bottom(slot),
             FieldComparatorEnum::SortedNumericDV(c) => c.set_bottom(slot),
+            FieldComparatorEnum::SortedSetDV(c) => c.set_bottom(slot),
+            FieldComparatorEnum::LatLonDistance(c) => c.set_bottom(slot),
         }
     }
 
@@ -181,6 +211,8 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.compare_bottom(value),
             FieldComparatorEnum::NumericDV(c) => c.compare_bottom(value),
             FieldComparatorEnum::SortedNumericDV(c) => c.compare_bottom(value),
+            FieldComparatorEnum::SortedSetDV(c) => c.compare_bottom(value),
+            FieldComparatorEnum::LatLonDistance(c) => c.compare_bottom(value),
         }
     }
 
@@ -190,6 +222,8 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.copy(slot, value),
             FieldComparatorEnum::NumericDV(c) => c.copy(slot, value),
             FieldComparatorEnum::SortedNumericDV(c) => c.copy(slot, value),
+            FieldComparatorEnum::SortedSetDV(c) => c.copy(slot, value),
+            FieldComparatorEnum::LatLonDistance(c) => c.copy(slot, value),
         }
     }
 
@@ -202,6 +236,8 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.get_information_from_reader(reader),
             FieldComparatorEnum::NumericDV(c) => c.get_information_from_reader(reader),
             FieldComparatorEnum::SortedNumericDV(c) => c.get_information_from_reader(reader),
+            FieldComparatorEnum::SortedSetDV(c) => c.get_information_from_reader(reader),
+            FieldComparatorEnum::LatLonDistance(c) => c.get_information_from_reader(reader),
         }
     }
 
@@ -211,6 +247,8 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.get_type(),
             FieldComparatorEnum::NumericDV(c) => c.get_type(),
             FieldComparatorEnum::SortedNumericDV(c) => c.get_type(),
+            FieldComparatorEnum::SortedSetDV(c) => c.get_type(),
+            FieldComparatorEnum::LatLonDistance(c) => c.get_type(),
         }
     }
 }
@@ -222,6 +260,8 @@ impl fmt::Display for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => write!(f, "FieldComparatorEnum({})", c),
             FieldComparatorEnum::NumericDV(c) => write!(f, "FieldComparatorEnum({})", c),
             FieldComparatorEnum::SortedNumericDV(c) => write!(f, "FieldComparatorEnum({})", c),
+            FieldComparatorEnum::SortedSetDV(c) => write!(f, "FieldComparatorEnum({})", c),
+            FieldComparatorEnum::LatLonDistance(c) => write!(f, "FieldComparatorEnum({})", c),
         }
     }
 }
@@ -258,6 +298,15 @@ impl FieldComparator for RelevanceComparator {
         VariantValue::Float(self.scores[slot])
     }
 
+    fn compare_values(&self, v1: &VariantValue, v2: &VariantValue) -> Ordering {
+        match (v1, v2) {
+            (VariantValue::Float(s1), VariantValue::Float(s2)) => {
+                s2.partial_cmp(s1).unwrap_or(Ordering::Equal)
+            }
+            _ => v2.cmp(v1),
+        }
+    }
+
     fn set_bottom(&mut self, slot: usize) {
         self.bottom = self.scores[slot];
     }
@@ -432,7 +481,7 @@ impl<T: DocValuesSource> FieldComparator for NumericDocValuesComparator<T> {
         let doc_id = value.doc();
         let value = self.get_doc_value(doc_id)?;
         if let Some(ref mut bits) = self.docs_with_fields {
-            if value.is_zero() && bits.get(doc_id as usize)? {
+            if value.is_zero() && !bits.get(doc_id as usize)? {
                 return Ok(self.bottom.cmp(self.missing_value.as_ref().unwrap()));
             }
         }
@@ -444,7 +493,7 @@ impl<T: DocValuesSource> FieldComparator for NumericDocValuesComparator<T> {
         let doc_id = value.doc();
         let mut value = self.get_doc_value(doc_id)?;
         if let Some(ref mut bits) = self.docs_with_fields {
-            if value.is_zero() && bits.get(doc_id as usize)? {
+            if value.is_zero() && !bits.get(doc_id as usize)? {
                 value = self.missing_value.as_ref().unwrap().clone();
             }
         }
@@ -485,6 +534,260 @@ impl<T: DocValuesSource> fmt::Display for NumericDocValuesComparator<T> {
     }
 }
 
+/// Sorts by the value a `SortedSetSelector` selects in a `SortedSetDocValues`
+/// field, comparing ordinals for documents of the same segment and the
+/// values themselves otherwise.
+pub struct SortedSetDocValuesComparator {
+    field: String,
+    selector: SortedSetSelectorType,
+    missing_last: bool,
+    // selected value by slot, `None` if the document has no value
+    values: Vec<Option<Vec<u8>>>,
+    // selected ordinal by slot, only comparable within the same segment
+    ords: Vec<i64>,
+    reader_gens: Vec<usize>,
+    current_reader_gen: usize,
+    current_values: Option<SortedSetSelector>,
+    bottom: usize,
+}
+
+impl SortedSetDocValuesComparator {
+    pub fn new(
+        num_hits: usize,
+        field: String,
+        selector: SortedSetSelectorType,
+        missing_last: bool,
+    ) -> Self {
+        SortedSetDocValuesComparator {
+            field,
+            selector,
+            missing_last,
+            values: vec![None; num_hits],
+            ords: vec![-1; num_hits],
+            reader_gens: vec![0; num_hits],
+            current_reader_gen: 0,
+            current_values: None,
+            bottom: 0,
+        }
+    }
+
+    fn compare_selected(&self, v1: Option<&[u8]>, v2: Option<&[u8]>) -> Ordering {
+        match (v1, v2) {
+            (Some(v1), Some(v2)) => v1.cmp(v2),
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) if self.missing_last => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) if self.missing_last => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+        }
+    }
+
+    fn compare_ords(&self, ord1: i64, ord2: i64) -> Ordering {
+        let missing_ord = if self.missing_last {
+            i64::max_value()
+        } else {
+            -1
+        };
+        let ord1 = if ord1 < 0 { missing_ord } else { ord1 };
+        let ord2 = if ord2 < 0 { missing_ord } else { ord2 };
+        ord1.cmp(&ord2)
+    }
+}
+
+impl FieldComparator for SortedSetDocValuesComparator {
+    fn compare(&self, slot1: usize, slot2: usize) -> Ordering {
+        if self.reader_gens[slot1] == self.reader_gens[slot2] {
+            self.compare_ords(self.ords[slot1], self.ords[slot2])
+        } else {
+            self.compare_selected(self.values[slot1].as_deref(), self.values[slot2].as_deref())
+        }
+    }
+
+    /// Returns the selected value, an empty `Binary` if the document has no
+    /// value (empty values can't be indexed).
+    fn value(&self, slot: usize) -> VariantValue {
+        VariantValue::Binary(self.values[slot].clone().unwrap_or_default())
+    }
+
+    fn compare_values(&self, v1: &VariantValue, v2: &VariantValue) -> Ordering {
+        // empty values can't be indexed, so an empty `Binary` is a missing value
+        fn selected(v: &VariantValue) -> Option<&[u8]> {
+            match v {
+                VariantValue::Binary(b) if !b.is_empty() => Some(b.as_slice()),
+                _ => None,
+            }
+        }
+        self.compare_selected(selected(v1), selected(v2))
+    }
+
+    fn set_bottom(&mut self, slot: usize) {
+        self.bottom = slot;
+    }
+
+    fn compare_bottom(&mut self, value: ComparatorValue) -> Result<Ordering> {
+        debug_assert!(value.is_doc());
+        let values = self.current_values.as_mut().unwrap();
+        let ord = values.get_ord(value.doc())?;
+        if self.reader_gens[self.bottom] == self.current_reader_gen {
+            return Ok(self.compare_ords(self.ords[self.bottom], ord));
+        }
+        let value = if ord < 0 {
+            None
+        } else {
+            Some(values.lookup_ord(ord)?)
+        };
+        Ok(self.compare_selected(self.values[self.bottom].as_deref(), value.as_deref()))
+    }
+
+    fn copy(&mut self, slot: usize, value: ComparatorValue) -> Result<()> {
+        debug_assert!(value.is_doc());
+        let values = self.current_values.as_mut().unwrap();
+        let ord = values.get_ord(value.doc())?;
+        self.values[slot] = if ord < 0 {
+            None
+        } else {
+            Some(values.lookup_ord(ord)?)
+        };
+        self.ords[slot] = ord;
+        self.reader_gens[slot] = self.current_reader_gen;
+        Ok(())
+    }
+
+    fn get_information_from_reader<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<()> {
+        let doc_values = SortedSetSelector::doc_values(reader.reader, &self.field)?;
+        self.current_values = Some(SortedSetSelector::wrap(doc_values, self.selector));
+        // slots filled from the previous segments are compared by value
+        self.current_reader_gen += 1;
+        Ok(())
+    }
+
+    fn get_type(&self) -> SortFieldType {
+        SortFieldType::String
+    }
+}
+
+impl fmt::Display for SortedSetDocValuesComparator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SortedSetDocValuesComparator(field: {}, selector: {:?}, bottom: {})",
+            self.field, self.selector, self.bottom
+        )
+    }
+}
+
+/// Sorts by the distance from an origin to the closest point of a
+/// `LatLonDocValuesField`, documents without any point sort last.
+///
+/// Slots hold haversine sort keys, which are converted to meters only in
+/// `value`.
+pub struct LatLonDistanceComparator {
+    field: String,
+    latitude: f64,
+    longitude: f64,
+    values: Vec<f64>,
+    bottom: f64,
+    current_values: Option<Box<dyn SortedNumericDocValues>>,
+}
+
+impl LatLonDistanceComparator {
+    pub fn new(num_hits: usize, field: String, latitude: f64, longitude: f64) -> Self {
+        LatLonDistanceComparator {
+            field,
+            latitude,
+            longitude,
+            values: vec![0.0; num_hits],
+            bottom: 0.0,
+            current_values: None,
+        }
+    }
+
+    fn sort_key(&mut self, doc_id: DocId) -> Result<f64> {
+        let mut min_key = f64::INFINITY;
+        if let Some(ref mut values) = self.current_values {
+            values.set_document(doc_id)?;
+            for i in 0..values.count() {
+                let (lat, lon) = decode_lat_lon(values.value_at(i)?);
+                let key = haversin_sort_key(self.latitude, self.longitude, lat, lon);
+                if key < min_key {
+                    min_key = key;
+                }
+            }
+        }
+        Ok(min_key)
+    }
+}
+
+impl FieldComparator for LatLonDistanceComparator {
+    fn compare(&self, slot1: usize, slot2: usize) -> Ordering {
+        // sort keys are never NaN
+        self.values[slot1]
+            .partial_cmp(&self.values[slot2])
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /// Returns the distance in meters, `f64::INFINITY` if the document has
+    /// no point.
+    fn value(&self, slot: usize) -> VariantValue {
+        VariantValue::Double(haversin_meters_from_sort_key(self.values[slot]))
+    }
+
+    fn compare_values(&self, v1: &VariantValue, v2: &VariantValue) -> Ordering {
+        match (v1, v2) {
+            (VariantValue::Double(d1), VariantValue::Double(d2)) => {
+                d1.partial_cmp(d2).unwrap_or(Ordering::Equal)
+            }
+            _ => v1.cmp(v2),
+        }
+    }
+
+    fn set_bottom(&mut self, slot: usize) {
+        self.bottom = self.values[slot];
+    }
+
+    fn compare_bottom(&mut self, value: ComparatorValue) -> Result<Ordering> {
+        debug_assert!(value.is_doc());
+        let key = self.sort_key(value.doc())?;
+        Ok(self.bottom.partial_cmp(&key).unwrap_or(Ordering::Equal))
+    }
+
+    fn copy(&mut self, slot: usize, value: ComparatorValue) -> Result<()> {
+        debug_assert!(value.is_doc());
+        self.values[slot] = self.sort_key(value.doc())?;
+        Ok(())
+    }
+
+    fn get_information_from_reader<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<()> {
+        self.current_values = match reader.reader.field_info(&self.field) {
+            Some(fi) if fi.doc_values_type == DocValuesType::SortedNumeric => {
+                Some(reader.reader.get_sorted_numeric_doc_values(&self.field)?)
+            }
+            _ => None,
+        };
+        Ok(())
+    }
+
+    fn get_type(&self) -> SortFieldType {
+        SortFieldType::Custom
+    }
+}
+
+impl fmt::Display for LatLonDistanceComparator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LatLonDistanceComparator(field: {}, latitude: {}, longitude: {}, bottom: {})",
+            self.field, self.latitude, self.longitude, self.bottom
+        )
+    }
+}
+
 pub trait DocValuesSource {
     fn numeric_doc_values<C: Codec>(
         &self,
@@ -577,4 +880,49 @@ mod tests {
             Ordering::Greater
         );
     }
+
+    #[test]
+    fn test_sorted_set_comparator_missing() {
+        let mut comparator =
+            SortedSetDocValuesComparator::new(3, "tags".into(), SortedSetSelectorType::Min, true);
+        comparator.values = vec![Some(b"b".to_vec()), None, Some(b"a".to_vec())];
+        comparator.ords = vec![3, -1, 0];
+        // slots 0 and 1 come from the same segment, slot 2 from another one
+        comparator.reader_gens = vec![1, 1, 2];
+
+        assert_eq!(comparator.compare(0, 1), Ordering::Less);
+        assert_eq!(comparator.compare(0, 2), Ordering::Greater);
+        assert_eq!(comparator.compare(2, 1), Ordering::Less);
+        assert_eq!(comparator.value(1), VariantValue::Binary(vec![]));
+
+        comparator.missing_last = false;
+        assert_eq!(comparator.compare(0, 1), Ordering::Greater);
+        assert_eq!(comparator.compare(2, 1), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_lat_lon_distance_comparator() {
+        let mut comparator = LatLonDistanceComparator::new(3, "location".into(), 0.0, 0.0);
+        comparator.values = vec![
+            haversin_sort_key(0.0, 0.0, 1.0, 1.0),
+            f64::INFINITY,
+            haversin_sort_key(0.0, 0.0, 0.5, 0.5),
+        ];
+
+        assert_eq!(comparator.compare(0, 1), Ordering::Less);
+        assert_eq!(comparator.compare(0, 2), Ordering::Greater);
+        assert_eq!(comparator.value(1), VariantValue::Double(f64::INFINITY));
+        match comparator.value(2) {
+            VariantValue::Double(d) => assert!((d - 78_626.0).abs() < 100.0, "{}", d),
+            _ => unreachable!(),
+        }
+
+        comparator.set_bottom(0);
+        // a segment without the field, all the documents sort last
+        comparator.current_values = None;
+        assert_eq!(
+            comparator.compare_bottom(ComparatorValue::Doc(0)).unwrap(),
+            Ordering::Less
+        );
+    }
 }
@@ -11,16 +11,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::codec::doc_values::{NumericDocValues, SortedNumericDocValues};
+use core::codec::doc_values::lucene54::DocValues;
+use core::codec::doc_values::{
+    EmptySortedSetDocValues, NumericDocValues, SortedNumericDocValues, SortedSetDocValues,
+    NO_MORE_ORDS,
+};
 use core::codec::Codec;
-use core::index::reader::SearchLeafReader;
+use core::doc::DocValuesType;
+use core::index::reader::{LeafReader, SearchLeafReader};
 use core::search::sort_field::{
     DefaultDocValuesSource, DocComparator, DocValuesSource, FieldComparatorEnum,
-    NumericDocValuesComparator, RelevanceComparator,
+    LatLonDistanceComparator, NumericDocValuesComparator, RelevanceComparator,
+    SortedSetDocValuesComparator,
 };
+use core::util::geo::{check_latitude, check_longitude};
 use core::util::{sortable_double_bits, sortable_float_bits};
 use core::util::{BitsMut, DocId, VariantValue};
 
+use std::{f32, f64};
+
 use error::ErrorKind::IllegalArgument;
 use error::Result;
 
@@ -46,6 +55,25 @@ pub enum SortedSetSelectorType {
     MiddleMax,
 }
 
+impl SortedSetSelectorType {
+    /// Selects the representative value of a non empty slice of ordinals
+    /// sorted in ascending order.
+    ///
+    /// For an even number of values, `MiddleMin` selects the lower of the two
+    /// middle values and `MiddleMax` the upper one.
+    #[inline]
+    pub fn select<T: Copy>(self, sorted_ords: &[T]) -> T {
+        debug_assert!(!sorted_ords.is_empty());
+        let len = sorted_ords.len();
+        match self {
+            SortedSetSelectorType::Min => sorted_ords[0],
+            SortedSetSelectorType::Max => sorted_ords[len - 1],
+            SortedSetSelectorType::MiddleMin => sorted_ords[(len - 1) / 2],
+            SortedSetSelectorType::MiddleMax => sorted_ords[len / 2],
+        }
+    }
+}
+
 #[derive(PartialEq, Debug, Clone, Copy, Eq)]
 pub enum SortedNumericSelectorType {
     Min,
@@ -62,7 +90,8 @@ pub enum SortFieldMissingValue {
 pub enum SortField {
     Simple(SimpleSortField),
     SortedNumeric(SortedNumericSortField),
-    // SortedSet(SortedSetSortField),
+    SortedSet(SortedSetSortField),
+    LatLonDistance(LatLonDistanceSortField),
 }
 
 impl SortField {
@@ -74,6 +103,8 @@ impl SortField {
         match self {
             SortField::Simple(s) => &s.field,
             SortField::SortedNumeric(s) => &s.raw_field.field,
+            SortField::SortedSet(s) => &s.raw_field.field,
+            SortField::LatLonDistance(s) => &s.raw_field.field,
         }
     }
 
@@ -81,6 +112,8 @@ impl SortField {
         match self {
             SortField::Simple(s) => s.field_type,
             SortField::SortedNumeric(s) => s.raw_field.field_type,
+            SortField::SortedSet(s) => s.raw_field.field_type,
+            SortField::LatLonDistance(s) => s.raw_field.field_type,
         }
     }
 
@@ -88,6 +121,8 @@ impl SortField {
         match self {
             SortField::Simple(s) => s.is_reverse,
             SortField::SortedNumeric(s) => s.raw_field.is_reverse,
+            SortField::SortedSet(s) => s.raw_field.is_reverse,
+            SortField::LatLonDistance(s) => s.raw_field.is_reverse,
         }
     }
 
@@ -95,6 +130,9 @@ impl SortField {
         match self {
             SortField::Simple(s) => s.missing_value.as_ref(),
             SortField::SortedNumeric(s) => s.raw_field.missing_value.as_ref(),
+            // see `SortedSetSortField::missing_value`
+            SortField::SortedSet(_) => None,
+            SortField::LatLonDistance(_) => None,
         }
     }
 
@@ -102,6 +140,8 @@ impl SortField {
         match self {
             SortField::Simple(s) => s.needs_scores(),
             SortField::SortedNumeric(s) => s.raw_field.needs_scores(),
+            SortField::SortedSet(s) => s.raw_field.needs_scores(),
+            SortField::LatLonDistance(_) => false,
         }
     }
 
@@ -113,9 +153,59 @@ impl SortField {
             SortField::SortedNumeric(s) => {
                 s.raw_field.missing_value = value;
             }
+            SortField::SortedSet(_) => {
+                // the placement of missing values is set with
+                // `SortedSetSortField::set_missing_value`
+                debug_assert!(value.is_none());
+            }
+            SortField::LatLonDistance(_) => {
+                // documents without a point always sort last
+                debug_assert!(value.is_none());
+            }
+        }
+    }
+
+    /// Returns where documents without a value sort for string sorts, `None`
+    /// for the other sorts, which use `missing_value` instead.
+    pub fn missing_placement(&self) -> Option<SortFieldMissingValue> {
+        match self {
+            SortField::Simple(s) if s.field_type == SortFieldType::String => {
+                Some(s.missing_placement)
+            }
+            SortField::SortedSet(s) => Some(s.missing_value),
+            _ => None,
         }
     }
 
+    /// Sorts documents without a value first or last.
+    ///
+    /// As in Lucene the placement is relative to the natural order of the
+    /// field, so a reversed sort reverses it too. Numeric sorts use the
+    /// smallest or the largest value of their type as missing value, score,
+    /// doc and distance sorts don't support it.
+    pub fn set_missing_placement(&mut self, placement: SortFieldMissingValue) -> Result<()> {
+        match self {
+            SortField::Simple(s) if s.field_type == SortFieldType::String => {
+                s.missing_placement = placement;
+            }
+            SortField::Simple(s) => {
+                s.missing_value = Some(numeric_missing_value(s.field_type, placement)?);
+            }
+            SortField::SortedNumeric(s) => {
+                s.raw_field.missing_value = Some(numeric_missing_value(s.real_type, placement)?);
+            }
+            SortField::SortedSet(s) => {
+                s.missing_value = placement;
+            }
+            SortField::LatLonDistance(_) => {
+                bail!(IllegalArgument(
+                    "documents without a point always sort last".into()
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_comparator(
         &self,
         num_hits: usize,
@@ -124,16 +214,42 @@ impl SortField {
         match self {
             SortField::Simple(s) => s.get_comparator(num_hits, missing_value),
             SortField::SortedNumeric(s) => s.get_comparator(num_hits, missing_value),
+            SortField::SortedSet(s) => s.get_comparator(num_hits),
+            SortField::LatLonDistance(s) => s.get_comparator(num_hits),
         }
     }
 }
 
+fn numeric_missing_value(
+    field_type: SortFieldType,
+    placement: SortFieldMissingValue,
+) -> Result<VariantValue> {
+    let first = placement == SortFieldMissingValue::StringFirst;
+    let value = match field_type {
+        SortFieldType::Int if first => VariantValue::Int(i32::min_value()),
+        SortFieldType::Int => VariantValue::Int(i32::max_value()),
+        SortFieldType::Long if first => VariantValue::Long(i64::min_value()),
+        SortFieldType::Long => VariantValue::Long(i64::max_value()),
+        SortFieldType::Float if first => VariantValue::Float(f32::NEG_INFINITY),
+        SortFieldType::Float => VariantValue::Float(f32::INFINITY),
+        SortFieldType::Double if first => VariantValue::Double(f64::NEG_INFINITY),
+        SortFieldType::Double => VariantValue::Double(f64::INFINITY),
+        _ => bail!(IllegalArgument(format!(
+            "missing values can't be placed for sort type {:?}",
+            field_type
+        ))),
+    };
+    Ok(value)
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct SimpleSortField {
     field: String,
     field_type: SortFieldType,
     is_reverse: bool,
     missing_value: Option<VariantValue>,
+    // only used by string sorts
+    missing_placement: SortFieldMissingValue,
 }
 
 impl SimpleSortField {
@@ -143,6 +259,7 @@ impl SimpleSortField {
             field_type,
             is_reverse,
             missing_value: None,
+            missing_placement: SortFieldMissingValue::StringFirst,
         }
     }
 
@@ -152,6 +269,7 @@ impl SimpleSortField {
             field_type: SortFieldType::Score,
             is_reverse: false,
             missing_value: None,
+            missing_placement: SortFieldMissingValue::StringFirst,
         }
     }
 
@@ -179,8 +297,15 @@ impl SimpleSortField {
         match self.field_type {
             SortFieldType::Score => FieldComparatorEnum::Score(RelevanceComparator::new(num_hits)),
             SortFieldType::Doc => FieldComparatorEnum::Doc(DocComparator::new(num_hits)),
+            // a single valued `SortedDocValues` field, or the smallest value
+            // of a `SortedSetDocValues` one
             SortFieldType::String => {
-                unimplemented!();
+                FieldComparatorEnum::SortedSetDV(SortedSetDocValuesComparator::new(
+                    num_hits,
+                    self.field.clone(),
+                    SortedSetSelectorType::Min,
+                    self.missing_placement == SortFieldMissingValue::StringLast,
+                ))
             }
             _ => {
                 // debug_assert!(missing_value.is_some());
@@ -422,25 +547,190 @@ impl NumericDocValues for SortedNumAsNumDocValues {
     }
 }
 
-/// SortField for {@link SortedSetDocValues}.
+/// SortField for `SortedSetDocValues`.
 ///
 /// A SortedSetDocValues contains multiple values for a field, so sorting with
 /// this technique "selects" a value as the representative sort value for the document.
 ///
 /// By default, the minimum value in the set is selected as the sort value, but
-/// this can be customized. Selectors other than the default do have some limitations
-/// to ensure that all selections happen in constant-time for performance.
+/// this can be customized.
 ///
 /// Like sorting by string, this also supports sorting missing values as first or last,
-/// via {@link #setMissingValue(Object)}.
-/// @see SortedSetSelector
-/// TODO, may implement later
-#[allow(dead_code)]
-struct SortedSetSortField {
+/// via `set_missing_value`. Missing values sort first by default.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SortedSetSortField {
     selector: SortedSetSelectorType,
+    missing_value: SortFieldMissingValue,
+    raw_field: SimpleSortField,
+}
+
+impl SortedSetSortField {
+    pub fn with_field(field: String, reverse: bool) -> Self {
+        Self::new(field, reverse, SortedSetSelectorType::Min)
+    }
+
+    pub fn new(field: String, reverse: bool, selector: SortedSetSelectorType) -> Self {
+        let raw_field = SimpleSortField::new(field, SortFieldType::Custom, reverse);
+        SortedSetSortField {
+            selector,
+            missing_value: SortFieldMissingValue::StringFirst,
+            raw_field,
+        }
+    }
+
+    pub fn selector(&self) -> SortedSetSelectorType {
+        self.selector
+    }
+
+    pub fn missing_value(&self) -> SortFieldMissingValue {
+        self.missing_value
+    }
+
+    pub fn set_missing_value(&mut self, missing_value: SortFieldMissingValue) {
+        self.missing_value = missing_value;
+    }
+
+    pub fn get_comparator(&self, num_hits: usize) -> FieldComparatorEnum {
+        FieldComparatorEnum::SortedSetDV(SortedSetDocValuesComparator::new(
+            num_hits,
+            self.raw_field.field.clone(),
+            self.selector,
+            self.missing_value == SortFieldMissingValue::StringLast,
+        ))
+    }
+
+    #[inline]
+    pub fn raw_field(&self) -> &SimpleSortField {
+        &self.raw_field
+    }
+}
+
+/// SortField for the distance from an origin to the points of a
+/// `LatLonDocValuesField`, see `LatLonDocValuesField::new_distance_sort`.
+///
+/// Documents are sorted by ascending haversine distance to their closest
+/// point, documents without any point sort last. The distance in meters of
+/// each hit is exposed as a `VariantValue::Double` in `FieldDoc::fields`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatLonDistanceSortField {
+    latitude: f64,
+    longitude: f64,
     raw_field: SimpleSortField,
 }
 
+// the origin is validated, so it is never NaN
+impl Eq for LatLonDistanceSortField {}
+
+impl LatLonDistanceSortField {
+    pub fn new(field: String, latitude: f64, longitude: f64) -> Result<Self> {
+        check_latitude(latitude)?;
+        check_longitude(longitude)?;
+        Ok(LatLonDistanceSortField {
+            latitude,
+            longitude,
+            raw_field: SimpleSortField::new(field, SortFieldType::Custom, false),
+        })
+    }
+
+    pub fn latitude(&self) -> f64 {
+        self.latitude
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self.longitude
+    }
+
+    pub fn get_comparator(&self, num_hits: usize) -> FieldComparatorEnum {
+        FieldComparatorEnum::LatLonDistance(LatLonDistanceComparator::new(
+            num_hits,
+            self.raw_field.field.clone(),
+            self.latitude,
+            self.longitude,
+        ))
+    }
+
+    #[inline]
+    pub fn raw_field(&self) -> &SimpleSortField {
+        &self.raw_field
+    }
+}
+
+/// Selects a value from the document's set to use as the representative value
+///
+/// This provides a single valued view over the SortedSet, with the ordinals
+/// and values of the underlying `SortedSetDocValues`.
+pub struct SortedSetSelector {
+    doc_values: Box<dyn SortedSetDocValues>,
+    selector: SortedSetSelectorType,
+    ords: Vec<i64>,
+}
+
+impl SortedSetSelector {
+    pub fn wrap(doc_values: Box<dyn SortedSetDocValues>, selector: SortedSetSelectorType) -> Self {
+        SortedSetSelector {
+            doc_values,
+            selector,
+            ords: Vec::new(),
+        }
+    }
+
+    /// Returns the values of a `SortedSet` field, or of a `Sorted` one
+    /// viewed as a set of at most one value. A segment without the field
+    /// has no value for any document.
+    pub fn doc_values<R: LeafReader + ?Sized>(
+        reader: &R,
+        field: &str,
+    ) -> Result<Box<dyn SortedSetDocValues>> {
+        let doc_values: Box<dyn SortedSetDocValues> = match reader.field_info(field) {
+            Some(fi) if fi.doc_values_type == DocValuesType::SortedSet => {
+                reader.get_sorted_set_doc_values(field)?
+            }
+            Some(fi) if fi.doc_values_type == DocValuesType::Sorted => Box::new(
+                DocValues::singleton_sorted_doc_values(reader.get_sorted_doc_values(field)?),
+            ),
+            _ => Box::new(EmptySortedSetDocValues),
+        };
+        Ok(doc_values)
+    }
+
+    /// Returns the ordinal of the selected value, `-1` if the document has
+    /// no value.
+    pub fn get_ord(&mut self, doc_id: DocId) -> Result<i64> {
+        self.doc_values.set_document(doc_id)?;
+        if self.selector == SortedSetSelectorType::Min {
+            // ords come in ascending order
+            return self.doc_values.next_ord();
+        }
+        self.ords.clear();
+        loop {
+            let ord = self.doc_values.next_ord()?;
+            if ord == NO_MORE_ORDS {
+                break;
+            }
+            self.ords.push(ord);
+        }
+        if self.ords.is_empty() {
+            Ok(-1)
+        } else {
+            Ok(self.selector.select(&self.ords))
+        }
+    }
+
+    pub fn lookup_ord(&mut self, ord: i64) -> Result<Vec<u8>> {
+        self.doc_values.lookup_ord(ord)
+    }
+
+    /// Returns the selected value, `None` if the document has no value.
+    pub fn get(&mut self, doc_id: DocId) -> Result<Option<Vec<u8>>> {
+        let ord = self.get_ord(doc_id)?;
+        if ord < 0 {
+            Ok(None)
+        } else {
+            self.lookup_ord(ord).map(Some)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -470,4 +760,105 @@ mod tests {
         assert_eq!(SortFieldType::Doc, sort_field.field_type());
         assert_eq!(true, sort_field.is_reverse());
     }
+
+    #[test]
+    fn test_sorted_set_selector_type() {
+        let ords = [1, 3, 5, 8];
+        assert_eq!(SortedSetSelectorType::Min.select(&ords), 1);
+        assert_eq!(SortedSetSelectorType::Max.select(&ords), 8);
+        assert_eq!(SortedSetSelectorType::MiddleMin.select(&ords), 3);
+        assert_eq!(SortedSetSelectorType::MiddleMax.select(&ords), 5);
+        assert_eq!(SortedSetSelectorType::MiddleMin.select(&ords[..3]), 3);
+        assert_eq!(SortedSetSelectorType::MiddleMax.select(&ords[..3]), 3);
+    }
+
+    #[test]
+    fn test_sorted_set_sort_field() {
+        let mut sort_field =
+            SortedSetSortField::new("tags".into(), true, SortedSetSelectorType::Max);
+        assert_eq!(
+            sort_field.missing_value(),
+            SortFieldMissingValue::StringFirst
+        );
+        sort_field.set_missing_value(SortFieldMissingValue::StringLast);
+
+        let sort_field = SortField::SortedSet(sort_field);
+        assert_eq!("tags", sort_field.field());
+        assert_eq!(SortFieldType::Custom, sort_field.field_type());
+        assert!(sort_field.is_reverse());
+        assert!(!sort_field.needs_scores());
+    }
+
+    #[test]
+    fn test_missing_placement() {
+        let mut sort_field = SortField::Simple(SimpleSortField::new(
+            "name".into(),
+            SortFieldType::String,
+            false,
+        ));
+        assert_eq!(
+            sort_field.missing_placement(),
+            Some(SortFieldMissingValue::StringFirst)
+        );
+        sort_field
+            .set_missing_placement(SortFieldMissingValue::StringLast)
+            .unwrap();
+        assert_eq!(
+            sort_field.missing_placement(),
+            Some(SortFieldMissingValue::StringLast)
+        );
+        assert!(sort_field.missing_value().is_none());
+        match sort_field.get_comparator(1, None) {
+            FieldComparatorEnum::SortedSetDV(_) => {}
+            _ => unreachable!(),
+        }
+
+        let mut sort_field = SortField::SortedNumeric(SortedNumericSortField::new(
+            "price".into(),
+            SortFieldType::Double,
+            true,
+            SortedNumericSelectorType::Max,
+        ));
+        sort_field
+            .set_missing_placement(SortFieldMissingValue::StringLast)
+            .unwrap();
+        assert!(sort_field.missing_placement().is_none());
+        assert_eq!(
+            sort_field.missing_value(),
+            Some(&VariantValue::Double(f64::INFINITY))
+        );
+
+        let mut sort_field =
+            SortField::Simple(SimpleSortField::new("id".into(), SortFieldType::Int, false));
+        sort_field
+            .set_missing_placement(SortFieldMissingValue::StringFirst)
+            .unwrap();
+        assert_eq!(
+            sort_field.missing_value(),
+            Some(&VariantValue::Int(i32::min_value()))
+        );
+
+        let mut sort_field = SortField::new_score();
+        assert!(sort_field
+            .set_missing_placement(SortFieldMissingValue::StringLast)
+            .is_err());
+    }
+
+    #[test]
+    fn test_lat_lon_distance_sort_field() {
+        let sort_field = SortField::LatLonDistance(
+            LatLonDistanceSortField::new("location".into(), 40.0, -74.0).unwrap(),
+        );
+        assert_eq!(sort_field.field(), "location");
+        assert_eq!(sort_field.field_type(), SortFieldType::Custom);
+        assert!(!sort_field.is_reverse());
+        assert!(!sort_field.needs_scores());
+        match sort_field.get_comparator(1, None) {
+            FieldComparatorEnum::LatLonDistance(_) => {}
+            _ => unreachable!(),
+        }
+
+        assert!(LatLonDistanceSortField::new("location".into(), 91.0, 0.0).is_err());
+        assert!(LatLonDistanceSortField::new("location".into(), 0.0, -181.0).is_err());
+    }
 }
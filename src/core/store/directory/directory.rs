@@ -16,6 +16,7 @@ use std::fmt;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use core::store::directory::Lock;
 use core::store::io::{BufferedChecksumIndexInput, DataOutput, IndexInput, IndexOutput};
 use core::store::IOContext;
 use error::Result;
@@ -75,6 +76,12 @@ pub trait Directory: fmt::Display {
 
     fn rename(&self, source: &str, dest: &str) -> Result<()>;
 
+    /// Returns the lock `name` of this directory, held until it's released
+    /// or dropped.
+    ///
+    /// Fails with `LockObtainFailed` if the lock is held by someone else.
+    fn obtain_lock(&self, name: &str) -> Result<Box<dyn Lock>>;
+
     fn copy_from<D: Directory>(
         &self,
         from: Arc<D>,
@@ -104,11 +111,12 @@ pub trait Directory: fmt::Display {
 /// `Lock` is valid before any destructive filesystem operation.
 pub struct LockValidatingDirectoryWrapper<D: Directory> {
     dir: Arc<D>,
+    write_lock: Arc<dyn Lock>,
 }
 
 impl<D: Directory> LockValidatingDirectoryWrapper<D> {
-    pub fn new(dir: Arc<D>) -> Self {
-        LockValidatingDirectoryWrapper { dir }
+    pub fn new(dir: Arc<D>, write_lock: Arc<dyn Lock>) -> Self {
+        LockValidatingDirectoryWrapper { dir, write_lock }
     }
 }
 
@@ -126,6 +134,7 @@ impl<D: Directory> Directory for LockValidatingDirectoryWrapper<D> {
     type TempOutput = D::TempOutput;
 
     fn create_output(&self, name: &str, context: &IOContext) -> Result<Self::IndexOutput> {
+        self.write_lock.ensure_valid()?;
         self.dir.create_output(name, context)
     }
 
@@ -143,18 +152,22 @@ impl<D: Directory> Directory for LockValidatingDirectoryWrapper<D> {
     }
 
     fn delete_file(&self, name: &str) -> Result<()> {
+        self.write_lock.ensure_valid()?;
         self.dir.delete_file(name)
     }
 
     fn sync(&self, name: &HashSet<String>) -> Result<()> {
+        self.write_lock.ensure_valid()?;
         self.dir.sync(name)
     }
 
     fn sync_meta_data(&self) -> Result<()> {
+        self.write_lock.ensure_valid()?;
         self.dir.sync_meta_data()
     }
 
     fn rename(&self, source: &str, dest: &str) -> Result<()> {
+        self.write_lock.ensure_valid()?;
         self.dir.rename(source, dest)
     }
 
@@ -165,6 +178,7 @@ impl<D: Directory> Directory for LockValidatingDirectoryWrapper<D> {
         dest: &str,
         ctx: &IOContext,
     ) -> Result<()> {
+        self.write_lock.ensure_valid()?;
         self.dir.copy_from(from, src, dest, ctx)
     }
 }
@@ -213,6 +227,10 @@ default impl<T: FilterDirectory> Directory for T {
         self.dir().rename(source, dest)
     }
 
+    fn obtain_lock(&self, name: &str) -> Result<Box<dyn Lock>> {
+        self.dir().obtain_lock(name)
+    }
+
     fn copy_from<D: Directory>(
         &self,
         from: Arc<D>,
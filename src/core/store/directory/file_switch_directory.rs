@@ -0,0 +1,304 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use core::store::directory::{Directory, Lock};
+use core::store::io::{DataOutput, IndexInput, IndexOutput};
+use core::store::IOContext;
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+/// Expert: A Directory instance that switches files between two other
+/// Directory instances.
+///
+/// Files with the specified extensions are placed in the primary directory;
+/// others are placed in the secondary directory, e.g. postings and doc values
+/// on an `MmapDirectory` and stored fields on a `FSDirectory`. Note that the
+/// compound file of a segment (`cfs`) holds all of its files, so compound
+/// files should be disabled to split a segment between both directories.
+pub struct FileSwitchDirectory<P: Directory, S: Directory> {
+    primary_extensions: HashSet<String>,
+    primary: Arc<P>,
+    secondary: Arc<S>,
+}
+
+impl<P: Directory, S: Directory> FileSwitchDirectory<P, S> {
+    pub fn new(primary_extensions: HashSet<String>, primary: Arc<P>, secondary: Arc<S>) -> Self {
+        FileSwitchDirectory {
+            primary_extensions,
+            primary,
+            secondary,
+        }
+    }
+
+    pub fn primary_dir(&self) -> &Arc<P> {
+        &self.primary
+    }
+
+    pub fn secondary_dir(&self) -> &Arc<S> {
+        &self.secondary
+    }
+
+    fn is_primary(&self, name: &str) -> bool {
+        self.primary_extensions.contains(extension(name))
+    }
+}
+
+/// Returns the extension of the file, the empty string if it has none.
+fn extension(name: &str) -> &str {
+    match name.rfind('.') {
+        Some(i) => &name[i + 1..],
+        None => "",
+    }
+}
+
+impl<P: Directory, S: Directory> Directory for FileSwitchDirectory<P, S> {
+    type IndexOutput = FileSwitchOutput<P::IndexOutput, S::IndexOutput>;
+    type TempOutput = FileSwitchOutput<P::TempOutput, S::TempOutput>;
+
+    fn list_all(&self) -> Result<Vec<String>> {
+        // both sides may be the same directory, so dedup the names
+        let mut files = BTreeSet::new();
+        for name in self.primary.list_all()? {
+            files.insert(name);
+        }
+        for name in self.secondary.list_all()? {
+            files.insert(name);
+        }
+        Ok(files.into_iter().collect())
+    }
+
+    fn file_length(&self, name: &str) -> Result<i64> {
+        if self.is_primary(name) {
+            self.primary.file_length(name)
+        } else {
+            self.secondary.file_length(name)
+        }
+    }
+
+    fn create_output(&self, name: &str, ctx: &IOContext) -> Result<Self::IndexOutput> {
+        if self.is_primary(name) {
+            Ok(FileSwitchOutput::Primary(
+                self.primary.create_output(name, ctx)?,
+            ))
+        } else {
+            Ok(FileSwitchOutput::Secondary(
+                self.secondary.create_output(name, ctx)?,
+            ))
+        }
+    }
+
+    fn open_input(&self, name: &str, ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+        if self.is_primary(name) {
+            self.primary.open_input(name, ctx)
+        } else {
+            self.secondary.open_input(name, ctx)
+        }
+    }
+
+    fn create_temp_output(
+        &self,
+        prefix: &str,
+        suffix: &str,
+        ctx: &IOContext,
+    ) -> Result<Self::TempOutput> {
+        // temp files are named "{prefix}_{suffix}_{counter}.tmp"
+        if self.primary_extensions.contains("tmp") {
+            Ok(FileSwitchOutput::Primary(
+                self.primary.create_temp_output(prefix, suffix, ctx)?,
+            ))
+        } else {
+            Ok(FileSwitchOutput::Secondary(
+                self.secondary.create_temp_output(prefix, suffix, ctx)?,
+            ))
+        }
+    }
+
+    fn delete_file(&self, name: &str) -> Result<()> {
+        if self.is_primary(name) {
+            self.primary.delete_file(name)
+        } else {
+            self.secondary.delete_file(name)
+        }
+    }
+
+    fn sync(&self, names: &HashSet<String>) -> Result<()> {
+        let (primary_names, secondary_names): (HashSet<String>, HashSet<String>) = names
+            .iter()
+            .cloned()
+            .partition(|name| self.is_primary(name));
+        if !primary_names.is_empty() {
+            self.primary.sync(&primary_names)?;
+        }
+        if !secondary_names.is_empty() {
+            self.secondary.sync(&secondary_names)?;
+        }
+        Ok(())
+    }
+
+    fn sync_meta_data(&self) -> Result<()> {
+        self.primary.sync_meta_data()?;
+        self.secondary.sync_meta_data()
+    }
+
+    fn rename(&self, source: &str, dest: &str) -> Result<()> {
+        match (self.is_primary(source), self.is_primary(dest)) {
+            (true, true) => self.primary.rename(source, dest),
+            (false, false) => self.secondary.rename(source, dest),
+            _ => bail!(IllegalArgument(format!(
+                "source '{}' and dest '{}' are in different directories",
+                source, dest
+            ))),
+        }
+    }
+
+    fn obtain_lock(&self, name: &str) -> Result<Box<dyn Lock>> {
+        if self.is_primary(name) {
+            self.primary.obtain_lock(name)
+        } else {
+            self.secondary.obtain_lock(name)
+        }
+    }
+
+    fn resolve(&self, name: &str) -> PathBuf {
+        if self.is_primary(name) {
+            self.primary.resolve(name)
+        } else {
+            self.secondary.resolve(name)
+        }
+    }
+}
+
+impl<P: Directory, S: Directory> fmt::Display for FileSwitchDirectory<P, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "FileSwitchDirectory(primary: {}, secondary: {})",
+            self.primary, self.secondary
+        )
+    }
+}
+
+/// `IndexOutput` of `FileSwitchDirectory`, writing to either of its
+/// directories.
+pub enum FileSwitchOutput<P: IndexOutput, S: IndexOutput> {
+    Primary(P),
+    Secondary(S),
+}
+
+impl<P: IndexOutput, S: IndexOutput> DataOutput for FileSwitchOutput<P, S> {}
+
+impl<P: IndexOutput, S: IndexOutput> Write for FileSwitchOutput<P, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileSwitchOutput::Primary(o) => o.write(buf),
+            FileSwitchOutput::Secondary(o) => o.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FileSwitchOutput::Primary(o) => o.flush(),
+            FileSwitchOutput::Secondary(o) => o.flush(),
+        }
+    }
+}
+
+impl<P: IndexOutput, S: IndexOutput> IndexOutput for FileSwitchOutput<P, S> {
+    fn name(&self) -> &str {
+        match self {
+            FileSwitchOutput::Primary(o) => o.name(),
+            FileSwitchOutput::Secondary(o) => o.name(),
+        }
+    }
+
+    fn file_pointer(&self) -> i64 {
+        match self {
+            FileSwitchOutput::Primary(o) => o.file_pointer(),
+            FileSwitchOutput::Secondary(o) => o.file_pointer(),
+        }
+    }
+
+    fn checksum(&self) -> Result<i64> {
+        match self {
+            FileSwitchOutput::Primary(o) => o.checksum(),
+            FileSwitchOutput::Secondary(o) => o.checksum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::directory::RAMDirectory;
+
+    fn new_dir() -> FileSwitchDirectory<RAMDirectory, RAMDirectory> {
+        let extensions = ["doc", "tim", "dvd"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        FileSwitchDirectory::new(
+            extensions,
+            Arc::new(RAMDirectory::new()),
+            Arc::new(RAMDirectory::new()),
+        )
+    }
+
+    fn write_file<D: Directory>(dir: &D, name: &str) {
+        let mut out = dir.create_output(name, &IOContext::Default).unwrap();
+        out.write_int(1).unwrap();
+    }
+
+    #[test]
+    fn test_file_switch_directory() {
+        let dir = new_dir();
+        for name in &["_0.doc", "_0.fdt", "_0_Lucene54_0.dvd", "segments_1"] {
+            write_file(&dir, name);
+        }
+
+        assert_eq!(
+            dir.primary_dir().list_all().unwrap(),
+            vec!["_0.doc".to_string(), "_0_Lucene54_0.dvd".to_string()]
+        );
+        assert_eq!(
+            dir.secondary_dir().list_all().unwrap(),
+            vec!["_0.fdt".to_string(), "segments_1".to_string()]
+        );
+        assert_eq!(dir.list_all().unwrap().len(), 4);
+        assert_eq!(dir.file_length("_0.fdt").unwrap(), 4);
+        let mut input = dir.open_input("_0.doc", &IOContext::READ).unwrap();
+        assert_eq!(input.read_int().unwrap(), 1);
+
+        let names = ["_0.doc", "_0.fdt"].iter().map(|n| n.to_string()).collect();
+        dir.sync(&names).unwrap();
+
+        dir.rename("_0.fdt", "_1.fdt").unwrap();
+        assert!(dir.secondary_dir().file_length("_1.fdt").is_ok());
+        assert!(dir.rename("_0.doc", "_1.fdt").is_err());
+
+        dir.delete_file("_0.doc").unwrap();
+        assert_eq!(
+            dir.list_all().unwrap(),
+            vec![
+                "_0_Lucene54_0.dvd".to_string(),
+                "_1.fdt".to_string(),
+                "segments_1".to_string()
+            ]
+        );
+    }
+}
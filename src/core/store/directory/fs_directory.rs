@@ -16,10 +16,10 @@ use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
 use core::codec::segment_infos::segment_file_name;
-use core::store::directory::Directory;
+use core::store::directory::{Directory, Lock, LockFactory, NativeFSLockFactory};
 use core::store::io::{FSIndexOutput, IndexInput, MmapIndexInput};
 use core::store::IOContext;
 use core::util::to_base36;
@@ -30,17 +30,30 @@ use error::Result;
 ///
 /// However, it has poor concurrent performance (multiple threads will bottleneck)
 /// as it synchronizes when multiple threads read from the same file.
+///
+/// Its locks are obtained from a `NativeFSLockFactory` unless another
+/// factory is given by `with_lock_factory`.
 pub struct FSDirectory {
     pub directory: PathBuf,
     pending_deletes: RwLock<BTreeSet<String>>,
     pub ops_since_last_delete: AtomicUsize,
     pub next_temp_file_counter: AtomicUsize,
+    lock_factory: Arc<dyn LockFactory>,
 }
 
 impl FSDirectory {
     pub fn with_path<T: AsRef<Path> + ?Sized>(directory: &T) -> Result<Self> {
         Self::new(directory)
     }
+
+    pub fn with_lock_factory<T: AsRef<Path> + ?Sized>(
+        directory: &T,
+        lock_factory: Arc<dyn LockFactory>,
+    ) -> Result<Self> {
+        let mut dir = Self::new(directory)?;
+        dir.lock_factory = lock_factory;
+        Ok(dir)
+    }
 }
 
 impl FSDirectory {
@@ -60,6 +73,7 @@ impl FSDirectory {
             pending_deletes: RwLock::new(BTreeSet::new()),
             ops_since_last_delete: AtomicUsize::new(0),
             next_temp_file_counter: AtomicUsize::new(0),
+            lock_factory: Arc::new(NativeFSLockFactory),
         })
     }
 
@@ -235,6 +249,10 @@ impl Directory for FSDirectory {
         self.maybe_delete_pending_files()
     }
 
+    fn obtain_lock(&self, name: &str) -> Result<Box<dyn Lock>> {
+        self.lock_factory.obtain_lock(&self.directory, name)
+    }
+
     fn resolve(&self, name: &str) -> PathBuf {
         self.directory.join(name)
     }
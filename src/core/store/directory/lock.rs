@@ -0,0 +1,454 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use error::ErrorKind::{AlreadyClosed, LockObtainFailed};
+use error::Result;
+
+/// Name of the lock held by an `IndexWriter` on its directory.
+pub const WRITE_LOCK_NAME: &str = "write.lock";
+
+/// An interprocess mutex lock, obtained by `Directory::obtain_lock`.
+///
+/// The lock is released by `close` or when it is dropped.
+pub trait Lock: Send + Sync {
+    /// Releases the lock, further `ensure_valid` calls fail.
+    fn close(&self) -> Result<()>;
+
+    /// Best effort check that this lock is still valid, e.g. that it wasn't
+    /// released or that its lock file wasn't deleted by an external force.
+    /// Call this before any destructive operation protected by the lock.
+    fn ensure_valid(&self) -> Result<()>;
+}
+
+/// Creates the `Lock`s of a directory.
+///
+/// `dir` is the path of the locked directory, it's ignored by the factories
+/// that don't lock files.
+pub trait LockFactory: Send + Sync {
+    /// Obtains the lock `lock_name`, failing with `LockObtainFailed` if it's
+    /// already held.
+    fn obtain_lock(&self, dir: &Path, lock_name: &str) -> Result<Box<dyn Lock>>;
+}
+
+lazy_static! {
+    // lock files held by this process, `flock` alone can't be trusted to
+    // detect them on every platform
+    static ref LOCK_HELD: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+/// A `LockFactory` using the native OS file locks (`flock`), the default
+/// of `FSDirectory`.
+///
+/// The locks are released by the OS when the process exits, even if it
+/// crashes. The lock file itself is left in the directory, this is harmless.
+///
+/// *NOTE*: the OS locks may not work on network file systems.
+#[derive(Default)]
+pub struct NativeFSLockFactory;
+
+impl LockFactory for NativeFSLockFactory {
+    fn obtain_lock(&self, dir: &Path, lock_name: &str) -> Result<Box<dyn Lock>> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(lock_name);
+        // the file may already exist, it's fine
+        OpenOptions::new().write(true).create(true).open(&path)?;
+        let real_path = path.canonicalize()?;
+        let modified = fs::metadata(&real_path)?.modified()?;
+
+        if !LOCK_HELD.lock()?.insert(real_path.clone()) {
+            bail!(LockObtainFailed(format!(
+                "lock held by this process: {}",
+                real_path.display()
+            )));
+        }
+        let file = match OpenOptions::new()
+            .write(true)
+            .open(&real_path)
+            .and_then(|file| try_lock_file(&file).map(|locked| (file, locked)))
+        {
+            Ok((file, true)) => file,
+            Ok((_, false)) => {
+                LOCK_HELD.lock()?.remove(&real_path);
+                bail!(LockObtainFailed(format!(
+                    "lock held by another program: {}",
+                    real_path.display()
+                )));
+            }
+            Err(e) => {
+                LOCK_HELD.lock()?.remove(&real_path);
+                return Err(e.into());
+            }
+        };
+
+        Ok(Box::new(NativeFSLock {
+            real_path,
+            file: Mutex::new(Some(file)),
+            modified,
+        }))
+    }
+}
+
+#[cfg(unix)]
+fn try_lock_file(file: &File) -> io::Result<bool> {
+    use libc;
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock_file(_file: &File) -> io::Result<bool> {
+    // only `LOCK_HELD` protects the lock
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn unlock_file(file: &File) -> io::Result<()> {
+    use libc;
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn unlock_file(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+struct NativeFSLock {
+    real_path: PathBuf,
+    // `None` once released
+    file: Mutex<Option<File>>,
+    modified: SystemTime,
+}
+
+impl Lock for NativeFSLock {
+    fn close(&self) -> Result<()> {
+        if let Some(file) = self.file.lock()?.take() {
+            LOCK_HELD.lock()?.remove(&self.real_path);
+            unlock_file(&file)?;
+        }
+        Ok(())
+    }
+
+    fn ensure_valid(&self) -> Result<()> {
+        if self.file.lock()?.is_none() {
+            bail!(AlreadyClosed(format!(
+                "lock instance already released: {}",
+                self.real_path.display()
+            )));
+        }
+        if !LOCK_HELD.lock()?.contains(&self.real_path) {
+            bail!(AlreadyClosed(format!(
+                "lock path unexpectedly cleared from map: {}",
+                self.real_path.display()
+            )));
+        }
+        let modified = fs::metadata(&self.real_path)?.modified()?;
+        if modified != self.modified {
+            bail!(AlreadyClosed(format!(
+                "underlying file changed by an external force: {}",
+                self.real_path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for NativeFSLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            warn!("release lock {} failed: {:?}", self.real_path.display(), e);
+        }
+    }
+}
+
+/// A `LockFactory` creating a lock file, which is deleted when the lock is
+/// released.
+///
+/// The lock file is left behind if the process crashes, it must then be
+/// removed manually before the directory can be locked again. Prefer
+/// `NativeFSLockFactory` unless the file system lacks native locks.
+#[derive(Default)]
+pub struct SimpleFSLockFactory;
+
+impl LockFactory for SimpleFSLockFactory {
+    fn obtain_lock(&self, dir: &Path, lock_name: &str) -> Result<Box<dyn Lock>> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(lock_name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                bail!(LockObtainFailed(format!(
+                    "lock held elsewhere: {}",
+                    path.display()
+                )));
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
+        let modified = fs::metadata(&path)?.modified()?;
+        Ok(Box::new(SimpleFSLock {
+            path,
+            modified,
+            closed: Mutex::new(false),
+        }))
+    }
+}
+
+struct SimpleFSLock {
+    path: PathBuf,
+    modified: SystemTime,
+    closed: Mutex<bool>,
+}
+
+impl Lock for SimpleFSLock {
+    fn close(&self) -> Result<()> {
+        let mut closed = self.closed.lock()?;
+        if !*closed {
+            *closed = true;
+            // only delete the file if it's still ours
+            if fs::metadata(&self.path)?.modified()? != self.modified {
+                bail!(AlreadyClosed(format!(
+                    "lock file changed by an external force: {}",
+                    self.path.display()
+                )));
+            }
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    fn ensure_valid(&self) -> Result<()> {
+        if *self.closed.lock()? {
+            bail!(AlreadyClosed(format!(
+                "lock instance already released: {}",
+                self.path.display()
+            )));
+        }
+        let modified = fs::metadata(&self.path)?.modified()?;
+        if modified != self.modified {
+            bail!(AlreadyClosed(format!(
+                "underlying file changed by an external force: {}",
+                self.path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SimpleFSLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            warn!("release lock {} failed: {:?}", self.path.display(), e);
+        }
+    }
+}
+
+/// A `LockFactory` for a single directory instance, locks are only held in
+/// memory. It's the default of the in memory directories, and can be used
+/// for a file system directory if all the writers of the process share the
+/// same directory instance and no other process writes it.
+#[derive(Default)]
+pub struct SingleInstanceLockFactory {
+    locks: Arc<Mutex<HashSet<String>>>,
+}
+
+impl LockFactory for SingleInstanceLockFactory {
+    fn obtain_lock(&self, _dir: &Path, lock_name: &str) -> Result<Box<dyn Lock>> {
+        if !self.locks.lock()?.insert(lock_name.to_string()) {
+            bail!(LockObtainFailed(format!(
+                "lock instance already obtained: {}",
+                lock_name
+            )));
+        }
+        Ok(Box::new(SingleInstanceLock {
+            locks: Arc::clone(&self.locks),
+            lock_name: lock_name.to_string(),
+            closed: Mutex::new(false),
+        }))
+    }
+}
+
+struct SingleInstanceLock {
+    locks: Arc<Mutex<HashSet<String>>>,
+    lock_name: String,
+    closed: Mutex<bool>,
+}
+
+impl Lock for SingleInstanceLock {
+    fn close(&self) -> Result<()> {
+        let mut closed = self.closed.lock()?;
+        if !*closed {
+            *closed = true;
+            self.locks.lock()?.remove(&self.lock_name);
+        }
+        Ok(())
+    }
+
+    fn ensure_valid(&self) -> Result<()> {
+        if *self.closed.lock()? {
+            bail!(AlreadyClosed(format!(
+                "lock instance already released: {}",
+                self.lock_name
+            )));
+        }
+        if !self.locks.lock()?.contains(&self.lock_name) {
+            bail!(AlreadyClosed(format!(
+                "lock instance was invalidated from map: {}",
+                self.lock_name
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SingleInstanceLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            warn!("release lock {} failed: {:?}", self.lock_name, e);
+        }
+    }
+}
+
+/// A `LockFactory` that doesn't lock anything.
+///
+/// *WARNING*: only use it if you are sure no two writers ever write the
+/// same directory, else the index gets corrupted.
+#[derive(Default)]
+pub struct NoLockFactory;
+
+impl LockFactory for NoLockFactory {
+    fn obtain_lock(&self, _dir: &Path, _lock_name: &str) -> Result<Box<dyn Lock>> {
+        Ok(Box::new(NoLock))
+    }
+}
+
+struct NoLock;
+
+impl Lock for NoLock {
+    fn close(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn ensure_valid(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+    use error::{Error, ErrorKind};
+
+    fn is_obtain_failed(res: Result<Box<dyn Lock>>) -> bool {
+        match res {
+            Err(Error(ErrorKind::LockObtainFailed(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    fn check_factory(factory: &dyn LockFactory, dir: &Path) {
+        let lock = factory.obtain_lock(dir, WRITE_LOCK_NAME).unwrap();
+        lock.ensure_valid().unwrap();
+        assert!(is_obtain_failed(factory.obtain_lock(dir, WRITE_LOCK_NAME)));
+        // other locks are independent
+        let other = factory.obtain_lock(dir, "other.lock").unwrap();
+        other.close().unwrap();
+
+        lock.close().unwrap();
+        assert!(lock.ensure_valid().is_err());
+        let lock = factory.obtain_lock(dir, WRITE_LOCK_NAME).unwrap();
+        drop(lock);
+        factory.obtain_lock(dir, WRITE_LOCK_NAME).unwrap();
+    }
+
+    #[test]
+    fn test_native_fs_lock_factory() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        check_factory(&NativeFSLockFactory, temp_dir.path());
+    }
+
+    #[test]
+    fn test_simple_fs_lock_factory() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        check_factory(&SimpleFSLockFactory, dir);
+        // the lock file is the lock
+        let lock = SimpleFSLockFactory.obtain_lock(dir, "a.lock").unwrap();
+        fs::remove_file(dir.join("a.lock")).unwrap();
+        assert!(lock.ensure_valid().is_err());
+    }
+
+    #[test]
+    fn test_single_instance_lock_factory() {
+        let factory = SingleInstanceLockFactory::default();
+        check_factory(&factory, Path::new(""));
+        // another instance doesn't see the locks
+        let _lock = factory.obtain_lock(Path::new(""), WRITE_LOCK_NAME).unwrap();
+        SingleInstanceLockFactory::default()
+            .obtain_lock(Path::new(""), WRITE_LOCK_NAME)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_index_writer_write_lock() {
+        use core::index::writer::{IndexWriter, IndexWriterConfig};
+        use core::store::directory::FSDirectory;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config = Arc::new(IndexWriterConfig::default());
+        let directory = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let writer = IndexWriter::new(Arc::clone(&directory), Arc::clone(&config)).unwrap();
+        match IndexWriter::new(Arc::clone(&directory), Arc::clone(&config)) {
+            Err(Error(ErrorKind::LockObtainFailed(_), _)) => {}
+            _ => panic!("a second writer must not obtain the write lock"),
+        }
+
+        // closing the writer releases the lock
+        writer.close().unwrap();
+        IndexWriter::new(directory, config).unwrap();
+    }
+
+    #[test]
+    fn test_no_lock_factory() {
+        let _lock = NoLockFactory
+            .obtain_lock(Path::new(""), WRITE_LOCK_NAME)
+            .unwrap();
+        NoLockFactory
+            .obtain_lock(Path::new(""), WRITE_LOCK_NAME)
+            .unwrap();
+    }
+}
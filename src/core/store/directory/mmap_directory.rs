@@ -19,7 +19,7 @@ use std::sync::{Arc, Mutex, Weak};
 
 use memmap::Mmap;
 
-use core::store::directory::{Directory, FSDirectory, FilterDirectory};
+use core::store::directory::{Directory, FSDirectory, FilterDirectory, LockFactory};
 use core::store::io::{FSIndexOutput, IndexInput, MmapIndexInput, ReadOnlySource};
 use core::store::IOContext;
 use error::Result;
@@ -153,12 +153,23 @@ pub struct MmapDirectory {
 
 impl MmapDirectory {
     pub fn new<T: AsRef<Path>>(directory: &T) -> Result<MmapDirectory> {
-        let directory = FSDirectory::new(directory)?;
-        Ok(MmapDirectory {
+        Ok(Self::with_fs_directory(FSDirectory::new(directory)?))
+    }
+
+    pub fn with_lock_factory<T: AsRef<Path>>(
+        directory: &T,
+        lock_factory: Arc<dyn LockFactory>,
+    ) -> Result<MmapDirectory> {
+        let directory = FSDirectory::with_lock_factory(directory, lock_factory)?;
+        Ok(Self::with_fs_directory(directory))
+    }
+
+    fn with_fs_directory(directory: FSDirectory) -> MmapDirectory {
+        MmapDirectory {
             directory,
             preload: false,
             mmap_cache: Arc::new(Mutex::new(MmapCache::default())),
-        })
+        }
     }
 }
 
@@ -15,14 +15,30 @@ mod directory;
 
 pub use self::directory::*;
 
+mod file_switch_directory;
+
+pub use self::file_switch_directory::*;
+
 mod fs_directory;
 
 pub use self::fs_directory::*;
 
+mod lock;
+
+pub use self::lock::*;
+
 mod mmap_directory;
 
 pub use self::mmap_directory::*;
 
+mod nrt_caching_directory;
+
+pub use self::nrt_caching_directory::*;
+
+mod ram_directory;
+
+pub use self::ram_directory::*;
+
 mod tracking_directory_wrapper;
 
 pub use self::tracking_directory_wrapper::*;
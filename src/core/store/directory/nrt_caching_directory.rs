@@ -0,0 +1,405 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use core::store::directory::{Directory, FilterDirectory, Lock, RAMDirectory, RAMIndexOutput};
+use core::store::io::{DataOutput, IndexInput, IndexOutput};
+use core::store::IOContext;
+use error::ErrorKind::IllegalState;
+use error::Result;
+
+const MB: f64 = 1024.0 * 1024.0;
+
+/// Wraps a `RAMDirectory` around any provided delegate directory, to be used
+/// during near-real-time search.
+///
+/// Small files written by a flush, as decided from the `FlushInfo` of the
+/// `IOContext`, are kept in memory as long as the cache stays within
+/// `max_cached_mb`. They are written through to the delegate when they are
+/// synced or renamed, e.g. on commit, and files written by merges always go
+/// to the delegate. Since a segment is usually flushed, searched and then
+/// merged away before the next commit, most of its files never hit the disk.
+pub struct NRTCachingDirectory<D: Directory> {
+    directory: Arc<D>,
+    cache: Arc<RAMDirectory>,
+    max_flush_size_bytes: u64,
+    max_cached_bytes: u64,
+    uncache_lock: Mutex<()>,
+}
+
+impl<D: Directory> NRTCachingDirectory<D> {
+    /// Files of a flush estimated at more than `max_flush_size_mb` are never
+    /// cached, and no file is cached once the cache holds `max_cached_mb`.
+    pub fn new(directory: Arc<D>, max_flush_size_mb: f64, max_cached_mb: f64) -> Self {
+        NRTCachingDirectory {
+            directory,
+            cache: Arc::new(RAMDirectory::new()),
+            max_flush_size_bytes: (max_flush_size_mb * MB) as u64,
+            max_cached_bytes: (max_cached_mb * MB) as u64,
+            uncache_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn delegate(&self) -> &Arc<D> {
+        &self.directory
+    }
+
+    /// Returns the names of the files currently held in memory.
+    pub fn list_cached_files(&self) -> Result<Vec<String>> {
+        self.cache.list_all()
+    }
+
+    /// Returns the number of bytes held in memory.
+    pub fn cached_bytes(&self) -> Result<usize> {
+        self.cache.ram_bytes_used()
+    }
+
+    fn do_cache_write(&self, ctx: &IOContext) -> Result<bool> {
+        let bytes = match ctx {
+            IOContext::Merge(_) => {
+                return Ok(false);
+            }
+            IOContext::Flush(info) => info.estimated_segment_size(),
+            _ => 0,
+        };
+        Ok(bytes <= self.max_flush_size_bytes
+            && bytes + self.cache.ram_bytes_used()? as u64 <= self.max_cached_bytes)
+    }
+
+    /// Moves the file from the cache to the delegate, if it is cached.
+    fn uncache(&self, name: &str) -> Result<()> {
+        // only one thread may copy a given file
+        let _guard = self.uncache_lock.lock()?;
+        if !self.cache.file_exists(name)? {
+            return Ok(());
+        }
+        self.directory
+            .copy_from(Arc::clone(&self.cache), name, name, &IOContext::Default)?;
+        self.cache.delete_file(name)
+    }
+}
+
+impl<D: Directory> FilterDirectory for NRTCachingDirectory<D> {
+    type Dir = D;
+
+    #[inline]
+    fn dir(&self) -> &Self::Dir {
+        &*self.directory
+    }
+}
+
+impl<D: Directory> Directory for NRTCachingDirectory<D> {
+    type IndexOutput = NRTCachingOutput<D::IndexOutput>;
+    type TempOutput = NRTCachingOutput<D::TempOutput>;
+
+    fn list_all(&self) -> Result<Vec<String>> {
+        let mut files: HashSet<String> = self.cache.list_all()?.into_iter().collect();
+        for name in self.directory.list_all()? {
+            if !files.insert(name.clone()) {
+                bail!(IllegalState(format!(
+                    "file {} appears both in delegate and in cache",
+                    name
+                )));
+            }
+        }
+        let mut files: Vec<String> = files.into_iter().collect();
+        files.sort();
+        Ok(files)
+    }
+
+    fn file_length(&self, name: &str) -> Result<i64> {
+        if self.cache.file_exists(name)? {
+            self.cache.file_length(name)
+        } else {
+            self.directory.file_length(name)
+        }
+    }
+
+    fn create_output(&self, name: &str, ctx: &IOContext) -> Result<Self::IndexOutput> {
+        if self.do_cache_write(ctx)? {
+            Ok(NRTCachingOutput::Cached(
+                self.cache.create_output(name, ctx)?,
+            ))
+        } else {
+            if self.cache.file_exists(name)? {
+                self.cache.delete_file(name)?;
+            }
+            Ok(NRTCachingOutput::Delegate(
+                self.directory.create_output(name, ctx)?,
+            ))
+        }
+    }
+
+    fn open_input(&self, name: &str, ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+        if self.cache.file_exists(name)? {
+            self.cache.open_input(name, ctx)
+        } else {
+            self.directory.open_input(name, ctx)
+        }
+    }
+
+    fn create_temp_output(
+        &self,
+        prefix: &str,
+        suffix: &str,
+        ctx: &IOContext,
+    ) -> Result<Self::TempOutput> {
+        if !self.do_cache_write(ctx)? {
+            return Ok(NRTCachingOutput::Delegate(
+                self.directory.create_temp_output(prefix, suffix, ctx)?,
+            ));
+        }
+
+        // the cache names its temp files on its own, so skip the names the
+        // delegate already holds
+        let mut collisions = vec![];
+        let output = loop {
+            let output = self.cache.create_temp_output(prefix, suffix, ctx)?;
+            if self.directory.file_length(output.name()).is_ok() {
+                collisions.push(output.name().to_string());
+                continue;
+            }
+            break output;
+        };
+        for name in &collisions {
+            self.cache.delete_file(name)?;
+        }
+        Ok(NRTCachingOutput::Cached(output))
+    }
+
+    fn delete_file(&self, name: &str) -> Result<()> {
+        if self.cache.file_exists(name)? {
+            self.cache.delete_file(name)
+        } else {
+            self.directory.delete_file(name)
+        }
+    }
+
+    fn sync(&self, names: &HashSet<String>) -> Result<()> {
+        for name in names {
+            self.uncache(name)?;
+        }
+        self.directory.sync(names)
+    }
+
+    fn sync_meta_data(&self) -> Result<()> {
+        self.directory.sync_meta_data()
+    }
+
+    fn rename(&self, source: &str, dest: &str) -> Result<()> {
+        self.uncache(source)?;
+        if self.cache.file_exists(dest)? {
+            self.cache.delete_file(dest)?;
+        }
+        self.directory.rename(source, dest)
+    }
+
+    fn obtain_lock(&self, name: &str) -> Result<Box<dyn Lock>> {
+        self.directory.obtain_lock(name)
+    }
+}
+
+impl<D: Directory> fmt::Display for NRTCachingDirectory<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "NRTCachingDirectory({}; max_cache_mb={}, max_flush_size_mb={})",
+            self.directory,
+            self.max_cached_bytes as f64 / MB,
+            self.max_flush_size_bytes as f64 / MB
+        )
+    }
+}
+
+/// `IndexOutput` of `NRTCachingDirectory`, writing either to the cache or
+/// to the delegate directory.
+pub enum NRTCachingOutput<O: IndexOutput> {
+    Cached(RAMIndexOutput),
+    Delegate(O),
+}
+
+impl<O: IndexOutput> DataOutput for NRTCachingOutput<O> {}
+
+impl<O: IndexOutput> Write for NRTCachingOutput<O> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            NRTCachingOutput::Cached(o) => o.write(buf),
+            NRTCachingOutput::Delegate(o) => o.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            NRTCachingOutput::Cached(o) => o.flush(),
+            NRTCachingOutput::Delegate(o) => o.flush(),
+        }
+    }
+}
+
+impl<O: IndexOutput> IndexOutput for NRTCachingOutput<O> {
+    fn name(&self) -> &str {
+        match self {
+            NRTCachingOutput::Cached(o) => o.name(),
+            NRTCachingOutput::Delegate(o) => o.name(),
+        }
+    }
+
+    fn file_pointer(&self) -> i64 {
+        match self {
+            NRTCachingOutput::Cached(o) => o.file_pointer(),
+            NRTCachingOutput::Delegate(o) => o.file_pointer(),
+        }
+    }
+
+    fn checksum(&self) -> Result<i64> {
+        match self {
+            NRTCachingOutput::Cached(o) => o.checksum(),
+            NRTCachingOutput::Delegate(o) => o.checksum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+    use core::store::directory::FSDirectory;
+    use core::store::{FlushInfo, MergeInfo};
+
+    fn write_file<D: Directory>(dir: &D, name: &str, len: usize, ctx: &IOContext) {
+        let mut out = dir.create_output(name, ctx).unwrap();
+        out.write_bytes(&vec![7u8; len], 0, len).unwrap();
+    }
+
+    #[test]
+    fn test_nrt_caching_directory() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let fs_dir = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let dir = NRTCachingDirectory::new(Arc::clone(&fs_dir), 1.0, 2.0);
+
+        let flush = IOContext::Flush(FlushInfo::with_segment_size(10, 1024));
+        write_file(&dir, "_0.fdt", 1024, &flush);
+        assert_eq!(dir.list_cached_files().unwrap(), vec!["_0.fdt".to_string()]);
+        assert!(fs_dir.list_all().unwrap().is_empty());
+        assert_eq!(dir.file_length("_0.fdt").unwrap(), 1024);
+        let mut input = dir.open_input("_0.fdt", &IOContext::READ).unwrap();
+        assert_eq!(input.read_byte().unwrap(), 7u8);
+
+        // merges and large flushes bypass the cache
+        let merge = IOContext::Merge(MergeInfo::new(10, 1024, false, None));
+        write_file(&dir, "_1.fdt", 1024, &merge);
+        let large = IOContext::Flush(FlushInfo::with_segment_size(10, 4 * MB as u64));
+        write_file(&dir, "_2.fdt", 1024, &large);
+        assert_eq!(dir.list_cached_files().unwrap().len(), 1);
+        assert_eq!(
+            dir.list_all().unwrap(),
+            vec![
+                "_0.fdt".to_string(),
+                "_1.fdt".to_string(),
+                "_2.fdt".to_string()
+            ]
+        );
+
+        // syncing writes the cached file through to the delegate
+        let mut names = HashSet::new();
+        names.insert("_0.fdt".to_string());
+        dir.sync(&names).unwrap();
+        assert!(dir.list_cached_files().unwrap().is_empty());
+        assert_eq!(dir.cached_bytes().unwrap(), 0);
+        assert_eq!(fs_dir.file_length("_0.fdt").unwrap(), 1024);
+
+        write_file(&dir, "pending_segments_1", 16, &IOContext::Default);
+        dir.rename("pending_segments_1", "segments_1").unwrap();
+        assert!(dir.list_cached_files().unwrap().is_empty());
+        assert_eq!(fs_dir.file_length("segments_1").unwrap(), 16);
+
+        write_file(&dir, "_3.fdt", 16, &flush);
+        dir.delete_file("_3.fdt").unwrap();
+        assert!(dir.list_cached_files().unwrap().is_empty());
+        assert!(dir.delete_file("_3.fdt").is_err());
+    }
+
+    #[test]
+    fn test_nrt_caching_directory_index_writer() {
+        use core::doc::{Field, FieldType, Fieldable, IndexOptions};
+        use core::index::reader::IndexReader;
+        use core::index::writer::{IndexWriter, IndexWriterConfig};
+        use core::util::VariantValue;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let fs_dir = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        let dir = Arc::new(NRTCachingDirectory::new(Arc::clone(&fs_dir), 5.0, 60.0));
+        let config = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(Arc::clone(&dir), config).unwrap();
+
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        for id in &["1", "2", "3"] {
+            let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(Field::new(
+                "id".into(),
+                field_type.clone(),
+                Some(VariantValue::VString(id.to_string())),
+                None,
+            ))];
+            writer.add_document(doc).unwrap();
+        }
+
+        // the near-real-time segment lives in memory until the commit
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.num_docs(), 3);
+        assert!(!dir.list_cached_files().unwrap().is_empty());
+
+        writer.commit().unwrap();
+        assert!(dir.list_cached_files().unwrap().is_empty());
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_nrt_caching_directory_large_flush() {
+        use core::doc::{Field, FieldType, Fieldable, IndexOptions};
+        use core::index::reader::IndexReader;
+        use core::index::writer::{IndexWriter, IndexWriterConfig};
+        use core::util::VariantValue;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let fs_dir = Arc::new(FSDirectory::with_path(temp_dir.path()).unwrap());
+        // the buffered postings of a single document exceed the 1KB flush budget
+        let dir = Arc::new(NRTCachingDirectory::new(Arc::clone(&fs_dir), 0.001, 60.0));
+        let config = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(Arc::clone(&dir), config).unwrap();
+
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(Field::new(
+            "id".into(),
+            field_type,
+            Some(VariantValue::VString("1".into())),
+            None,
+        ))];
+        writer.add_document(doc).unwrap();
+
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.num_docs(), 1);
+        let is_postings = |name: &String| name.ends_with(".doc");
+        assert!(!dir.list_cached_files().unwrap().iter().any(is_postings));
+        assert!(fs_dir.list_all().unwrap().iter().any(is_postings));
+        writer.close().unwrap();
+    }
+}
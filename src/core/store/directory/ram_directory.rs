@@ -0,0 +1,461 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use core::codec::segment_infos::segment_file_name;
+use core::store::directory::{Directory, Lock, LockFactory, SingleInstanceLockFactory};
+use core::store::io::{DataOutput, IndexInput, IndexOutput, RAMIndexInput};
+use core::store::IOContext;
+use core::util::to_base36;
+use error::Result;
+
+use flate2::Crc;
+
+struct RAMFile {
+    // tells apart the files successively created under the same name
+    id: usize,
+    data: Arc<Vec<u8>>,
+}
+
+type FileMap = Arc<RwLock<HashMap<String, RAMFile>>>;
+
+/// A heap-backed `Directory` implementation.
+///
+/// Files are written through `RAMIndexOutput`s and become readable once the
+/// output is flushed or dropped. Inputs share the written bytes, so
+/// opening, cloning and slicing a file never copies it. It is meant for
+/// small, short-lived indexes, e.g. tests or per-request indexes, whose
+/// whole content fits in memory.
+///
+/// Its locks are obtained from a `SingleInstanceLockFactory` unless another
+/// factory is given by `with_lock_factory`.
+pub struct RAMDirectory {
+    files: FileMap,
+    next_file_id: AtomicUsize,
+    pub next_temp_file_counter: AtomicUsize,
+    lock_factory: Arc<dyn LockFactory>,
+}
+
+impl Default for RAMDirectory {
+    fn default() -> Self {
+        Self::with_lock_factory(Arc::new(SingleInstanceLockFactory::default()))
+    }
+}
+
+impl RAMDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_lock_factory(lock_factory: Arc<dyn LockFactory>) -> Self {
+        RAMDirectory {
+            files: Arc::new(RwLock::new(HashMap::new())),
+            next_file_id: AtomicUsize::new(0),
+            next_temp_file_counter: AtomicUsize::new(0),
+            lock_factory,
+        }
+    }
+
+    /// Returns the number of bytes held by all the files of this directory.
+    pub fn ram_bytes_used(&self) -> Result<usize> {
+        Ok(self.files.read()?.values().map(|f| f.data.len()).sum())
+    }
+
+    pub fn file_exists(&self, name: &str) -> Result<bool> {
+        Ok(self.files.read()?.contains_key(name))
+    }
+
+    fn file(&self, name: &str) -> Result<Arc<Vec<u8>>> {
+        match self.files.read()?.get(name) {
+            Some(file) => Ok(Arc::clone(&file.data)),
+            None => bail!(no_such_file(name)),
+        }
+    }
+
+    fn new_output(&self, name: String) -> Result<RAMIndexOutput> {
+        let id = self.next_file_id.fetch_add(1, Ordering::AcqRel);
+        let file = RAMFile {
+            id,
+            data: Arc::new(Vec::new()),
+        };
+        self.files.write()?.insert(name.clone(), file);
+        Ok(RAMIndexOutput::new(name, id, Arc::clone(&self.files)))
+    }
+}
+
+fn no_such_file(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("file not found: {}", name))
+}
+
+impl Directory for RAMDirectory {
+    type IndexOutput = RAMIndexOutput;
+    type TempOutput = RAMIndexOutput;
+
+    fn list_all(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self.files.read()?.keys().cloned().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn file_length(&self, name: &str) -> Result<i64> {
+        Ok(self.file(name)?.len() as i64)
+    }
+
+    fn create_output(&self, name: &str, _context: &IOContext) -> Result<Self::IndexOutput> {
+        self.new_output(name.to_string())
+    }
+
+    fn open_input(&self, name: &str, _ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+        let file = self.file(name)?;
+        Ok(Box::new(RAMIndexInput::new(name.to_string(), file)))
+    }
+
+    fn create_temp_output(
+        &self,
+        prefix: &str,
+        suffix: &str,
+        _ctx: &IOContext,
+    ) -> Result<Self::TempOutput> {
+        loop {
+            let name = segment_file_name(
+                prefix,
+                &format!(
+                    "{}_{}",
+                    suffix,
+                    to_base36(self.next_temp_file_counter.fetch_add(1, Ordering::AcqRel) as u64)
+                ),
+                "tmp",
+            );
+
+            if self.files.read()?.contains_key(&name) {
+                continue;
+            }
+
+            return self.new_output(name);
+        }
+    }
+
+    fn delete_file(&self, name: &str) -> Result<()> {
+        if self.files.write()?.remove(name).is_none() {
+            bail!(no_such_file(name));
+        }
+        Ok(())
+    }
+
+    fn sync(&self, names: &HashSet<String>) -> Result<()> {
+        // nothing to make durable, but syncing a missing file is still an error
+        let files = self.files.read()?;
+        for name in names {
+            if !files.contains_key(name) {
+                bail!(no_such_file(name));
+            }
+        }
+        Ok(())
+    }
+
+    fn sync_meta_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn rename(&self, source: &str, dest: &str) -> Result<()> {
+        let mut files = self.files.write()?;
+        match files.remove(source) {
+            Some(file) => {
+                files.insert(dest.to_string(), file);
+                Ok(())
+            }
+            None => bail!(no_such_file(source)),
+        }
+    }
+
+    fn obtain_lock(&self, name: &str) -> Result<Box<dyn Lock>> {
+        self.lock_factory.obtain_lock(Path::new(""), name)
+    }
+}
+
+impl fmt::Display for RAMDirectory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RAMDirectory@{:p}", &*self.files)
+    }
+}
+
+/// `IndexOutput` of `RAMDirectory`.
+///
+/// The bytes written since the last publication are buffered and appended to
+/// the directory's file on `flush` and when the output is dropped. The file is
+/// extended in place, unless an input opened before still reads it.
+pub struct RAMIndexOutput {
+    name: String,
+    id: usize,
+    pending: Vec<u8>,
+    file_pointer: usize,
+    crc: Crc,
+    files: FileMap,
+}
+
+impl RAMIndexOutput {
+    fn new(name: String, id: usize, files: FileMap) -> RAMIndexOutput {
+        RAMIndexOutput {
+            name,
+            id,
+            pending: Vec::new(),
+            file_pointer: 0,
+            crc: Crc::new(),
+            files,
+        }
+    }
+
+    fn publish(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut files = self.files.write()?;
+        // the file may have been deleted, renamed or created again while being written
+        let id = self.id;
+        if let Some(file) = files.get_mut(&self.name).filter(|f| f.id == id) {
+            let data = Arc::make_mut(&mut file.data);
+            if data.is_empty() {
+                mem::swap(data, &mut self.pending);
+            } else {
+                data.extend_from_slice(&self.pending);
+            }
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl Drop for RAMIndexOutput {
+    fn drop(&mut self) {
+        if let Err(ref e) = self.publish() {
+            error!("Oops, failed to flush {}, errmsg: {:?}", self.name, e);
+        }
+    }
+}
+
+impl DataOutput for RAMIndexOutput {}
+
+impl Write for RAMIndexOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.crc.update(buf);
+        self.file_pointer += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.publish()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+}
+
+impl IndexOutput for RAMIndexOutput {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn file_pointer(&self) -> i64 {
+        self.file_pointer as i64
+    }
+
+    fn checksum(&self) -> Result<i64> {
+        Ok((self.crc.sum() as i64) & 0xffff_ffffi64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::directory::WRITE_LOCK_NAME;
+    use error::{Error, ErrorKind};
+
+    fn write_file(dir: &RAMDirectory, name: &str) {
+        let mut out = dir.create_output(name, &IOContext::Default).unwrap();
+        out.write_byte(b'a').unwrap();
+        out.write_short(0x7F_i16).unwrap();
+        out.write_long(567_890).unwrap();
+        out.write_int(1_234_567).unwrap();
+        out.write_byte(b'b').unwrap();
+        assert_eq!(out.file_pointer(), 16);
+    }
+
+    fn is_not_found<T>(res: Result<T>) -> bool {
+        match res {
+            Err(Error(ErrorKind::IoError(ref e), _)) => e.kind() == io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_ram_directory_read_write() {
+        let dir = RAMDirectory::new();
+        write_file(&dir, "test.bin");
+        assert_eq!(dir.list_all().unwrap(), vec!["test.bin".to_string()]);
+        assert_eq!(dir.file_length("test.bin").unwrap(), 16);
+        assert_eq!(dir.ram_bytes_used().unwrap(), 16);
+
+        let mut input = dir.open_input("test.bin", &IOContext::Default).unwrap();
+        assert_eq!(input.len(), 16);
+        assert_eq!(input.read_byte().unwrap(), b'a');
+        assert_eq!(input.read_short().unwrap(), 0x7F_i16);
+
+        let mut slice = input.slice("from3", 3, 13).unwrap();
+        assert_eq!(slice.read_long().unwrap(), 567_890_i64);
+        assert_eq!(slice.read_int().unwrap(), 1_234_567_i32);
+        assert!(slice.read_int().is_err());
+        assert!(input.slice("too long", 3, 14).is_err());
+
+        let random = input.random_access_slice(1, 15).unwrap();
+        assert_eq!(random.read_short(0).unwrap(), 0x7F_i16);
+        assert_eq!(random.read_long(2).unwrap(), 567_890);
+        assert_eq!(random.read_byte(14).unwrap(), b'b');
+        assert!(random.read_int(12).is_err());
+    }
+
+    #[test]
+    fn test_ram_directory_file_ops() {
+        let dir = RAMDirectory::new();
+        write_file(&dir, "a");
+        write_file(&dir, "b");
+
+        let mut names = HashSet::new();
+        names.insert("a".to_string());
+        dir.sync(&names).unwrap();
+        dir.sync_meta_data().unwrap();
+
+        // rename overwrites the destination
+        dir.rename("a", "b").unwrap();
+        assert_eq!(dir.list_all().unwrap(), vec!["b".to_string()]);
+        assert!(is_not_found(dir.sync(&names)));
+        assert!(is_not_found(dir.rename("a", "c")));
+        assert!(is_not_found(dir.open_input("a", &IOContext::Default)));
+
+        // an open input still reads a deleted file
+        let mut input = dir.open_input("b", &IOContext::Default).unwrap();
+        dir.delete_file("b").unwrap();
+        assert!(is_not_found(dir.delete_file("b")));
+        assert!(is_not_found(dir.file_length("b")));
+        assert_eq!(input.read_byte().unwrap(), b'a');
+
+        let out = dir
+            .create_temp_output("_0", "test", &IOContext::Default)
+            .unwrap();
+        assert_eq!(out.name(), "_0_test_0.tmp");
+        assert_eq!(dir.list_all().unwrap(), vec!["_0_test_0.tmp".to_string()]);
+    }
+
+    #[test]
+    fn test_ram_index_output_flush() {
+        let dir = RAMDirectory::new();
+        let mut out = dir.create_output("a", &IOContext::Default).unwrap();
+        out.write_int(1).unwrap();
+        assert_eq!(dir.file_length("a").unwrap(), 0);
+        out.flush().unwrap();
+        assert_eq!(dir.file_length("a").unwrap(), 4);
+
+        // an input keeps reading the bytes published when it was opened
+        let before = dir.open_input("a", &IOContext::Default).unwrap();
+        out.write_int(2).unwrap();
+        out.flush().unwrap();
+        out.write_int(3).unwrap();
+        drop(out);
+        assert_eq!(before.len(), 4);
+        let mut input = dir.open_input("a", &IOContext::Default).unwrap();
+        assert_eq!(input.len(), 12);
+        assert_eq!(input.read_int().unwrap(), 1);
+        assert_eq!(input.read_int().unwrap(), 2);
+        assert_eq!(input.read_int().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_stale_ram_index_output() {
+        let dir = RAMDirectory::new();
+        let mut deleted = dir.create_output("a", &IOContext::Default).unwrap();
+        deleted.write_int(1).unwrap();
+        dir.delete_file("a").unwrap();
+        write_file(&dir, "a");
+        deleted.write_int(2).unwrap();
+        drop(deleted);
+        assert_eq!(dir.file_length("a").unwrap(), 16);
+
+        // a new output replaces the file of the previous one
+        let mut replaced = dir.create_output("b", &IOContext::Default).unwrap();
+        write_file(&dir, "b");
+        replaced.write_long(3).unwrap();
+        replaced.flush().unwrap();
+        assert_eq!(dir.file_length("b").unwrap(), 16);
+
+        // the file follows a rename, not its output
+        let mut renamed = dir.create_output("c", &IOContext::Default).unwrap();
+        renamed.write_int(4).unwrap();
+        renamed.flush().unwrap();
+        dir.rename("c", "d").unwrap();
+        renamed.write_int(5).unwrap();
+        drop(renamed);
+        assert_eq!(dir.file_length("d").unwrap(), 4);
+        assert!(is_not_found(dir.file_length("c")));
+    }
+
+    #[test]
+    fn test_ram_directory_lock() {
+        let dir = RAMDirectory::new();
+        let lock = dir.obtain_lock(WRITE_LOCK_NAME).unwrap();
+        assert!(dir.obtain_lock(WRITE_LOCK_NAME).is_err());
+        // locks are per directory instance
+        RAMDirectory::new().obtain_lock(WRITE_LOCK_NAME).unwrap();
+        lock.close().unwrap();
+        dir.obtain_lock(WRITE_LOCK_NAME).unwrap();
+    }
+
+    #[test]
+    fn test_ram_directory_index_writer() {
+        use core::doc::{Field, FieldType, Fieldable, IndexOptions};
+        use core::index::reader::IndexReader;
+        use core::index::writer::{IndexWriter, IndexWriterConfig};
+        use core::util::VariantValue;
+
+        let directory = Arc::new(RAMDirectory::new());
+        let config = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(Arc::clone(&directory), config).unwrap();
+
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        field_type.tokenized = false;
+        for id in &["1", "2", "3"] {
+            let doc: Vec<Box<dyn Fieldable>> = vec![Box::new(Field::new(
+                "id".into(),
+                field_type.clone(),
+                Some(VariantValue::VString(id.to_string())),
+                None,
+            ))];
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.num_docs(), 3);
+        assert!(directory
+            .list_all()
+            .unwrap()
+            .iter()
+            .any(|name| name.starts_with("segments_")));
+        writer.close().unwrap();
+    }
+}
@@ -18,6 +18,7 @@ use std::cmp::min;
 use std::io::{self, Read, Write};
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct ByteArrayRef(Arc<Vec<u8>>);
 
 impl ByteArrayRef {
@@ -35,6 +36,7 @@ impl AsRef<[u8]> for ByteArrayRef {
 /// DataInput backed by a byte array.
 ///
 /// *WARNING:* This class omits all low-level checks.
+#[derive(Clone)]
 pub struct ByteArrayDataInput<T: AsRef<[u8]>> {
     bytes: T,
     pos: usize,
@@ -35,6 +35,10 @@ mod mmap_index_input;
 
 pub use self::mmap_index_input::*;
 
+mod ram_index_input;
+
+pub use self::ram_index_input::*;
+
 mod data_output;
 
 pub use self::data_output::*;
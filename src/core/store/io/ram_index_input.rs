@@ -0,0 +1,185 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::store::io::{DataInput, IndexInput, RandomAccessInput};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::io::{self, Read};
+use std::sync::Arc;
+
+/// `IndexInput` over a shared, immutable in-memory buffer.
+///
+/// Clones and slices share the underlying bytes, so they never copy
+/// the file content.
+#[derive(Clone)]
+pub struct RAMIndexInput {
+    source: Arc<Vec<u8>>,
+    offset: usize,
+    length: usize,
+    position: usize,
+    description: String,
+}
+
+impl RAMIndexInput {
+    pub fn new(description: String, source: Arc<Vec<u8>>) -> RAMIndexInput {
+        let length = source.len();
+        RAMIndexInput {
+            source,
+            offset: 0,
+            length,
+            position: 0,
+            description,
+        }
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        &self.source[self.offset..self.offset + self.length]
+    }
+
+    fn slice_impl(&self, description: &str, offset: i64, length: i64) -> Result<RAMIndexInput> {
+        if offset < 0 || length < 0 || (offset + length) as usize > self.length {
+            bail!(IllegalArgument(format!(
+                "slice() {} out of bounds: offset={}, length={}, fileLength={}: {}",
+                description, offset, length, self.length, self.description
+            )));
+        }
+        Ok(RAMIndexInput {
+            source: Arc::clone(&self.source),
+            offset: self.offset + offset as usize,
+            length: length as usize,
+            position: 0,
+            description: description.to_string(),
+        })
+    }
+
+    #[inline]
+    fn check_random_access(&self, from: u64, len: u64) -> Result<()> {
+        if from + len > self.length as u64 {
+            let msg = format!(
+                "invalid position, expecting 0 < pos < {}, got: {}",
+                self.length, from
+            );
+            bail!(IllegalArgument(msg));
+        }
+        Ok(())
+    }
+}
+
+impl IndexInput for RAMIndexInput {
+    fn clone(&self) -> Result<Box<dyn IndexInput>> {
+        Ok(Box::new(Clone::clone(self)))
+    }
+
+    fn file_pointer(&self) -> i64 {
+        self.position as i64
+    }
+
+    fn seek(&mut self, pos: i64) -> Result<()> {
+        if pos < 0 || pos as usize > self.length {
+            bail!(IllegalArgument(format!(
+                "seek to {} beyond the end of {} (length={})",
+                pos, self.description, self.length
+            )));
+        }
+        self.position = pos as usize;
+        Ok(())
+    }
+
+    #[inline]
+    fn len(&self) -> u64 {
+        self.length as u64
+    }
+
+    fn name(&self) -> &str {
+        &self.description
+    }
+
+    fn random_access_slice(&self, offset: i64, length: i64) -> Result<Box<dyn RandomAccessInput>> {
+        let boxed = self.slice_impl("RandomAccessSlice", offset, length)?;
+        Ok(Box::new(boxed))
+    }
+
+    fn slice(&self, description: &str, offset: i64, length: i64) -> Result<Box<dyn IndexInput>> {
+        let boxed = self.slice_impl(description, offset, length)?;
+        Ok(Box::new(boxed))
+    }
+
+    #[inline(always)]
+    unsafe fn get_and_advance(&mut self, length: usize) -> *const u8 {
+        debug_assert!(self.position + length <= self.length);
+        let ptr = self.source.as_ptr().add(self.offset + self.position);
+        self.position += length;
+        ptr
+    }
+}
+
+impl DataInput for RAMIndexInput {
+    fn read_byte(&mut self) -> Result<u8> {
+        if self.position >= self.length {
+            bail!(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer"
+            ));
+        }
+        let b = self.source[self.offset + self.position];
+        self.position += 1;
+        Ok(b)
+    }
+
+    fn skip_bytes(&mut self, count: usize) -> Result<()> {
+        if self.position + count > self.length {
+            bail!(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer"
+            ));
+        }
+        self.position += count;
+        Ok(())
+    }
+}
+
+impl Read for RAMIndexInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let position = self.position;
+        let count = buf.len().min(self.length - position);
+        buf[..count].copy_from_slice(&self.bytes()[position..position + count]);
+
+        self.position += count;
+        Ok(count)
+    }
+}
+
+impl RandomAccessInput for RAMIndexInput {
+    fn read_byte(&self, pos: u64) -> Result<u8> {
+        self.check_random_access(pos, 1)?;
+        Ok(self.bytes()[pos as usize])
+    }
+
+    fn read_short(&self, pos: u64) -> Result<i16> {
+        self.check_random_access(pos, 2)?;
+        (&self.bytes()[pos as usize..]).read_short()
+    }
+
+    fn read_int(&self, pos: u64) -> Result<i32> {
+        self.check_random_access(pos, 4)?;
+        (&self.bytes()[pos as usize..]).read_int()
+    }
+
+    fn read_long(&self, pos: u64) -> Result<i64> {
+        self.check_random_access(pos, 8)?;
+        (&self.bytes()[pos as usize..]).read_long()
+    }
+}
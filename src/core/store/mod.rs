@@ -46,11 +46,29 @@ impl IOContext {
 #[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub struct FlushInfo {
     num_docs: u32,
+    estimated_segment_size: u64,
 }
 
 impl FlushInfo {
+    /// Creates a `FlushInfo` whose segment size is unknown.
     pub fn new(num_docs: u32) -> Self {
-        FlushInfo { num_docs }
+        Self::with_segment_size(num_docs, 0)
+    }
+
+    pub fn with_segment_size(num_docs: u32, estimated_segment_size: u64) -> Self {
+        FlushInfo {
+            num_docs,
+            estimated_segment_size,
+        }
+    }
+
+    pub fn num_docs(&self) -> u32 {
+        self.num_docs
+    }
+
+    /// The estimated size in bytes of the flushed files, 0 if unknown.
+    pub fn estimated_segment_size(&self) -> u64 {
+        self.estimated_segment_size
     }
 }
 
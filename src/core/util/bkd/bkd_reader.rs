@@ -22,11 +22,12 @@ use core::util::bkd::{
     BKD_VERSION_START,
 };
 use core::util::math;
-use core::util::DocId;
+use core::util::{sortable_bytes2int, sortable_bytes2long, Bits, DocId};
 
 use error::{ErrorKind, Result};
 
 use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use std::sync::Arc;
 
 /// Used to track all state for a single call to {@link #intersect}.
@@ -344,6 +345,19 @@ impl BKDReader {
         &self,
         visitor: &'a mut IV,
     ) -> Result<IntersectState<'a, IV>> {
+        let index_tree = self.create_index_tree()?;
+
+        Ok(IntersectState::new(
+            self.input.as_ref().clone()?,
+            self.num_dims,
+            self.packed_bytes_length,
+            self.max_points_in_leaf_node,
+            visitor,
+            index_tree,
+        ))
+    }
+
+    fn create_index_tree(&self) -> Result<Box<dyn IndexTree>> {
         let index_tree: Box<dyn IndexTree> = if !self.packed_index.is_empty() {
             Box::new(PackedIndexTree::new(
                 self.bytes_per_dim,
@@ -366,14 +380,107 @@ impl BKDReader {
             ))
         };
 
-        Ok(IntersectState::new(
-            self.input.as_ref().clone()?,
-            self.num_dims,
-            self.packed_bytes_length,
-            self.max_points_in_leaf_node,
-            visitor,
-            index_tree,
-        ))
+        Ok(index_tree)
+    }
+
+    /// Finds the `k` points closest to the target of `distance`, sorted by increasing distance
+    /// (ties are broken by doc id). Docs not set in `live_docs` are skipped.
+    ///
+    /// Cells of the tree are walked best-first: the cell with the smallest lower bound of the
+    /// distance is always expanded next, and the walk stops as soon as no pending cell can hold
+    /// a point closer than the current k-th hit.
+    pub fn nearest(
+        &self,
+        distance: &impl PointDistance,
+        k: usize,
+        live_docs: Option<&dyn Bits>,
+    ) -> Result<Vec<NearestPoint>> {
+        if k == 0 || self.point_count == 0 {
+            return Ok(vec![]);
+        }
+        let mut collector = NearestCollector {
+            distance,
+            k,
+            live_docs,
+            hits: BinaryHeap::with_capacity(k + 1),
+        };
+
+        let mut input = self.input.as_ref().clone()?;
+        let mut scratch_doc_ids = vec![0 as DocId; self.max_points_in_leaf_node];
+        let mut scratch_packed_value = vec![0u8; self.packed_bytes_length];
+        let mut common_prefix_lengths = vec![0i32; self.num_dims];
+
+        let mut queue = BinaryHeap::new();
+        queue.push(NearestCell {
+            index_tree: self.create_index_tree()?,
+            distance: distance.cell_distance(&self.min_packed_value, &self.max_packed_value),
+            min_packed_value: self.min_packed_value.clone(),
+            max_packed_value: self.max_packed_value.clone(),
+        });
+
+        while let Some(mut cell) = queue.pop() {
+            if !collector.is_competitive_cell(cell.distance) {
+                // every remaining cell is even further away
+                break;
+            }
+
+            if cell.index_tree.is_leaf_node() {
+                // In the unbalanced case it's possible the left most node only has one child:
+                if cell.index_tree.node_exists() {
+                    let count = self.read_doc_ids(
+                        input.as_mut(),
+                        cell.index_tree.leaf_block_fp(),
+                        &mut scratch_doc_ids,
+                    )?;
+                    self.visit_doc_values(
+                        &mut common_prefix_lengths,
+                        &mut scratch_packed_value,
+                        input.as_mut(),
+                        &scratch_doc_ids,
+                        count,
+                        &mut collector,
+                    )?;
+                }
+            } else {
+                let split_dim = cell.index_tree.split_dim() as usize;
+                let split_dim_value = cell.index_tree.split_dim_value();
+                let offset = split_dim * self.bytes_per_dim;
+                let end = offset + self.bytes_per_dim;
+
+                let mut left_max_packed_value = cell.max_packed_value.clone();
+                left_max_packed_value[offset..end].copy_from_slice(&split_dim_value);
+                let left_distance =
+                    distance.cell_distance(&cell.min_packed_value, &left_max_packed_value);
+                if collector.is_competitive_cell(left_distance) {
+                    let mut left_tree = cell.index_tree.clone_tree();
+                    left_tree.push_left()?;
+                    queue.push(NearestCell {
+                        index_tree: left_tree,
+                        distance: left_distance,
+                        min_packed_value: cell.min_packed_value.clone(),
+                        max_packed_value: left_max_packed_value,
+                    });
+                }
+
+                let mut right_min_packed_value = cell.min_packed_value;
+                right_min_packed_value[offset..end].copy_from_slice(&split_dim_value);
+                let right_distance =
+                    distance.cell_distance(&right_min_packed_value, &cell.max_packed_value);
+                if collector.is_competitive_cell(right_distance) {
+                    cell.index_tree.push_right()?;
+                    queue.push(NearestCell {
+                        index_tree: cell.index_tree,
+                        distance: right_distance,
+                        min_packed_value: right_min_packed_value,
+                        max_packed_value: cell.max_packed_value,
+                    });
+                }
+            }
+        }
+
+        let mut hits: Vec<NearestPoint> = collector.hits.into_iter().map(|h| h.0).collect();
+        hits.sort_by(NearestPoint::cmp_by_distance);
+        Ok(hits)
     }
 
     /// Fast path: this is called when the query box fully encompasses all cells under this
@@ -646,10 +753,215 @@ pub trait IndexTree {
     fn set_split_dim_value(&mut self, data: &[u8]);
     /// Only valid after pushLeft or pushRight, not pop!
     fn leaf_block_fp(&self) -> i64;
+    /// Returns a copy positioned on the same node, to walk another path from there.
+    fn clone_tree(&self) -> Box<dyn IndexTree>;
+}
+
+/// Measures the distance from a fixed target to the points of a BKD tree, see
+/// `BKDReader::nearest`.
+pub trait PointDistance {
+    /// Distance from the target to the given packed point.
+    fn distance(&self, packed_value: &[u8]) -> f64;
+
+    /// A lower bound of the distance from the target to any point inside the given cell.
+    fn cell_distance(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> f64;
+}
+
+/// Euclidean distance over integer points, i.e. points whose dimensions were encoded with
+/// `int2sortable_bytes` (4 bytes per dim) or `long2sortable_bytes` (8 bytes per dim).
+pub struct EuclideanDistance {
+    target: Vec<f64>,
+    bytes_per_dim: usize,
+}
+
+impl EuclideanDistance {
+    pub fn new(target: &[u8], num_dims: usize) -> Result<EuclideanDistance> {
+        if num_dims == 0 || target.len() % num_dims != 0 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "target of length {} can't be split in {} dims",
+                target.len(),
+                num_dims
+            )));
+        }
+        let bytes_per_dim = target.len() / num_dims;
+        if bytes_per_dim != 4 && bytes_per_dim != 8 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "only 4 or 8 bytes per dim are supported, got {}",
+                bytes_per_dim
+            )));
+        }
+        let mut distance = EuclideanDistance {
+            target: Vec::with_capacity(num_dims),
+            bytes_per_dim,
+        };
+        for dim in 0..num_dims {
+            let value = distance.decode(target, dim);
+            distance.target.push(value);
+        }
+        Ok(distance)
+    }
+
+    fn decode(&self, packed_value: &[u8], dim: usize) -> f64 {
+        let offset = dim * self.bytes_per_dim;
+        let bytes = &packed_value[offset..offset + self.bytes_per_dim];
+        if self.bytes_per_dim == 4 {
+            f64::from(sortable_bytes2int(bytes))
+        } else {
+            sortable_bytes2long(bytes) as f64
+        }
+    }
+}
+
+impl PointDistance for EuclideanDistance {
+    fn distance(&self, packed_value: &[u8]) -> f64 {
+        let mut sum = 0f64;
+        for (dim, target) in self.target.iter().enumerate() {
+            let delta = self.decode(packed_value, dim) - *target;
+            sum += delta * delta;
+        }
+        sum.sqrt()
+    }
+
+    fn cell_distance(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> f64 {
+        let mut sum = 0f64;
+        for (dim, target) in self.target.iter().enumerate() {
+            let min = self.decode(min_packed_value, dim);
+            let max = self.decode(max_packed_value, dim);
+            let delta = if *target < min {
+                min - *target
+            } else if *target > max {
+                *target - max
+            } else {
+                0f64
+            };
+            sum += delta * delta;
+        }
+        sum.sqrt()
+    }
+}
+
+/// A hit of `BKDReader::nearest`.
+#[derive(Clone, Debug)]
+pub struct NearestPoint {
+    pub doc: DocId,
+    pub packed_value: Vec<u8>,
+    pub distance: f64,
+}
+
+impl NearestPoint {
+    pub fn cmp_by_distance(&self, other: &NearestPoint) -> Ordering {
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or(Ordering::Equal)
+            .then(self.doc.cmp(&other.doc))
+    }
+}
+
+// max-heap entry: the worst hit is on top
+struct NearestHit(NearestPoint);
+
+impl PartialEq for NearestHit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for NearestHit {}
+
+impl PartialOrd for NearestHit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NearestHit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp_by_distance(&other.0)
+    }
+}
+
+// min-heap entry: the closest cell is on top
+struct NearestCell {
+    // positioned on the node of this cell
+    index_tree: Box<dyn IndexTree>,
+    distance: f64,
+    min_packed_value: Vec<u8>,
+    max_packed_value: Vec<u8>,
+}
+
+impl PartialEq for NearestCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for NearestCell {}
+
+impl PartialOrd for NearestCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NearestCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+            .then(other.index_tree.node_id().cmp(&self.index_tree.node_id()))
+    }
+}
+
+struct NearestCollector<'a, D: PointDistance> {
+    distance: &'a D,
+    k: usize,
+    live_docs: Option<&'a dyn Bits>,
+    hits: BinaryHeap<NearestHit>,
+}
+
+impl<'a, D: PointDistance> NearestCollector<'a, D> {
+    fn is_competitive_cell(&self, distance: f64) -> bool {
+        // a cell at the same distance as the bottom may still hold a smaller doc id
+        self.hits.len() < self.k || distance <= (self.hits.peek().unwrap().0).distance
+    }
+}
+
+impl<'a, D: PointDistance> IntersectVisitor for NearestCollector<'a, D> {
+    fn visit(&mut self, _doc_id: DocId) -> Result<()> {
+        bail!(ErrorKind::IllegalState(
+            "nearest search must visit packed values".into()
+        ))
+    }
+
+    fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
+        if let Some(live_docs) = self.live_docs {
+            if !live_docs.get(doc_id as usize)? {
+                return Ok(());
+            }
+        }
+        let hit = NearestPoint {
+            doc: doc_id,
+            packed_value: packed_value.to_vec(),
+            distance: self.distance.distance(packed_value),
+        };
+        if self.hits.len() < self.k {
+            self.hits.push(NearestHit(hit));
+        } else if hit.cmp_by_distance(&self.hits.peek().unwrap().0) == Ordering::Less {
+            self.hits.pop();
+            self.hits.push(NearestHit(hit));
+        }
+        Ok(())
+    }
+
+    fn compare(&self, _min_packed_value: &[u8], _max_packed_value: &[u8]) -> Relation {
+        Relation::CellCrossesQuery
+    }
 }
 
 /// Used to walk the in-heap index
 // @lucene.internal
+#[derive(Clone)]
 pub struct SimpleIndexTree {
     node_id: i32,
     // level is 1-based so that we can do level-1 w/o checking each time:
@@ -754,9 +1066,14 @@ impl IndexTree for SimpleIndexTree {
     fn leaf_block_fp(&self) -> i64 {
         unimplemented!()
     }
+
+    fn clone_tree(&self) -> Box<dyn IndexTree> {
+        Box::new(self.clone())
+    }
 }
 
 /// Reads the original simple yet heap-heavy index format
+#[derive(Clone)]
 pub struct LegacyIndexTree {
     leaf_block_fp: i64,
     split_dim_value: Vec<u8>,
@@ -889,12 +1206,17 @@ impl IndexTree for LegacyIndexTree {
         debug_assert!(self.is_leaf_node());
         self.leaf_block_fp
     }
+
+    fn clone_tree(&self) -> Box<dyn IndexTree> {
+        Box::new(self.clone())
+    }
 }
 
 /// Reads the new packed bytes index format which can be up to ~63% smaller than the legacy index
 /// format on 20M NYC taxis tests.  This
 /// format takes advantage of the limited access pattern to the BKD tree at search time, i.e.
 /// starting at the root node and recursing  downwards one child at a time.
+#[derive(Clone)]
 pub struct PackedIndexTree {
     // used to read the packed bytes
     input: ByteArrayDataInput<ByteArrayRef>,
@@ -1136,6 +1458,10 @@ impl IndexTree for PackedIndexTree {
         );
         self.leaf_block_fp_stack[self.index_tree.level as usize]
     }
+
+    fn clone_tree(&self) -> Box<dyn IndexTree> {
+        Box::new(self.clone())
+    }
 }
 
 pub struct MergeReader<'a, IV: IntersectVisitor + 'a> {
@@ -1372,4 +1698,8 @@ impl IndexTree for StubIndexTree {
     fn leaf_block_fp(&self) -> i64 {
         unreachable!()
     }
+
+    fn clone_tree(&self) -> Box<dyn IndexTree> {
+        unreachable!()
+    }
 }
@@ -13,6 +13,9 @@
 
 use core::util::{fill_slice, BytesRef};
 
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
 /// Class that Posting and PostingVector use to write byte
 /// streams into shared fixed-size bytes arrays.  The idea
 /// is to allocate slices of increasing lengths For
@@ -337,14 +340,26 @@ impl ByteBlockAllocator for DirectAllocator {
 /// A simple `Allocator` that never recycles, but tracks how much total RAM is in use.
 pub struct DirectTrackingAllocator {
     block_size: usize,
+    bytes_used: Arc<AtomicI64>,
 }
 
 impl DirectTrackingAllocator {
     pub fn new() -> Self {
+        Self::with_counter(Arc::new(AtomicI64::new(0)))
+    }
+
+    /// Creates an allocator adding the size of the blocks it allocates to
+    /// `bytes_used`, and subtracting the size of the recycled ones.
+    pub fn with_counter(bytes_used: Arc<AtomicI64>) -> Self {
         DirectTrackingAllocator {
             block_size: ByteBlockPool::BYTE_BLOCK_SIZE,
+            bytes_used,
         }
     }
+
+    pub fn bytes_used(&self) -> i64 {
+        self.bytes_used.load(Ordering::Acquire)
+    }
 }
 
 impl ByteBlockAllocator for DirectTrackingAllocator {
@@ -354,17 +369,22 @@ impl ByteBlockAllocator for DirectTrackingAllocator {
 
     fn recycle_byte_blocks(&mut self, blocks: &mut [Vec<u8>], start: usize, end: usize) {
         for i in start..end {
+            self.bytes_used
+                .fetch_sub(blocks[i].len() as i64, Ordering::AcqRel);
             blocks[i] = vec![];
         }
     }
 
     fn byte_block(&mut self) -> Vec<u8> {
+        self.bytes_used
+            .fetch_add(self.block_size as i64, Ordering::AcqRel);
         vec![0u8; self.block_size]
     }
 
     fn shallow_copy(&self) -> Box<dyn ByteBlockAllocator> {
         Box::new(DirectTrackingAllocator {
             block_size: self.block_size,
+            bytes_used: Arc::clone(&self.bytes_used),
         })
     }
 }
@@ -228,6 +228,12 @@ impl BytesRefHash {
         }
     }
 
+    /// Returns the id of the given bytes, or -1 if there is no mapping for
+    /// them.
+    pub fn find(&self, bytes: &BytesRef) -> i32 {
+        self.ids[self.find_hash(bytes)]
+    }
+
     /// Adds a "arbitrary" int offset instead of a BytesRef
     /// term.  This is used in the indexer to hold the hash for term
     /// vectors, because they do not redundantly store the bytes term
@@ -0,0 +1,152 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::util::geo::{check_latitude, check_longitude};
+
+use error::Result;
+
+const LAT_SCALE: f64 = (0x1u64 << 32) as f64 / 180.0;
+const LAT_DECODE: f64 = 1.0 / LAT_SCALE;
+const LON_SCALE: f64 = (0x1u64 << 32) as f64 / 360.0;
+const LON_DECODE: f64 = 1.0 / LON_SCALE;
+
+// the largest value below `value`, for a positive finite `value`
+#[inline]
+fn next_down(value: f64) -> f64 {
+    debug_assert!(value > 0.0 && value.is_finite());
+    f64::from_bits(value.to_bits() - 1)
+}
+
+/// Quantizes a latitude to a 32 bit integer, rounding down.
+///
+/// The latitude must be in `[-90, 90]`, see `check_latitude`.
+pub fn encode_latitude(latitude: f64) -> i32 {
+    debug_assert!(check_latitude(latitude).is_ok());
+    // the maximum possible value cannot be encoded without overflow
+    let latitude = if latitude == 90.0 {
+        next_down(latitude)
+    } else {
+        latitude
+    };
+    (latitude / LAT_DECODE).floor() as i32
+}
+
+/// Quantizes a latitude to a 32 bit integer, rounding up.
+pub fn encode_latitude_ceil(latitude: f64) -> i32 {
+    debug_assert!(check_latitude(latitude).is_ok());
+    // values rounded up past the largest encoded value are clamped to it,
+    // an overflowing float to int cast is undefined
+    (latitude / LAT_DECODE)
+        .ceil()
+        .min(f64::from(i32::max_value())) as i32
+}
+
+/// Quantizes a longitude to a 32 bit integer, rounding down.
+///
+/// The longitude must be in `[-180, 180]`, see `check_longitude`.
+pub fn encode_longitude(longitude: f64) -> i32 {
+    debug_assert!(check_longitude(longitude).is_ok());
+    let longitude = if longitude == 180.0 {
+        next_down(longitude)
+    } else {
+        longitude
+    };
+    (longitude / LON_DECODE).floor() as i32
+}
+
+/// Quantizes a longitude to a 32 bit integer, rounding up.
+pub fn encode_longitude_ceil(longitude: f64) -> i32 {
+    debug_assert!(check_longitude(longitude).is_ok());
+    (longitude / LON_DECODE)
+        .ceil()
+        .min(f64::from(i32::max_value())) as i32
+}
+
+/// Returns the latitude of an encoded value, which is the largest
+/// quantized latitude not greater than the encoded one.
+#[inline]
+pub fn decode_latitude(encoded: i32) -> f64 {
+    f64::from(encoded) * LAT_DECODE
+}
+
+/// Returns the longitude of an encoded value.
+#[inline]
+pub fn decode_longitude(encoded: i32) -> f64 {
+    f64::from(encoded) * LON_DECODE
+}
+
+/// Encodes a point in a single `i64`: the latitude in the upper 32 bits and
+/// the longitude in the lower ones.
+pub fn encode_lat_lon(latitude: f64, longitude: f64) -> Result<i64> {
+    check_latitude(latitude)?;
+    check_longitude(longitude)?;
+    let lat = i64::from(encode_latitude(latitude));
+    let lon = i64::from(encode_longitude(longitude)) & 0xFFFF_FFFF;
+    Ok((lat << 32) | lon)
+}
+
+/// Decodes a value encoded with `encode_lat_lon` to `(latitude, longitude)`.
+#[inline]
+pub fn decode_lat_lon(encoded: i64) -> (f64, f64) {
+    (
+        decode_latitude((encoded >> 32) as i32),
+        decode_longitude(encoded as i32),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        for &(lat, lon) in &[
+            (0.0, 0.0),
+            (40.7143528, -74.0059731),
+            (-90.0, -180.0),
+            (90.0, 180.0),
+        ] {
+            let lat_enc = encode_latitude(lat);
+            let lon_enc = encode_longitude(lon);
+            assert!(decode_latitude(lat_enc) <= lat);
+            assert!(lat - decode_latitude(lat_enc) < 1e-7);
+            assert!(decode_longitude(lon_enc) <= lon);
+            assert!(lon - decode_longitude(lon_enc) < 1e-7);
+            assert!(encode_latitude_ceil(lat) >= lat_enc);
+            assert!(encode_longitude_ceil(lon) >= lon_enc);
+        }
+        assert_eq!(encode_latitude(-90.0), i32::min_value());
+        assert_eq!(encode_latitude(90.0), i32::max_value());
+        assert_eq!(encode_longitude(180.0), i32::max_value());
+    }
+
+    #[test]
+    fn test_encode_ceil_bounds() {
+        assert_eq!(encode_latitude_ceil(90.0), i32::max_value());
+        assert_eq!(encode_longitude_ceil(180.0), i32::max_value());
+        assert_eq!(encode_latitude_ceil(-90.0), i32::min_value());
+        assert_eq!(encode_longitude_ceil(-180.0), i32::min_value());
+        assert_eq!(encode_latitude_ceil(next_down(90.0)), i32::max_value());
+    }
+
+    #[test]
+    fn test_encode_lat_lon() {
+        let encoded = encode_lat_lon(40.7143528, -74.0059731).unwrap();
+        let (lat, lon) = decode_lat_lon(encoded);
+        assert_eq!(lat, decode_latitude(encode_latitude(40.7143528)));
+        assert_eq!(lon, decode_longitude(encode_longitude(-74.0059731)));
+
+        assert!(encode_lat_lon(91.0, 0.0).is_err());
+        assert!(encode_lat_lon(0.0, ::std::f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::f64;
+
+use core::codec::points::Relation;
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+/// mean radius of the earth, in meters (WGS84)
+pub const EARTH_MEAN_RADIUS_METERS: f64 = 6_371_008.771_4;
+
+pub const MIN_LAT_INCL: f64 = -90.0;
+pub const MAX_LAT_INCL: f64 = 90.0;
+pub const MIN_LON_INCL: f64 = -180.0;
+pub const MAX_LON_INCL: f64 = 180.0;
+
+/// Returns an error if the latitude is not in `[-90, 90]`.
+pub fn check_latitude(latitude: f64) -> Result<()> {
+    if latitude.is_nan() || latitude < MIN_LAT_INCL || latitude > MAX_LAT_INCL {
+        bail!(IllegalArgument(format!(
+            "invalid latitude {}; must be between {} and {}",
+            latitude, MIN_LAT_INCL, MAX_LAT_INCL
+        )));
+    }
+    Ok(())
+}
+
+/// Returns an error if the longitude is not in `[-180, 180]`.
+pub fn check_longitude(longitude: f64) -> Result<()> {
+    if longitude.is_nan() || longitude < MIN_LON_INCL || longitude > MAX_LON_INCL {
+        bail!(IllegalArgument(format!(
+            "invalid longitude {}; must be between {} and {}",
+            longitude, MIN_LON_INCL, MAX_LON_INCL
+        )));
+    }
+    Ok(())
+}
+
+/// Returns a value that sorts like the haversine distance between two
+/// points, cheaper to compute than the distance itself.
+///
+/// Convert it to meters with `haversin_meters_from_sort_key`.
+pub fn haversin_sort_key(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let x1 = lat1.to_radians();
+    let x2 = lat2.to_radians();
+    let h1 = 1.0 - (x1 - x2).cos();
+    let h2 = 1.0 - (lon1 - lon2).to_radians().cos();
+    let h = h1 + x1.cos() * x2.cos() * h2;
+    // clobber crazy precision so subsequent rounding does not create ties
+    f64::from_bits(h.to_bits() & 0xFFFF_FFFF_FFFF_FFF8)
+}
+
+/// Returns the distance in meters for a sort key computed with
+/// `haversin_sort_key`.
+pub fn haversin_meters_from_sort_key(sort_key: f64) -> f64 {
+    if sort_key.is_infinite() {
+        return sort_key;
+    }
+    EARTH_MEAN_RADIUS_METERS * 2.0 * (sort_key * 0.5).sqrt().min(1.0).asin()
+}
+
+/// Returns the haversine distance in meters between two points.
+pub fn haversin_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    haversin_meters_from_sort_key(haversin_sort_key(lat1, lon1, lat2, lon2))
+}
+
+/// Returns the smallest haversine sort key whose distance is at least
+/// `radius_meters`, so that a point is within the radius iff its sort key is
+/// not greater, without computing `asin` for each point.
+pub fn distance_query_sort_key(radius_meters: f64) -> f64 {
+    // effectively infinite
+    let max_meters = haversin_meters_from_sort_key(f64::MAX);
+    if radius_meters >= max_meters {
+        return f64::MAX;
+    }
+    // a search through the non-negative doubles, which sort like their bits
+    let mut lo = 0i64;
+    let mut hi = f64::MAX.to_bits() as i64;
+    while lo <= hi {
+        let mid = lo + (hi - lo) / 2;
+        let sort_key = f64::from_bits(mid as u64);
+        let mid_radius = haversin_meters_from_sort_key(sort_key);
+        if mid_radius == radius_meters {
+            return sort_key;
+        } else if mid_radius > radius_meters {
+            hi = mid - 1;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    // the radius can't be computed exactly with the haversine formula
+    f64::from_bits(lo as u64)
+}
+
+// whether the longitude range is within 90 degrees of `lon`, taking the
+// dateline into account
+fn within_90_lon_degrees(lon: f64, min_lon: f64, max_lon: f64) -> bool {
+    let lon = if max_lon <= lon - 180.0 {
+        lon - 360.0
+    } else if min_lon >= lon + 180.0 {
+        lon + 360.0
+    } else {
+        lon
+    };
+    max_lon <= lon + 90.0 && min_lon >= lon - 90.0
+}
+
+/// Computes the relation between a box, which must not cross the dateline,
+/// and the circle of center `(lat, lon)` whose points have a sort key not
+/// greater than `distance_sort_key`.
+///
+/// `axis_lat` is the latitude at which the circle touches its bounding box
+/// longitudes, see `Rectangle::axis_lat`.
+#[allow(clippy::too_many_arguments)]
+pub fn relate_box_to_circle(
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+    lat: f64,
+    lon: f64,
+    distance_sort_key: f64,
+    axis_lat: f64,
+) -> Relation {
+    debug_assert!(min_lon <= max_lon);
+    let corner_keys = [
+        haversin_sort_key(lat, lon, min_lat, min_lon),
+        haversin_sort_key(lat, lon, min_lat, max_lon),
+        haversin_sort_key(lat, lon, max_lat, min_lon),
+        haversin_sort_key(lat, lon, max_lat, max_lon),
+    ];
+
+    // the circle can only cross the box without containing one of its corners
+    // if the center is within the longitudes of the box, or if the box
+    // contains the latitude of the widest part of the circle
+    if (lon < min_lon || lon > max_lon)
+        && (axis_lat + AXIS_LAT_ERROR < min_lat || axis_lat - AXIS_LAT_ERROR > max_lat)
+        && corner_keys.iter().all(|k| *k > distance_sort_key)
+    {
+        return Relation::CellOutsideQuery;
+    }
+
+    if within_90_lon_degrees(lon, min_lon, max_lon)
+        && corner_keys.iter().all(|k| *k <= distance_sort_key)
+    {
+        return Relation::CellInsideQuery;
+    }
+
+    Relation::CellCrossesQuery
+}
+
+/// error of `Rectangle::axis_lat`, in degrees
+pub const AXIS_LAT_ERROR: f64 = 0.1 / EARTH_MEAN_RADIUS_METERS * 180.0 / ::std::f64::consts::PI;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversin() {
+        assert_eq!(haversin_meters(40.0, -74.0, 40.0, -74.0), 0.0);
+        // half the circumference
+        let half = haversin_meters(0.0, 0.0, 0.0, 180.0);
+        assert!((half - EARTH_MEAN_RADIUS_METERS * ::std::f64::consts::PI).abs() < 1.0);
+
+        // New York to London, about 5570km
+        let d = haversin_meters(40.7143528, -74.0059731, 51.5072, -0.1275);
+        assert!((d - 5_570_000.0).abs() < 10_000.0, "{}", d);
+
+        let k1 = haversin_sort_key(0.0, 0.0, 1.0, 1.0);
+        let k2 = haversin_sort_key(0.0, 0.0, 2.0, 2.0);
+        assert!(k1 < k2);
+        assert_eq!(
+            haversin_meters_from_sort_key(::std::f64::INFINITY),
+            ::std::f64::INFINITY
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check_latitude(90.0).is_ok());
+        assert!(check_latitude(-90.1).is_err());
+        assert!(check_longitude(-180.0).is_ok());
+        assert!(check_longitude(180.5).is_err());
+        assert!(check_longitude(::std::f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_distance_query_sort_key() {
+        for radius in &[0.0, 1.0, 1000.0, 123_456.789, 5_000_000.0] {
+            let key = distance_query_sort_key(*radius);
+            assert!(haversin_meters_from_sort_key(key) >= *radius);
+            if key > 0.0 {
+                let below = f64::from_bits(key.to_bits() - 1);
+                assert!(haversin_meters_from_sort_key(below) < *radius);
+            }
+        }
+        assert_eq!(distance_query_sort_key(1e9), f64::MAX);
+    }
+
+    #[test]
+    fn test_relate_box_to_circle() {
+        let key = distance_query_sort_key(100_000.0);
+        // a small box around the center
+        assert_eq!(
+            relate_box_to_circle(-0.1, 0.1, -0.1, 0.1, 0.0, 0.0, key, 0.0),
+            Relation::CellInsideQuery
+        );
+        // a far away box
+        assert_eq!(
+            relate_box_to_circle(10.0, 11.0, 10.0, 11.0, 0.0, 0.0, key, 0.0),
+            Relation::CellOutsideQuery
+        );
+        // a box containing the circle
+        assert_eq!(
+            relate_box_to_circle(-10.0, 10.0, -10.0, 10.0, 0.0, 0.0, key, 0.0),
+            Relation::CellCrossesQuery
+        );
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Geo utilities: the encoding of latitude and longitude in indexed values
+//! and distance computations on the earth.
+
+mod geo_encoding;
+
+pub use self::geo_encoding::*;
+
+mod geo_utils;
+
+pub use self::geo_utils::*;
+
+mod polygon;
+
+pub use self::polygon::*;
+
+mod rectangle;
+
+pub use self::rectangle::*;
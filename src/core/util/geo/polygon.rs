@@ -0,0 +1,458 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::f64;
+use std::fmt;
+
+use core::codec::points::Relation;
+use core::util::geo::{check_latitude, check_longitude};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+/// A closed polygon on the earth, in degrees, with optional holes.
+///
+/// The polygon must not cross the dateline, the first and last points must
+/// be the same, and holes may not have holes of their own. Points are
+/// expected in counter-clockwise order, but either order works.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polygon {
+    polygon_lats: Vec<f64>,
+    polygon_lons: Vec<f64>,
+    holes: Vec<Polygon>,
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+}
+
+impl Polygon {
+    pub fn new(
+        polygon_lats: Vec<f64>,
+        polygon_lons: Vec<f64>,
+        holes: Vec<Polygon>,
+    ) -> Result<Polygon> {
+        if polygon_lats.len() != polygon_lons.len() {
+            bail!(IllegalArgument(
+                "polygon_lats and polygon_lons must be equal length".into()
+            ));
+        }
+        if polygon_lats.len() < 4 {
+            bail!(IllegalArgument(
+                "at least 4 polygon points required".into()
+            ));
+        }
+        let last = polygon_lats.len() - 1;
+        if polygon_lats[0] != polygon_lats[last] || polygon_lons[0] != polygon_lons[last] {
+            bail!(IllegalArgument(format!(
+                "first and last points of the polygon must be the same (it must close itself): \
+                 polygon_lats[0]={} polygon_lats[{}]={} polygon_lons[0]={} polygon_lons[{}]={}",
+                polygon_lats[0], last, polygon_lats[last], polygon_lons[0], last, polygon_lons[last]
+            )));
+        }
+        for hole in &holes {
+            if !hole.holes.is_empty() {
+                bail!(IllegalArgument("holes may not contain holes".into()));
+            }
+        }
+
+        let mut min_lat = f64::INFINITY;
+        let mut max_lat = f64::NEG_INFINITY;
+        let mut min_lon = f64::INFINITY;
+        let mut max_lon = f64::NEG_INFINITY;
+        for (lat, lon) in polygon_lats.iter().zip(&polygon_lons) {
+            check_latitude(*lat)?;
+            check_longitude(*lon)?;
+            min_lat = min_lat.min(*lat);
+            max_lat = max_lat.max(*lat);
+            min_lon = min_lon.min(*lon);
+            max_lon = max_lon.max(*lon);
+        }
+        Ok(Polygon {
+            polygon_lats,
+            polygon_lons,
+            holes,
+            min_lat,
+            max_lat,
+            min_lon,
+            max_lon,
+        })
+    }
+
+    pub fn polygon_lats(&self) -> &[f64] {
+        &self.polygon_lats
+    }
+
+    pub fn polygon_lons(&self) -> &[f64] {
+        &self.polygon_lons
+    }
+
+    pub fn holes(&self) -> &[Polygon] {
+        &self.holes
+    }
+
+    pub fn min_lat(&self) -> f64 {
+        self.min_lat
+    }
+
+    pub fn max_lat(&self) -> f64 {
+        self.max_lat
+    }
+
+    pub fn min_lon(&self) -> f64 {
+        self.min_lon
+    }
+
+    pub fn max_lon(&self) -> f64 {
+        self.max_lon
+    }
+}
+
+impl fmt::Display for Polygon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, (lat, lon)) in self.polygon_lats.iter().zip(&self.polygon_lons).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "[{}, {}]", lat, lon)?;
+        }
+        write!(f, "]")?;
+        if !self.holes.is_empty() {
+            write!(f, ", holes=[")?;
+            for (i, hole) in self.holes.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", hole)?;
+            }
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+// the sign of the cross product of (b - a) and (c - a), with x as longitude
+// and y as latitude
+fn orient(a_lat: f64, a_lon: f64, b_lat: f64, b_lon: f64, c_lat: f64, c_lon: f64) -> f64 {
+    (b_lon - a_lon) * (c_lat - a_lat) - (b_lat - a_lat) * (c_lon - a_lon)
+}
+
+// whether the segments p and q intersect, touching included
+#[allow(clippy::too_many_arguments)]
+fn segments_intersect(
+    p1_lat: f64,
+    p1_lon: f64,
+    p2_lat: f64,
+    p2_lon: f64,
+    q1_lat: f64,
+    q1_lon: f64,
+    q2_lat: f64,
+    q2_lon: f64,
+) -> bool {
+    let d1 = orient(q1_lat, q1_lon, q2_lat, q2_lon, p1_lat, p1_lon);
+    let d2 = orient(q1_lat, q1_lon, q2_lat, q2_lon, p2_lat, p2_lon);
+    let d3 = orient(p1_lat, p1_lon, p2_lat, p2_lon, q1_lat, q1_lon);
+    let d4 = orient(p1_lat, p1_lon, p2_lat, p2_lon, q2_lat, q2_lon);
+    if d1 * d2 > 0.0 || d3 * d4 > 0.0 {
+        return false;
+    }
+    if d1 == 0.0 && d2 == 0.0 {
+        // collinear, check that the projections overlap
+        return p1_lat.min(p2_lat) <= q1_lat.max(q2_lat)
+            && q1_lat.min(q2_lat) <= p1_lat.max(p2_lat)
+            && p1_lon.min(p2_lon) <= q1_lon.max(q2_lon)
+            && q1_lon.min(q2_lon) <= p1_lon.max(p2_lon);
+    }
+    true
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BoundingBox {
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+}
+
+impl BoundingBox {
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lon >= self.min_lon && lon <= self.max_lon
+    }
+
+    fn disjoint(&self, other: &BoundingBox) -> bool {
+        other.max_lat < self.min_lat
+            || other.min_lat > self.max_lat
+            || other.max_lon < self.min_lon
+            || other.min_lon > self.max_lon
+    }
+
+    fn within(&self, other: &BoundingBox) -> bool {
+        self.min_lat >= other.min_lat
+            && self.max_lat <= other.max_lat
+            && self.min_lon >= other.min_lon
+            && self.max_lon <= other.max_lon
+    }
+}
+
+// a single polygon of a `Polygon2D`
+#[derive(Debug)]
+struct Component {
+    bbox: BoundingBox,
+    lats: Vec<f64>,
+    lons: Vec<f64>,
+    holes: Vec<Component>,
+}
+
+impl Component {
+    fn new(polygon: &Polygon) -> Component {
+        Component {
+            bbox: BoundingBox {
+                min_lat: polygon.min_lat,
+                max_lat: polygon.max_lat,
+                min_lon: polygon.min_lon,
+                max_lon: polygon.max_lon,
+            },
+            lats: polygon.polygon_lats.clone(),
+            lons: polygon.polygon_lons.clone(),
+            holes: polygon.holes.iter().map(Component::new).collect(),
+        }
+    }
+
+    // ray casting, ignoring the holes
+    fn contains_shell(&self, lat: f64, lon: f64) -> bool {
+        if !self.bbox.contains(lat, lon) {
+            return false;
+        }
+        let mut inside = false;
+        for i in 1..self.lats.len() {
+            let (lat1, lon1) = (self.lats[i - 1], self.lons[i - 1]);
+            let (lat2, lon2) = (self.lats[i], self.lons[i]);
+            if (lat1 > lat) != (lat2 > lat)
+                && lon < (lon2 - lon1) * (lat - lat1) / (lat2 - lat1) + lon1
+            {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        self.contains_shell(lat, lon) && !self.holes.iter().any(|h| h.contains(lat, lon))
+    }
+
+    // whether an edge of the shell intersects the box
+    fn edges_intersect(&self, b: &BoundingBox) -> bool {
+        for i in 1..self.lats.len() {
+            let (lat1, lon1) = (self.lats[i - 1], self.lons[i - 1]);
+            let (lat2, lon2) = (self.lats[i], self.lons[i]);
+            let edge = BoundingBox {
+                min_lat: lat1.min(lat2),
+                max_lat: lat1.max(lat2),
+                min_lon: lon1.min(lon2),
+                max_lon: lon1.max(lon2),
+            };
+            if b.disjoint(&edge) {
+                continue;
+            }
+            if b.contains(lat1, lon1) || b.contains(lat2, lon2) {
+                return true;
+            }
+            let box_edges = [
+                (b.min_lat, b.min_lon, b.min_lat, b.max_lon),
+                (b.min_lat, b.max_lon, b.max_lat, b.max_lon),
+                (b.max_lat, b.max_lon, b.max_lat, b.min_lon),
+                (b.max_lat, b.min_lon, b.min_lat, b.min_lon),
+            ];
+            for &(q1_lat, q1_lon, q2_lat, q2_lon) in &box_edges {
+                if segments_intersect(lat1, lon1, lat2, lon2, q1_lat, q1_lon, q2_lat, q2_lon) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn relate(&self, b: &BoundingBox) -> Relation {
+        if self.bbox.disjoint(b) {
+            return Relation::CellOutsideQuery;
+        }
+        if self.bbox.within(b) || self.edges_intersect(b) {
+            return Relation::CellCrossesQuery;
+        }
+        // no edge crosses the box, so it is either fully inside or fully
+        // outside of the shell
+        if !self.contains_shell(b.min_lat, b.min_lon) {
+            return Relation::CellOutsideQuery;
+        }
+        for hole in &self.holes {
+            match hole.relate(b) {
+                Relation::CellInsideQuery => return Relation::CellOutsideQuery,
+                Relation::CellCrossesQuery => return Relation::CellCrossesQuery,
+                Relation::CellOutsideQuery => {}
+            }
+        }
+        Relation::CellInsideQuery
+    }
+}
+
+/// A set of polygons, to check whether points and boxes are within them.
+///
+/// Edges are checked linearly, so this is meant for polygons of reasonable
+/// size.
+#[derive(Debug)]
+pub struct Polygon2D {
+    bbox: BoundingBox,
+    components: Vec<Component>,
+}
+
+impl Polygon2D {
+    pub fn create(polygons: &[Polygon]) -> Result<Polygon2D> {
+        if polygons.is_empty() {
+            bail!(IllegalArgument("at least one polygon is required".into()));
+        }
+        let components: Vec<_> = polygons.iter().map(Component::new).collect();
+        let mut bbox = components[0].bbox;
+        for c in &components[1..] {
+            bbox.min_lat = bbox.min_lat.min(c.bbox.min_lat);
+            bbox.max_lat = bbox.max_lat.max(c.bbox.max_lat);
+            bbox.min_lon = bbox.min_lon.min(c.bbox.min_lon);
+            bbox.max_lon = bbox.max_lon.max(c.bbox.max_lon);
+        }
+        Ok(Polygon2D { bbox, components })
+    }
+
+    pub fn min_lat(&self) -> f64 {
+        self.bbox.min_lat
+    }
+
+    pub fn max_lat(&self) -> f64 {
+        self.bbox.max_lat
+    }
+
+    pub fn min_lon(&self) -> f64 {
+        self.bbox.min_lon
+    }
+
+    pub fn max_lon(&self) -> f64 {
+        self.bbox.max_lon
+    }
+
+    /// Returns true if the point is within one of the polygons and not in
+    /// one of its holes.
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        self.bbox.contains(lat, lon) && self.components.iter().any(|c| c.contains(lat, lon))
+    }
+
+    /// Computes the relation between a box and the polygons.
+    pub fn relate(&self, min_lat: f64, max_lat: f64, min_lon: f64, max_lon: f64) -> Relation {
+        let b = BoundingBox {
+            min_lat,
+            max_lat,
+            min_lon,
+            max_lon,
+        };
+        if self.bbox.disjoint(&b) {
+            return Relation::CellOutsideQuery;
+        }
+        let mut crosses = false;
+        for component in &self.components {
+            match component.relate(&b) {
+                Relation::CellInsideQuery => return Relation::CellInsideQuery,
+                Relation::CellCrossesQuery => crosses = true,
+                Relation::CellOutsideQuery => {}
+            }
+        }
+        if crosses {
+            Relation::CellCrossesQuery
+        } else {
+            Relation::CellOutsideQuery
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f64, max: f64) -> (Vec<f64>, Vec<f64>) {
+        (
+            vec![min, min, max, max, min],
+            vec![min, max, max, min, min],
+        )
+    }
+
+    #[test]
+    fn test_polygon_validation() {
+        assert!(Polygon::new(vec![0.0, 1.0, 0.0], vec![0.0, 1.0, 0.0], vec![]).is_err());
+        assert!(Polygon::new(
+            vec![0.0, 1.0, 1.0, 0.5],
+            vec![0.0, 0.0, 1.0, 0.0],
+            vec![]
+        )
+        .is_err());
+        assert!(Polygon::new(
+            vec![0.0, 91.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0, 0.0],
+            vec![]
+        )
+        .is_err());
+
+        let (lats, lons) = square(0.0, 1.0);
+        let hole = Polygon::new(lats.clone(), lons.clone(), vec![]).unwrap();
+        let with_hole = Polygon::new(lats.clone(), lons.clone(), vec![hole]).unwrap();
+        assert!(Polygon::new(lats, lons, vec![with_hole]).is_err());
+    }
+
+    #[test]
+    fn test_contains_with_hole() {
+        let (lats, lons) = square(2.0, 3.0);
+        let hole = Polygon::new(lats, lons, vec![]).unwrap();
+        let (lats, lons) = square(0.0, 5.0);
+        let polygon = Polygon::new(lats, lons, vec![hole]).unwrap();
+        assert_eq!(polygon.min_lat(), 0.0);
+        assert_eq!(polygon.max_lon(), 5.0);
+
+        let polygon2d = Polygon2D::create(&[polygon]).unwrap();
+        assert!(polygon2d.contains(1.0, 1.0));
+        assert!(polygon2d.contains(4.0, 2.5));
+        assert!(!polygon2d.contains(2.5, 2.5));
+        assert!(!polygon2d.contains(6.0, 1.0));
+    }
+
+    #[test]
+    fn test_relate() {
+        let (lats, lons) = square(2.0, 3.0);
+        let hole = Polygon::new(lats, lons, vec![]).unwrap();
+        let (lats, lons) = square(0.0, 5.0);
+        let polygon = Polygon::new(lats, lons, vec![hole]).unwrap();
+        let (lats, lons) = square(10.0, 11.0);
+        let other = Polygon::new(lats, lons, vec![]).unwrap();
+        let polygon2d = Polygon2D::create(&[polygon, other]).unwrap();
+
+        // in the shell
+        assert_eq!(polygon2d.relate(0.5, 1.5, 0.5, 1.5), Relation::CellInsideQuery);
+        // in the hole
+        assert_eq!(polygon2d.relate(2.2, 2.8, 2.2, 2.8), Relation::CellOutsideQuery);
+        // across the hole boundary
+        assert_eq!(polygon2d.relate(1.5, 2.5, 1.5, 2.5), Relation::CellCrossesQuery);
+        // around the hole
+        assert_eq!(polygon2d.relate(1.5, 3.5, 1.5, 3.5), Relation::CellCrossesQuery);
+        // between the polygons
+        assert_eq!(polygon2d.relate(6.0, 9.0, 6.0, 9.0), Relation::CellOutsideQuery);
+        // in the second polygon
+        assert_eq!(polygon2d.relate(10.2, 10.8, 10.2, 10.8), Relation::CellInsideQuery);
+        // containing everything
+        assert_eq!(polygon2d.relate(-1.0, 12.0, -1.0, 12.0), Relation::CellCrossesQuery);
+    }
+}
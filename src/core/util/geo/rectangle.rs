@@ -0,0 +1,163 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::f64::consts::PI;
+use std::fmt;
+
+use core::util::geo::{
+    check_latitude, check_longitude, EARTH_MEAN_RADIUS_METERS, MAX_LAT_INCL, MAX_LON_INCL,
+    MIN_LAT_INCL, MIN_LON_INCL,
+};
+
+use error::Result;
+
+/// A latitude/longitude box, in degrees.
+///
+/// `min_lon > max_lon` means the box crosses the dateline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rectangle {
+    pub min_lat: f64,
+    pub max_lat: f64,
+    pub min_lon: f64,
+    pub max_lon: f64,
+}
+
+impl Rectangle {
+    pub fn new(min_lat: f64, max_lat: f64, min_lon: f64, max_lon: f64) -> Result<Rectangle> {
+        check_latitude(min_lat)?;
+        check_latitude(max_lat)?;
+        check_longitude(min_lon)?;
+        check_longitude(max_lon)?;
+        Ok(Rectangle {
+            min_lat,
+            max_lat,
+            min_lon,
+            max_lon,
+        })
+    }
+
+    pub fn crosses_dateline(&self) -> bool {
+        self.max_lon < self.min_lon
+    }
+
+    /// Returns the bounding box of the points within `radius_meters` of the
+    /// center.
+    pub fn from_point_distance(
+        center_lat: f64,
+        center_lon: f64,
+        radius_meters: f64,
+    ) -> Result<Rectangle> {
+        check_latitude(center_lat)?;
+        check_longitude(center_lon)?;
+        let rad_lat = center_lat.to_radians();
+        let rad_lon = center_lon.to_radians();
+        // pad the radius a little for the error of the haversine formula
+        let rad_distance = (radius_meters + 7e-2) / EARTH_MEAN_RADIUS_METERS;
+        let mut min_lat = rad_lat - rad_distance;
+        let mut max_lat = rad_lat + rad_distance;
+        let (min_lon, max_lon) =
+            if min_lat > MIN_LAT_INCL.to_radians() && max_lat < MAX_LAT_INCL.to_radians() {
+                let delta_lon = (rad_distance.sin() / rad_lat.cos()).asin();
+                let mut min_lon = rad_lon - delta_lon;
+                if min_lon < MIN_LON_INCL.to_radians() {
+                    min_lon += 2.0 * PI;
+                }
+                let mut max_lon = rad_lon + delta_lon;
+                if max_lon > MAX_LON_INCL.to_radians() {
+                    max_lon -= 2.0 * PI;
+                }
+                (min_lon, max_lon)
+            } else {
+                // a pole is within the distance
+                min_lat = min_lat.max(MIN_LAT_INCL.to_radians());
+                max_lat = max_lat.min(MAX_LAT_INCL.to_radians());
+                (MIN_LON_INCL.to_radians(), MAX_LON_INCL.to_radians())
+            };
+
+        Ok(Rectangle {
+            min_lat: min_lat.to_degrees().max(MIN_LAT_INCL),
+            max_lat: max_lat.to_degrees().min(MAX_LAT_INCL),
+            min_lon: min_lon.to_degrees().max(MIN_LON_INCL),
+            max_lon: max_lon.to_degrees().min(MAX_LON_INCL),
+        })
+    }
+
+    /// Returns the latitude at which the circle of the given center and
+    /// radius touches the longitudes of its bounding box.
+    pub fn axis_lat(center_lat: f64, radius_meters: f64) -> f64 {
+        // with r the radius and c1 the colatitude of the center, the point of
+        // colatitude c2 at which the circle touches a meridian forms a right
+        // spherical triangle with the pole, so cos(c1) = cos(r) * cos(c2)
+        let r = radius_meters / EARTH_MEAN_RADIUS_METERS;
+        let c1 = (MAX_LAT_INCL - center_lat.abs()).to_radians();
+        if c1 <= r {
+            // the pole is within the circle
+            return MAX_LAT_INCL.copysign(center_lat);
+        }
+        let c2 = (c1.cos() / r.cos()).min(1.0).acos();
+        (MAX_LAT_INCL - c2.to_degrees()).copysign(center_lat)
+    }
+}
+
+impl fmt::Display for Rectangle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Rectangle(lat={} TO {} lon={} TO {}{})",
+            self.min_lat,
+            self.max_lat,
+            self.min_lon,
+            self.max_lon,
+            if self.crosses_dateline() {
+                " [crossesDateline]"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::geo::haversin_meters;
+
+    #[test]
+    fn test_from_point_distance() {
+        let rect = Rectangle::from_point_distance(40.0, -74.0, 10_000.0).unwrap();
+        assert!(!rect.crosses_dateline());
+        assert!(rect.min_lat < 40.0 && rect.max_lat > 40.0);
+        // the box edges are a bit more than the radius away from the center
+        let d = haversin_meters(40.0, -74.0, rect.max_lat, -74.0);
+        assert!(d >= 10_000.0 && d < 10_001.0, "{}", d);
+        let d = haversin_meters(40.0, -74.0, 40.0, rect.max_lon);
+        assert!(d >= 10_000.0, "{}", d);
+
+        let rect = Rectangle::from_point_distance(0.0, 179.99, 10_000.0).unwrap();
+        assert!(rect.crosses_dateline());
+
+        let rect = Rectangle::from_point_distance(89.99, 0.0, 10_000.0).unwrap();
+        assert_eq!(rect.max_lat, 90.0);
+        assert_eq!(rect.min_lon, -180.0);
+        assert_eq!(rect.max_lon, 180.0);
+    }
+
+    #[test]
+    fn test_axis_lat() {
+        assert!(Rectangle::axis_lat(0.0, 1000.0).abs() < 1e-9);
+        let lat = Rectangle::axis_lat(45.0, 100_000.0);
+        assert!(lat > 45.0 && lat < 46.0, "{}", lat);
+        assert!(Rectangle::axis_lat(-45.0, 100_000.0) < -45.0);
+        assert_eq!(Rectangle::axis_lat(89.5, 100_000.0), 90.0);
+    }
+}
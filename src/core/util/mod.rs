@@ -16,6 +16,7 @@ pub type DocId = i32;
 pub mod bkd;
 pub mod external;
 pub mod fst;
+pub mod geo;
 pub mod packed;
 
 mod numeric;
@@ -114,6 +115,10 @@ mod disi;
 
 pub use self::disi::*;
 
+mod vector_util;
+
+pub use self::vector_util::*;
+
 use std::ops::Deref;
 
 use core::codec::doc_values::NumericDocValues;
@@ -0,0 +1,187 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+
+use error::{ErrorKind, Result};
+
+/// Similarity function used to compare two dense vectors.
+///
+/// Every variant maps its raw measure into a score where larger means more similar,
+/// so the result can be used directly as a document score.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize)]
+pub enum VectorSimilarity {
+    /// `1 / (1 + squared_distance(a, b))`
+    Euclidean,
+    /// `(1 + dot_product(a, b)) / 2`, vectors are expected to be unit length
+    DotProduct,
+    /// `(1 + cosine(a, b)) / 2`
+    Cosine,
+}
+
+impl VectorSimilarity {
+    pub fn compare(&self, a: &[f32], b: &[f32]) -> f32 {
+        match *self {
+            VectorSimilarity::Euclidean => 1.0 / (1.0 + squared_distance(a, b)),
+            VectorSimilarity::DotProduct => (1.0 + dot_product(a, b)).max(0.0) / 2.0,
+            VectorSimilarity::Cosine => (1.0 + cosine(a, b)) / 2.0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            VectorSimilarity::Euclidean => "EUCLIDEAN",
+            VectorSimilarity::DotProduct => "DOT_PRODUCT",
+            VectorSimilarity::Cosine => "COSINE",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<VectorSimilarity> {
+        match name {
+            "EUCLIDEAN" => Ok(VectorSimilarity::Euclidean),
+            "DOT_PRODUCT" => Ok(VectorSimilarity::DotProduct),
+            "COSINE" => Ok(VectorSimilarity::Cosine),
+            _ => bail!(ErrorKind::IllegalArgument(format!(
+                "unknown vector similarity: {}",
+                name
+            ))),
+        }
+    }
+
+    pub fn ordinal(&self) -> u8 {
+        match *self {
+            VectorSimilarity::Euclidean => 0,
+            VectorSimilarity::DotProduct => 1,
+            VectorSimilarity::Cosine => 2,
+        }
+    }
+
+    pub fn from_ordinal(ordinal: u8) -> Result<VectorSimilarity> {
+        match ordinal {
+            0 => Ok(VectorSimilarity::Euclidean),
+            1 => Ok(VectorSimilarity::DotProduct),
+            2 => Ok(VectorSimilarity::Cosine),
+            _ => bail!(ErrorKind::CorruptIndex(format!(
+                "invalid vector similarity ordinal: {}",
+                ordinal
+            ))),
+        }
+    }
+}
+
+impl Default for VectorSimilarity {
+    fn default() -> Self {
+        VectorSimilarity::Euclidean
+    }
+}
+
+impl fmt::Display for VectorSimilarity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// the loops below are unrolled into 4 independent accumulators, which lets
+// the compiler keep them in separate registers and auto-vectorize the body.
+
+pub fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+    let mut acc = [0f32; 4];
+    let chunks = len / 4 * 4;
+    let mut i = 0;
+    while i < chunks {
+        acc[0] += a[i] * b[i];
+        acc[1] += a[i + 1] * b[i + 1];
+        acc[2] += a[i + 2] * b[i + 2];
+        acc[3] += a[i + 3] * b[i + 3];
+        i += 4;
+    }
+    let mut res = acc[0] + acc[1] + acc[2] + acc[3];
+    for j in chunks..len {
+        res += a[j] * b[j];
+    }
+    res
+}
+
+pub fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert_eq!(a.len(), b.len());
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+    let mut acc = [0f32; 4];
+    let chunks = len / 4 * 4;
+    let mut i = 0;
+    while i < chunks {
+        let d0 = a[i] - b[i];
+        let d1 = a[i + 1] - b[i + 1];
+        let d2 = a[i + 2] - b[i + 2];
+        let d3 = a[i + 3] - b[i + 3];
+        acc[0] += d0 * d0;
+        acc[1] += d1 * d1;
+        acc[2] += d2 * d2;
+        acc[3] += d3 * d3;
+        i += 4;
+    }
+    let mut res = acc[0] + acc[1] + acc[2] + acc[3];
+    for j in chunks..len {
+        let d = a[j] - b[j];
+        res += d * d;
+    }
+    res
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot = dot_product(a, b);
+    let norm = (dot_product(a, a) * dot_product(b, b)).sqrt();
+    if norm == 0.0 {
+        0.0
+    } else {
+        dot / norm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vector_math() {
+        let a = [1.0f32, 2.0, 3.0, 4.0, 5.0];
+        let b = [5.0f32, 4.0, 3.0, 2.0, 1.0];
+        assert!((dot_product(&a, &b) - 35.0).abs() < 1e-6);
+        assert!((squared_distance(&a, &b) - 40.0).abs() < 1e-6);
+        assert!((cosine(&a, &a) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine(&a, &[0.0; 5]), 0.0);
+    }
+
+    #[test]
+    fn test_similarity() {
+        let a = [1.0f32, 0.0];
+        let b = [0.0f32, 1.0];
+        assert!((VectorSimilarity::Euclidean.compare(&a, &a) - 1.0).abs() < 1e-6);
+        assert!((VectorSimilarity::Euclidean.compare(&a, &b) - 1.0 / 3.0).abs() < 1e-6);
+        assert!((VectorSimilarity::DotProduct.compare(&a, &b) - 0.5).abs() < 1e-6);
+        assert!((VectorSimilarity::Cosine.compare(&a, &a) - 1.0).abs() < 1e-6);
+
+        for sim in &[
+            VectorSimilarity::Euclidean,
+            VectorSimilarity::DotProduct,
+            VectorSimilarity::Cosine,
+        ] {
+            assert_eq!(VectorSimilarity::from_name(sim.name()).unwrap(), *sim);
+            assert_eq!(VectorSimilarity::from_ordinal(sim.ordinal()).unwrap(), *sim);
+        }
+        assert!(VectorSimilarity::from_name("MANHATTAN").is_err());
+    }
+}
@@ -69,6 +69,11 @@ error_chain! {
             description(errmsg)
             display("Runtime Error: {}", errmsg)
         }
+
+        LockObtainFailed(errmsg: String) {
+            description(errmsg)
+            display("Lock obtain failed: {}", errmsg)
+        }
     }
 
     foreign_links {